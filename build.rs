@@ -0,0 +1,40 @@
+use std::{env, fs, path::Path, process::Command};
+
+/// Record what this binary was built from for `tau-cli version`.
+fn main() {
+    let manifest = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&manifest)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    // The engine's version is whichever the lock file resolved, not the requirement in Cargo.toml
+    let lock = fs::read_to_string(Path::new(&manifest).join("Cargo.lock")).unwrap_or_default();
+    let engine = lock
+        .split("[[package]]")
+        .find(|p| p.contains("name = \"tau-engine\""))
+        .and_then(|p| p.lines().find_map(|l| l.strip_prefix("version = ")))
+        .map(|v| v.trim_matches('"').to_string())
+        .unwrap_or_else(|| "unknown".into());
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(k, _)| {
+            k.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .filter(|f| f != "default")
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=TAU_COMMIT={}", commit);
+    println!("cargo:rustc-env=TAU_ENGINE_VERSION={}", engine);
+    println!("cargo:rustc-env=TAU_FEATURES={}", features.join(","));
+    println!(
+        "cargo:rustc-env=TAU_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
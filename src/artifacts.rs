@@ -0,0 +1,362 @@
+use std::{
+    convert::TryInto,
+    fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::mpsc::{self, SyncSender},
+    thread,
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::event::filetime;
+use crate::input::Input;
+use crate::iocs::split_csv;
+
+/// The Windows execution artifacts which can be read.
+#[derive(Clone, Copy)]
+pub enum Artifact {
+    Prefetch,
+    /// CSV exported by AppCompatCacheParser.
+    Shimcache,
+    /// CSV exported by AmcacheParser.
+    Amcache,
+}
+
+fn u16_at(b: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(b.get(at..at + 2)?.try_into().ok()?))
+}
+fn u32_at(b: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(b.get(at..at + 4)?.try_into().ok()?))
+}
+fn u64_at(b: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(b.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn utf16(b: &[u8]) -> String {
+    let units: Vec<u16> = b
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// The largest decompressed prefetch file read, larger sizes in the header are corrupt.
+const MAX_PREFETCH_SIZE: usize = 64 * 1024 * 1024;
+
+/// Decompress LZXPRESS Huffman data, as described in MS-XCA, which Windows 10 uses for prefetch files.
+fn xpress_huffman(input: &[u8], size: usize) -> Option<Vec<u8>> {
+    if size > MAX_PREFETCH_SIZE {
+        return None;
+    }
+    let mut out = Vec::with_capacity(size);
+    let mut pos = 0;
+    while out.len() < size {
+        // Each 64KiB block of output starts with the bit lengths of its 512 symbols
+        let lengths: Vec<u8> = input
+            .get(pos..pos + 256)?
+            .iter()
+            .flat_map(|b| [b & 0x0f, b >> 4])
+            .collect();
+        pos += 256;
+        let mut table = vec![0u16; 1 << 15];
+        let mut entry = 0;
+        for bits in 1..=15 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, l)| **l == bits) {
+                let count = 1 << (15 - bits);
+                table.get_mut(entry..entry + count)?.fill(symbol as u16);
+                entry += count;
+            }
+        }
+        if entry != table.len() {
+            return None;
+        }
+        let read16 = |pos: usize| u16_at(input, pos).unwrap_or(0) as u32;
+        let mut bits = (read16(pos) << 16) | read16(pos + 2);
+        pos += 4;
+        let mut extra: i32 = 16;
+        let mut consume = |bits: &mut u32, pos: &mut usize, n: u32| {
+            *bits = bits.checked_shl(n).unwrap_or(0);
+            extra -= n as i32;
+            if extra < 0 {
+                *bits |= read16(*pos) << -extra;
+                extra += 16;
+                *pos += 2;
+            }
+        };
+        // A match may run on past the end of the block, the next block starts where it ends
+        let end = (out.len() + 65536).min(size);
+        while out.len() < end {
+            let symbol = table[(bits >> 17) as usize] as usize;
+            consume(&mut bits, &mut pos, lengths[symbol] as u32);
+            if symbol < 256 {
+                out.push(symbol as u8);
+                continue;
+            }
+            let symbol = symbol - 256;
+            let offset_bits = (symbol >> 4) as u32;
+            let mut length = symbol & 0x0f;
+            if length == 15 {
+                length = *input.get(pos)? as usize;
+                pos += 1;
+                if length == 255 {
+                    length = u16_at(input, pos)? as usize;
+                    pos += 2;
+                    if length == 0 {
+                        length = u32_at(input, pos)? as usize;
+                        pos += 4;
+                    }
+                    length = length.checked_sub(15)?;
+                }
+                length += 15;
+            }
+            length += 3;
+            let offset = match offset_bits {
+                0 => 1,
+                n => ((bits >> (32 - n)) + (1 << n)) as usize,
+            };
+            consume(&mut bits, &mut pos, offset_bits);
+            let start = out.len().checked_sub(offset)?;
+            for i in 0..length.min(size - out.len()) {
+                out.push(out[start + i]);
+            }
+        }
+    }
+    Some(out)
+}
+
+/// Parse a prefetch file, decompressing it first when it is in the Windows 10 `MAM` format.
+pub fn prefetch(data: &[u8], source: &str) -> Result<Value, String> {
+    let invalid = || format!("{} is not a valid prefetch file", source);
+    let decompressed;
+    let data = match data.get(..3) {
+        Some(b"MAM") => {
+            let size = u32_at(data, 4).ok_or_else(invalid)? as usize;
+            // A set high bit on the fourth byte means a CRC comes before the compressed data
+            let start = match data.get(3) {
+                Some(b) if b & 0x80 != 0 => 12,
+                _ => 8,
+            };
+            decompressed = xpress_huffman(data.get(start..).ok_or_else(invalid)?, size)
+                .ok_or_else(|| format!("Unable to decompress {}", source))?;
+            &decompressed[..]
+        }
+        _ => data,
+    };
+    if data.get(4..8) != Some(b"SCCA") {
+        return Err(invalid());
+    }
+    let version = u32_at(data, 0).ok_or_else(invalid)?;
+    let field = |at: usize| u32_at(data, at).ok_or_else(invalid);
+    let (runs, run_count) = match version {
+        17 => (vec![u64_at(data, 0x78)], field(0x90)?),
+        23 => (vec![u64_at(data, 0x80)], field(0x98)?),
+        26 | 30 | 31 => {
+            let runs = (0..8).map(|i| u64_at(data, 0x80 + i * 8)).collect();
+            // Some Windows 10 builds moved the run count
+            let count = match field(0xd0)? {
+                0 => field(0xc8)?,
+                n => n,
+            };
+            (runs, count)
+        }
+        _ => {
+            return Err(format!(
+                "Unsupported prefetch version {} in {}",
+                version, source
+            ))
+        }
+    };
+    let (names_offset, names_size) = (field(0x64)? as usize, field(0x68)? as usize);
+    let files: Vec<String> = data
+        .get(names_offset..names_offset + names_size)
+        .map(|b| utf16(b).split('\0').map(|s| s.to_string()).collect())
+        .unwrap_or_default();
+    let (volumes_offset, volume_count) = (field(0x6c)? as usize, field(0x70)? as usize);
+    let entry_size = match version {
+        17 | 23 => 40,
+        _ => 104,
+    };
+    let mut volumes = Vec::new();
+    for i in 0..volume_count.min(64) {
+        let entry = volumes_offset + i * entry_size;
+        let (path_offset, path_len) = match (u32_at(data, entry), u32_at(data, entry + 4)) {
+            (Some(o), Some(l)) => (volumes_offset + o as usize, l as usize * 2),
+            _ => break,
+        };
+        volumes.push(json!({
+            "device": data.get(path_offset..path_offset + path_len).map(utf16),
+            "created": u64_at(data, entry + 8).map(filetime),
+            "serial": u32_at(data, entry + 16).map(|s| format!("{:08X}", s)),
+        }));
+    }
+    let executable = data.get(0x10..0x4c).map(utf16).unwrap_or_default();
+    Ok(json!({
+        "artifact": "prefetch",
+        "source": source,
+        "version": version,
+        "executable": executable.split('\0').next(),
+        "hash": field(0x4c).map(|h| format!("{:08X}", h))?,
+        "run_count": run_count,
+        "last_run": runs.into_iter().flatten().filter(|t| *t != 0).map(filetime).collect::<Vec<_>>(),
+        "files": files.into_iter().filter(|f| !f.is_empty()).collect::<Vec<_>>(),
+        "volumes": volumes,
+    }))
+}
+
+/// Turn a row of a Shimcache or Amcache export into an event, adding `path`, `sha1`, `executed` and
+/// `last_modified` fields under the same names for both.
+fn export_row(artifact: Artifact, header: &[String], line: &str, source: &str) -> Value {
+    let mut row: Map<String, Value> = header
+        .iter()
+        .cloned()
+        .zip(split_csv(line).into_iter().map(Value::String))
+        .collect();
+    let column = |names: &[&str]| -> Option<String> {
+        names
+            .iter()
+            .find_map(|n| row.get(*n).and_then(|v| v.as_str()))
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_string())
+    };
+    let path = column(&["Path", "FullPath"]);
+    let last_modified = column(&[
+        "LastModifiedTimeUTC",
+        "FileKeyLastWriteTimestamp",
+        "LastModified",
+    ]);
+    // Amcache file ids are the SHA1 with four leading zeros
+    let sha1 = column(&["SHA1", "FileID"]).map(|h| match h.len() {
+        44 => h[4..].to_lowercase(),
+        _ => h.to_lowercase(),
+    });
+    let executed = match column(&["Executed"]).as_deref() {
+        Some("Yes") | Some("True") => json!(true),
+        Some("No") | Some("False") => json!(false),
+        _ => Value::Null,
+    };
+    let name = match artifact {
+        Artifact::Amcache => "amcache",
+        _ => "shimcache",
+    };
+    row.insert("artifact".into(), json!(name));
+    row.insert("source".into(), json!(source));
+    row.insert("path".into(), json!(path));
+    row.insert("sha1".into(), json!(sha1));
+    row.insert("executed".into(), executed);
+    row.insert("last_modified".into(), json!(last_modified));
+    Value::Object(row)
+}
+
+fn read(
+    artifact: Artifact,
+    path: &PathBuf,
+    tx: &SyncSender<Result<Value, String>>,
+) -> Result<bool, String> {
+    let source = path.display().to_string();
+    if let Artifact::Prefetch = artifact {
+        let data =
+            fs::read(path).map_err(|_| format!("Unable to read input file at {}.", source))?;
+        return Ok(tx.send(prefetch(&data, &source)).is_ok());
+    }
+    let file =
+        fs::File::open(path).map_err(|_| format!("Unable to read input file at {}.", source))?;
+    let mut lines = BufReader::new(file).lines().map_while(Result::ok);
+    let header = match lines.next() {
+        Some(h) => split_csv(h.trim_start_matches('\u{feff}')),
+        None => return Ok(true),
+    };
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        if tx
+            .send(Ok(export_row(artifact, &header, &line, &source)))
+            .is_err()
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Read prefetch files, or Shimcache and Amcache CSV exports, as events.
+pub fn open(artifact: Artifact, paths: Vec<PathBuf>) -> Result<Input, String> {
+    for path in paths.iter() {
+        if !path.is_file() {
+            return Err(format!("Unable to read input file at {}.", path.display()));
+        }
+    }
+    let (tx, rx) = mpsc::sync_channel(1024);
+    thread::spawn(move || {
+        for path in paths {
+            match read(artifact, &path, &tx) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    if tx.send(Err(e)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A block whose table gives the literals 0 to 254 8 bit codes, their own values, and the
+    /// matches 256, of 3 bytes at offset 1, and 271 9 bit codes, followed by `codes`.
+    fn block(codes: &[(u32, u32)]) -> Vec<u8> {
+        let mut block = vec![0u8; 256];
+        let mut length = |symbol: usize, bits: u8| block[symbol / 2] |= bits << (4 * (symbol % 2));
+        (0..255).for_each(|s| length(s, 8));
+        length(256, 9);
+        length(271, 9);
+        let mut bits = Vec::new();
+        for (code, n) in codes {
+            bits.extend((0..*n).rev().map(|i| (code >> i) & 1 == 1));
+        }
+        // The decoder reads 32 bits ahead, then 16 more each time it runs short
+        let refills = bits.len().saturating_sub(16).div_ceil(16);
+        bits.resize((2 + refills) * 16, false);
+        for word in bits.chunks(16) {
+            let word = word.iter().fold(0u16, |w, b| (w << 1) | *b as u16);
+            block.extend(word.to_le_bytes());
+        }
+        block
+    }
+
+    #[test]
+    fn literals() {
+        let input = block(&[(b'a' as u32, 8), (b'b' as u32, 8), (b'c' as u32, 8)]);
+        assert_eq!(xpress_huffman(&input, 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn matches_may_cross_blocks() {
+        let mut codes = vec![(b'a' as u32, 8); 65535];
+        codes.push((510, 9));
+        let mut input = block(&codes);
+        input.extend(block(&[(b'b' as u32, 8), (b'b' as u32, 8)]));
+        let out = xpress_huffman(&input, 65540).unwrap();
+        assert_eq!(out.len(), 65540);
+        assert!(out[..65538].iter().all(|b| *b == b'a'));
+        assert_eq!(&out[65538..], b"bb");
+    }
+
+    #[test]
+    fn matches_stop_at_the_size() {
+        let input = block(&[(b'a' as u32, 8), (510, 9)]);
+        assert_eq!(xpress_huffman(&input, 2).unwrap(), b"aa");
+    }
+
+    #[test]
+    fn corrupt_sizes_are_rejected() {
+        let input = block(&[(b'a' as u32, 8)]);
+        assert!(xpress_huffman(&input, usize::MAX).is_none());
+        assert!(xpress_huffman(&input[..100], 1).is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    env, fs,
+    io::{stderr, stdout, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+use rayon::prelude::*;
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use crate::output::csv_escape;
+use crate::MATCHED;
+
+#[derive(StructOpt)]
+pub struct Batch {
+    /// A YAML file of jobs, each with a name, rules, inputs, an output and optional args.
+    #[structopt(long, parse(from_os_str))]
+    jobs: PathBuf,
+
+    /// The number of jobs to run at once, 0 runs one per core.
+    #[structopt(long, default_value = "0")]
+    workers: usize,
+}
+
+#[derive(Deserialize)]
+struct Jobs {
+    /// Rules used by jobs which don't list their own.
+    #[serde(default)]
+    rules: Vec<PathBuf>,
+    /// Arguments passed to every job, before the job's own.
+    #[serde(default)]
+    args: Vec<String>,
+    jobs: Vec<Job>,
+}
+
+#[derive(Deserialize)]
+struct Job {
+    name: String,
+    #[serde(default)]
+    rules: Vec<PathBuf>,
+    input: Vec<PathBuf>,
+    output: PathBuf,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+struct Outcome {
+    code: Option<i32>,
+    matches: usize,
+    seconds: f64,
+    /// Lines written to stderr other than the stats.
+    messages: Vec<String>,
+}
+
+/// Total the matches reported by --stats, keeping every other line of stderr.
+fn read_stats(stderr: &str) -> (usize, Vec<String>) {
+    let (mut matches, mut messages, mut in_stats) = (0, Vec::new(), false);
+    for line in stderr.lines() {
+        if line == "Rule Name, Matches" {
+            in_stats = true;
+            continue;
+        }
+        if in_stats {
+            match line.rsplit_once(", ").map(|(_, c)| c.parse::<usize>()) {
+                Some(Ok(count)) => {
+                    matches += count;
+                    continue;
+                }
+                _ => in_stats = false,
+            }
+        }
+        messages.push(line.to_string());
+    }
+    (matches, messages)
+}
+
+impl Batch {
+    fn run_job(&self, exe: &Path, dir: &Path, jobs: &Jobs, job: &Job) -> Outcome {
+        let start = Instant::now();
+        let rules = match job.rules.is_empty() {
+            true => &jobs.rules,
+            false => &job.rules,
+        };
+        let mut command = Command::new(exe);
+        command.arg("match");
+        for rule in rules {
+            command.arg(format!("--rules={}", dir.join(rule).display()));
+        }
+        for input in job.input.iter() {
+            command.arg(format!("--input={}", dir.join(input).display()));
+        }
+        command
+            .arg(format!("--output={}", dir.join(&job.output).display()))
+            .arg("--stats")
+            .args(jobs.args.iter())
+            .args(job.args.iter())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null());
+        let (code, matches, messages) = match command.output() {
+            Ok(output) => {
+                let (matches, messages) = read_stats(&String::from_utf8_lossy(&output.stderr));
+                (output.status.code(), matches, messages)
+            }
+            Err(e) => (None, 0, vec![format!("Unable to start job, {}", e)]),
+        };
+        Outcome {
+            code,
+            matches,
+            seconds: start.elapsed().as_secs_f64(),
+            messages,
+        }
+    }
+
+    pub fn run(self) -> Result<(), String> {
+        let jobs: Jobs = serde_yaml::from_str(
+            &fs::read_to_string(&self.jobs)
+                .map_err(|_| format!("Unable to read data from {}.", self.jobs.display()))?,
+        )
+        .map_err(|e| format!("Invalid jobs file {}, {}", self.jobs.display(), e))?;
+        for job in jobs.jobs.iter() {
+            if job.rules.is_empty() && jobs.rules.is_empty() {
+                return Err(format!("Job {} has no rules", job.name));
+            }
+        }
+        let dir = self.jobs.parent().unwrap_or_else(|| Path::new(""));
+        // Each job is a run of this binary, so one failing can't take the others down
+        let exe = env::current_exe().map_err(|e| format!("Unable to find tau-cli, {}", e))?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.workers)
+            .build()
+            .map_err(|e| format!("Unable to start workers, {}", e))?;
+        let outcomes: Vec<Outcome> = pool.install(|| {
+            jobs.jobs
+                .par_iter()
+                .map(|job| self.run_job(&exe, dir, &jobs, job))
+                .collect()
+        });
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let _ = writeln!(stdout, "Job, Status, Matches, Seconds");
+        let mut failed = 0;
+        for (job, outcome) in jobs.jobs.iter().zip(outcomes.iter()) {
+            // --fail-fast exits with MATCHED when anything matched, which is still a success
+            let status = match outcome.code {
+                Some(0) | Some(MATCHED) => "ok".to_string(),
+                Some(code) => format!("failed ({})", code),
+                None => "failed".to_string(),
+            };
+            if !matches!(outcome.code, Some(0) | Some(MATCHED)) {
+                failed += 1;
+            }
+            let _ = writeln!(
+                stdout,
+                "{}, {}, {}, {:.2}",
+                csv_escape(&job.name),
+                status,
+                outcome.matches,
+                outcome.seconds
+            );
+        }
+        for (job, outcome) in jobs.jobs.iter().zip(outcomes.iter()) {
+            for message in outcome.messages.iter() {
+                let _ = writeln!(stderr, "{}: {}", job.name, message);
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            n => Err(format!("{} of {} jobs failed", n, jobs.jobs.len())),
+        }
+    }
+}
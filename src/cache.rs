@@ -0,0 +1,210 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use uuid::Uuid;
+
+/// How long a partly written entry can go unmodified before pruning removes it, as left by a run
+/// which exited part way through a file.
+const STALE: Duration = Duration::from_secs(60 * 60);
+
+/// A directory of input files which have already been parsed, stored as MessagePack so later runs over
+/// the same files can skip JSON parsing.
+pub struct Cache {
+    dir: PathBuf,
+    max_files: usize,
+    /// Whether malformed lines are repaired, which changes the events a file holds.
+    lenient: bool,
+}
+
+/// Where to read an input file's events from.
+pub enum Entry {
+    /// The file has been parsed before.
+    Hit(fs::File),
+    /// The file needs parsing, events should be written to the writer as they are parsed.
+    Miss(CacheWriter),
+}
+
+/// Writes a file's entry to a temporary file, which is removed unless the entry is committed.
+pub struct CacheWriter {
+    tmp: PathBuf,
+    dest: PathBuf,
+    writer: BufWriter<fs::File>,
+    /// The file being cached and its size and modification time when opened.
+    source: PathBuf,
+    stamp: (u64, Duration),
+    committed: bool,
+}
+
+/// A file's size and modification time.
+fn stamp(path: &Path) -> io::Result<(u64, Duration)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((metadata.len(), modified))
+}
+
+impl Cache {
+    pub fn new(dir: &Path, max_files: usize, lenient: bool) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|_| format!("Could not create cache directory at {}", dir.display()))?;
+        Ok(Cache {
+            dir: dir.to_path_buf(),
+            max_files,
+            lenient,
+        })
+    }
+
+    /// The cache entry for a file is named after its path, size and modification time, so edited files
+    /// are parsed again, and whether malformed lines are repaired. The name is a hash which is the
+    /// same across builds, so entries outlive upgrades.
+    fn key(&self, path: &Path, (len, modified): (u64, Duration)) -> io::Result<String> {
+        // Repaired lines are only cached when repairing, so runs without it still see them fail
+        let key = format!(
+            "{}\n{}\n{}.{:09}\n{}",
+            fs::canonicalize(path)?.display(),
+            len,
+            modified.as_secs(),
+            modified.subsec_nanos(),
+            self.lenient
+        );
+        Ok(format!(
+            "{}.msgpack",
+            Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes()).simple()
+        ))
+    }
+
+    pub fn open(&self, path: &Path) -> io::Result<Entry> {
+        let stamp = stamp(path)?;
+        let dest = self.dir.join(self.key(path, stamp)?);
+        if let Ok(f) = fs::File::open(&dest) {
+            // Mark the entry as recently used so it survives pruning
+            let _ = f.set_modified(std::time::SystemTime::now());
+            return Ok(Entry::Hit(f));
+        }
+        let tmp = dest.with_extension(format!("{}.tmp", std::process::id()));
+        Ok(Entry::Miss(CacheWriter {
+            writer: BufWriter::new(fs::File::create(&tmp)?),
+            tmp,
+            dest,
+            source: path.to_path_buf(),
+            stamp,
+            committed: false,
+        }))
+    }
+
+    /// Remove the least recently used entries beyond the maximum number of cached files, and
+    /// partly written entries which are no longer being written.
+    pub fn prune(&self) -> io::Result<()> {
+        let mut entries = Vec::new();
+        let now = SystemTime::now();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let modified = entry.metadata()?.modified()?;
+            match entry.path().extension().and_then(|e| e.to_str()) {
+                Some("msgpack") => entries.push((modified, entry.path())),
+                Some("tmp") if now.duration_since(modified).unwrap_or_default() > STALE => {
+                    let _ = fs::remove_file(entry.path());
+                }
+                _ => {}
+            }
+        }
+        entries.sort();
+        let excess = entries.len().saturating_sub(self.max_files);
+        for (_, path) in entries.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl CacheWriter {
+    pub fn write(&mut self, json: &serde_json::Value) -> io::Result<()> {
+        rmp_serde::encode::write(&mut self.writer, json).map_err(io::Error::other)
+    }
+
+    /// Make the entry available to later runs, this is only called once the whole file has been read.
+    /// A file which changed while it was read is left uncached, as the entry may not match it.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if stamp(&self.source)? == self.stamp {
+            fs::rename(&self.tmp, &self.dest)?;
+            self.committed = true;
+        }
+        Ok(())
+    }
+
+    /// Discard the entry, its temporary file is removed as the writer is dropped.
+    pub fn abandon(self) {}
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn entries_are_committed_once_read() {
+        let (dir, input) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let path = input.path().join("a.json");
+        fs::write(&path, "{}\n").unwrap();
+        let cache = Cache::new(dir.path(), 10, false).unwrap();
+        let key = cache.key(&path, stamp(&path).unwrap()).unwrap();
+        assert_eq!(key, cache.key(&path, stamp(&path).unwrap()).unwrap());
+        match cache.open(&path).unwrap() {
+            Entry::Miss(mut w) => {
+                w.write(&json!({})).unwrap();
+                w.commit().unwrap();
+            }
+            Entry::Hit(_) => panic!("Nothing is cached yet"),
+        }
+        assert_eq!(entries(dir.path()), vec![key]);
+        assert!(matches!(cache.open(&path).unwrap(), Entry::Hit(_)));
+    }
+
+    #[test]
+    fn abandoned_and_changed_entries_are_removed() {
+        let (dir, input) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let path = input.path().join("a.json");
+        fs::write(&path, "{}\n").unwrap();
+        let cache = Cache::new(dir.path(), 10, false).unwrap();
+        match cache.open(&path).unwrap() {
+            Entry::Miss(w) => w.abandon(),
+            Entry::Hit(_) => panic!("Nothing is cached yet"),
+        }
+        assert!(entries(dir.path()).is_empty());
+        match cache.open(&path).unwrap() {
+            Entry::Miss(w) => {
+                fs::write(&path, "{}\n{}\n").unwrap();
+                w.commit().unwrap();
+            }
+            Entry::Hit(_) => panic!("Nothing is cached yet"),
+        }
+        assert!(entries(dir.path()).is_empty());
+        // A writer dropped part way through the file leaves nothing behind
+        drop(cache.open(&path).unwrap());
+        assert!(entries(dir.path()).is_empty());
+    }
+}
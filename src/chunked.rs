@@ -0,0 +1,133 @@
+use std::{
+    fs,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::mpsc::{self, SyncSender},
+    thread,
+};
+
+use rayon::prelude::*;
+use serde_json::Value;
+
+use crate::input::{self, Input, Options};
+use crate::lenient;
+use crate::limits;
+use crate::shard::line_start;
+
+/// The size chunks of a file are split into before being moved forward to the start of a line.
+const CHUNK: u64 = 8 * 1024 * 1024;
+/// Chunks read ahead per thread, bounding memory while keeping every thread busy. Fewer are read
+/// ahead under memory pressure.
+const WINDOW: usize = 4;
+
+/// Read and parse the lines of a byte range of a file.
+fn read_chunk(
+    path: &PathBuf,
+    start: u64,
+    end: u64,
+    options: &Options,
+) -> Vec<Result<Value, String>> {
+    let mut data = Vec::with_capacity((end - start) as usize);
+    let read = fs::File::open(path).and_then(|mut f| {
+        f.seek(SeekFrom::Start(start))?;
+        f.take(end - start).read_to_end(&mut data)
+    });
+    if let Err(e) = read {
+        return vec![Err(format!("Unable to read {}, {}", path.display(), e))];
+    }
+    // The last line of a chunk ends with a newline, except perhaps at the end of the file
+    let data = data.strip_suffix(b"\n").unwrap_or(&data);
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let mut batch = Vec::new();
+    for line in data.split(|b| *b == b'\n') {
+        lenient::parse_into(line, options.lenient, &mut batch);
+    }
+    batch
+}
+
+/// The ranges of a file's chunks, each starting and ending at the start of a line.
+fn chunks(path: &PathBuf) -> io::Result<Vec<(u64, u64)>> {
+    let len = fs::metadata(path)?.len();
+    let mut file = BufReader::new(fs::File::open(path)?);
+    let mut bounds = vec![0];
+    let mut offset = CHUNK;
+    while offset < len {
+        let start = line_start(&mut file, offset, len)?;
+        if start > *bounds.last().unwrap_or(&0) {
+            bounds.push(start);
+        }
+        offset = start.max(offset) + CHUNK;
+    }
+    bounds.push(len);
+    bounds.dedup();
+    Ok(bounds.windows(2).map(|w| (w[0], w[1])).collect())
+}
+
+fn read_file(
+    pool: &rayon::ThreadPool,
+    path: &PathBuf,
+    tx: &SyncSender<Vec<Result<Value, String>>>,
+    options: &Options,
+) -> Result<bool, String> {
+    let chunks =
+        chunks(path).map_err(|_| format!("Unable to read input file at {}.", path.display()))?;
+    let mut rest = &chunks[..];
+    while !rest.is_empty() {
+        let (window, next) =
+            rest.split_at(limits::batch(pool.current_num_threads() * WINDOW).min(rest.len()));
+        rest = next;
+        // Chunks are taken by whichever thread is free, and collected back in file order
+        let parsed: Vec<Vec<Result<Value, String>>> = pool.install(|| {
+            window
+                .par_iter()
+                .map(|(start, end)| read_chunk(path, *start, *end, options))
+                .collect()
+        });
+        for batch in parsed {
+            if tx.send(batch).is_err() {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Read JSON lines files by splitting them into chunks aligned to lines, which are read and parsed
+/// on a pool of threads. Events are still given in file order.
+pub fn open(paths: Vec<PathBuf>, threads: usize, options: Options) -> Result<Input, String> {
+    for path in paths.iter() {
+        if !path.is_file() {
+            return Err(format!("Unable to read input file at {}.", path.display()));
+        }
+        if input::is_compressed(path) {
+            return Err(format!(
+                "{} is compressed, so can't be split into chunks for --threads",
+                path.display()
+            ));
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| format!("Unable to start threads, {}", e))?;
+    let (tx, rx) = mpsc::sync_channel(pool.current_num_threads() * WINDOW);
+    thread::spawn(move || {
+        for path in paths {
+            match read_file(&pool, &path, &tx, &options) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    if tx.send(vec![Err(e)]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(Input::Batches {
+        rx,
+        batch: Vec::new().into_iter(),
+    })
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{stderr, stdout, Write},
+    path::{Path, PathBuf},
+};
+
+use structopt::StructOpt;
+
+use crate::input::Input;
+use crate::output::csv_escape;
+
+/// The group used for matches which don't record the rule that produced them.
+const UNTAGGED: &str = "*";
+
+#[derive(StructOpt)]
+pub struct Compare {
+    /// The earlier match output, either a file or a directory of per rule files.
+    #[structopt(parse(from_os_str))]
+    a: PathBuf,
+
+    /// The later match output, either a file or a directory of per rule files.
+    #[structopt(parse(from_os_str))]
+    b: PathBuf,
+
+    /// Comma separated fields identifying a match, the whole event is used when none are given.
+    #[structopt(long, use_delimiter = true)]
+    key: Vec<String>,
+
+    /// List every added and removed match after the summary.
+    #[structopt(long)]
+    details: bool,
+}
+
+type Matches = BTreeMap<String, BTreeSet<String>>;
+
+impl Compare {
+    /// Load the keys of every match grouped by rule, rules come from the file names of a directory of
+    /// per rule outputs, or the `tau.tags` of tagged matches.
+    fn load(&self, path: &Path) -> Result<Matches, String> {
+        let mut matches = Matches::new();
+        let files = match path.is_dir() {
+            true => {
+                let mut files = Vec::new();
+                for entry in fs::read_dir(path)
+                    .map_err(|_| format!("Unable to read directory {}.", path.display()))?
+                {
+                    let entry = entry.map_err(|e| e.to_string())?;
+                    if entry.path().is_file() {
+                        files.push((
+                            Some(entry.file_name().to_string_lossy().into_owned()),
+                            entry.path(),
+                        ));
+                    }
+                }
+                files
+            }
+            false => vec![(None, path.to_path_buf())],
+        };
+        let mut stderr = stderr();
+        for (rule, file) in files {
+            for res in Input::open(Some(vec![file]), None, None)? {
+                let json = match res {
+                    Ok(json) => json,
+                    Err(e) => {
+                        let _ = writeln!(stderr, "{}", e);
+                        continue;
+                    }
+                };
+                let key = crate::event::key(&json, &self.key);
+                let rules: Vec<String> = match &rule {
+                    Some(r) => vec![r.clone()],
+                    None => match crate::event::get(&json, "tau.tags").and_then(|t| t.as_array()) {
+                        Some(tags) => tags
+                            .iter()
+                            .filter_map(|t| t.as_str().map(|s| s.to_string()))
+                            .collect(),
+                        None => vec![UNTAGGED.to_string()],
+                    },
+                };
+                for r in rules {
+                    matches.entry(r).or_default().insert(key.clone());
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    pub fn run(self) -> Result<(), String> {
+        let a = self.load(&self.a)?;
+        let b = self.load(&self.b)?;
+        let empty = BTreeSet::new();
+        let rules: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+        let mut stdout = stdout();
+        let mut write = || -> std::io::Result<()> {
+            writeln!(stdout, "Rule, Added, Removed, Unchanged")?;
+            for rule in rules.iter() {
+                let (before, after) = (
+                    a.get(*rule).unwrap_or(&empty),
+                    b.get(*rule).unwrap_or(&empty),
+                );
+                writeln!(
+                    stdout,
+                    "{}, {}, {}, {}",
+                    csv_escape(rule),
+                    after.difference(before).count(),
+                    before.difference(after).count(),
+                    before.intersection(after).count()
+                )?;
+            }
+            if self.details {
+                for rule in rules.iter() {
+                    let (before, after) = (
+                        a.get(*rule).unwrap_or(&empty),
+                        b.get(*rule).unwrap_or(&empty),
+                    );
+                    for key in after.difference(before) {
+                        writeln!(stdout, "+ {} {}", rule, key)?;
+                    }
+                    for key in before.difference(after) {
+                        writeln!(stdout, "- {} {}", rule, key)?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        write().map_err(|e| format!("Unable to write comparison, {}", e))
+    }
+}
@@ -0,0 +1,150 @@
+use std::{collections::HashMap, str::FromStr};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::event;
+
+/// A feature of a string value computed into a field of its own.
+#[derive(Clone, Copy)]
+pub enum Feature {
+    /// The Shannon entropy of the characters, in bits per character.
+    Entropy,
+    /// The number of characters.
+    Length,
+}
+
+impl Feature {
+    fn name(&self) -> &'static str {
+        match self {
+            Feature::Entropy => "entropy",
+            Feature::Length => "length",
+        }
+    }
+
+    fn compute(&self, s: &str) -> Value {
+        match self {
+            Feature::Entropy => entropy(s).into(),
+            Feature::Length => s.chars().count().into(),
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "entropy" => Ok(Feature::Entropy),
+            "length" => Ok(Feature::Length),
+            _ => Err(format!(
+                "Unknown computed feature {}, expected entropy or length",
+                s
+            )),
+        }
+    }
+}
+
+/// A computed field given as `feature(field)`, written alongside the field with the feature's name
+/// as a suffix, e.g. `entropy(ServiceName)` is written to `ServiceName_entropy`.
+pub struct Computation {
+    feature: Feature,
+    field: String,
+    target: String,
+}
+
+impl FromStr for Computation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (feature, field) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .filter(|(_, f)| !f.is_empty())
+            .ok_or_else(|| format!("Expected feature(field), got {}", s))?;
+        let feature: Feature = feature.trim().parse()?;
+        let field = field.trim().to_string();
+        Ok(Computation {
+            target: format!("{}_{}", field, feature.name()),
+            feature,
+            field,
+        })
+    }
+}
+
+fn entropy(s: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in s.chars() {
+        *counts.entry(c).or_insert(0) += 1;
+    }
+    let total = counts.values().sum::<usize>() as f64;
+    let bits: f64 = counts
+        .values()
+        .map(|n| {
+            let p = *n as f64 / total;
+            -p * p.log2()
+        })
+        .sum();
+    // Rounded so the same value always reads the same, and -0.0 for single characters is avoided
+    (bits * 1000.0).round().abs() / 1000.0
+}
+
+/// Add each computed field to an event, skipping those whose field is missing or isn't a string.
+pub fn compute(json: &mut Value, computations: &[Computation]) {
+    for c in computations {
+        let value = match event::get(json, &c.field) {
+            Some(Value::String(s)) => c.feature.compute(s),
+            _ => continue,
+        };
+        event::set(json, &c.target, value);
+    }
+}
+
+/// A regex given as `field:regex` whose named groups are extracted from the field's value into
+/// fields alongside it, e.g. `Url:\.(?P<extension>\w+)$` extracts `Url_extension`.
+pub struct Capture {
+    field: String,
+    pattern: Regex,
+}
+
+impl FromStr for Capture {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, pattern) = s
+            .split_once(':')
+            .filter(|(f, p)| !f.is_empty() && !p.is_empty())
+            .ok_or_else(|| format!("Expected field:regex, got {}", s))?;
+        let pattern =
+            Regex::new(pattern).map_err(|e| format!("Invalid capture regex {}, {}", pattern, e))?;
+        if pattern.capture_names().flatten().next().is_none() {
+            return Err(format!("The capture regex {} has no named groups", pattern));
+        }
+        Ok(Capture {
+            field: field.to_string(),
+            pattern,
+        })
+    }
+}
+
+/// Extract the named groups of each capture into an event, from the first match in the field's
+/// value. Groups which don't take part in the match are left out.
+pub fn capture(json: &mut Value, captures: &[Capture]) {
+    for c in captures {
+        let found: Vec<(String, Value)> = match event::get(json, &c.field) {
+            Some(Value::String(s)) => match c.pattern.captures(s) {
+                Some(caps) => c
+                    .pattern
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        let value = caps.name(name)?.as_str();
+                        Some((format!("{}_{}", c.field, name), value.into()))
+                    })
+                    .collect(),
+                None => continue,
+            },
+            _ => continue,
+        };
+        for (target, value) in found {
+            event::set(json, &target, value);
+        }
+    }
+}
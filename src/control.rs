@@ -0,0 +1,139 @@
+use std::{
+    io::{stdin, stdout, BufRead, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde_json::{json, Value};
+use tau_engine::Rule;
+
+use crate::rules;
+
+/// The channel a controlling program drives tau-cli over.
+#[derive(Clone, Copy)]
+pub enum Control {
+    Stdio,
+}
+
+impl FromStr for Control {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stdio" => Ok(Control::Stdio),
+            _ => Err(format!("Unknown control channel {}, expected stdio", s)),
+        }
+    }
+}
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// A request which was understood but failed, such as rules which didn't load.
+const FAILED: i64 = -32000;
+
+struct Session {
+    rules: Vec<(String, Rule)>,
+    counts: Vec<usize>,
+    events: u64,
+}
+
+impl Session {
+    /// Replace the loaded rules with those at `paths`.
+    fn load_rules(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let paths: Vec<PathBuf> = match params.get("paths").and_then(|p| p.as_array()) {
+            Some(paths) => paths
+                .iter()
+                .map(|p| p.as_str().map(PathBuf::from))
+                .collect::<Option<_>>()
+                .ok_or((INVALID_PARAMS, "paths must be strings".to_string()))?,
+            None => return Err((INVALID_PARAMS, "expected a paths array".into())),
+        };
+        self.rules = rules::load(&paths).map_err(|e| (FAILED, e))?;
+        self.counts = vec![0; self.rules.len()];
+        self.events = 0;
+        Ok(json!({ "rules": self.rules.iter().map(|(id, _)| id).collect::<Vec<_>>() }))
+    }
+
+    /// The names of the loaded rules an event matches.
+    fn matches(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let event = params
+            .get("event")
+            .ok_or((INVALID_PARAMS, "expected an event".to_string()))?;
+        self.events += 1;
+        let mut matched = Vec::new();
+        for ((id, rule), count) in self.rules.iter().zip(self.counts.iter_mut()) {
+            if rule.matches(event) {
+                *count += 1;
+                matched.push(id.as_str());
+            }
+        }
+        Ok(json!({ "matches": matched }))
+    }
+
+    fn stats(&self) -> Value {
+        let rules: Vec<Value> = self
+            .rules
+            .iter()
+            .zip(self.counts.iter())
+            .map(|((id, _), count)| json!({ "name": id, "matches": count }))
+            .collect();
+        json!({ "events": self.events, "rules": rules })
+    }
+}
+
+/// Answer JSON-RPC 2.0 requests, one per line on stdin, until `shutdown` or the end of stdin. The
+/// methods are `load_rules` with `paths`, `match` with an `event`, `stats` and `shutdown`.
+pub fn serve(paths: &[PathBuf]) -> Result<(), String> {
+    let rules = rules::load(paths)?;
+    let mut session = Session {
+        counts: vec![0; rules.len()],
+        rules,
+        events: 0,
+    };
+    let mut stdout = stdout();
+    for line in stdin().lock().lines() {
+        let line = line.map_err(|e| format!("Unable to read request, {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, result, shutdown) = match serde_json::from_str::<Value>(&line) {
+            Err(e) => (Value::Null, Err((PARSE_ERROR, e.to_string())), false),
+            Ok(request) => {
+                let id = request.get("id").cloned();
+                let params = request.get("params").cloned().unwrap_or(Value::Null);
+                let method = request.get("method").and_then(|m| m.as_str());
+                let result = match method {
+                    Some("load_rules") => session.load_rules(&params),
+                    Some("match") => session.matches(&params),
+                    Some("stats") => Ok(session.stats()),
+                    Some("shutdown") => Ok(Value::Null),
+                    Some(m) => Err((METHOD_NOT_FOUND, format!("Unknown method {}", m))),
+                    None => Err((INVALID_REQUEST, "expected a method".into())),
+                };
+                // Requests without an id are notifications, which get no response
+                match id {
+                    Some(id) => (id, result, method == Some("shutdown")),
+                    None if method == Some("shutdown") => return Ok(()),
+                    None => continue,
+                }
+            }
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message }
+            }),
+        };
+        writeln!(stdout, "{}", response)
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("Unable to write response, {}", e))?;
+        if shutdown {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use std::{
+    fs,
+    io::{self, stderr, stdout, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde_yaml::{Mapping, Value as Yaml};
+use structopt::StructOpt;
+use uuid::Uuid;
+
+use crate::optimize::parse;
+use crate::rules::{self, Metadata};
+
+/// A rule format tau rules can be converted to.
+#[derive(Clone, Copy)]
+pub enum Target {
+    Sigma,
+}
+
+impl FromStr for Target {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sigma" => Ok(Target::Sigma),
+            _ => Err(format!("Unknown rule format {}, expected sigma", s)),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Convert {
+    /// The rules to convert.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+
+    /// The format to convert to, only sigma is supported.
+    #[structopt(long, default_value = "sigma")]
+    to: Target,
+
+    /// A directory to write each converted rule to, named after the rule's file. The rules are written to stdout when not given.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+/// Metadata Sigma shares with tau, copied across as it is and in Sigma's usual order after the
+/// title and id.
+const SIGMA_METADATA: &[&str] = &[
+    "status",
+    "description",
+    "references",
+    "author",
+    "date",
+    "modified",
+    "tags",
+];
+
+/// Escape Sigma's wildcards and the backslashes which would otherwise escape them.
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' => escaped.push('\\'),
+            '\\' if matches!(chars.peek(), Some('*' | '?' | '\\') | None) => escaped.push('\\'),
+            _ => {}
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A tau value as a Sigma modifier and value, e.g. `*x*` as `contains` and `x`, and whether
+/// Sigma would match it without case where tau matches with it.
+fn value(value: &Yaml) -> Result<(Option<&'static str>, Yaml, bool), String> {
+    let s = match value {
+        Yaml::String(s) => s,
+        Yaml::Mapping(_) | Yaml::Sequence(_) => {
+            return Err("nested values have no Sigma equivalent".into())
+        }
+        v => return Ok((None, v.clone(), false)),
+    };
+    let (s, insensitive) = match s.strip_prefix('i') {
+        Some(s) => (s, true),
+        None => (s.as_str(), false),
+    };
+    // Sigma's regexes match with case, like tau's
+    if let Some(regex) = s.strip_prefix('?') {
+        let regex = match insensitive {
+            true => format!("(?i){}", regex),
+            false => regex.to_string(),
+        };
+        return Ok((Some("re"), Yaml::String(regex), false));
+    }
+    for (op, modifier) in [(">=", "gte"), ("<=", "lte"), (">", "gt"), ("<", "lt")] {
+        if let Some(n) = s.strip_prefix(op).and_then(|n| n.parse::<f64>().ok()) {
+            let n = match n.fract() == 0.0 {
+                true => Yaml::from(n as i64),
+                false => Yaml::from(n),
+            };
+            return Ok((Some(modifier), n, false));
+        }
+    }
+    let (starts, ends) = (s.starts_with('*'), s.len() > 1 && s.ends_with('*'));
+    let inner = escape(s.trim_start_matches('*').trim_end_matches('*'));
+    let (modifier, value) = match (starts, ends) {
+        (true, true) => (Some("contains"), inner),
+        (true, false) => (Some("endswith"), inner),
+        (false, true) => (Some("startswith"), inner),
+        (false, false) => (None, escape(s)),
+    };
+    // Sigma matches strings without case, so only those tau matches with case change
+    Ok((modifier, Yaml::String(value), !insensitive))
+}
+
+/// A field and its values with Sigma modifiers, values which need different modifiers can't share
+/// a field in Sigma.
+fn field(name: &str, tau: &Yaml, warnings: &mut Vec<String>) -> Result<(Yaml, Yaml), String> {
+    if name.contains('(') {
+        return Err(format!("the cast in {} has no Sigma equivalent", name));
+    }
+    let mut convert = |v: &Yaml| {
+        let (m, converted, uncased) = value(v)?;
+        if uncased {
+            warnings.push(format!(
+                "{} matches {:?} with case, Sigma will match it without",
+                name,
+                v.as_str().unwrap_or_default()
+            ));
+        }
+        Ok::<_, String>((m, converted))
+    };
+    let (modifier, converted) = match tau {
+        Yaml::Sequence(values) => {
+            let mut converted = Vec::new();
+            let mut modifier = None;
+            for (i, v) in values.iter().enumerate() {
+                let (m, v) = convert(v)?;
+                if i > 0 && m != modifier {
+                    return Err(format!(
+                        "the values of {} compare differently, which Sigma can't express in one field",
+                        name
+                    ));
+                }
+                modifier = m;
+                converted.push(v);
+            }
+            (modifier, Yaml::Sequence(converted))
+        }
+        v => convert(v)?,
+    };
+    let key = match modifier {
+        Some(m) => format!("{}|{}", name, m),
+        None => name.to_string(),
+    };
+    Ok((Yaml::String(key), converted))
+}
+
+fn fields(tau: &Mapping, warnings: &mut Vec<String>) -> Result<Yaml, String> {
+    let mut sigma = Mapping::new();
+    for (k, v) in tau.iter() {
+        let (k, v) = field(k.as_str().unwrap_or_default(), v, warnings)?;
+        sigma.insert(k, v);
+    }
+    Ok(Yaml::Mapping(sigma))
+}
+
+/// A tau rule as a Sigma rule, with warnings for anything which didn't carry over. Rules whose
+/// detection can't be expressed in Sigma are an error.
+pub fn sigma(rule: &Yaml, id: &str) -> Result<(Yaml, Vec<String>), String> {
+    let detection = rule
+        .get("detection")
+        .and_then(|d| d.as_mapping())
+        .ok_or("there is no detection")?;
+    let condition = detection
+        .get(&"condition".into())
+        .and_then(|c| c.as_str())
+        .ok_or("there is no condition")?;
+    if parse(condition).is_none() {
+        return Err(format!(
+            "the condition {} uses functions or comparisons Sigma doesn't have",
+            condition
+        ));
+    }
+    let mut warnings = Vec::new();
+    let mut converted = Mapping::new();
+    for (k, v) in detection.iter() {
+        let name = k.as_str().unwrap_or_default();
+        let v = match (name, v) {
+            ("condition", v) => v.clone(),
+            (_, Yaml::Mapping(m)) => fields(m, &mut warnings)?,
+            (_, Yaml::Sequence(s)) if s.iter().all(|a| a.is_mapping()) => Yaml::Sequence(
+                s.iter()
+                    .filter_map(|a| a.as_mapping())
+                    .map(|m| fields(m, &mut warnings))
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => return Err(format!("the identifier {} has no Sigma equivalent", name)),
+        };
+        converted.insert(k.clone(), v);
+    }
+
+    let mut sigma = Mapping::new();
+    let copy = |sigma: &mut Mapping, key: &str| {
+        if let Some(v) = rule.get(key) {
+            sigma.insert(key.into(), v.clone());
+        }
+    };
+    copy(&mut sigma, "title");
+    // Sigma ids are UUIDs, others are turned into one so the same rule always gets the same id
+    let uuid = match Uuid::parse_str(id) {
+        Ok(u) => u,
+        Err(_) => Uuid::new_v5(&Uuid::NAMESPACE_OID, id.as_bytes()),
+    };
+    sigma.insert("id".into(), uuid.to_string().into());
+    for key in SIGMA_METADATA {
+        copy(&mut sigma, key);
+    }
+    match rule.get("logsource") {
+        Some(l) => {
+            sigma.insert("logsource".into(), l.clone());
+        }
+        None => {
+            warnings.push("there is no logsource, one needs adding before use".into());
+            sigma.insert("logsource".into(), Yaml::Mapping(Mapping::new()));
+        }
+    }
+    sigma.insert("detection".into(), Yaml::Mapping(converted));
+    copy(&mut sigma, "falsepositives");
+    copy(&mut sigma, "level");
+    if rule.get("phase").is_some() {
+        warnings.push("Sigma has no phases, the rule's phase is dropped".into());
+    }
+    if rule.get("true_positives").is_some() || rule.get("true_negatives").is_some() {
+        warnings.push("Sigma has no tests, the true positives and negatives are dropped".into());
+    }
+    Ok((Yaml::Mapping(sigma), warnings))
+}
+
+impl Convert {
+    pub fn run(self) -> Result<(), String> {
+        let mut stderr = stderr();
+        let mut failed = 0;
+        for path in self.rules.iter() {
+            let data = fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+            let rule: Yaml = serde_yaml::from_str(&data)
+                .map_err(|e| format!("Unable to load {} as a rule, {}", path.display(), e))?;
+            let id = rules::id(path, &Metadata::load(&data))?;
+            let converted = match self.to {
+                Target::Sigma => sigma(&rule, &id),
+            };
+            let (converted, warnings) = match converted {
+                Ok(c) => c,
+                Err(e) => {
+                    let _ = writeln!(stderr, "Unable to convert {}, {}", path.display(), e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            for warning in warnings {
+                let _ = writeln!(stderr, "{}: {}", path.display(), warning);
+            }
+            let yaml = serde_yaml::to_string(&converted)
+                .map_err(|e| format!("Unable to write {} as YAML, {}", path.display(), e))?;
+            match &self.output {
+                Some(dir) => {
+                    let file = dir.join(path.file_name().unwrap_or_default());
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&file)
+                        .and_then(|mut f| f.write_all(yaml.as_bytes()))
+                        .map_err(|e| match e.kind() {
+                            io::ErrorKind::AlreadyExists => {
+                                format!("{} already exists", file.display())
+                            }
+                            _ => format!("Could not create output file at {}", file.display()),
+                        })?;
+                }
+                None => stdout()
+                    .write_all(yaml.as_bytes())
+                    .map_err(|e| format!("Unable to write the rule, {}", e))?,
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            n => Err(format!(
+                "{} of {} rules could not be converted",
+                n,
+                self.rules.len()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(tau: &str) -> (Yaml, Vec<String>) {
+        let rule: Yaml = serde_yaml::from_str(tau).unwrap();
+        sigma(&rule, "rule").unwrap()
+    }
+
+    #[test]
+    fn insensitive_regexes_stay_regexes() {
+        let (modifier, regex, uncased) = value(&Yaml::from("i?^cmd")).unwrap();
+        assert_eq!(
+            (modifier, regex, uncased),
+            (Some("re"), Yaml::from("(?i)^cmd"), false)
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        let modifier = |v: &str| value(&Yaml::from(v)).unwrap();
+        assert_eq!(modifier("i*x*"), (Some("contains"), Yaml::from("x"), false));
+        assert_eq!(modifier("*x"), (Some("endswith"), Yaml::from("x"), true));
+        assert_eq!(modifier(">=4"), (Some("gte"), Yaml::from(4), false));
+        assert_eq!(modifier("a?b"), (None, Yaml::from("a\\?b"), true));
+    }
+
+    #[test]
+    fn case_changes_are_warned_about() {
+        let (_, warnings) = convert(
+            "detection:\n  a:\n    Image: ['*cmd.exe', 'i*powershell.exe']\n    Id: 1\n  condition: a\nlogsource: {}\n",
+        );
+        assert_eq!(
+            warnings,
+            vec!["Image matches \"*cmd.exe\" with case, Sigma will match it without"]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use serde_yaml::{Mapping, Value as Yaml};
+use structopt::StructOpt;
+
+use crate::input::Input;
+use crate::optimize::{parse, Expression};
+use crate::output::csv_escape;
+use crate::rules::{self, Claim, Metadata};
+
+/// The most clauses a rule's condition is expanded to before it's considered too complex to compare.
+const MAX_CLAUSES: usize = 512;
+
+#[derive(StructOpt)]
+pub struct Dedupe {
+    /// The rules to compare.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+
+    /// A corpus to check the overlaps against, counting the events each rule of a pair matches.
+    #[structopt(short, long, parse(from_os_str))]
+    input: Option<Vec<PathBuf>>,
+}
+
+/// A field matching, or with `negated` not matching, any of a set of values.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Literal {
+    field: String,
+    values: BTreeSet<String>,
+    negated: bool,
+}
+
+impl Literal {
+    /// Whether this literal holding means `other` does too.
+    fn implies(&self, other: &Literal) -> bool {
+        self.field == other.field
+            && self.negated == other.negated
+            && match self.negated {
+                false => self.values.is_subset(&other.values),
+                true => other.values.is_subset(&self.values),
+            }
+    }
+}
+
+/// Literals which must all hold.
+type Clause = BTreeSet<Literal>;
+
+/// A condition in disjunctive normal form, clauses of which any one must hold.
+type Normal = BTreeSet<Clause>;
+
+fn text(value: &Yaml) -> String {
+    match value {
+        Yaml::String(s) => s.clone(),
+        v => serde_yaml::to_string(v)
+            .unwrap_or_default()
+            .trim_start_matches("---")
+            .trim()
+            .to_string(),
+    }
+}
+
+fn clause(fields: &Mapping) -> Clause {
+    fields
+        .iter()
+        .map(|(k, v)| Literal {
+            field: text(k),
+            values: match v {
+                Yaml::Sequence(s) => s.iter().map(text).collect(),
+                v => std::iter::once(text(v)).collect(),
+            },
+            negated: false,
+        })
+        .collect()
+}
+
+fn identifier(value: &Yaml) -> Option<Normal> {
+    match value {
+        Yaml::Mapping(m) => Some(std::iter::once(clause(m)).collect()),
+        Yaml::Sequence(s) => s.iter().map(|a| a.as_mapping().map(clause)).collect(),
+        _ => None,
+    }
+}
+
+/// Every way of picking one clause from each of `normals`, joined.
+fn and(normals: Vec<Normal>) -> Option<Normal> {
+    let mut product: Normal = std::iter::once(Clause::new()).collect();
+    for normal in normals {
+        let mut next = Normal::new();
+        for a in product.iter() {
+            for b in normal.iter() {
+                next.insert(a.union(b).cloned().collect());
+                if next.len() > MAX_CLAUSES {
+                    return None;
+                }
+            }
+        }
+        product = next;
+    }
+    Some(product)
+}
+
+fn not(normal: Normal) -> Option<Normal> {
+    // By De Morgan, not any clause means every clause has a literal which doesn't hold
+    and(normal
+        .into_iter()
+        .map(|clause| {
+            clause
+                .into_iter()
+                .map(|mut l| {
+                    l.negated = !l.negated;
+                    std::iter::once(l).collect()
+                })
+                .collect()
+        })
+        .collect())
+}
+
+fn normal(e: &Expression, detection: &Mapping) -> Option<Normal> {
+    let normal = match e {
+        Expression::Identifier(i) => identifier(detection.get(&i.as_str().into())?)?,
+        Expression::Not(inner) => not(normal(inner, detection)?)?,
+        Expression::And(terms) => and(terms
+            .iter()
+            .map(|t| normal(t, detection))
+            .collect::<Option<_>>()?)?,
+        Expression::Or(terms) => {
+            let mut union = Normal::new();
+            for t in terms {
+                union.extend(normal(t, detection)?);
+            }
+            union
+        }
+    };
+    Some(simplify(normal))
+}
+
+/// Drop clauses which can never hold, and those made redundant by a clause they include.
+fn simplify(normal: Normal) -> Normal {
+    let possible: Vec<Clause> = normal
+        .into_iter()
+        .filter(|c| {
+            !c.iter().any(|l| {
+                c.iter()
+                    .any(|o| o.negated != l.negated && o.field == l.field && o.values == l.values)
+            })
+        })
+        .collect();
+    possible
+        .iter()
+        .filter(|c| !possible.iter().any(|o| o != *c && o.is_subset(c)))
+        .cloned()
+        .collect()
+}
+
+/// A rule's condition in normal form, `None` when it uses functions or is too large to expand.
+fn condition(rule: &Yaml) -> Option<Normal> {
+    let detection = rule.get("detection")?.as_mapping()?;
+    let condition = parse(detection.get(&"condition".into())?.as_str()?)?;
+    normal(&condition, detection)
+}
+
+/// Whether every event matching `a` also matches `b`. This only finds the cases where each of
+/// `a`'s clauses is narrower than one of `b`'s, so it can miss some.
+fn within(a: &Normal, b: &Normal) -> bool {
+    a.iter().all(|ca| {
+        b.iter()
+            .any(|cb| cb.iter().all(|lb| ca.iter().any(|la| la.implies(lb))))
+    })
+}
+
+impl Dedupe {
+    pub fn run(self) -> Result<(), String> {
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let (mut names, mut normals, mut taken) = (Vec::new(), Vec::new(), HashMap::new());
+        for path in self.rules.iter() {
+            let data = fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+            let id = rules::id(path, &Metadata::load(&data))?;
+            rules::claim(&mut taken, Claim::Id, &id, path)?;
+            let rule: Yaml = serde_yaml::from_str(&data)
+                .map_err(|e| format!("Unable to load {} as a rule, {}", path.display(), e))?;
+            let normal = condition(&rule);
+            if normal.is_none() {
+                let _ = writeln!(
+                    stderr,
+                    "{} has a condition too complex to compare, skipping it",
+                    id
+                );
+            }
+            names.push(id);
+            normals.push(normal);
+        }
+        let mut overlaps = Vec::new();
+        for i in 0..normals.len() {
+            for j in i + 1..normals.len() {
+                let (a, b) = match (&normals[i], &normals[j]) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => continue,
+                };
+                if a == b {
+                    overlaps.push((i, j, "identical"));
+                } else if within(a, b) {
+                    overlaps.push((i, j, "subset"));
+                } else if within(b, a) {
+                    overlaps.push((j, i, "subset"));
+                }
+            }
+        }
+
+        // Without a corpus the overlaps are reported as found, with one they're counted against it
+        let loaded = match &self.input {
+            Some(_) => rules::load(&self.rules)?,
+            None => Vec::new(),
+        };
+        let mut counts = vec![(0, 0, 0); overlaps.len()];
+        if self.input.is_some() {
+            for res in Input::open(self.input.clone(), None, None)? {
+                let json = match res {
+                    Ok(json) => json,
+                    Err(e) => {
+                        let _ = writeln!(stderr, "{}", e);
+                        continue;
+                    }
+                };
+                let matched: Vec<bool> = loaded.iter().map(|(_, r)| r.matches(&json)).collect();
+                for ((a, b, _), count) in overlaps.iter().zip(counts.iter_mut()) {
+                    count.0 += matched[*a] as usize;
+                    count.1 += matched[*b] as usize;
+                    count.2 += (matched[*a] && matched[*b]) as usize;
+                }
+            }
+        }
+        let mut write = || -> std::io::Result<()> {
+            match self.input.is_some() {
+                true => writeln!(
+                    stdout,
+                    "Rule, Other Rule, Relation, Rule Matches, Other Matches, Both, Confirmed"
+                )?,
+                false => writeln!(stdout, "Rule, Other Rule, Relation")?,
+            }
+            for ((a, b, relation), (ca, cb, both)) in overlaps.iter().zip(counts.iter()) {
+                write!(
+                    stdout,
+                    "{}, {}, {}",
+                    csv_escape(&names[*a]),
+                    csv_escape(&names[*b]),
+                    relation
+                )?;
+                if self.input.is_some() {
+                    let confirmed = match *relation {
+                        "identical" => ca == both && cb == both,
+                        _ => ca == both,
+                    };
+                    write!(stdout, ", {}, {}, {}, {}", ca, cb, both, confirmed)?;
+                }
+                writeln!(stdout)?;
+            }
+            Ok(())
+        };
+        write().map_err(|e| format!("Unable to write overlaps, {}", e))
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::input::Input;
+
+/// The socket used by `docker://` when no other is given.
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Parse an input of the form `docker://` or `docker://<socket path>`.
+pub fn socket(input: &Path) -> Option<PathBuf> {
+    let rest = input.to_str()?.strip_prefix("docker:/")?;
+    // Paths collapse the double slash, so both forms are accepted
+    let rest = rest.strip_prefix('/').unwrap_or(rest);
+    match rest {
+        "" => Some(PathBuf::from(DEFAULT_SOCKET)),
+        p => Some(PathBuf::from(p)),
+    }
+}
+
+/// Reads the body of a chunked HTTP response.
+struct Chunked<R> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Read for Chunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut line = String::new();
+            // The CRLF ending the previous chunk comes before the next size
+            while line.trim().is_empty() {
+                line.clear();
+                if self.inner.read_line(&mut line)? == 0 {
+                    self.done = true;
+                    return Ok(0);
+                }
+            }
+            self.remaining = usize::from_str_radix(line.trim(), 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+/// Make a GET request to the Docker API, returning the body.
+#[cfg(unix)]
+fn get(socket: &Path, path: &str) -> io::Result<Box<dyn Read + Send>> {
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n\r\n",
+        path
+    )?;
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    let mut chunked = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+            {
+                chunked = true;
+            }
+        }
+    }
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(io::Error::other(format!(
+            "Docker API returned {} for {}",
+            status.trim(),
+            path
+        )));
+    }
+    match chunked {
+        true => Ok(Box::new(Chunked {
+            inner: reader,
+            remaining: 0,
+            done: false,
+        })),
+        false => Ok(Box::new(reader)),
+    }
+}
+
+#[cfg(not(unix))]
+fn get(_socket: &Path, _path: &str) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "docker inputs are only supported on unix",
+    ))
+}
+
+/// The metadata events from a container are labelled with.
+fn inspect(socket: &Path, id: &str) -> io::Result<(Value, bool)> {
+    let info: Value = serde_json::from_reader(get(socket, &format!("/containers/{}/json", id))?)?;
+    let tty = info["Config"]["Tty"].as_bool().unwrap_or(false);
+    let metadata = json!({
+        "id": info["Id"],
+        "name": info["Name"].as_str().map(|n| n.trim_start_matches('/')),
+        "image": info["Config"]["Image"],
+        "labels": info["Config"]["Labels"],
+    });
+    Ok((metadata, tty))
+}
+
+/// Send a line of container output, as is when it is a JSON object and otherwise wrapped in a `log` field.
+fn send_line(
+    tx: &Sender<Result<Value, String>>,
+    metadata: &Value,
+    stream: &str,
+    line: &[u8],
+) -> bool {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim_end();
+    if line.is_empty() {
+        return true;
+    }
+    let mut event = match serde_json::from_str(line) {
+        Ok(Value::Object(o)) => Value::Object(o),
+        _ => json!({ "log": line }),
+    };
+    let mut docker = metadata.clone();
+    docker["stream"] = json!(stream);
+    event["docker"] = docker;
+    tx.send(Ok(event)).is_ok()
+}
+
+/// Follow the output of a container from now on.
+fn follow_logs(socket: PathBuf, id: String, tx: Sender<Result<Value, String>>) {
+    thread::spawn(move || -> io::Result<()> {
+        let (metadata, tty) = inspect(&socket, &id)?;
+        let path = format!("/containers/{}/logs?follow=1&stdout=1&stderr=1&tail=0", id);
+        let mut body = BufReader::new(get(&socket, &path)?);
+        if tty {
+            // Terminals merge stdout and stderr into a single raw stream
+            let mut line = Vec::new();
+            while body.read_until(b'\n', &mut line)? > 0 {
+                if !send_line(&tx, &metadata, "stdout", &line) {
+                    break;
+                }
+                line.clear();
+            }
+            return Ok(());
+        }
+        // Otherwise each frame has an 8 byte header holding the stream and the frame's length
+        let mut pending: [Vec<u8>; 2] = [Vec::new(), Vec::new()];
+        let mut header = [0; 8];
+        while body.read_exact(&mut header).is_ok() {
+            let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let mut frame = vec![0; len];
+            body.read_exact(&mut frame)?;
+            let (i, stream) = match header[0] {
+                2 => (1, "stderr"),
+                _ => (0, "stdout"),
+            };
+            pending[i].extend_from_slice(&frame);
+            while let Some(end) = pending[i].iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = pending[i].drain(..=end).collect();
+                if !send_line(&tx, &metadata, stream, &line) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    });
+}
+
+/// Stream container events and the output of running containers, following containers as they start.
+pub fn open(socket: &Path) -> Result<Input, String> {
+    let running: Vec<Value> = get(socket, "/containers/json")
+        .and_then(|r| serde_json::from_reader(r).map_err(io::Error::from))
+        .map_err(|e| format!("Unable to connect to Docker at {}, {}", socket.display(), e))?;
+    let events = get(
+        socket,
+        "/events?filters=%7B%22type%22%3A%5B%22container%22%5D%7D",
+    )
+    .map_err(|e| format!("Unable to read Docker events, {}", e))?;
+    let (tx, rx) = mpsc::channel();
+    for container in running.iter() {
+        if let Some(id) = container["Id"].as_str() {
+            follow_logs(socket.to_path_buf(), id.to_string(), tx.clone());
+        }
+    }
+    let socket = socket.to_path_buf();
+    thread::spawn(move || {
+        for event in serde_json::Deserializer::from_reader(events).into_iter::<Value>() {
+            let mut event = match event {
+                Ok(e @ Value::Object(_)) => e,
+                Ok(_) => continue,
+                Err(_) => break,
+            };
+            if event["Action"] == "start" {
+                if let Some(id) = event["id"].as_str() {
+                    follow_logs(socket.clone(), id.to_string(), tx.clone());
+                }
+            }
+            event["docker"] = json!({ "id": event["id"], "stream": "events" });
+            if tx.send(Ok(event)).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
@@ -0,0 +1,263 @@
+use std::{
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+use structopt::{
+    clap::{App, ArgSettings},
+    StructOpt,
+};
+
+use crate::environment;
+use crate::explain;
+
+#[derive(StructOpt)]
+pub struct Docs {
+    /// Write a man page, in roff.
+    #[structopt(long, conflicts_with = "markdown")]
+    man: bool,
+
+    /// Write a Markdown reference.
+    #[structopt(long)]
+    markdown: bool,
+
+    /// Rules to add to the reference, each with its detection logic as 'explain' describes it.
+    #[structopt(short, long, parse(from_os_str))]
+    rules: Vec<PathBuf>,
+}
+
+/// An option or argument as documented.
+struct Arg {
+    usage: String,
+    help: String,
+}
+
+/// A command and its options as documented, read from its argument definitions so the reference
+/// is never out of date.
+struct Page {
+    name: String,
+    about: String,
+    args: Vec<Arg>,
+    subcommands: Vec<Page>,
+}
+
+impl Page {
+    fn new(app: &App, name: String) -> Self {
+        let p = &app.p;
+        let mut args = Vec::new();
+        let value = |names: Option<Vec<&str>>, name: &str, multiple: bool| {
+            let names = names.unwrap_or_else(|| vec![name]);
+            let names: Vec<String> = names.iter().map(|n| format!("<{}>", n)).collect();
+            format!("{}{}", names.join(" "), if multiple { "..." } else { "" })
+        };
+        for (_, a) in p.positionals.iter() {
+            if a.b.is_set(ArgSettings::Hidden) {
+                continue;
+            }
+            let names =
+                a.v.val_names
+                    .as_ref()
+                    .map(|v| v.values().cloned().collect());
+            args.push(Arg {
+                usage: value(names, a.b.name, a.b.is_set(ArgSettings::Multiple)),
+                help: a.b.help.unwrap_or("").to_string(),
+            });
+        }
+        let switch = |short: Option<char>, long: Option<&str>| {
+            let mut names = Vec::new();
+            names.extend(short.map(|s| format!("-{}", s)));
+            names.extend(long.map(|l| format!("--{}", l)));
+            names.join(", ")
+        };
+        let mut options = Vec::new();
+        for f in p.flags.iter().filter(|f| !f.b.is_set(ArgSettings::Hidden)) {
+            options.push((
+                f.s.long.unwrap_or(f.b.name),
+                Arg {
+                    usage: switch(f.s.short, f.s.long),
+                    help: f.b.help.unwrap_or("").to_string(),
+                },
+            ));
+        }
+        for o in p.opts.iter().filter(|o| !o.b.is_set(ArgSettings::Hidden)) {
+            let names =
+                o.v.val_names
+                    .as_ref()
+                    .map(|v| v.values().cloned().collect());
+            let help = o.b.help.unwrap_or("");
+            let help = match o.v.default_val {
+                Some(default) => format!(
+                    "{}. Defaults to {}.",
+                    help.trim_end_matches('.'),
+                    default.to_string_lossy()
+                ),
+                None => help.to_string(),
+            };
+            options.push((
+                o.s.long.unwrap_or(o.b.name),
+                Arg {
+                    usage: format!(
+                        "{} {}",
+                        switch(o.s.short, o.s.long),
+                        value(names, o.b.name, o.b.is_set(ArgSettings::Multiple))
+                    ),
+                    help,
+                },
+            ));
+        }
+        options.sort_by_key(|(long, _)| *long);
+        args.extend(options.into_iter().map(|(_, a)| a));
+        let mut subcommands: Vec<Page> = p
+            .subcommands
+            .iter()
+            .map(|s| Page::new(s, format!("{} {}", name, s.p.meta.name)))
+            .collect();
+        subcommands.sort_by(|a, b| a.name.cmp(&b.name));
+        Page {
+            name,
+            about: p.meta.about.unwrap_or("").to_string(),
+            args,
+            subcommands,
+        }
+    }
+}
+
+/// Escape text for roff, where a leading dot or quote starts a request and backslashes escape.
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    match text.starts_with('.') || text.starts_with('\'') {
+        true => format!("\\&{}", text),
+        false => text,
+    }
+}
+
+fn man(page: &Page, out: &mut String) {
+    for arg in page.args.iter() {
+        out.push_str(&format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            roff(&arg.usage),
+            roff(&arg.help)
+        ));
+    }
+}
+
+/// Write a page's subcommands as man sections, then their own subcommands after each.
+fn man_subcommands(page: &Page, out: &mut String) {
+    for sub in page.subcommands.iter() {
+        out.push_str(&format!(".SS {}\n{}\n", roff(&sub.name), roff(&sub.about)));
+        man(sub, out);
+        man_subcommands(sub, out);
+    }
+}
+
+fn markdown(page: &Page, out: &mut String) {
+    for arg in page.args.iter() {
+        out.push_str(&format!("- `{}` {}\n", arg.usage, arg.help));
+    }
+}
+
+/// Write a page's subcommands as Markdown sections, their own subcommands a heading level deeper.
+fn markdown_subcommands(page: &Page, level: usize, out: &mut String) {
+    for sub in page.subcommands.iter() {
+        out.push_str(&format!(
+            "\n{} {}\n\n{}\n\n",
+            "#".repeat(level),
+            sub.name,
+            sub.about
+        ));
+        markdown(sub, out);
+        markdown_subcommands(sub, level + 1, out);
+    }
+}
+
+impl Docs {
+    pub fn run(self) -> Result<(), String> {
+        let page = Page::new(&crate::Cli::clap(), "tau-cli".into());
+        let rules = self
+            .rules
+            .iter()
+            .map(|path| explain::file(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let version = env!("CARGO_PKG_VERSION");
+        let mut out = String::new();
+        match (self.man, self.markdown) {
+            (true, _) => {
+                out.push_str(&format!(
+                    ".TH TAU\\-CLI 1 \"\" \"tau\\-cli {}\"\n.SH NAME\ntau\\-cli \\- {}\n",
+                    version,
+                    roff(&page.about)
+                ));
+                out.push_str(".SH SYNOPSIS\n\\fBtau\\-cli\\fR [OPTIONS]\n.br\n\\fBtau\\-cli\\fR <SUBCOMMAND> [OPTIONS]\n");
+                out.push_str(".SH SUBCOMMANDS\nWithout a subcommand, tau\\-cli runs match with the options given.\n");
+                man_subcommands(&page, &mut out);
+                out.push_str(".SH ENVIRONMENT\nOptions of the match subcommand can be set with environment variables, the command line takes precedence.\n");
+                for (variable, long, value) in environment::variables() {
+                    out.push_str(&format!(
+                        ".TP\n\\fB{}\\fR\n\\-\\-{}, {}\n",
+                        roff(&variable),
+                        roff(long),
+                        value
+                    ));
+                }
+                if !rules.is_empty() {
+                    out.push_str(".SH RULES\n");
+                }
+                for (heading, lines) in rules.iter() {
+                    out.push_str(&format!(".SS {}\n.nf\n", roff(heading)));
+                    for line in lines {
+                        out.push_str(&format!("{}\n", roff(line)));
+                    }
+                    out.push_str(".fi\n");
+                }
+            }
+            (false, true) => {
+                out.push_str(&format!("# tau-cli {}\n\n{}\n\n", version, page.about));
+                out.push_str("## Subcommands\n\nWithout a subcommand, tau-cli runs match with the options given.\n");
+                markdown_subcommands(&page, 3, &mut out);
+                out.push_str("\n## Environment\n\nOptions of the match subcommand can be set with environment variables, the command line takes precedence.\n\n| Variable | Option | Value |\n| --- | --- | --- |\n");
+                for (variable, long, value) in environment::variables() {
+                    out.push_str(&format!("| `{}` | `--{}` | {} |\n", variable, long, value));
+                }
+                if !rules.is_empty() {
+                    out.push_str("\n## Rules\n");
+                }
+                for (heading, lines) in rules.iter() {
+                    out.push_str(&format!("\n### {}\n\n```text\n", heading));
+                    for line in lines {
+                        out.push_str(&format!("{}\n", line));
+                    }
+                    out.push_str("```\n");
+                }
+            }
+            (false, false) => {
+                return Err("Choose the format of the docs, --man or --markdown".into())
+            }
+        }
+        stdout()
+            .write_all(out.as_bytes())
+            .map_err(|e| format!("Unable to write the docs, {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::clap::{Arg as ClapArg, SubCommand};
+
+    #[test]
+    fn nested_subcommands_are_written() {
+        let app = App::new("tau-cli").subcommand(
+            SubCommand::with_name("outer").about("Outer").subcommand(
+                SubCommand::with_name("inner")
+                    .about("Inner")
+                    .arg(ClapArg::with_name("deep").long("deep").help("Deeply")),
+            ),
+        );
+        let page = Page::new(&app, "tau-cli".into());
+        let mut out = String::new();
+        markdown_subcommands(&page, 3, &mut out);
+        assert!(out.contains("\n### tau-cli outer\n\nOuter\n"));
+        assert!(out.contains("\n#### tau-cli outer inner\n\nInner\n"));
+        assert!(out.contains("- `--deep` Deeply\n"));
+    }
+}
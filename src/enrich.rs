@@ -0,0 +1,422 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::iocs::HASH_PATTERN;
+
+/// A stage adding context to matching events before they are output.
+pub enum Enricher {
+    Hashes(HashEnricher),
+    Dns(DnsEnricher),
+    Context(ContextEnricher),
+}
+
+impl Enricher {
+    /// Add context to an event, writing any problem which doesn't stop enrichment to `stderr`.
+    pub fn enrich(&mut self, json: &mut Value, stderr: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Enricher::Hashes(e) => e.enrich(json),
+            Enricher::Dns(e) => e.enrich(json),
+            Enricher::Context(e) => return e.enrich(json, stderr),
+        }
+        Ok(())
+    }
+}
+
+/// Insert a value under the event's `tau` object.
+fn insert_tau(json: &mut Value, key: &str, value: Value) {
+    if let Some(object) = json.as_object_mut() {
+        let tau = object.entry("tau").or_insert_with(|| json!({}));
+        if let Some(tau) = tau.as_object_mut() {
+            tau.insert(key.into(), value);
+        }
+    }
+}
+
+const VIRUSTOTAL_URL: &str = "https://www.virustotal.com/api/v3/files/";
+/// How long a VirusTotal request can take before it's an error.
+const VIRUSTOTAL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Hashes waiting to be looked up, beyond which hashes wait for a later event to be queued.
+const VIRUSTOTAL_QUEUE: usize = 1024;
+
+/// Looks up the file hashes of matching events, appending the verdicts to the event's `tau.hashes`.
+pub struct HashEnricher {
+    fields: Vec<String>,
+    pattern: Regex,
+    known: HashMap<String, String>,
+    virustotal: Option<VirusTotal>,
+    cache: HashMap<String, Value>,
+}
+
+/// Looks hashes up on a thread of its own, so waiting on the rate limit and the API doesn't hold
+/// up matching. Hashes are `pending` until their verdict arrives.
+struct VirusTotal {
+    /// Hashes queued or being looked up.
+    pending: HashSet<String>,
+    queue: SyncSender<String>,
+    verdicts: mpsc::Receiver<(String, Value)>,
+}
+
+impl VirusTotal {
+    fn new(key: String, per_minute: u32) -> Self {
+        let (queue, hashes) = mpsc::sync_channel::<String>(VIRUSTOTAL_QUEUE);
+        let (tx, verdicts) = mpsc::channel();
+        let interval = Duration::from_secs(60) / per_minute.max(1);
+        let agent = ureq::AgentBuilder::new()
+            .timeout(VIRUSTOTAL_TIMEOUT)
+            .build();
+        thread::spawn(move || {
+            let mut last: Option<Instant> = None;
+            for hash in hashes {
+                // Wait out the rate limit rather than dropping the lookup
+                if let Some(wait) = last.and_then(|l| interval.checked_sub(l.elapsed())) {
+                    thread::sleep(wait);
+                }
+                last = Some(Instant::now());
+                let verdict = VirusTotal::lookup(&agent, &key, &hash);
+                if tx.send((hash, verdict)).is_err() {
+                    break;
+                }
+            }
+        });
+        VirusTotal {
+            pending: HashSet::new(),
+            queue,
+            verdicts,
+        }
+    }
+
+    /// Queue a hash to be looked up unless it already is.
+    fn request(&mut self, hash: &str) -> Value {
+        if !self.pending.contains(hash) && self.queue.try_send(hash.to_string()).is_ok() {
+            self.pending.insert(hash.to_string());
+        }
+        json!({ "hash": hash, "source": "virustotal", "verdict": "pending" })
+    }
+
+    fn lookup(agent: &ureq::Agent, key: &str, hash: &str) -> Value {
+        match agent
+            .get(&format!("{}{}", VIRUSTOTAL_URL, hash))
+            .set("x-apikey", key)
+            .call()
+        {
+            Ok(resp) => match resp.into_json::<Value>() {
+                Ok(body) => {
+                    let stats = &body["data"]["attributes"]["last_analysis_stats"];
+                    let malicious = stats["malicious"].as_u64().unwrap_or(0);
+                    json!({
+                        "hash": hash,
+                        "source": "virustotal",
+                        "verdict": if malicious > 0 { "malicious" } else { "clean" },
+                        "detections": malicious,
+                    })
+                }
+                Err(e) => lookup_error(hash, e),
+            },
+            Err(ureq::Error::Status(404, _)) => {
+                json!({ "hash": hash, "source": "virustotal", "verdict": "unknown" })
+            }
+            Err(e) => lookup_error(hash, e),
+        }
+    }
+}
+
+fn lookup_error(hash: &str, e: impl std::fmt::Display) -> Value {
+    json!({ "hash": hash, "source": "virustotal", "verdict": "error", "error": e.to_string() })
+}
+
+impl HashEnricher {
+    /// `known` is a CSV of hashes with an optional verdict in the second column, `virustotal` is
+    /// an API key and the number of lookups allowed per minute.
+    pub fn new(
+        fields: Vec<String>,
+        known: Option<&Path>,
+        virustotal: Option<(String, u32)>,
+    ) -> Result<Self, String> {
+        let mut hashes = HashMap::new();
+        if let Some(path) = known {
+            let data = fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+            for line in data.lines().map(|l| l.trim()) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut columns = line.splitn(2, ',').map(|c| c.trim());
+                if let Some(hash) = columns.next() {
+                    let verdict = columns.next().filter(|v| !v.is_empty()).unwrap_or("known");
+                    hashes.insert(hash.to_lowercase(), verdict.to_string());
+                }
+            }
+        }
+        Ok(HashEnricher {
+            fields,
+            pattern: Regex::new(HASH_PATTERN).expect("valid regex"),
+            known: hashes,
+            virustotal: virustotal.map(|(key, per_minute)| VirusTotal::new(key, per_minute)),
+            cache: HashMap::new(),
+        })
+    }
+
+    pub fn enrich(&mut self, json: &mut Value) {
+        let mut hashes = Vec::new();
+        for field in self.fields.iter() {
+            if let Some(Value::String(s)) = crate::event::get(json, field) {
+                for m in self.pattern.find_iter(s) {
+                    let hash = m.as_str().to_lowercase();
+                    if !hashes.contains(&hash) {
+                        hashes.push(hash);
+                    }
+                }
+            }
+        }
+        if hashes.is_empty() {
+            return;
+        }
+        if let Some(vt) = self.virustotal.as_mut() {
+            for (hash, verdict) in vt.verdicts.try_iter() {
+                vt.pending.remove(&hash);
+                // Errors are not cached so the lookup is retried on the next event
+                if verdict["verdict"] != "error" {
+                    self.cache.insert(hash, verdict);
+                }
+            }
+        }
+        let verdicts: Vec<Value> = hashes.iter().map(|h| self.lookup(h)).collect();
+        insert_tau(json, "hashes", Value::Array(verdicts));
+    }
+
+    fn lookup(&mut self, hash: &str) -> Value {
+        if let Some(v) = self.cache.get(hash) {
+            return v.clone();
+        }
+        let verdict = match (self.known.get(hash), self.virustotal.as_mut()) {
+            (Some(v), _) => json!({ "hash": hash, "source": "local", "verdict": v }),
+            (None, Some(vt)) => return vt.request(hash),
+            (None, None) => json!({ "hash": hash, "source": "local", "verdict": "unknown" }),
+        };
+        self.cache.insert(hash.to_string(), verdict.clone());
+        verdict
+    }
+}
+
+/// Threads resolving queries, a lookup which hangs holds on to one until the resolver gives up.
+const DNS_WORKERS: usize = 4;
+/// Queries waiting for a worker, beyond which lookups fail straight away rather than queue.
+const DNS_QUEUE: usize = 64;
+/// How long a failed or timed out lookup is remembered before it's tried again.
+const DNS_NEGATIVE_TTL: Duration = Duration::from_secs(300);
+
+type DnsResult = Result<Value, String>;
+
+/// Resolve a query with the system resolver, an IP to its PTR name or a domain to its addresses.
+fn resolve(query: &str) -> DnsResult {
+    let result = match query.parse::<IpAddr>() {
+        Ok(ip) => dns_lookup::lookup_addr(&ip).map(|name| json!({ "ptr": name })),
+        Err(_) => dns_lookup::lookup_host(query).map(|addrs| {
+            let a: Vec<String> = addrs
+                .iter()
+                .filter(|a| a.is_ipv4())
+                .map(|a| a.to_string())
+                .collect();
+            json!({ "a": a })
+        }),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// Resolves IPs to PTR names and domains to addresses, appending the results to the event's `tau.dns`.
+pub struct DnsEnricher {
+    fields: Vec<String>,
+    timeout: Duration,
+    /// Results by query, failures with when they expire.
+    cache: HashMap<String, (Value, Option<Instant>)>,
+    queries: SyncSender<(String, mpsc::Sender<DnsResult>)>,
+}
+
+impl DnsEnricher {
+    pub fn new(fields: Vec<String>, timeout: Duration) -> Self {
+        let (queries, rx) = mpsc::sync_channel::<(String, mpsc::Sender<DnsResult>)>(DNS_QUEUE);
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..DNS_WORKERS {
+            let rx = rx.clone();
+            thread::spawn(move || loop {
+                // Workers stop once the enricher is dropped
+                let (query, reply) = match rx.lock().map(|rx| rx.recv()) {
+                    Ok(Ok(q)) => q,
+                    _ => return,
+                };
+                let _ = reply.send(resolve(&query));
+            });
+        }
+        DnsEnricher {
+            fields,
+            timeout,
+            cache: HashMap::new(),
+            queries,
+        }
+    }
+
+    pub fn enrich(&mut self, json: &mut Value) {
+        let mut queries = Vec::new();
+        for field in self.fields.iter() {
+            if let Some(Value::String(s)) = crate::event::get(json, field) {
+                if !s.is_empty() && !queries.contains(s) {
+                    queries.push(s.clone());
+                }
+            }
+        }
+        if queries.is_empty() {
+            return;
+        }
+        let results: Vec<Value> = queries.iter().map(|q| self.lookup(q)).collect();
+        insert_tau(json, "dns", Value::Array(results));
+    }
+
+    fn lookup(&mut self, query: &str) -> Value {
+        match self.cache.get(query) {
+            Some((v, None)) => return v.clone(),
+            Some((v, Some(expires))) if Instant::now() < *expires => return v.clone(),
+            _ => {}
+        }
+        // The system resolver has no timeout of its own, so a lookup which takes too long is left to
+        // its worker
+        let (tx, rx) = mpsc::channel();
+        let result = match self.queries.try_send((query.to_string(), tx)) {
+            Ok(()) => match rx.recv_timeout(self.timeout) {
+                Ok(result) => result,
+                Err(_) => Err("timed out".into()),
+            },
+            Err(_) => Err("too many lookups in progress".into()),
+        };
+        let (mut result, expires) = match result {
+            Ok(v) => (v, None),
+            Err(e) => (
+                json!({ "error": e }),
+                Some(Instant::now() + DNS_NEGATIVE_TTL),
+            ),
+        };
+        result["query"] = Value::String(query.to_string());
+        self.cache
+            .insert(query.to_string(), (result.clone(), expires));
+        result
+    }
+}
+
+/// Joins an inventory of assets onto matching events by a key such as the host name, adding the
+/// asset's entry to the event's `tau.context`. The inventory is read again whenever it changes, so
+/// it can be kept up to date while following input.
+pub struct ContextEnricher {
+    path: PathBuf,
+    key: String,
+    interval: Duration,
+    checked: Instant,
+    modified: Option<SystemTime>,
+    assets: HashMap<String, Value>,
+}
+
+/// Keys are compared without case, host names are rarely written consistently.
+fn asset_key(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.to_lowercase()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+impl ContextEnricher {
+    /// `key` is the field holding the key in both the events and the inventory's entries, and
+    /// `interval` how often the inventory is checked for changes.
+    pub fn new(path: PathBuf, key: String, interval: Duration) -> Result<Self, String> {
+        let mut enricher = ContextEnricher {
+            path,
+            key,
+            interval,
+            checked: Instant::now(),
+            modified: None,
+            assets: HashMap::new(),
+        };
+        enricher.load()?;
+        Ok(enricher)
+    }
+
+    /// Read the inventory, a JSON array or lines of entries holding the key field.
+    fn load(&mut self) -> Result<(), String> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let data = fs::read_to_string(&self.path)
+            .map_err(|_| format!("Unable to read data from {}.", self.path.display()))?;
+        let invalid = |e: serde_json::Error| {
+            format!(
+                "Unable to parse the inventory {}, {}",
+                self.path.display(),
+                e
+            )
+        };
+        let entries = match serde_json::from_str::<Value>(&data) {
+            Ok(Value::Array(a)) => a,
+            Ok(entry @ Value::Object(_)) => vec![entry],
+            Ok(_) => {
+                return Err(format!(
+                    "The inventory {} holds no entries",
+                    self.path.display()
+                ))
+            }
+            Err(_) => data
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()
+                .map_err(invalid)?,
+        };
+        let mut assets = HashMap::new();
+        for entry in entries {
+            if let Some(key) = crate::event::get(&entry, &self.key).and_then(asset_key) {
+                assets.insert(key, entry);
+            }
+        }
+        self.assets = assets;
+        self.modified = modified;
+        Ok(())
+    }
+
+    /// Read the inventory again if it has changed since it was last read. A bad edit keeps the
+    /// entries already read, so a mistake doesn't strip the context from everything.
+    fn reload(&mut self, stderr: &mut dyn Write) -> io::Result<()> {
+        if self.checked.elapsed() < self.interval {
+            return Ok(());
+        }
+        self.checked = Instant::now();
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != self.modified {
+            if let Err(e) = self.load() {
+                writeln!(stderr, "{}, keeping the previous inventory", e)?;
+                self.modified = modified;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn enrich(&mut self, json: &mut Value, stderr: &mut dyn Write) -> io::Result<()> {
+        self.reload(stderr)?;
+        let asset = crate::event::get(json, &self.key)
+            .and_then(asset_key)
+            .and_then(|k| self.assets.get(&k));
+        if let Some(asset) = asset {
+            let asset = asset.clone();
+            insert_tau(json, "context", asset);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::{env, ffi::OsString};
+
+enum Kind {
+    /// Paths separated as in PATH, each passed as its own value.
+    Paths,
+    Value,
+    /// Set unless empty, 0, false or no.
+    Flag,
+}
+
+/// Options which can be set with a `TAU_` environment variable, by long name and short name.
+const OPTIONS: &[(&str, Option<char>, Kind)] = &[
+    ("rules", Some('r'), Kind::Paths),
+    ("input", Some('i'), Kind::Paths),
+    ("input-format", None, Kind::Value),
+    ("output", Some('o'), Kind::Value),
+    ("output-format", None, Kind::Value),
+    ("output-fields", None, Kind::Value),
+    ("overwrite", Some('f'), Kind::Flag),
+    ("encrypt-output", None, Kind::Value),
+    ("shared-output", None, Kind::Value),
+    ("retain", None, Kind::Value),
+    ("retain-size", None, Kind::Value),
+    ("output-mode", None, Kind::Value),
+    ("output-owner", None, Kind::Value),
+    ("output-extension", None, Kind::Value),
+    ("route", None, Kind::Value),
+    ("token-file", None, Kind::Value),
+    ("secret-cmd", None, Kind::Value),
+    ("emit-min-level", None, Kind::Value),
+    ("filter-rules", None, Kind::Value),
+    ("optimize-rules", None, Kind::Flag),
+    ("explain", None, Kind::Flag),
+    ("stamp-origin", None, Kind::Flag),
+    ("wrap", None, Kind::Flag),
+    ("group-by", None, Kind::Value),
+    ("agg", None, Kind::Value),
+    ("top", None, Kind::Value),
+    ("by", None, Kind::Value),
+    ("time-field", None, Kind::Value),
+    ("timechart", None, Kind::Value),
+    ("timechart-format", None, Kind::Value),
+    ("stats", None, Kind::Flag),
+    ("errors-format", None, Kind::Value),
+    ("max-count", Some('m'), Kind::Value),
+    ("max-count-per-file", None, Kind::Flag),
+    ("fail-fast", None, Kind::Flag),
+    ("per-file-stats", None, Kind::Flag),
+    ("ioc-file", None, Kind::Paths),
+    ("ioc-fields", None, Kind::Value),
+    ("ioc-index", None, Kind::Value),
+    ("ioc-index-min-values", None, Kind::Value),
+    ("ioc-index-false-positive-rate", None, Kind::Value),
+    ("enrich-hashes", None, Kind::Value),
+    ("enrich-dns", None, Kind::Value),
+    ("baseline", None, Kind::Paths),
+    ("baseline-key", None, Kind::Value),
+    ("name-by", None, Kind::Value),
+    ("idempotency-key", None, Kind::Value),
+    ("ledger", None, Kind::Value),
+    ("schema", None, Kind::Value),
+    ("prefilter-expr", None, Kind::Value),
+    ("locales", None, Kind::Value),
+    ("max-memory", None, Kind::Value),
+    ("nice", None, Kind::Value),
+    ("cpu-limit", None, Kind::Value),
+    ("rule-timeout", None, Kind::Value),
+    ("trace", None, Kind::Value),
+    ("dead-letter", None, Kind::Value),
+    ("max-line-bytes", None, Kind::Value),
+    ("oversize-lines", None, Kind::Value),
+    ("lenient-json", None, Kind::Flag),
+    ("read-until-eof-stable", None, Kind::Value),
+    ("progress", None, Kind::Value),
+    ("progress-interval", None, Kind::Value),
+    ("cache-dir", None, Kind::Value),
+    ("schedule", None, Kind::Value),
+    ("schedule-state", None, Kind::Value),
+    ("watch-rules", None, Kind::Flag),
+    ("watch-input", None, Kind::Value),
+    ("on-processed", None, Kind::Value),
+    ("serve-k8s-audit", None, Kind::Value),
+    ("control", None, Kind::Value),
+    ("threads", None, Kind::Value),
+    ("pipeline", None, Kind::Value),
+];
+
+/// The environment variable setting an option, e.g. `TAU_RULE_TIMEOUT` for `--rule-timeout`.
+fn variable(long: &str) -> String {
+    format!("TAU_{}", long.replace('-', "_").to_uppercase())
+}
+
+/// Each environment variable with the option it sets, and how its value is read.
+pub fn variables() -> impl Iterator<Item = (String, &'static str, &'static str)> {
+    OPTIONS.iter().map(|(long, _, kind)| {
+        let value = match kind {
+            Kind::Paths => "paths separated as in PATH",
+            Kind::Value => "a value",
+            Kind::Flag => "set unless empty, 0, false or no",
+        };
+        (variable(long), *long, value)
+    })
+}
+
+/// Whether the command line already sets an option, which takes precedence over the environment.
+fn given(args: &[OsString], long: &str, short: Option<char>) -> bool {
+    let (flag, assigned) = (format!("--{}", long), format!("--{}=", long));
+    // Short options can be clustered, e.g. -fm5, where the rest of the cluster after one which
+    // takes a value is that value
+    let clustered = |cluster: &str, s: char| {
+        for c in cluster.chars() {
+            if c == s {
+                return true;
+            }
+            let takes_value = OPTIONS
+                .iter()
+                .any(|(_, short, kind)| *short == Some(c) && !matches!(kind, Kind::Flag));
+            if takes_value {
+                return false;
+            }
+        }
+        false
+    };
+    args.iter()
+        .skip(1)
+        .filter_map(|a| a.to_str())
+        .take_while(|a| *a != "--")
+        .any(|a| {
+            a == flag
+                || a.starts_with(&assigned)
+                || match (short, a.strip_prefix('-')) {
+                    (Some(s), Some(cluster)) if !cluster.starts_with('-') => clustered(cluster, s),
+                    _ => false,
+                }
+        })
+}
+
+/// The command line with options set by `TAU_` environment variables added, for those options
+/// which it doesn't set itself. They are added straight after the program name, or after the
+/// match subcommand when it's given, so they always belong to matching rather than another
+/// subcommand.
+pub fn args(args: Vec<OsString>) -> Vec<OsString> {
+    let mut added = Vec::new();
+    for (long, short, kind) in OPTIONS {
+        let value = match env::var_os(variable(long)) {
+            Some(v) if !v.is_empty() && !given(&args, long, *short) => v,
+            _ => continue,
+        };
+        match kind {
+            Kind::Paths => {
+                for path in env::split_paths(&value) {
+                    let mut arg = OsString::from(format!("--{}=", long));
+                    arg.push(path);
+                    added.push(arg);
+                }
+            }
+            Kind::Value => {
+                let mut arg = OsString::from(format!("--{}=", long));
+                arg.push(value);
+                added.push(arg);
+            }
+            Kind::Flag => {
+                let off = matches!(
+                    value.to_str().map(|v| v.to_lowercase()).as_deref(),
+                    Some("0" | "false" | "no")
+                );
+                if !off {
+                    added.push(OsString::from(format!("--{}", long)));
+                }
+            }
+        }
+    }
+    let start = match args.get(1).and_then(|a| a.to_str()) {
+        Some("match") => 2,
+        _ => 1,
+    };
+    let mut args = args.into_iter();
+    let first: Vec<OsString> = args.by_ref().take(start).collect();
+    first.into_iter().chain(added).chain(args).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_line(args: &[&str]) -> Vec<OsString> {
+        std::iter::once("tau-cli")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect()
+    }
+
+    #[test]
+    fn long_options_are_given() {
+        let args = command_line(&["--stats", "--rules=a.yml"]);
+        assert!(given(&args, "stats", None));
+        assert!(given(&args, "rules", Some('r')));
+        assert!(!given(&args, "explain", None));
+        // Only the option itself counts, not one it's the start of
+        assert!(!given(
+            &command_line(&["--max-count-per-file"]),
+            "max-count",
+            Some('m')
+        ));
+    }
+
+    #[test]
+    fn clustered_short_options_are_given() {
+        let args = command_line(&["-fm5"]);
+        assert!(given(&args, "overwrite", Some('f')));
+        assert!(given(&args, "max-count", Some('m')));
+        // What follows an option which takes a value is its value
+        let args = command_line(&["-mf"]);
+        assert!(given(&args, "max-count", Some('m')));
+        assert!(!given(&args, "overwrite", Some('f')));
+        // Nothing after -- is an option
+        assert!(!given(&command_line(&["--", "-f"]), "overwrite", Some('f')));
+    }
+}
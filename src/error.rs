@@ -0,0 +1,135 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde_json::json;
+
+/// Why tau-cli couldn't start matching, so wrappers can tell a broken rule from a bad option
+/// without reading the message.
+pub enum CliError {
+    /// A rule or indicator file which couldn't be loaded, or clashes with another.
+    RuleLoad {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    /// An input file or directory which couldn't be read.
+    Io {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    /// An output, dead letter, trace or ledger file which couldn't be created.
+    OutputCreate { path: PathBuf, message: String },
+    /// Options which can't be used together, or a setting or file configuring them which is invalid.
+    Config(String),
+}
+
+impl CliError {
+    pub fn rule(path: &Path, message: impl Into<String>) -> Self {
+        CliError::RuleLoad {
+            path: Some(path.to_path_buf()),
+            message: message.into(),
+        }
+    }
+
+    pub fn output(path: &Path, message: impl Into<String>) -> Self {
+        CliError::OutputCreate {
+            path: path.to_path_buf(),
+            message: message.into(),
+        }
+    }
+
+    /// An input which couldn't be read, with its path when the failure is down to one file.
+    pub fn io(path: Option<&Path>, message: impl Into<String>) -> Self {
+        CliError::Io {
+            path: path.map(Path::to_path_buf),
+            message: message.into(),
+        }
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        CliError::Config(message.into())
+    }
+
+    /// The error's category as written by --errors-format json.
+    pub fn category(&self) -> &'static str {
+        match self {
+            CliError::RuleLoad { .. } => "rule_load",
+            CliError::Io { .. } => "io",
+            CliError::OutputCreate { .. } => "output_create",
+            CliError::Config(_) => "config",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let path = match self {
+            CliError::RuleLoad { path, .. } | CliError::Io { path, .. } => path.as_ref(),
+            CliError::OutputCreate { path, .. } => Some(path),
+            CliError::Config(_) => None,
+        };
+        json!({
+            "error": self.category(),
+            "message": self.to_string(),
+            "path": path.map(|p| p.display().to_string()),
+        })
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::RuleLoad { message, .. }
+            | CliError::Io { message, .. }
+            | CliError::OutputCreate { message, .. }
+            | CliError::Config(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// How errors are written to stderr.
+#[derive(Clone, Copy)]
+pub enum ErrorsFormat {
+    Text,
+    /// A line of JSON with the error's category, message and path.
+    Json,
+}
+
+impl FromStr for ErrorsFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ErrorsFormat::Text),
+            "json" => Ok(ErrorsFormat::Json),
+            _ => Err(format!(
+                "Unknown errors format {}, expected text or json",
+                s
+            )),
+        }
+    }
+}
+
+impl ErrorsFormat {
+    pub fn write(&self, w: &mut impl std::io::Write, e: &CliError) -> std::io::Result<()> {
+        match self {
+            ErrorsFormat::Text => writeln!(w, "{}", e),
+            ErrorsFormat::Json => writeln!(w, "{}", e.to_json()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_carry_their_path() {
+        let error = CliError::io(Some(Path::new("logs/*.json")), "No files match logs/*.json");
+        assert_eq!(
+            error.to_json(),
+            json!({ "error": "io", "message": "No files match logs/*.json", "path": "logs/*.json" })
+        );
+        let error = CliError::config("--output-fields requires --output-format cef or leef");
+        assert_eq!(error.to_json()["path"], serde_json::Value::Null);
+    }
+}
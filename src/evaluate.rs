@@ -0,0 +1,134 @@
+use std::{
+    fs,
+    io::{stderr, stdout, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::input::Input;
+use crate::output::csv_escape;
+use crate::rules;
+
+#[derive(StructOpt)]
+pub struct Evaluate {
+    /// The rules to evaluate.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+
+    /// The labelled corpus.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    input: Vec<PathBuf>,
+
+    /// JSON lines labelling the corpus's events in order, by default labels are read from the events.
+    #[structopt(long, parse(from_os_str))]
+    labels: Option<PathBuf>,
+
+    /// The field holding the names of the rules an event should match, unused with --labels.
+    #[structopt(long, default_value = "labels")]
+    label_field: String,
+
+    /// List every false positive and false negative after the summary.
+    #[structopt(long)]
+    details: bool,
+}
+
+#[derive(Default)]
+struct Score {
+    true_positives: usize,
+    false_positives: Vec<usize>,
+    false_negatives: Vec<usize>,
+}
+
+/// A ratio as a percentage, or `-` when there is nothing to divide by.
+fn percent(n: usize, d: usize) -> String {
+    match d {
+        0 => "-".to_string(),
+        d => format!("{:.1}%", n as f64 * 100.0 / d as f64),
+    }
+}
+
+impl Evaluate {
+    pub fn run(self) -> Result<(), String> {
+        let rules = rules::load(&self.rules)?;
+        let mut labels = match &self.labels {
+            Some(path) => {
+                let file = fs::File::open(path)
+                    .map_err(|_| format!("Unable to read input file at {}.", path.display()))?;
+                Some(BufReader::new(file).lines())
+            }
+            None => None,
+        };
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let mut scores: Vec<Score> = rules.iter().map(|_| Score::default()).collect();
+        let mut events = 0;
+        for res in Input::open(Some(self.input.clone()), None, None)? {
+            // Errors still take a line, so labels stay in step with the corpus
+            events += 1;
+            let expected = match labels.as_mut().map(|l| l.next()) {
+                Some(Some(Ok(line))) => Some(
+                    serde_json::from_str::<Value>(&line)
+                        .map_err(|e| format!("Invalid labels for event {}, {}", events, e))?,
+                ),
+                Some(_) => return Err(format!("No labels for event {}", events)),
+                None => None,
+            };
+            let json = match res {
+                Ok(json) => json,
+                Err(e) => {
+                    let _ = writeln!(stderr, "{}", e);
+                    continue;
+                }
+            };
+            for ((name, rule), score) in rules.iter().zip(scores.iter_mut()) {
+                let should = match &expected {
+                    Some(l) => rules::has_label(l, name),
+                    None => rules::labelled(&json, &self.label_field, name),
+                };
+                match (rule.matches(&json), should) {
+                    (true, true) => score.true_positives += 1,
+                    (true, false) => score.false_positives.push(events),
+                    (false, true) => score.false_negatives.push(events),
+                    (false, false) => {}
+                }
+            }
+        }
+        let _ = writeln!(
+            stdout,
+            "Rule, True Positives, False Positives, False Negatives, Precision, Recall"
+        );
+        for ((name, _), score) in rules.iter().zip(scores.iter()) {
+            let (tp, fp, fn_) = (
+                score.true_positives,
+                score.false_positives.len(),
+                score.false_negatives.len(),
+            );
+            let _ = writeln!(
+                stdout,
+                "{}, {}, {}, {}, {}, {}",
+                csv_escape(name),
+                tp,
+                fp,
+                fn_,
+                percent(tp, tp + fp),
+                percent(tp, tp + fn_)
+            );
+        }
+        if self.details {
+            let _ = writeln!(stdout, "Rule, Result, Event");
+            for ((name, _), score) in rules.iter().zip(scores.iter()) {
+                for (result, list) in [
+                    ("false positive", &score.false_positives),
+                    ("false negative", &score.false_negatives),
+                ] {
+                    for event in list {
+                        let _ = writeln!(stdout, "{}, {}, {}", csv_escape(name), result, event);
+                    }
+                }
+            }
+        }
+        let _ = writeln!(stderr, "Evaluated {} events", events);
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+/// Seconds between the FILETIME epoch, 1601, and the unix epoch.
+const FILETIME_EPOCH: u64 = 11_644_473_600;
+
+/// Seconds from the unix epoch to the end of 9999, the last time RFC 3339 can represent.
+pub const RFC3339_MAX: u64 = 253_402_300_799;
+
+/// Look up a dot separated field path, e.g. `a.b`, in an event.
+pub fn get<'a>(json: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(json, |v, key| match v {
+        Value::Object(o) => o.get(key),
+        Value::Array(a) => key.parse::<usize>().ok().and_then(|i| a.get(i)),
+        _ => None,
+    })
+}
+
+/// Look up a dot separated field path for modification.
+pub fn get_mut<'a>(json: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    path.split('.').try_fold(json, |v, key| match v {
+        Value::Object(o) => o.get_mut(key),
+        Value::Array(a) => key.parse::<usize>().ok().and_then(move |i| a.get_mut(i)),
+        _ => None,
+    })
+}
+
+/// Set a dot separated field path, adding objects for any parts of the path which are missing or
+/// aren't objects or arrays. Array items are only replaced, never added.
+pub fn set(json: &mut Value, path: &str, value: Value) {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (parent, key),
+        None => ("", path),
+    };
+    let mut current = json;
+    for part in parent.split('.').filter(|p| !p.is_empty()) {
+        current = match current {
+            Value::Array(a) => match part.parse::<usize>().ok().filter(|i| *i < a.len()) {
+                Some(i) => &mut a[i],
+                None => return,
+            },
+            v => {
+                if !v.is_object() {
+                    *v = Value::Object(Default::default());
+                }
+                v.as_object_mut()
+                    .expect("just made an object")
+                    .entry(part)
+                    .or_insert(Value::Null)
+            }
+        };
+    }
+    match current {
+        Value::Array(a) => {
+            if let Some(item) = key.parse::<usize>().ok().and_then(|i| a.get_mut(i)) {
+                *item = value;
+            }
+        }
+        v => {
+            if !v.is_object() {
+                *v = Value::Object(Default::default());
+            }
+            if let Some(o) = v.as_object_mut() {
+                o.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+/// Convert numeric strings to numbers and `"true"`/`"false"` to booleans, throughout the value.
+pub fn coerce(json: &mut Value) {
+    match json {
+        Value::Object(o) => o.values_mut().for_each(coerce),
+        Value::Array(a) => a.iter_mut().for_each(coerce),
+        Value::String(s) => {
+            if let Some(v) = coerce_str(s) {
+                *json = v;
+            }
+        }
+        _ => {}
+    }
+}
+
+fn coerce_str(s: &str) -> Option<Value> {
+    match s {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    // Only canonical numbers are converted, so values like '007' or '+1' keep their meaning
+    if let Ok(i) = s.parse::<i64>() {
+        return match i.to_string() == s {
+            true => Some(Value::from(i)),
+            false => None,
+        };
+    }
+    if let Ok(u) = s.parse::<u64>() {
+        return match u.to_string() == s {
+            true => Some(Value::from(u)),
+            false => None,
+        };
+    }
+    match s.parse::<f64>() {
+        Ok(f) if f.is_finite() && s.contains(&['.', 'e', 'E'][..]) && !s.starts_with('+') => {
+            serde_json::Number::from_f64(f).map(Value::Number)
+        }
+        _ => None,
+    }
+}
+
+/// Lowercase every string throughout the value.
+pub fn lowercase(json: &mut Value) {
+    match json {
+        Value::Object(o) => o.values_mut().for_each(lowercase),
+        Value::Array(a) => a.iter_mut().for_each(lowercase),
+        Value::String(s) => *s = s.to_lowercase(),
+        _ => {}
+    }
+}
+
+/// A string identifying an event by the values of the given fields, or the whole event when no fields
+/// are given.
+pub fn key(json: &Value, fields: &[String]) -> String {
+    match fields.is_empty() {
+        true => json.to_string(),
+        false => Value::Array(
+            fields
+                .iter()
+                .map(|f| get(json, f).cloned().unwrap_or(Value::Null))
+                .collect(),
+        )
+        .to_string(),
+    }
+}
+
+/// Format a time as an RFC 3339 timestamp, `None` past 9999 where the format ends.
+pub fn rfc3339(time: SystemTime) -> Option<String> {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) if d.as_secs() > RFC3339_MAX => None,
+        _ => Some(humantime::format_rfc3339_millis(time).to_string()),
+    }
+}
+
+/// Format a Windows FILETIME, 100ns intervals since 1601, as an RFC 3339 timestamp. Times before
+/// the unix epoch, usually unset fields, and corrupt times past 9999 are `null`.
+pub fn filetime(ticks: u64) -> Value {
+    match (ticks / 10_000_000).checked_sub(FILETIME_EPOCH) {
+        Some(secs) if secs <= RFC3339_MAX => {
+            let time = UNIX_EPOCH
+                + Duration::from_secs(secs)
+                + Duration::from_nanos((ticks % 10_000_000) * 100);
+            Value::String(humantime::format_rfc3339_micros(time).to_string())
+        }
+        _ => Value::Null,
+    }
+}
+
+/// Read a time from an RFC 3339 timestamp or a number of seconds since the unix epoch, `None` when
+/// the number is too large for a time.
+pub fn time(value: &Value) -> Option<SystemTime> {
+    match value {
+        Value::String(s) => humantime::parse_rfc3339_weak(s.trim_end_matches('Z')).ok(),
+        Value::Number(n) => {
+            let secs = n.as_f64().filter(|s| s.is_finite() && *s >= 0.0)?;
+            UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(secs).ok()?)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn time_reads_timestamps_and_seconds() {
+        let expected = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(time(&json!("2023-11-14T22:13:20Z")), Some(expected));
+        assert_eq!(time(&json!(1_700_000_000)), Some(expected));
+        assert_eq!(time(&json!(-1)), None);
+    }
+
+    #[test]
+    fn time_rejects_numbers_too_large_for_a_time() {
+        assert_eq!(time(&json!(1e20)), None);
+        assert_eq!(time(&json!(f64::MAX)), None);
+    }
+
+    #[test]
+    fn times_past_9999_are_not_formatted() {
+        assert_eq!(filetime(u64::MAX), Value::Null);
+        assert_eq!(filetime(0), Value::Null);
+        assert_eq!(
+            filetime(116_444_736_000_000_000),
+            json!("1970-01-01T00:00:00.000000Z")
+        );
+        let time = UNIX_EPOCH + Duration::from_secs(RFC3339_MAX + 1);
+        assert_eq!(rfc3339(time), None);
+    }
+}
@@ -0,0 +1,880 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    sync::mpsc::{self, SyncSender},
+    thread,
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::event::filetime;
+use crate::input::Input;
+
+/// Chunks start after the 4096 byte file header, each is 64KiB.
+const HEADER_SIZE: usize = 4096;
+const CHUNK_SIZE: usize = 65536;
+/// Records start after the chunk header and its string and template tables.
+const RECORDS_START: usize = 512;
+/// How deeply elements and templates may nest, so a corrupt record can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+/// The most elements and pieces of text a record may expand to, so templates nesting copies of
+/// other templates can't grow a record without bound.
+const MAX_NODES: usize = 100_000;
+
+/// Part of an element's text, or of an attribute's value.
+#[derive(Clone)]
+enum Piece {
+    Value(Value),
+    /// A value taken from the template instance, optional substitutions are left out when null.
+    Substitution {
+        id: u16,
+        optional: bool,
+    },
+    /// Binary XML given as a substitution value, such as the `UserData` of an event.
+    Fragment(Vec<Content>),
+}
+
+#[derive(Clone)]
+enum Content {
+    Element(Element),
+    Text(Piece),
+}
+
+#[derive(Clone)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, Vec<Piece>)>,
+    children: Vec<Content>,
+}
+
+/// What a chunk's records share as they're read.
+struct State {
+    /// The templates used so far, by their offset in the chunk.
+    templates: HashMap<usize, Vec<Content>>,
+    /// How many more nodes the record being read may expand to.
+    budget: usize,
+}
+
+impl State {
+    fn spend(&mut self, nodes: usize) -> Option<()> {
+        self.budget = self.budget.checked_sub(nodes)?;
+        Some(())
+    }
+}
+
+/// Reads the binary XML of a chunk, with offsets checked on every read so a corrupt file can't
+/// panic. Names and templates are referred to by their offset in the chunk.
+struct Parser<'a, 'b> {
+    chunk: &'a [u8],
+    pos: usize,
+    end: usize,
+    /// Elements within a substitution value have no dependency identifier.
+    in_substitution: bool,
+    state: &'b mut State,
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn utf16(data: &[u8]) -> String {
+    String::from_utf16_lossy(
+        &data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>(),
+    )
+    .trim_end_matches('\0')
+    .to_string()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn guid(data: &[u8]) -> Option<String> {
+    let data: &[u8; 16] = data.get(..16)?.try_into().ok()?;
+    Some(format!(
+        "{:08X}-{:04X}-{:04X}-{}-{}",
+        u32::from_le_bytes(data[..4].try_into().ok()?),
+        u16::from_le_bytes([data[4], data[5]]),
+        u16::from_le_bytes([data[6], data[7]]),
+        hex(&data[8..10]),
+        hex(&data[10..])
+    ))
+}
+
+fn sid(data: &[u8]) -> Option<String> {
+    let count = *data.get(1)? as usize;
+    let authority = data
+        .get(2..8)?
+        .iter()
+        .fold(0u64, |a, b| (a << 8) | *b as u64);
+    let mut sid = format!("S-{}-{}", data.first()?, authority);
+    for i in 0..count {
+        sid.push_str(&format!("-{}", u32_at(data, 8 + i * 4)?));
+    }
+    Some(sid)
+}
+
+fn systemtime(data: &[u8]) -> Option<String> {
+    let field = |i: usize| u16_at(data, i * 2);
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        field(0)?,
+        field(1)?,
+        field(3)?,
+        field(4)?,
+        field(5)?,
+        field(6)?,
+        field(7)?
+    ))
+}
+
+/// Decode a value by its binary XML type, unknown types are given as hex.
+fn value(kind: u8, data: &[u8]) -> Value {
+    // Arrays of strings are separated by nulls, other arrays are of fixed size values
+    if kind & 0x80 != 0 {
+        let kind = kind & 0x7f;
+        let size = match kind {
+            0x01 => return json!(utf16(data).split('\0').collect::<Vec<_>>()),
+            0x02 => {
+                let text = String::from_utf8_lossy(data);
+                return json!(text.trim_end_matches('\0').split('\0').collect::<Vec<_>>());
+            }
+            0x03 | 0x04 => 1,
+            0x05 | 0x06 => 2,
+            0x07 | 0x08 | 0x0b | 0x0d | 0x14 => 4,
+            0x09 | 0x0a | 0x0c | 0x11 | 0x15 => 8,
+            0x0f | 0x12 => 16,
+            _ => return json!(hex(data)),
+        };
+        return Value::Array(data.chunks_exact(size).map(|v| value(kind, v)).collect());
+    }
+    let decoded = match kind {
+        0x00 => Some(Value::Null),
+        0x01 => Some(json!(utf16(data))),
+        0x02 => Some(json!(String::from_utf8_lossy(data).trim_end_matches('\0'))),
+        0x03 => data.first().map(|b| json!(*b as i8)),
+        0x04 => data.first().map(|b| json!(b)),
+        0x05 => u16_at(data, 0).map(|v| json!(v as i16)),
+        0x06 => u16_at(data, 0).map(|v| json!(v)),
+        0x07 => u32_at(data, 0).map(|v| json!(v as i32)),
+        0x08 => u32_at(data, 0).map(|v| json!(v)),
+        0x09 => u64_at(data, 0).map(|v| json!(v as i64)),
+        0x0a => u64_at(data, 0).map(|v| json!(v)),
+        0x0b => u32_at(data, 0).map(|v| json!(f32::from_bits(v))),
+        0x0c => u64_at(data, 0).map(|v| json!(f64::from_bits(v))),
+        0x0d => u32_at(data, 0).map(|v| json!(v != 0)),
+        0x0e => Some(json!(hex(data))),
+        0x0f => guid(data).map(Value::String),
+        0x10 if data.len() == 4 => u32_at(data, 0).map(|v| json!(format!("0x{:x}", v))),
+        0x10 => u64_at(data, 0).map(|v| json!(format!("0x{:x}", v))),
+        0x11 => u64_at(data, 0).map(filetime),
+        0x12 => systemtime(data).map(Value::String),
+        0x13 => sid(data).map(Value::String),
+        0x14 => u32_at(data, 0).map(|v| json!(format!("0x{:x}", v))),
+        0x15 => u64_at(data, 0).map(|v| json!(format!("0x{:x}", v))),
+        0x23 => Some(json!(utf16(data))),
+        _ => None,
+    };
+    decoded.unwrap_or_else(|| json!(hex(data)))
+}
+
+impl Parser<'_, '_> {
+    fn u8(&mut self) -> Option<u8> {
+        let v = *self.chunk.get(self.pos).filter(|_| self.pos < self.end)?;
+        self.pos += 1;
+        Some(v)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let v = u16_at(self.chunk, self.pos)?;
+        self.pos += 2;
+        Some(v)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let v = u32_at(self.chunk, self.pos)?;
+        self.pos += 4;
+        Some(v)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u16()? as usize * 2;
+        let text = utf16(self.chunk.get(self.pos..self.pos + len)?);
+        self.pos += len;
+        Some(text)
+    }
+
+    /// A name, written where it's first used and referred to by its offset after that.
+    fn name(&mut self) -> Option<String> {
+        let offset = self.u32()? as usize;
+        let len = u16_at(self.chunk, offset + 6)? as usize * 2;
+        let name = utf16(self.chunk.get(offset + 8..offset + 8 + len)?);
+        if offset == self.pos {
+            // The name's header, characters and null terminator
+            self.pos += 8 + len + 2;
+        }
+        Some(name)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.chunk
+            .get(self.pos)
+            .copied()
+            .filter(|_| self.pos < self.end)
+    }
+
+    /// The tokens making up an element's content or a fragment, up to the end of the element or
+    /// the stream.
+    fn contents(&mut self, depth: usize) -> Option<Vec<Content>> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let mut contents = Vec::new();
+        while let Some(token) = self.u8() {
+            self.state.spend(1)?;
+            match token & 0xbf {
+                0x00 | 0x04 => break,
+                0x01 => contents.push(Content::Element(self.element(token, depth)?)),
+                0x05 | 0x07 | 0x08 | 0x09 | 0x0d | 0x0e => {
+                    contents.push(Content::Text(self.piece(token)?))
+                }
+                0x0a => {
+                    self.name()?;
+                }
+                0x0b => {
+                    self.string()?;
+                }
+                0x0c => contents.extend(self.template_instance(depth)?),
+                0x0f => self.pos += 3,
+                _ => return None,
+            }
+        }
+        Some(contents)
+    }
+
+    /// A value, substitution or reference making up text or an attribute's value.
+    fn piece(&mut self, token: u8) -> Option<Piece> {
+        Some(match token & 0xbf {
+            0x05 => {
+                let kind = self.u8()?;
+                match kind {
+                    0x01 => Piece::Value(Value::String(self.string()?)),
+                    _ => return None,
+                }
+            }
+            0x07 => Piece::Value(Value::String(self.string()?)),
+            0x08 => {
+                let c = char::from_u32(self.u16()? as u32).unwrap_or('\u{fffd}');
+                Piece::Value(Value::String(c.to_string()))
+            }
+            0x09 => {
+                let entity = match self.name()?.as_str() {
+                    "amp" => "&",
+                    "lt" => "<",
+                    "gt" => ">",
+                    "quot" => "\"",
+                    "apos" => "'",
+                    _ => "",
+                };
+                Piece::Value(Value::String(entity.to_string()))
+            }
+            0x0d | 0x0e => {
+                let id = self.u16()?;
+                // The expected type, the value's own type is used instead
+                self.u8()?;
+                Piece::Substitution {
+                    id,
+                    optional: token & 0xbf == 0x0e,
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    fn element(&mut self, token: u8, depth: usize) -> Option<Element> {
+        if !self.in_substitution {
+            self.pos += 2;
+        }
+        let _size = self.u32()?;
+        let name = self.name()?;
+        if token & 0x40 != 0 {
+            let _size = self.u32()?;
+        }
+        let mut attributes = Vec::new();
+        while matches!(self.peek(), Some(t) if t & 0xbf == 0x06) {
+            self.pos += 1;
+            let name = self.name()?;
+            let mut pieces = Vec::new();
+            while let Some(t) = self.peek() {
+                match t & 0xbf {
+                    0x05 | 0x08 | 0x09 | 0x0d | 0x0e => {
+                        self.pos += 1;
+                        pieces.push(self.piece(t)?);
+                    }
+                    _ => break,
+                }
+            }
+            attributes.push((name, pieces));
+        }
+        let children = match self.u8()? {
+            0x02 => self.contents(depth + 1)?,
+            0x03 => Vec::new(),
+            _ => return None,
+        };
+        Some(Element {
+            name,
+            attributes,
+            children,
+        })
+    }
+
+    /// The contents of a template with the instance's values in place of its substitutions. A
+    /// template is defined in place where it's first used and referred to by its offset after.
+    fn template_instance(&mut self, depth: usize) -> Option<Vec<Content>> {
+        self.pos += 1;
+        let _id = self.u32()?;
+        let offset = self.u32()? as usize;
+        let size = u32_at(self.chunk, offset + 20)? as usize;
+        if offset == self.pos {
+            self.pos += 24 + size;
+        }
+        if !self.state.templates.contains_key(&offset) {
+            let mut parser = Parser {
+                chunk: self.chunk,
+                pos: offset + 24,
+                end: (offset + 24 + size).min(self.chunk.len()),
+                in_substitution: false,
+                state: self.state,
+            };
+            let template = parser.contents(depth + 1)?;
+            self.state.templates.insert(offset, template);
+        }
+        let count = self.u32()? as usize;
+        let mut descriptors = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            let size = self.u16()? as usize;
+            let kind = self.u8()?;
+            self.pos += 1;
+            descriptors.push((size, kind));
+        }
+        let mut values = Vec::with_capacity(descriptors.len());
+        for (size, kind) in descriptors {
+            let data = self.chunk.get(self.pos..self.pos + size)?;
+            values.push(match kind {
+                0x00 => None,
+                0x21 => {
+                    let mut parser = Parser {
+                        chunk: self.chunk,
+                        pos: self.pos,
+                        end: self.pos + size,
+                        in_substitution: true,
+                        state: self.state,
+                    };
+                    Some(Piece::Fragment(parser.contents(depth + 1)?))
+                }
+                _ => Some(Piece::Value(value(kind, data))),
+            });
+            self.pos += size;
+        }
+        // The template was read within a record's budget, so copying it is bounded too
+        let template = self.state.templates.get(&offset)?.clone();
+        substitute(&template, &values, self.state)
+    }
+}
+
+/// The nodes in a piece, counting those of a fragment.
+fn nodes(piece: &Piece) -> usize {
+    match piece {
+        Piece::Fragment(contents) => 1 + contents_nodes(contents),
+        _ => 1,
+    }
+}
+
+fn contents_nodes(contents: &[Content]) -> usize {
+    contents
+        .iter()
+        .map(|c| match c {
+            Content::Element(e) => {
+                let attributes = e.attributes.iter().flat_map(|(_, p)| p).map(nodes);
+                1 + attributes.sum::<usize>() + contents_nodes(&e.children)
+            }
+            Content::Text(p) => nodes(p),
+        })
+        .sum()
+}
+
+fn substitute_pieces(
+    pieces: &[Piece],
+    values: &[Option<Piece>],
+    state: &mut State,
+) -> Option<Vec<Piece>> {
+    let mut substituted = Vec::new();
+    for piece in pieces {
+        let piece = match piece {
+            Piece::Substitution { id, optional } => match (values.get(*id as usize), optional) {
+                (Some(Some(v)), _) => v,
+                (_, true) => continue,
+                (_, false) => &Piece::Value(Value::Null),
+            },
+            p => p,
+        };
+        state.spend(nodes(piece))?;
+        substituted.push(piece.clone());
+    }
+    Some(substituted)
+}
+
+/// Fill in a template's substitutions with an instance's values, `None` once the record has
+/// expanded to more than `MAX_NODES`.
+fn substitute(
+    template: &[Content],
+    values: &[Option<Piece>],
+    state: &mut State,
+) -> Option<Vec<Content>> {
+    let mut contents = Vec::new();
+    for content in template {
+        match content {
+            Content::Element(e) => {
+                state.spend(1)?;
+                let mut attributes = Vec::new();
+                for (name, pieces) in e.attributes.iter() {
+                    let pieces = substitute_pieces(pieces, values, state)?;
+                    if !pieces.is_empty() {
+                        attributes.push((name.clone(), pieces));
+                    }
+                }
+                contents.push(Content::Element(Element {
+                    name: e.name.clone(),
+                    attributes,
+                    children: substitute(&e.children, values, state)?,
+                }));
+            }
+            Content::Text(p) => {
+                let pieces = substitute_pieces(std::slice::from_ref(p), values, state)?;
+                contents.extend(pieces.into_iter().map(Content::Text));
+            }
+        }
+    }
+    Some(contents)
+}
+
+/// Text made of several pieces is joined as a string, a single piece keeps its type.
+fn text(values: Vec<Value>) -> Option<Value> {
+    match values.len() {
+        0 => None,
+        1 => values.into_iter().next(),
+        _ => Some(Value::String(
+            values
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => s.clone(),
+                    Value::Null => String::new(),
+                    v => v.to_string(),
+                })
+                .collect(),
+        )),
+    }
+}
+
+/// Insert a child element, elements with the same name are gathered into an array.
+fn insert(object: &mut Map<String, Value>, key: String, value: Value) {
+    match object.get_mut(&key) {
+        Some(Value::Array(a)) => a.push(value),
+        Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+        None => {
+            object.insert(key, value);
+        }
+    }
+}
+
+/// Add contents to an element's children and text, flattening fragments into their parent.
+fn gather(contents: &[Content], children: &mut Map<String, Value>, texts: &mut Vec<Value>) {
+    for content in contents {
+        match content {
+            Content::Element(e) => {
+                // EventData's <Data Name="Image">...</Data> is keyed by its Name
+                let name = e
+                    .attributes
+                    .iter()
+                    .find(|(n, _)| e.name == "Data" && n == "Name")
+                    .and_then(|(_, p)| attribute(p));
+                match name {
+                    Some(Value::String(key)) => {
+                        let (mut c, mut t) = (Map::new(), Vec::new());
+                        gather(&e.children, &mut c, &mut t);
+                        insert(children, key, text(t).unwrap_or(Value::Null));
+                    }
+                    _ => insert(children, e.name.clone(), element(e)),
+                }
+            }
+            Content::Text(Piece::Fragment(f)) => gather(f, children, texts),
+            Content::Text(Piece::Value(v)) => texts.push(v.clone()),
+            Content::Text(Piece::Substitution { .. }) => {}
+        }
+    }
+}
+
+fn attribute(pieces: &[Piece]) -> Option<Value> {
+    text(
+        pieces
+            .iter()
+            .filter_map(|p| match p {
+                Piece::Value(v) => Some(v.clone()),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+/// An element as JSON, its attributes under `#attributes` and its text under `#text` when it
+/// has either alongside other content.
+fn element(e: &Element) -> Value {
+    let attributes: Map<String, Value> = e
+        .attributes
+        .iter()
+        .filter_map(|(n, p)| Some((n.clone(), attribute(p)?)))
+        .collect();
+    let (mut children, mut texts) = (Map::new(), Vec::new());
+    gather(&e.children, &mut children, &mut texts);
+    let text = text(texts);
+    if attributes.is_empty() && children.is_empty() {
+        return text.unwrap_or(Value::Null);
+    }
+    let mut object = Map::new();
+    if !attributes.is_empty() {
+        object.insert("#attributes".into(), Value::Object(attributes));
+    }
+    object.extend(children);
+    if let Some(text) = text {
+        object.insert("#text".into(), text);
+    }
+    Value::Object(object)
+}
+
+/// Send an event for each record in the chunk, returning false once events are no longer wanted.
+fn chunk(data: &[u8], source: &str, send: &mut dyn FnMut(Result<Value, String>) -> bool) -> bool {
+    let free = u32_at(data, 48).map_or(data.len(), |f| (f as usize).min(data.len()));
+    let mut state = State {
+        templates: HashMap::new(),
+        budget: MAX_NODES,
+    };
+    let mut pos = RECORDS_START;
+    while pos + 24 <= free && data.get(pos..pos + 4) == Some(&[0x2a, 0x2a, 0, 0][..]) {
+        let size = u32_at(data, pos + 4).unwrap_or(0) as usize;
+        if size < 28 || pos + size > data.len() {
+            break;
+        }
+        let id = u64_at(data, pos + 8).unwrap_or(0);
+        state.budget = MAX_NODES;
+        let mut parser = Parser {
+            chunk: data,
+            pos: pos + 24,
+            end: pos + size - 4,
+            in_substitution: false,
+            state: &mut state,
+        };
+        let event = match parser.contents(0) {
+            Some(contents) => {
+                let (mut children, mut texts) = (Map::new(), Vec::new());
+                gather(&contents, &mut children, &mut texts);
+                Ok(Value::Object(children))
+            }
+            None => Err(format!("Unable to read record {} of {}", id, source)),
+        };
+        if !send(event) {
+            return false;
+        }
+        pos += size;
+    }
+    true
+}
+
+/// Read up to `len` bytes into `buffer`, fewer only at the end of the file.
+fn read_block(reader: &mut impl Read, buffer: &mut Vec<u8>, len: usize) -> io::Result<usize> {
+    buffer.clear();
+    reader.take(len as u64).read_to_end(buffer)
+}
+
+/// Send an event for each record of an EVTX file, as `{"Event": {"System": ..., "EventData":
+/// ...}}`, returning false once events are no longer wanted. The file is read a chunk at a time.
+pub fn events(
+    mut reader: impl Read,
+    source: &str,
+    send: &mut dyn FnMut(Result<Value, String>) -> bool,
+) -> bool {
+    let error = |e: io::Error| Err(format!("Unable to read {}, {}", source, e));
+    let mut data = Vec::with_capacity(CHUNK_SIZE);
+    if let Err(e) = read_block(&mut reader, &mut data, HEADER_SIZE) {
+        return send(error(e));
+    }
+    if data.get(..8) != Some(b"ElfFile\0") {
+        return send(Err(format!("{} is not an EVTX file", source)));
+    }
+    // The header's count of chunks can be out of date in a file which wasn't closed cleanly
+    loop {
+        match read_block(&mut reader, &mut data, CHUNK_SIZE) {
+            Ok(0) => return true,
+            Ok(_) => {}
+            Err(e) => return send(error(e)),
+        }
+        if data.get(..8) != Some(b"ElfChnk\0") {
+            continue;
+        }
+        if !chunk(&data, source, send) {
+            return false;
+        }
+    }
+}
+
+fn read(path: PathBuf, tx: &SyncSender<Result<Value, String>>) -> bool {
+    let source = path.display().to_string();
+    match fs::File::open(&path) {
+        Ok(file) => events(io::BufReader::new(file), &source, &mut |e| {
+            tx.send(e).is_ok()
+        }),
+        Err(e) => tx
+            .send(Err(format!("Unable to read {}, {}", source, e)))
+            .is_ok(),
+    }
+}
+
+/// Read Windows event logs, emitting an event per record in the shape of the event's XML, with
+/// each `Data` of its `EventData` keyed by name, e.g. `Event.EventData.Image`.
+pub fn open(paths: Vec<PathBuf>) -> Result<Input, String> {
+    for path in paths.iter() {
+        if !path.is_file() {
+            return Err(format!("Unable to read input file at {}.", path.display()));
+        }
+    }
+    let (tx, rx) = mpsc::sync_channel(1024);
+    thread::spawn(move || {
+        for path in paths {
+            if !read(path, &tx) {
+                break;
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Binary XML written into a chunk, which offsets into it are taken from.
+    struct Chunk(Vec<u8>);
+
+    impl Chunk {
+        fn new() -> Self {
+            let mut data = b"ElfChnk\0".to_vec();
+            data.resize(RECORDS_START, 0);
+            Chunk(data)
+        }
+
+        fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+            self.0.extend_from_slice(bytes);
+            self
+        }
+
+        fn u16(&mut self, v: u16) -> &mut Self {
+            self.bytes(&v.to_le_bytes())
+        }
+
+        fn u32(&mut self, v: u32) -> &mut Self {
+            self.bytes(&v.to_le_bytes())
+        }
+
+        fn utf16(&mut self, s: &str) -> &mut Self {
+            for unit in s.encode_utf16() {
+                self.u16(unit);
+            }
+            self
+        }
+
+        /// A name written in place.
+        fn name(&mut self, name: &str) -> &mut Self {
+            let offset = self.0.len() as u32 + 4;
+            self.u32(offset).u32(0).u16(0).u16(name.len() as u16);
+            self.utf16(name).u16(0)
+        }
+
+        fn open(&mut self, name: &str) -> &mut Self {
+            self.bytes(&[0x01, 0, 0]).u32(0).name(name).bytes(&[0x02])
+        }
+
+        fn text(&mut self, text: &str) -> &mut Self {
+            self.bytes(&[0x05, 0x01]).u16(text.len() as u16).utf16(text)
+        }
+
+        fn close(&mut self) -> &mut Self {
+            self.bytes(&[0x04])
+        }
+
+        /// A record whose binary XML is written by `xml`.
+        fn record(&mut self, id: u64, xml: impl FnOnce(&mut Self)) -> &mut Self {
+            let start = self.0.len();
+            self.bytes(&[0x2a, 0x2a, 0, 0])
+                .u32(0)
+                .bytes(&id.to_le_bytes())
+                .bytes(&[0; 8]);
+            self.bytes(&[0x0f, 1, 1, 0]);
+            xml(self);
+            self.bytes(&[0x00]);
+            let size = (self.0.len() + 4 - start) as u32;
+            self.u32(size);
+            self.0[start + 4..start + 8].copy_from_slice(&size.to_le_bytes());
+            let free = self.0.len() as u32;
+            self.0[48..52].copy_from_slice(&free.to_le_bytes());
+            self
+        }
+
+        /// A template instance defined in place, its body written by `xml`, with no values.
+        fn template(&mut self, xml: impl FnOnce(&mut Self)) -> &mut Self {
+            self.bytes(&[0x0c, 0x01]).u32(0);
+            let offset = self.0.len() + 4;
+            self.u32(offset as u32).bytes(&[0; 24]);
+            self.bytes(&[0x0f, 1, 1, 0]);
+            xml(self);
+            self.bytes(&[0x00]);
+            let size = (self.0.len() - offset - 24) as u32;
+            self.0[offset + 20..offset + 24].copy_from_slice(&size.to_le_bytes());
+            self
+        }
+
+        /// An EVTX file holding the chunk.
+        fn file(&self) -> Vec<u8> {
+            let mut file = b"ElfFile\0".to_vec();
+            file.resize(HEADER_SIZE, 0);
+            let mut chunk = self.0.clone();
+            chunk.resize(CHUNK_SIZE, 0);
+            file.extend(chunk);
+            file
+        }
+    }
+
+    fn read(file: &[u8]) -> Vec<Result<Value, String>> {
+        let mut read = Vec::new();
+        events(file, "test.evtx", &mut |e| {
+            read.push(e);
+            true
+        });
+        read
+    }
+
+    #[test]
+    fn records_take_the_shape_of_their_xml() {
+        let mut chunk = Chunk::new();
+        chunk.record(1, |x| {
+            x.open("Event").open("System").open("EventID").text("4624");
+            x.close().close().close();
+        });
+        chunk.record(2, |x| {
+            x.open("Event").open("System").open("EventID").text("4625");
+            x.close().close().close();
+        });
+        let events = read(&chunk.file());
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1].as_ref().unwrap(),
+            &json!({ "Event": { "System": { "EventID": "4625" } } })
+        );
+    }
+
+    #[test]
+    fn template_values_are_substituted() {
+        let mut chunk = Chunk::new();
+        chunk.record(1, |x| {
+            x.template(|t| {
+                t.open("Event").open("EventData");
+                t.bytes(&[0x41, 0, 0]).u32(0).name("Data").u32(0);
+                t.bytes(&[0x06]).name("Name").text("Image").bytes(&[0x02]);
+                t.bytes(&[0x0d]).u16(0).bytes(&[0x01]);
+                t.close().close().close();
+            });
+            x.u32(1).u16(14).bytes(&[0x01, 0]).utf16("cmd.exe");
+        });
+        let events = read(&chunk.file());
+        assert_eq!(
+            events[0].as_ref().unwrap(),
+            &json!({ "Event": { "EventData": { "Image": "cmd.exe" } } })
+        );
+    }
+
+    #[test]
+    fn nested_templates_are_bounded() {
+        let mut chunk = Chunk::new();
+        // Each template holds two copies of the one before, doubling what a record expands to
+        let mut offsets = Vec::new();
+        let mut definitions = Chunk(vec![0; 32768]);
+        for level in 0..40 {
+            let offset = definitions.0.len();
+            definitions.bytes(&[0; 24]).bytes(&[0x0f, 1, 1, 0]);
+            match offsets.last() {
+                None => {
+                    definitions
+                        .bytes(&[0x01, 0, 0])
+                        .u32(0)
+                        .name("x")
+                        .bytes(&[0x03]);
+                }
+                Some(previous) => {
+                    for _ in 0..2 {
+                        definitions
+                            .bytes(&[0x0c, 0x01])
+                            .u32(level)
+                            .u32(*previous)
+                            .u32(0);
+                    }
+                }
+            }
+            definitions.bytes(&[0x00]);
+            let size = (definitions.0.len() - offset - 24) as u32;
+            definitions.0[offset + 20..offset + 24].copy_from_slice(&size.to_le_bytes());
+            offsets.push(offset as u32);
+        }
+        let last = *offsets.last().unwrap();
+        chunk.record(1, |x| {
+            x.bytes(&[0x0c, 0x01]).u32(40).u32(last).u32(0);
+        });
+        let records = chunk.0.len();
+        chunk.0.resize(32768, 0);
+        chunk.0.extend_from_slice(&definitions.0[32768..]);
+        assert!(chunk.0.len() <= CHUNK_SIZE && records <= 32768);
+        let events = read(&chunk.file());
+        assert_eq!(
+            events,
+            vec![Err("Unable to read record 1 of test.evtx".to_string())]
+        );
+    }
+
+    #[test]
+    fn files_which_are_not_evtx() {
+        let events = read(b"{}");
+        assert_eq!(
+            events,
+            vec![Err("test.evtx is not an EVTX file".to_string())]
+        );
+    }
+
+    #[test]
+    fn corrupt_values_are_hex() {
+        assert_eq!(value(0x08, &[1, 0, 0, 0]), json!(1));
+        assert_eq!(value(0x08, &[1, 0]), json!("0100"));
+        assert_eq!(value(0x11, &[0xff; 8]), Value::Null);
+        assert_eq!(value(0x86, &[1, 0, 2, 0]), json!([1, 2]));
+    }
+}
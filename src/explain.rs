@@ -0,0 +1,223 @@
+use std::{
+    fs,
+    io::{stdout, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::{Mapping, Value as Yaml};
+use structopt::StructOpt;
+
+use crate::optimize::{parse, Expression};
+
+#[derive(StructOpt)]
+pub struct Explain {
+    /// The rules to explain.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+}
+
+/// How a value is compared, e.g. `contains "x"` for `*x*` or `is greater than 5` for `>5`.
+fn comparison(value: &Yaml) -> (String, String) {
+    let s = match value {
+        Yaml::String(s) => s,
+        Yaml::Null => return ("is".into(), "null".into()),
+        v => {
+            let text = serde_yaml::to_string(v).unwrap_or_default();
+            let text = text.trim_start_matches("---").trim();
+            return ("equals".into(), text.to_string());
+        }
+    };
+    let quoted = |s: &str| format!("\"{}\"", s);
+    if let Some(regex) = s.strip_prefix('?') {
+        return ("matches the regex".into(), quoted(regex));
+    }
+    if let Some(rest) = s.strip_prefix('i') {
+        let (verb, operand) = comparison(&Yaml::String(rest.to_string()));
+        return (format!("{} (ignoring case)", verb), operand);
+    }
+    for (op, verb) in [
+        (">=", "is at least"),
+        ("<=", "is at most"),
+        (">", "is greater than"),
+        ("<", "is less than"),
+    ] {
+        if let Some(n) = s.strip_prefix(op).filter(|n| n.parse::<f64>().is_ok()) {
+            return (verb.into(), n.to_string());
+        }
+    }
+    let (starts, ends) = (s.starts_with('*'), s.len() > 1 && s.ends_with('*'));
+    let inner = s.trim_start_matches('*').trim_end_matches('*');
+    match (starts, ends) {
+        (true, true) => ("contains".into(), quoted(inner)),
+        (true, false) => ("ends with".into(), quoted(inner)),
+        (false, true) => ("starts with".into(), quoted(inner)),
+        (false, false) => ("equals".into(), quoted(s)),
+    }
+}
+
+/// A field's test, alternatives compared the same way are listed together.
+fn field(name: &str, value: &Yaml) -> String {
+    let values = match value {
+        Yaml::Sequence(s) => s,
+        v => {
+            let (verb, operand) = comparison(v);
+            return format!("{} {} {}", name, verb, operand);
+        }
+    };
+    let comparisons: Vec<(String, String)> = values.iter().map(comparison).collect();
+    match comparisons.first() {
+        Some((verb, _)) if comparisons.iter().all(|(v, _)| v == verb) => {
+            let operands: Vec<&str> = comparisons.iter().map(|(_, o)| o.as_str()).collect();
+            format!("{} {} any of {}", name, verb, operands.join(", "))
+        }
+        _ => {
+            let tests: Vec<String> = comparisons
+                .iter()
+                .map(|(v, o)| format!("{} {}", v, o))
+                .collect();
+            format!("{} {}", name, tests.join(" OR "))
+        }
+    }
+}
+
+fn indented(lines: &mut Vec<String>, depth: usize, line: String) {
+    lines.push(format!("{}{}", "  ".repeat(depth), line));
+}
+
+/// The lines explaining an identifier, its fields are listed on their own when `inline` as the
+/// caller already requires all of them.
+fn identifier(lines: &mut Vec<String>, depth: usize, value: &Yaml, inline: bool) {
+    let fields = |lines: &mut Vec<String>, depth: usize, fields: &Mapping, inline: bool| {
+        let depth = match inline || fields.len() == 1 {
+            true => depth,
+            false => {
+                indented(lines, depth, "ALL of:".into());
+                depth + 1
+            }
+        };
+        for (k, v) in fields.iter() {
+            let name = k.as_str().unwrap_or_default();
+            indented(lines, depth, field(name, v));
+        }
+    };
+    match value {
+        Yaml::Mapping(m) => fields(lines, depth, m, inline),
+        Yaml::Sequence(s) if s.len() == 1 && s[0].is_mapping() => {
+            identifier(lines, depth, &s[0], inline)
+        }
+        Yaml::Sequence(s) if s.iter().all(|a| a.is_mapping()) => {
+            indented(lines, depth, "ANY of:".into());
+            for a in s.iter().filter_map(|a| a.as_mapping()) {
+                fields(lines, depth + 1, a, false);
+            }
+        }
+        v => {
+            let text = serde_yaml::to_string(v).unwrap_or_default();
+            indented(
+                lines,
+                depth,
+                text.trim_start_matches("---").trim().to_string(),
+            );
+        }
+    }
+}
+
+fn expression(
+    lines: &mut Vec<String>,
+    depth: usize,
+    e: &Expression,
+    detection: &Mapping,
+    inline: bool,
+) {
+    match e {
+        Expression::Identifier(i) => match detection.get(&i.as_str().into()) {
+            Some(value) => identifier(lines, depth, value, inline),
+            None => indented(lines, depth, format!("{} (undefined)", i)),
+        },
+        Expression::Not(inner) => {
+            indented(lines, depth, "NOT:".into());
+            expression(lines, depth + 1, inner, detection, false);
+        }
+        Expression::And(terms) => {
+            indented(lines, depth, "ALL of:".into());
+            for t in terms {
+                expression(lines, depth + 1, t, detection, true);
+            }
+        }
+        Expression::Or(terms) => {
+            indented(lines, depth, "ANY of:".into());
+            for t in terms {
+                expression(lines, depth + 1, t, detection, false);
+            }
+        }
+    }
+}
+
+/// A rule's detection logic as indented pseudo-English, one line per test.
+pub fn explain(rule: &Yaml) -> Vec<String> {
+    let mut lines = Vec::new();
+    let detection = match rule.get("detection").and_then(|d| d.as_mapping()) {
+        Some(d) => d,
+        None => return lines,
+    };
+    let condition = detection
+        .get(&"condition".into())
+        .and_then(|c| c.as_str())
+        .unwrap_or_default();
+    match parse(condition) {
+        Some(e) => expression(&mut lines, 0, &e, detection, false),
+        // Conditions with functions or comparisons are shown as they are, with what they use
+        None => {
+            lines.push(format!("Condition: {}", condition));
+            for (k, v) in detection.iter() {
+                let name = k.as_str().unwrap_or_default();
+                if name != "condition" {
+                    lines.push(format!("Where {} is:", name));
+                    identifier(&mut lines, 1, v, false);
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// The explanation of a rule's YAML, `None` when it isn't a rule.
+pub fn text(data: &str) -> Option<String> {
+    let rule: Yaml = serde_yaml::from_str(data).ok()?;
+    let lines = explain(&rule);
+    match lines.is_empty() {
+        true => None,
+        false => Some(lines.join("\n")),
+    }
+}
+
+/// Read a rule file, returning a heading of its path and title with its explanation.
+pub fn file(path: &Path) -> Result<(String, Vec<String>), String> {
+    let data = fs::read_to_string(path)
+        .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+    let rule: Yaml = serde_yaml::from_str(&data)
+        .map_err(|e| format!("Unable to load {} as a rule, {}", path.display(), e))?;
+    let heading = match rule.get("title").and_then(|t| t.as_str()) {
+        Some(title) => format!("{}: {}", path.display(), title),
+        None => path.display().to_string(),
+    };
+    Ok((heading, explain(&rule)))
+}
+
+impl Explain {
+    pub fn run(self) -> Result<(), String> {
+        let mut stdout = stdout();
+        for path in self.rules.iter() {
+            let (heading, lines) = file(path)?;
+            let mut write = || -> std::io::Result<()> {
+                writeln!(stdout, "{}", heading)?;
+                for line in lines.iter() {
+                    writeln!(stdout, "  {}", line)?;
+                }
+                Ok(())
+            };
+            write().map_err(|e| format!("Unable to write explanation, {}", e))?;
+        }
+        Ok(())
+    }
+}
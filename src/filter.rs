@@ -0,0 +1,353 @@
+use std::{borrow::Cow, cmp::Ordering, str::FromStr};
+
+use serde_json::Value;
+
+use crate::event;
+use crate::rules::Level;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+/// An expression over the fields of a rule's metadata or of an event, e.g.
+/// `level >= high && tags contains "attack.persistence"` or `Channel == "Security" || EventID in [1, 3]`.
+#[derive(Clone)]
+pub enum Filter {
+    /// Whether the field is set to something other than false, null or an empty string or list.
+    Present(String),
+    Compare(String, Op, String),
+    In(String, Vec<String>),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    In,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+}
+
+fn tokens(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if_eq(&expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenList,
+            ']' => Token::CloseList,
+            ',' => Token::Comma,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Op(Op::Eq),
+            '!' if next_is('=') => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if next_is('=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' | '\'' => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => quoted.extend(chars.next()),
+                        Some(q) if q == c => break,
+                        Some(q) => quoted.push(q),
+                        None => return Err(format!("unclosed quote in {}", s)),
+                    }
+                }
+                Token::Quoted(quoted)
+            }
+            c if c.is_alphanumeric() || "_.-:@".contains(c) => {
+                let mut word = c.to_string();
+                while let Some(w) = chars.next_if(|w| w.is_alphanumeric() || "_.-:@".contains(*w)) {
+                    word.push(w);
+                }
+                match word.as_str() {
+                    "contains" => Token::Op(Op::Contains),
+                    "in" => Token::In,
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                }
+            }
+            c => return Err(format!("unexpected {} in {}", c, s)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// The deepest brackets and negations nest, so an expression can't exhaust the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        match self.tokens.get(self.next) == Some(token) {
+            true => {
+                self.next += 1;
+                true
+            }
+            false => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut left = self.and()?;
+        while self.eat(&Token::Or) {
+            left = Filter::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut left = self.unary()?;
+        while self.eat(&Token::And) {
+            left = Filter::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn value(&mut self, field: &str) -> Result<String, String> {
+        match self.take() {
+            Some(Token::Word(v)) | Some(Token::Quoted(v)) => Ok(v),
+            _ => Err(format!("{} is compared with nothing", field)),
+        }
+    }
+
+    /// The values of a list, `[a, b, c]`.
+    fn list(&mut self, field: &str) -> Result<Vec<String>, String> {
+        if !self.eat(&Token::OpenList) {
+            return Err(format!("{} in needs a list, e.g. [1, 3]", field));
+        }
+        let mut values = Vec::new();
+        while !self.eat(&Token::CloseList) {
+            if !values.is_empty() && !self.eat(&Token::Comma) {
+                return Err(format!("the list {} is in is left unclosed", field));
+            }
+            values.push(self.value(field)?);
+        }
+        Ok(values)
+    }
+
+    /// Parse something nested one level deeper.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("brackets and ! nest over {} deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        match self.take() {
+            Some(Token::Not) => Ok(Filter::Not(Box::new(self.nested(Self::unary)?))),
+            Some(Token::Open) => {
+                let inner = self.nested(Self::or)?;
+                match self.eat(&Token::Close) {
+                    true => Ok(inner),
+                    false => Err("a bracket is left unclosed".into()),
+                }
+            }
+            Some(Token::Word(field)) => match self.tokens.get(self.next) {
+                Some(Token::Op(op)) => {
+                    let op = *op;
+                    self.next += 1;
+                    let value = self.value(&field)?;
+                    Ok(Filter::Compare(field, op, value))
+                }
+                Some(Token::In) => {
+                    self.next += 1;
+                    let values = self.list(&field)?;
+                    Ok(Filter::In(field, values))
+                }
+                _ => Ok(Filter::Present(field)),
+            },
+            _ => Err("expected a field".into()),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokens(s)?,
+            next: 0,
+            depth: 0,
+        };
+        let filter = parser.or()?;
+        match parser.next < parser.tokens.len() {
+            true => Err(format!("unexpected trailing input in {}", s)),
+            false => Ok(filter),
+        }
+    }
+}
+
+/// A scalar's text, numbers and booleans as they're written.
+fn text(value: &Value) -> Option<Cow<'_, str>> {
+    match value {
+        Value::String(s) => Some(Cow::Borrowed(s)),
+        Value::Number(n) => Some(Cow::Owned(n.to_string())),
+        Value::Bool(b) => Some(Cow::Owned(b.to_string())),
+        _ => None,
+    }
+}
+
+/// Order two values, as levels when comparing a level, numbers when both are finite numbers and
+/// otherwise as text, so `NaN` and `inf` are only equal to themselves.
+fn order(field: &str, value: &str, expected: &str) -> Ordering {
+    if field == "level" {
+        if let (Ok(a), Ok(b)) = (value.parse::<Level>(), expected.parse::<Level>()) {
+            return a.cmp(&b);
+        }
+    }
+    let number = |s: &str| s.parse::<f64>().ok().filter(|n| n.is_finite());
+    match (number(value), number(expected)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        _ => value.cmp(expected),
+    }
+}
+
+/// Compare a field's value, any of whose items may pass when it's a list.
+fn compare(field: &str, value: &Value, op: Op, expected: &str) -> bool {
+    match (value, op) {
+        (Value::Array(a), Op::Contains) => a.iter().any(|v| compare(field, v, Op::Eq, expected)),
+        (Value::Array(a), Op::Ne) => !a.iter().any(|v| compare(field, v, Op::Eq, expected)),
+        (Value::Array(a), op) => a.iter().any(|v| compare(field, v, op, expected)),
+        (v, op) => match (text(v), op) {
+            (None, op) => op == Op::Ne,
+            (Some(t), Op::Contains) => t.contains(expected),
+            (Some(t), op) => {
+                let o = order(field, &t, expected);
+                match op {
+                    Op::Eq => o == Ordering::Equal,
+                    Op::Ne => o != Ordering::Equal,
+                    Op::Lt => o == Ordering::Less,
+                    Op::Le => o != Ordering::Greater,
+                    Op::Gt => o == Ordering::Greater,
+                    Op::Ge => o != Ordering::Less,
+                    Op::Contains => false,
+                }
+            }
+        },
+    }
+}
+
+impl Filter {
+    /// Whether a rule's metadata, the top level fields of its YAML, or an event passes the filter.
+    /// A field which isn't set only passes `!=`.
+    pub fn matches(&self, json: &Value) -> bool {
+        let field = |name: &str| event::get(json, name).filter(|v| !v.is_null());
+        match self {
+            Filter::Present(name) => match field(name) {
+                Some(Value::Bool(b)) => *b,
+                Some(Value::String(s)) => !s.is_empty(),
+                Some(Value::Array(a)) => !a.is_empty(),
+                Some(_) => true,
+                None => false,
+            },
+            Filter::Compare(name, op, expected) => match field(name) {
+                Some(v) => compare(name, v, *op, expected),
+                None => *op == Op::Ne,
+            },
+            Filter::In(name, values) => match field(name) {
+                Some(v) => values.iter().any(|e| compare(name, v, Op::Eq, e)),
+                None => false,
+            },
+            Filter::Not(f) => !f.matches(json),
+            Filter::And(a, b) => a.matches(json) && b.matches(json),
+            Filter::Or(a, b) => a.matches(json) || b.matches(json),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(filter: &str, json: Value) -> bool {
+        filter.parse::<Filter>().unwrap().matches(&json)
+    }
+
+    #[test]
+    fn comparisons() {
+        let event = json!({ "Channel": "Security", "EventID": 4624, "tags": ["a", "b"] });
+        assert!(matches(
+            r#"Channel == "Security" && EventID in [1, 4624]"#,
+            event.clone()
+        ));
+        assert!(matches("EventID >= 1000 && EventID < 5000", event.clone()));
+        assert!(matches(
+            "tags contains b && !(tags contains c)",
+            event.clone()
+        ));
+        assert!(matches("Missing != x && !Missing", event.clone()));
+        assert!(!matches("EventID > 10000 || Channel == System", event));
+        assert!(matches("level >= high", json!({ "level": "critical" })));
+    }
+
+    #[test]
+    fn only_finite_numbers_compare_as_numbers() {
+        assert!(!matches("x == 1", json!({ "x": "NaN" })));
+        assert!(!matches("x != NaN", json!({ "x": "NaN" })));
+        assert!(matches("x == NaN", json!({ "x": "NaN" })));
+        assert!(!matches("x == 1", json!({ "x": "inf" })));
+        assert!(matches("x == 1.0", json!({ "x": 1 })));
+    }
+
+    #[test]
+    fn nesting_is_bounded() {
+        let deep = format!("{}x{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(deep.parse::<Filter>().is_err());
+        assert!("!".repeat(100_000).parse::<Filter>().is_err());
+        let shallow = format!("{}x{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(shallow.parse::<Filter>().is_ok());
+    }
+
+    #[test]
+    fn malformed_expressions() {
+        for bad in ["x ==", "(x", "x in 1", "x == 'a", "x y", "&&"] {
+            assert!(bad.parse::<Filter>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    collections::BTreeMap,
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::event;
+use crate::input::Input;
+use crate::output::csv_escape;
+use crate::rules;
+
+#[derive(StructOpt)]
+pub struct Fuzz {
+    /// The rules to fuzz.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+
+    /// Sample events the mutations are made to.
+    #[structopt(long, parse(from_os_str), required = true)]
+    seed_corpus: Vec<PathBuf>,
+
+    /// The number of mutated copies made of each event.
+    #[structopt(long, default_value = "32")]
+    mutations: usize,
+
+    /// The seed for choosing mutations, so a run can be repeated.
+    #[structopt(long)]
+    seed: Option<u64>,
+}
+
+/// The ways an event is changed, none of which should change what it means.
+const NOISE: &[&str] = &[
+    "zero width space",
+    "uppercase",
+    "trailing space",
+    "homoglyph",
+];
+
+/// Every leaf of an event with a path `event::get_mut` can find it by.
+pub fn leaves(field: String, json: &Value, out: &mut Vec<String>) {
+    let child = |key: String| match field.is_empty() {
+        true => key,
+        false => format!("{}.{}", field, key),
+    };
+    match json {
+        Value::Object(o) if !o.is_empty() => {
+            o.iter().for_each(|(k, v)| leaves(child(k.clone()), v, out))
+        }
+        Value::Array(a) if !a.is_empty() => a
+            .iter()
+            .enumerate()
+            .for_each(|(i, v)| leaves(child(i.to_string()), v, out)),
+        _ if !field.is_empty() => out.push(field),
+        _ => {}
+    }
+}
+
+pub fn delete(json: &mut Value, path: &str) {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((p, k)) => (event::get_mut(json, p), k),
+        None => (Some(json), path),
+    };
+    match parent {
+        Some(Value::Object(o)) => {
+            o.remove(key);
+        }
+        Some(Value::Array(a)) => {
+            if let Some(i) = key.parse::<usize>().ok().filter(|i| *i < a.len()) {
+                a.remove(i);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Change a value's type while keeping its meaning where there is one, e.g. `1` and `"1"`.
+fn flip_type(json: &Value) -> Value {
+    match json {
+        Value::String(s) => match serde_json::from_str::<Value>(s) {
+            Ok(v) if !v.is_string() => v,
+            _ => Value::Null,
+        },
+        Value::Null => Value::String(String::new()),
+        v => Value::String(v.to_string()),
+    }
+}
+
+fn noise(rng: &mut fastrand::Rng, s: &str) -> (&'static str, String) {
+    let kind = NOISE[rng.usize(..NOISE.len())];
+    let noisy = match kind {
+        "zero width space" => {
+            let at = s
+                .char_indices()
+                .map(|(i, _)| i)
+                .nth(rng.usize(..=s.chars().count()))
+                .unwrap_or(s.len());
+            format!("{}\u{200b}{}", &s[..at], &s[at..])
+        }
+        "uppercase" => s.to_uppercase(),
+        "trailing space" => format!("{} ", s),
+        // Cyrillic letters which look the same as Latin ones
+        _ => s
+            .chars()
+            .map(|c| match c {
+                'a' => 'а',
+                'e' => 'е',
+                'o' => 'о',
+                'c' => 'с',
+                c => c,
+            })
+            .collect(),
+    };
+    (kind, noisy)
+}
+
+/// Make one random mutation to an event, returning what was done.
+fn mutate(rng: &mut fastrand::Rng, json: &mut Value) -> Option<(&'static str, String)> {
+    let mut paths = Vec::new();
+    leaves(String::new(), json, &mut paths);
+    let path = paths.get(rng.usize(..paths.len().max(1)))?.clone();
+    if rng.usize(..3) == 0 {
+        delete(json, &path);
+        return Some(("delete", path));
+    }
+    let kind = match event::get_mut(json, &path)? {
+        Value::String(s) if rng.bool() => {
+            let (kind, noisy) = noise(rng, s);
+            *s = noisy;
+            kind
+        }
+        v => {
+            *v = flip_type(v);
+            "type flip"
+        }
+    };
+    Some((kind, path))
+}
+
+impl Fuzz {
+    pub fn run(self) -> Result<(), String> {
+        let rules = rules::load(&self.rules)?;
+        let mut rng = match self.seed {
+            Some(s) => fastrand::Rng::with_seed(s),
+            None => fastrand::Rng::new(),
+        };
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let mut flips: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+        let mut tried = 0;
+        let _ = writeln!(stdout, "Rule, Event, Mutation, Field, Before, After");
+        for (i, res) in Input::open(Some(self.seed_corpus.clone()), None, None)?.enumerate() {
+            let json = match res {
+                Ok(json) => json,
+                Err(e) => {
+                    let _ = writeln!(stderr, "{}", e);
+                    continue;
+                }
+            };
+            let before: Vec<bool> = rules.iter().map(|(_, r)| r.matches(&json)).collect();
+            for _ in 0..self.mutations {
+                let mut mutated = json.clone();
+                let (kind, field) = match mutate(&mut rng, &mut mutated) {
+                    Some(m) => m,
+                    None => break,
+                };
+                tried += 1;
+                for ((name, rule), before) in rules.iter().zip(before.iter()) {
+                    let after = rule.matches(&mutated);
+                    if after == *before {
+                        continue;
+                    }
+                    *flips.entry((name, kind)).or_default() += 1;
+                    let _ = writeln!(
+                        stdout,
+                        "{}, {}, {}, {}, {}, {}",
+                        csv_escape(name),
+                        i + 1,
+                        kind,
+                        csv_escape(&field),
+                        before,
+                        after
+                    );
+                }
+            }
+        }
+        let _ = writeln!(stderr, "Tried {} mutations", tried);
+        for ((name, kind), count) in flips {
+            let _ = writeln!(stderr, "{}, {} flipped by {}", name, count, kind);
+        }
+        Ok(())
+    }
+}
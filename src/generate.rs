@@ -0,0 +1,149 @@
+use std::{
+    fs,
+    io::{stderr, stdout, BufWriter, Write},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::event;
+use crate::rules;
+
+#[derive(StructOpt)]
+pub struct Generate {
+    /// The event to generate from, either a JSON object or JSON lines with one template per line.
+    #[structopt(short, long, parse(from_os_str))]
+    template: PathBuf,
+
+    /// The events generated per second, 0 generates as fast as possible.
+    #[structopt(long, default_value = "0")]
+    rate: u64,
+
+    /// The number of events to generate, events are generated until stopped when not given.
+    #[structopt(long)]
+    count: Option<u64>,
+
+    /// Comma separated fields given a random value like the template's in each event.
+    #[structopt(long, use_delimiter = true)]
+    mutate: Vec<String>,
+
+    /// Match the generated events against rules instead of writing them, reporting the throughput.
+    #[structopt(short, long, parse(from_os_str))]
+    rules: Vec<PathBuf>,
+
+    /// The seed for the generated values, so a run can be repeated.
+    #[structopt(long)]
+    seed: Option<u64>,
+}
+
+/// Replace a value with a random one of the same shape. Letters and digits in strings are replaced
+/// with ones of the same class, so IPs, hashes and hostnames still look like IPs, hashes and hostnames.
+pub fn randomise(rng: &mut fastrand::Rng, json: &mut Value) {
+    match json {
+        Value::String(s) => {
+            let hex = s.chars().all(|c| c.is_ascii_hexdigit());
+            *s = s
+                .chars()
+                .map(|c| match c {
+                    '0'..='9' => rng.char('0'..='9'),
+                    'a'..='f' if hex => rng.char('a'..='f'),
+                    'a'..='z' => rng.lowercase(),
+                    'A'..='Z' => rng.uppercase(),
+                    c => c,
+                })
+                .collect()
+        }
+        Value::Number(n) => {
+            *json = match (n.as_u64(), n.as_i64(), n.as_f64()) {
+                (Some(u), _, _) => Value::from(rng.u64(0..=u.saturating_mul(2).max(1))),
+                (_, Some(i), _) => Value::from(rng.i64(i.saturating_mul(2)..=0)),
+                (_, _, Some(f)) => serde_json::Number::from_f64(f * 2.0 * rng.f64())
+                    .map_or(Value::Null, Value::Number),
+                _ => return,
+            }
+        }
+        Value::Bool(b) => *b = rng.bool(),
+        Value::Array(a) => a.iter_mut().for_each(|v| randomise(rng, v)),
+        Value::Object(o) => o.values_mut().for_each(|v| randomise(rng, v)),
+        Value::Null => {}
+    }
+}
+
+impl Generate {
+    fn templates(&self) -> Result<Vec<Value>, String> {
+        let data = fs::read_to_string(&self.template)
+            .map_err(|_| format!("Unable to read input file at {}.", self.template.display()))?;
+        let templates = match serde_json::from_str::<Value>(&data) {
+            Ok(v) => vec![v],
+            Err(_) => data
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("Invalid template {}, {}", self.template.display(), e))?,
+        };
+        if templates.is_empty() {
+            return Err(format!("No templates in {}", self.template.display()));
+        }
+        Ok(templates)
+    }
+
+    pub fn run(self) -> Result<(), String> {
+        let templates = self.templates()?;
+        let rules = rules::load(&self.rules)?;
+        let mut rng = match self.seed {
+            Some(s) => fastrand::Rng::with_seed(s),
+            None => fastrand::Rng::new(),
+        };
+        let mut stdout = BufWriter::new(stdout());
+        let mut matches = vec![0u64; rules.len()];
+        let start = Instant::now();
+        let mut generated = 0;
+        while self.count.map(|c| generated < c).unwrap_or(true) {
+            // Ahead of the rate, so wait until the next event is due
+            if self.rate > 0 {
+                let due = Duration::from_secs_f64(generated as f64 / self.rate as f64);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    let _ = stdout.flush();
+                    thread::sleep(wait);
+                }
+            }
+            let mut json = templates[rng.usize(..templates.len())].clone();
+            for field in self.mutate.iter() {
+                if let Some(v) = event::get_mut(&mut json, field) {
+                    randomise(&mut rng, v);
+                }
+            }
+            generated += 1;
+            if rules.is_empty() {
+                // Stop quietly once whatever reads the events goes away
+                if serde_json::to_writer(&mut stdout, &json).is_err() || writeln!(stdout).is_err() {
+                    break;
+                }
+                continue;
+            }
+            for (i, (_, rule)) in rules.iter().enumerate() {
+                if rule.matches(&json) {
+                    matches[i] += 1;
+                }
+            }
+        }
+        let _ = stdout.flush();
+        let elapsed = start.elapsed().as_secs_f64();
+        let mut stderr = stderr();
+        let _ = writeln!(
+            stderr,
+            "Generated {} events in {:.2}s, {:.0} events per second",
+            generated,
+            elapsed,
+            generated as f64 / elapsed.max(f64::EPSILON)
+        );
+        for ((name, _), count) in rules.iter().zip(matches) {
+            let _ = writeln!(stderr, "{}, {} matches", name, count);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use serde_json::{json, Value};
+
+use crate::event;
+
+/// An aggregate written in each --group-by row.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Agg {
+    Count,
+    FirstSeen,
+    LastSeen,
+    /// The names of the rules which matched the group's events.
+    Rules,
+}
+
+impl FromStr for Agg {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(Agg::Count),
+            "first_seen" => Ok(Agg::FirstSeen),
+            "last_seen" => Ok(Agg::LastSeen),
+            "rules" => Ok(Agg::Rules),
+            _ => Err(format!(
+                "Unknown aggregate {}, expected count, first_seen, last_seen or rules",
+                s
+            )),
+        }
+    }
+}
+
+struct Group {
+    key: Vec<Value>,
+    count: u64,
+    first: Option<(SystemTime, Value)>,
+    last: Option<(SystemTime, Value)>,
+    /// The rules which matched the group's events, with how many of them each matched.
+    rules: BTreeMap<String, u64>,
+}
+
+/// Matches collapsed into a row per distinct value of the grouped fields.
+pub struct Groups {
+    fields: Vec<String>,
+    aggs: Vec<Agg>,
+    time_field: Option<String>,
+    groups: Vec<Group>,
+    index: HashMap<String, usize>,
+}
+
+impl Groups {
+    pub fn new(
+        fields: Vec<String>,
+        aggs: Vec<Agg>,
+        time_field: Option<String>,
+    ) -> Result<Self, String> {
+        let timed = aggs
+            .iter()
+            .any(|a| matches!(a, Agg::FirstSeen | Agg::LastSeen));
+        if timed && time_field.is_none() {
+            return Err("The first_seen and last_seen aggregates need --time-field".into());
+        }
+        Ok(Groups {
+            fields,
+            aggs,
+            time_field,
+            groups: Vec::new(),
+            index: HashMap::new(),
+        })
+    }
+
+    /// Add a matching event to its group, with the rules it matched.
+    pub fn add(&mut self, json: &Value, rules: &[&str]) {
+        let key = event::key(json, &self.fields);
+        let i = match self.index.get(&key) {
+            Some(&i) => i,
+            None => {
+                self.index.insert(key, self.groups.len());
+                self.groups.push(Group {
+                    key: self
+                        .fields
+                        .iter()
+                        .map(|f| event::get(json, f).cloned().unwrap_or(Value::Null))
+                        .collect(),
+                    count: 0,
+                    first: None,
+                    last: None,
+                    rules: BTreeMap::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        let group = &mut self.groups[i];
+        group.count += 1;
+        if self.aggs.contains(&Agg::Rules) {
+            for rule in rules {
+                *group.rules.entry(rule.to_string()).or_insert(0) += 1;
+            }
+        }
+        // Events without a readable time still count, they just can't be first or last
+        let value = self.time_field.as_ref().and_then(|f| event::get(json, f));
+        if let Some((time, value)) = value.and_then(|v| Some((event::time(v)?, v))) {
+            if group.first.as_ref().map(|(t, _)| time < *t).unwrap_or(true) {
+                group.first = Some((time, value.clone()));
+            }
+            if group.last.as_ref().map(|(t, _)| time > *t).unwrap_or(true) {
+                group.last = Some((time, value.clone()));
+            }
+        }
+    }
+
+    /// The groups largest first, ties keeping the order they were first seen in.
+    fn sorted(mut self) -> Self {
+        self.groups.sort_by_key(|g| std::cmp::Reverse(g.count));
+        self
+    }
+
+    /// A row for each group with the grouped fields and aggregates, the largest groups first.
+    pub fn rows(self) -> Vec<Value> {
+        let this = self.sorted();
+        let mut rows = Vec::new();
+        for group in this.groups {
+            let mut row = json!({});
+            for (field, value) in this.fields.iter().zip(group.key) {
+                event::set(&mut row, field, value);
+            }
+            let time = |t: &Option<(SystemTime, Value)>| match t {
+                Some((_, v)) => v.clone(),
+                None => Value::Null,
+            };
+            for agg in this.aggs.iter() {
+                let (name, value) = match agg {
+                    Agg::Count => ("count", json!(group.count)),
+                    Agg::FirstSeen => ("first_seen", time(&group.first)),
+                    Agg::LastSeen => ("last_seen", time(&group.last)),
+                    Agg::Rules => ("rules", json!(group.rules.keys().collect::<Vec<_>>())),
+                };
+                row[name] = value;
+            }
+            rows.push(row);
+        }
+        rows
+    }
+
+    /// The `n` largest groups as --top rows, each the value of the first grouped field with its
+    /// count and the count for each rule which matched it.
+    pub fn top(self, n: usize) -> Vec<Value> {
+        self.sorted()
+            .groups
+            .into_iter()
+            .take(n)
+            .map(|g| {
+                let value = g.key.into_iter().next().unwrap_or(Value::Null);
+                json!({ "value": value, "count": g.count, "rules": g.rules })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_counts_each_rule_in_the_largest_groups() {
+        let mut groups = Groups::new(vec!["host".into()], vec![Agg::Rules], None).unwrap();
+        groups.add(&json!({"host": "a"}), &["x"]);
+        groups.add(&json!({"host": "b"}), &["x", "y"]);
+        groups.add(&json!({"host": "b"}), &["y"]);
+        assert_eq!(
+            groups.top(1),
+            vec![json!({"value": "b", "count": 2, "rules": {"x": 1, "y": 2}})]
+        );
+    }
+}
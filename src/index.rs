@@ -0,0 +1,274 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, BufRead, BufWriter},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::input;
+use crate::output;
+
+/// An inverted index of the field/value pairs found in blocks of lines of each corpus file, used to
+/// skip blocks which can't match any of the loaded rules.
+#[derive(Deserialize, Serialize)]
+pub struct Index {
+    files: Vec<FileIndex>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct FileIndex {
+    path: PathBuf,
+    len: u64,
+    modified: u64,
+    blocks: Vec<Block>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Block {
+    start: u64,
+    end: u64,
+    /// Sorted hashes of every `field=value` pair in the block.
+    terms: Vec<u64>,
+}
+
+#[derive(StructOpt)]
+pub struct BuildIndex {
+    /// The corpus files to index.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    input: Vec<PathBuf>,
+
+    /// Path to write the index to.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Overwrite the index if it already exists.
+    #[structopt(short = "f", long)]
+    overwrite: bool,
+
+    /// The number of lines in each indexed block, smaller blocks skip more precisely but make a larger index.
+    #[structopt(long, default_value = "10000")]
+    block_lines: usize,
+}
+
+/// FNV-1a, used as the term hash must be stable between runs and builds.
+fn hash(field: &str, value: &str) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in field.bytes().chain(Some(0)).chain(value.bytes()) {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h
+}
+
+fn file_stamp(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok((metadata.len(), modified))
+}
+
+/// Collect the terms of every leaf value, array elements share their parent's field.
+fn terms(field: &str, json: &Value, out: &mut HashSet<u64>) {
+    match json {
+        Value::Object(o) => {
+            for (k, v) in o.iter() {
+                match field.is_empty() {
+                    true => terms(k, v, out),
+                    false => terms(&format!("{}.{}", field, k), v, out),
+                }
+            }
+        }
+        Value::Array(a) => {
+            for v in a.iter() {
+                terms(field, v, out);
+            }
+        }
+        Value::String(s) => {
+            out.insert(hash(field, s));
+        }
+        Value::Number(_) | Value::Bool(_) => {
+            out.insert(hash(field, &json.to_string()));
+        }
+        Value::Null => {}
+    }
+}
+
+impl BuildIndex {
+    pub fn run(self) -> Result<(), String> {
+        let mut index = Index { files: Vec::new() };
+        for path in self.input.iter() {
+            index.files.push(
+                Self::index_file(path, self.block_lines.max(1))
+                    .map_err(|e| format!("Unable to index {}, {}", path.display(), e))?,
+            );
+        }
+        let file = output::create_file(
+            &self.output,
+            &output::FileOptions {
+                overwrite: self.overwrite,
+                append: false,
+                mode: None,
+                owner: None,
+                dry_run: false,
+            },
+        )
+        .map_err(|_| format!("Could not create index at {}", self.output.display()))?;
+        rmp_serde::encode::write(&mut BufWriter::new(file), &index)
+            .map_err(|e| format!("Unable to write index, {}", e))
+    }
+
+    fn index_file(path: &Path, block_lines: usize) -> io::Result<FileIndex> {
+        // Blocks are byte ranges of the file, which mean nothing once decompressed
+        if input::is_compressed(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "compressed files can't be indexed",
+            ));
+        }
+        let (len, modified) = file_stamp(path)?;
+        let mut reader = io::BufReader::new(fs::File::open(path)?);
+        let mut blocks = Vec::new();
+        let mut block = HashSet::new();
+        let (mut start, mut pos, mut lines) = (0, 0, 0);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            if n > 0 {
+                pos += n as u64;
+                lines += 1;
+                // Unparsable lines have no terms, the block is still read when matching and reports them
+                if let Ok(json) = serde_json::from_str::<Value>(line.trim_end()) {
+                    terms("", &json, &mut block);
+                }
+            }
+            if (n == 0 && lines > 0) || lines == block_lines {
+                let mut sorted: Vec<u64> = block.drain().collect();
+                sorted.sort_unstable();
+                blocks.push(Block {
+                    start,
+                    end: pos,
+                    terms: sorted,
+                });
+                start = pos;
+                lines = 0;
+            }
+            if n == 0 {
+                break;
+            }
+        }
+        Ok(FileIndex {
+            path: fs::canonicalize(path)?,
+            len,
+            modified,
+            blocks,
+        })
+    }
+}
+
+/// The literal `field: value` pairs every match of a rule must contain, `None` when the rule's condition
+/// is too complex to tell, in which case nothing can be skipped.
+pub fn required_terms(rule: &str) -> Option<Vec<u64>> {
+    let yaml: serde_yaml::Value = serde_yaml::from_str(rule).ok()?;
+    let detection = yaml.get("detection")?.as_mapping()?;
+    let condition = detection.get(&"condition".into())?.as_str()?;
+    let mut required = Vec::new();
+    // Only conditions which are a conjunction of identifiers are understood
+    for token in condition
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|t| !t.is_empty())
+    {
+        if token == "and" {
+            continue;
+        }
+        let identifier = detection.get(&token.into())?.as_mapping()?;
+        for (field, value) in identifier.iter() {
+            let field = field.as_str()?;
+            let value = match value {
+                serde_yaml::Value::Sequence(s) if s.len() == 1 => &s[0],
+                v => v,
+            };
+            if let Some(v) = value.as_str() {
+                // Patterns and case insensitive values can't be looked up exactly
+                let literal =
+                    !v.is_empty() && !v.starts_with(&['*', '?', 'i'][..]) && !v.ends_with('*');
+                if literal {
+                    required.push(hash(field, v));
+                }
+            }
+        }
+    }
+    match required.is_empty() {
+        true => None,
+        false => Some(required),
+    }
+}
+
+impl Index {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = fs::File::open(path)
+            .map_err(|_| format!("Unable to read index at {}.", path.display()))?;
+        rmp_serde::from_read(io::BufReader::new(file))
+            .map_err(|e| format!("Invalid index {}, {}", path.display(), e))
+    }
+
+    /// Work out which byte ranges of each indexed file could hold a match for at least one of the rules,
+    /// given the terms each rule requires. Files missing from the index or changed since indexing are
+    /// left out of the plan and read in full.
+    pub fn plan(self, rules: &[Option<Vec<u64>>]) -> Plan {
+        let mut ranges = HashMap::new();
+        // A rule which could match anything means every block must be read
+        if rules.iter().any(|r| r.is_none()) {
+            return Plan { ranges };
+        }
+        for file in self.files {
+            match file_stamp(&file.path) {
+                Ok((len, modified)) if len == file.len && modified == file.modified => {}
+                _ => continue,
+            }
+            let mut read: Vec<(u64, u64)> = Vec::new();
+            for block in file.blocks {
+                let possible = rules
+                    .iter()
+                    .flatten()
+                    .any(|terms| terms.iter().all(|t| block.terms.binary_search(t).is_ok()));
+                if possible {
+                    match read.last_mut() {
+                        Some(last) if last.1 == block.start => last.1 = block.end,
+                        _ => read.push((block.start, block.end)),
+                    }
+                }
+            }
+            ranges.insert(file.path, read);
+        }
+        Plan { ranges }
+    }
+}
+
+/// The byte ranges of indexed files which need reading.
+pub struct Plan {
+    ranges: HashMap<PathBuf, Vec<(u64, u64)>>,
+}
+
+impl Plan {
+    /// A plan reading only the given ranges of each file, keyed by canonical path.
+    pub fn new(ranges: HashMap<PathBuf, Vec<(u64, u64)>>) -> Self {
+        Plan { ranges }
+    }
+
+    /// The ranges of a file to read, in reverse order, `None` when the whole file should be read.
+    pub fn ranges(&self, path: &Path) -> Option<Vec<(u64, u64)>> {
+        let path = fs::canonicalize(path).ok()?;
+        self.ranges
+            .get(&path)
+            .map(|r| r.iter().rev().cloned().collect())
+    }
+}
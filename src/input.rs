@@ -0,0 +1,953 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt, fs,
+    io::{self, stdin, BufRead, Read, Seek, Stdin, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::artifacts::Artifact;
+use crate::cache::{Cache, CacheWriter, Entry};
+use crate::index::Plan;
+use crate::lenient::{self, Events};
+
+// There is only ever one input, so the size of the variants doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum Input {
+    CommandLine {
+        stdin: Stdin,
+        line: String,
+        options: Options,
+        /// The rest of the events of a line holding several.
+        pending: VecDeque<serde_json::Value>,
+    },
+    /// Events which have already been parsed, such as those received by a server.
+    Events(std::vec::IntoIter<serde_json::Value>),
+    /// Events sent from other threads, read until every sender has gone.
+    Channel(std::sync::mpsc::Receiver<Result<serde_json::Value, String>>),
+    /// Batches of events sent from other threads, so each event doesn't cost a send.
+    Batches {
+        rx: std::sync::mpsc::Receiver<Vec<Result<serde_json::Value, String>>>,
+        batch: std::vec::IntoIter<Result<serde_json::Value, String>>,
+    },
+    Files {
+        paths: Vec<PathBuf>,
+        reader: Reader,
+        cache: Option<Cache>,
+        plan: Option<Plan>,
+        /// The number of files finished with, so callers can tell when the next one starts.
+        done: usize,
+        /// The file being read.
+        path: PathBuf,
+        options: Options,
+        /// The rest of the events of a line holding several.
+        pending: VecDeque<serde_json::Value>,
+    },
+    /// JSON values written one after another, however they are separated, from the given files in
+    /// turn or stdin when there are none.
+    Stream {
+        paths: Vec<PathBuf>,
+        stream: Stream,
+        done: usize,
+        path: Option<PathBuf>,
+    },
+}
+
+type Stream = serde_json::StreamDeserializer<
+    'static,
+    serde_json::de::IoRead<io::BufReader<Box<dyn Read + Send>>>,
+    serde_json::Value,
+>;
+
+fn stream(reader: Box<dyn Read + Send>) -> Stream {
+    serde_json::Deserializer::from_reader(io::BufReader::new(reader)).into_iter()
+}
+
+/// The format of the input files.
+pub enum Format {
+    Json,
+    /// Collections from triage tools, see `triage::open`.
+    Triage,
+    /// Packet captures, see `pcap::open`.
+    Pcap,
+    /// Offline Windows registry hives, see `reghive::open`.
+    RegHive,
+    /// Raw NTFS `$MFT` files, see `mft::open`.
+    Mft,
+    /// Bodyfiles as read by mactime, see `mft::open`.
+    Bodyfile,
+    /// Windows execution artifacts, see `artifacts::open`.
+    Artifact(Artifact),
+    /// Windows event logs, see `evtx::open`.
+    Evtx,
+    /// JSON objects written back to back without newlines between them, see `Input::stream`.
+    Concatenated,
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "triage" => Ok(Format::Triage),
+            "pcap" => Ok(Format::Pcap),
+            "reghive" => Ok(Format::RegHive),
+            "mft" => Ok(Format::Mft),
+            "bodyfile" => Ok(Format::Bodyfile),
+            "prefetch" => Ok(Format::Artifact(Artifact::Prefetch)),
+            "shimcache" => Ok(Format::Artifact(Artifact::Shimcache)),
+            "amcache" => Ok(Format::Artifact(Artifact::Amcache)),
+            "concatenated" => Ok(Format::Concatenated),
+            "evtx" => Ok(Format::Evtx),
+            _ => Err(format!(
+                "Unknown input format {}, expected json, concatenated, evtx, triage, pcap, reghive, mft, bodyfile, prefetch, shimcache or amcache",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do with a line longer than --max-line-bytes.
+#[derive(Clone, Copy)]
+pub enum Oversize {
+    /// Count the line and move on.
+    Skip,
+    /// Match the start of the line as the `message` field of an event.
+    Truncate,
+    /// Write the start of the line to the dead letter file.
+    DeadLetter,
+}
+
+impl FromStr for Oversize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Oversize::Skip),
+            "truncate" => Ok(Oversize::Truncate),
+            "dead-letter" => Ok(Oversize::DeadLetter),
+            _ => Err(format!(
+                "Unknown oversize action {}, expected skip, truncate or dead-letter",
+                s
+            )),
+        }
+    }
+}
+
+/// How lines of JSON are read, set from the run's options.
+#[derive(Clone, Copy, Default)]
+pub struct Options {
+    /// Repair malformed lines rather than report them, from --lenient-json.
+    pub lenient: bool,
+    /// The longest line read in bytes, 0 for no limit, from --max-line-bytes.
+    pub max_line_bytes: usize,
+    /// How long a file must stop growing for before its end is taken as the end, zero to not
+    /// wait, from --read-until-eof-stable.
+    pub eof_stable: Duration,
+}
+
+/// The first of a line's events, keeping the rest to be returned next.
+fn first(events: Events, pending: &mut VecDeque<serde_json::Value>) -> serde_json::Value {
+    match events {
+        Events::One(json) => json,
+        Events::Several(events) => {
+            pending.extend(events);
+            pending.pop_front().unwrap_or_default()
+        }
+    }
+}
+
+/// How often a file is checked for growth while waiting for it to stop growing.
+const EOF_POLL: Duration = Duration::from_millis(250);
+
+/// Wait at the end of a file until it hasn't grown for --read-until-eof-stable, returning whether
+/// there is more to read, so files still being written aren't cut short.
+fn grew(buffer: &mut io::BufReader<fs::File>, stable: Duration) -> bool {
+    if stable.is_zero() {
+        return false;
+    }
+    // Nothing is left buffered at the end of the file, so the file's position is the reader's
+    let position = match buffer.get_mut().stream_position() {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    let size = || buffer.get_ref().metadata().map(|m| m.len()).unwrap_or(0);
+    let (mut last, mut since) = (size(), Instant::now());
+    while since.elapsed() < stable {
+        thread::sleep(EOF_POLL.min(stable));
+        let now = size();
+        if now != last {
+            last = now;
+            since = Instant::now();
+        }
+    }
+    last > position
+}
+
+/// A line longer than --max-line-bytes, only the start of which was kept.
+#[derive(Debug)]
+pub struct LongLine {
+    pub bytes: usize,
+    pub start: String,
+    limit: usize,
+}
+
+impl fmt::Display for LongLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Line of {} bytes is over the limit of {} bytes",
+            self.bytes, self.limit
+        )
+    }
+}
+
+impl Error for LongLine {}
+
+/// Read a line as `read_line` would, but keep no more than `max` bytes of `line` in memory, counting
+/// what it already holds, the rest is read and thrown away. Returns the bytes read and whether the
+/// line was cut short.
+fn read_line(
+    reader: &mut impl BufRead,
+    line: &mut String,
+    max: usize,
+) -> io::Result<(usize, bool)> {
+    if max == 0 {
+        return reader.read_line(line).map(|n| (n, false));
+    }
+    // Room for the newline, so a line of exactly the limit is kept whole
+    let held = line.len();
+    let mut bytes = Vec::new();
+    let mut read = 0;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(a) => a,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            break;
+        }
+        let (used, done) = match available.iter().position(|b| *b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (available.len(), false),
+        };
+        let keep = used.min((max + 1).saturating_sub(held + bytes.len()));
+        bytes.extend_from_slice(&available[..keep]);
+        reader.consume(used);
+        read += used;
+        if done {
+            break;
+        }
+    }
+    let length = match bytes.ends_with(b"\n") {
+        true => read - 1,
+        false => read,
+    };
+    if held + length > max {
+        bytes.truncate(max.saturating_sub(held));
+        line.push_str(&String::from_utf8_lossy(&bytes));
+        return Ok((read, true));
+    }
+    let text = String::from_utf8(bytes).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        )
+    })?;
+    line.push_str(&text);
+    Ok((read, false))
+}
+
+/// The result of reading a line which is too long.
+fn long_line<T>(bytes: usize, line: &str, options: &Options) -> Result<T, Box<dyn Error>> {
+    Err(Box::new(LongLine {
+        bytes,
+        start: line.to_string(),
+        limit: options.max_line_bytes,
+    }))
+}
+
+/// The compression of an input file, told by its first bytes rather than its extension.
+enum Compression {
+    Gzip,
+    Zstd,
+    /// The snappy framing format, as Kafka dumps are written, rather than raw snappy blocks.
+    Snappy,
+    /// The LZ4 frame format, rather than raw LZ4 blocks.
+    Lz4,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+/// The stream identifier chunk every snappy framed stream starts with.
+const SNAPPY_MAGIC: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+const LZ4_MAGIC: &[u8] = &[0x04, 0x22, 0x4d, 0x18];
+
+/// Whether the file is compressed and how, leaving it at its start.
+fn compression(file: &mut fs::File) -> io::Result<Option<Compression>> {
+    let mut magic = Vec::with_capacity(SNAPPY_MAGIC.len());
+    Read::by_ref(file)
+        .take(SNAPPY_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    file.rewind()?;
+    Ok(match &magic[..] {
+        m if m.starts_with(GZIP_MAGIC) => Some(Compression::Gzip),
+        m if m.starts_with(ZSTD_MAGIC) => Some(Compression::Zstd),
+        m if m.starts_with(SNAPPY_MAGIC) => Some(Compression::Snappy),
+        m if m.starts_with(LZ4_MAGIC) => Some(Compression::Lz4),
+        _ => None,
+    })
+}
+
+/// Whether the file at `path` is gzip, zstd, snappy or LZ4 compressed.
+pub fn is_compressed(path: &Path) -> bool {
+    fs::File::open(path)
+        .and_then(|mut f| compression(&mut f))
+        .map(|c| c.is_some())
+        .unwrap_or(false)
+}
+
+/// Read a file's contents, decompressing it if it's gzip, zstd, snappy or LZ4 compressed.
+/// Concatenated gzip members and LZ4 frames, as some log shippers write, are read as one.
+pub fn decompress(mut file: fs::File) -> io::Result<Box<dyn Read + Send>> {
+    Ok(match compression(&mut file)? {
+        Some(Compression::Gzip) => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(file)?),
+        Some(Compression::Snappy) => Box::new(snap::read::FrameDecoder::new(file)),
+        Some(Compression::Lz4) => Box::new(Lz4Frames(lz4_flex::frame::FrameDecoder::new(
+            io::BufReader::new(file),
+        ))),
+        None => Box::new(file),
+    })
+}
+
+/// Reads every frame of an LZ4 file, the decoder ends its output at the end of each frame.
+struct Lz4Frames(lz4_flex::frame::FrameDecoder<io::BufReader<fs::File>>);
+
+impl Read for Lz4Frames {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.0.read(buf)?;
+            if read > 0 || buf.is_empty() || self.0.get_mut().fill_buf()?.is_empty() {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+/// The ledger `mark` records processed files in, kept in the same directory as the files.
+pub const LEDGER: &str = ".tau-processed";
+
+/// What to do with an input file once it has been matched.
+pub enum OnProcessed {
+    /// Move the file into the given directory, or a `processed` directory alongside it.
+    Move(Option<PathBuf>),
+    Delete,
+    /// Record the file in the ledger so later runs skip it.
+    Mark,
+}
+
+impl FromStr for OnProcessed {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("move", dir)) => Ok(OnProcessed::Move(Some(PathBuf::from(dir)))),
+            _ => match s {
+                "move" => Ok(OnProcessed::Move(None)),
+                "delete" => Ok(OnProcessed::Delete),
+                "mark" => Ok(OnProcessed::Mark),
+                _ => Err(format!(
+                    "Unsupported action {}, expected move, move:<dir>, delete or mark",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl OnProcessed {
+    pub fn apply(&self, path: &Path) -> Result<(), String> {
+        let parent = path.parent().unwrap_or(Path::new("."));
+        let name = path.file_name().unwrap_or_default();
+        match self {
+            OnProcessed::Move(dir) => {
+                let dir = dir.clone().unwrap_or_else(|| parent.join("processed"));
+                fs::create_dir_all(&dir)
+                    .and_then(|_| fs::rename(path, dir.join(name)))
+                    .map_err(|e| format!("Unable to move {}, {}", path.display(), e))
+            }
+            OnProcessed::Delete => fs::remove_file(path)
+                .map_err(|e| format!("Unable to delete {}, {}", path.display(), e)),
+            OnProcessed::Mark => fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(parent.join(LEDGER))
+                .and_then(|mut f| writeln!(f, "{}", name.to_string_lossy()))
+                .map_err(|e| format!("Unable to mark {}, {}", path.display(), e)),
+        }
+    }
+
+    /// Whether a file is the ledger, or has been recorded in the ledger alongside it by `mark`.
+    pub fn is_marked(path: &Path) -> bool {
+        let name = match path.file_name() {
+            Some(n) => n.to_string_lossy(),
+            None => return false,
+        };
+        if name == LEDGER {
+            return true;
+        }
+        let ledger = path.parent().unwrap_or(Path::new(".")).join(LEDGER);
+        match fs::read_to_string(ledger) {
+            Ok(data) => data.lines().any(|l| l == name),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Reads the events of a single input file, line readers reuse one buffer for every line.
+pub enum Reader {
+    Lines {
+        buffer: io::BufReader<fs::File>,
+        line: String,
+        cache: Option<CacheWriter>,
+    },
+    Cached(io::BufReader<fs::File>),
+    /// Lines of a gzip, zstd, snappy or LZ4 compressed file.
+    Compressed {
+        buffer: io::BufReader<Decoder>,
+        line: String,
+        cache: Option<CacheWriter>,
+    },
+    /// Only the given byte ranges of the file are read, the ranges are stored in reverse order.
+    Ranges {
+        buffer: io::BufReader<fs::File>,
+        line: String,
+        ranges: Vec<(u64, u64)>,
+        pos: u64,
+    },
+}
+
+impl Reader {
+    fn open(path: &PathBuf, cache: Option<&Cache>, plan: Option<&Plan>) -> io::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let compressed = compression(&mut file)?.is_some();
+        // Compressed files are turned away before a plan is made, see `Shard::plan`
+        if let Some(ranges) = plan.and_then(|p| p.ranges(path)) {
+            return Ok(Reader::Ranges {
+                buffer: io::BufReader::new(file),
+                line: String::new(),
+                ranges,
+                pos: 0,
+            });
+        }
+        let cache = match cache.map(|c| c.open(path)).transpose()? {
+            Some(Entry::Hit(f)) => return Ok(Reader::Cached(io::BufReader::new(f))),
+            Some(Entry::Miss(w)) => Some(w),
+            None => None,
+        };
+        match compressed {
+            true => Ok(Reader::Compressed {
+                buffer: io::BufReader::new(Decoder {
+                    reader: decompress(file)?,
+                    failed: false,
+                }),
+                line: String::new(),
+                cache,
+            }),
+            false => Ok(Reader::Lines {
+                buffer: io::BufReader::new(file),
+                line: String::new(),
+                cache,
+            }),
+        }
+    }
+}
+
+/// A decompressed file, which remembers whether it failed to decompress as it can't be read past
+/// the failure.
+pub struct Decoder {
+    reader: Box<dyn Read + Send>,
+    failed: bool,
+}
+
+impl Read for Decoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf);
+        self.failed |= read.is_err();
+        read
+    }
+}
+
+/// Parse a line read into `line`, caching the event if the file is being cached. The cache is
+/// committed at the end of the file, and abandoned if the file can't be read.
+fn parse_line(
+    res: io::Result<(usize, bool)>,
+    line: &str,
+    cache: &mut Option<CacheWriter>,
+    options: &Options,
+) -> Option<Result<Events, Box<dyn Error>>> {
+    match res {
+        Err(e) => {
+            if let Some(w) = cache.take() {
+                w.abandon();
+            }
+            Some(Err(e.into()))
+        }
+        Ok((0, _)) => {
+            if let Some(w) = cache.take() {
+                if let Err(e) = w.commit() {
+                    return Some(Err(e.into()));
+                }
+            }
+            None
+        }
+        Ok((n, true)) => Some(long_line(n, line, options)),
+        Ok(_) => {
+            let res = lenient::parse(line, options.lenient);
+            if let Some(w) = cache.as_mut() {
+                let written = match &res {
+                    Ok(Events::One(json)) => w.write(json),
+                    Ok(Events::Several(events)) => events.iter().try_for_each(|json| w.write(json)),
+                    Err(_) => Ok(()),
+                };
+                // A cache we can't write to just means the file is parsed again next time
+                if written.is_err() {
+                    if let Some(w) = cache.take() {
+                        w.abandon();
+                    }
+                }
+            }
+            Some(res.map_err(|e| e.into()))
+        }
+    }
+}
+
+impl Reader {
+    /// Read the events of the next line, or `None` at the end of the file.
+    fn read(&mut self, options: &Options) -> Option<Result<Events, Box<dyn Error>>> {
+        match self {
+            Reader::Lines {
+                ref mut buffer,
+                ref mut line,
+                ref mut cache,
+            } => {
+                line.clear();
+                let max = options.max_line_bytes;
+                let mut res = read_line(buffer, line, max);
+                // A file still being written can end part way through a line, or grow once read,
+                // and the limit is on the whole line rather than each part of it
+                while let Ok((n, false)) = res {
+                    if line.ends_with('\n') || !grew(buffer, options.eof_stable) {
+                        break;
+                    }
+                    res = read_line(buffer, line, max).map(|(more, long)| (n + more, long));
+                }
+                // Lines which aren't UTF-8 are skipped, but a file which can't be read is left
+                if matches!(&res, Err(e) if e.kind() != io::ErrorKind::InvalidData) {
+                    let _ = buffer.seek(io::SeekFrom::End(0));
+                }
+                parse_line(res, line, cache, options)
+            }
+            Reader::Compressed {
+                ref mut buffer,
+                ref mut line,
+                ref mut cache,
+            } => {
+                if buffer.get_ref().failed {
+                    return None;
+                }
+                line.clear();
+                let res = read_line(buffer, line, options.max_line_bytes);
+                let res = match res {
+                    Err(e) if buffer.get_ref().failed => Err(io::Error::new(
+                        e.kind(),
+                        format!("Unable to decompress, {}", e),
+                    )),
+                    res => res,
+                };
+                parse_line(res, line, cache, options)
+            }
+            Reader::Cached(buffer) => match buffer.fill_buf() {
+                Ok([]) => None,
+                Ok(_) => Some(
+                    rmp_serde::from_read(&mut *buffer)
+                        .map(Events::One)
+                        .map_err(|e| e.into()),
+                ),
+                Err(e) => Some(Err(e.into())),
+            },
+            Reader::Ranges {
+                ref mut buffer,
+                ref mut line,
+                ref mut ranges,
+                ref mut pos,
+            } => {
+                let (start, end) = *ranges.last()?;
+                if *pos >= end {
+                    ranges.pop();
+                    return self.read(options);
+                }
+                if *pos < start {
+                    if let Err(e) = buffer.seek(io::SeekFrom::Start(start)) {
+                        return Some(Err(e.into()));
+                    }
+                    *pos = start;
+                }
+                line.clear();
+                match read_line(buffer, line, options.max_line_bytes) {
+                    Err(_) | Ok((0, _)) => None,
+                    Ok((n, long)) => {
+                        *pos += n as u64;
+                        match long {
+                            true => Some(long_line(n, line, options)),
+                            false => {
+                                Some(lenient::parse(line, options.lenient).map_err(|e| e.into()))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Open a file of concatenated JSON, decompressing it if need be.
+fn open_stream(path: &Path) -> Result<Box<dyn Read + Send>, String> {
+    fs::File::open(path)
+        .and_then(decompress)
+        .map_err(|e| format!("Unable to read input file at {}, {}", path.display(), e))
+}
+
+impl Input {
+    /// Read from the given files, or stdin when there are none.
+    pub fn open(
+        paths: Option<Vec<PathBuf>>,
+        cache: Option<Cache>,
+        plan: Option<Plan>,
+    ) -> Result<Self, String> {
+        match paths {
+            Some(mut v) => match v.pop() {
+                Some(p) => {
+                    let reader = Reader::open(&p, cache.as_ref(), plan.as_ref())
+                        .map_err(|_e| format!("Unable to read input file at {}.", p.display()))?;
+                    Ok(Input::Files {
+                        paths: v,
+                        reader,
+                        cache,
+                        plan,
+                        done: 0,
+                        path: p,
+                        options: Options::default(),
+                        pending: VecDeque::new(),
+                    })
+                }
+                None => Err(
+                    "No input files provided, use -i or --input to specify one or more files"
+                        .into(),
+                ),
+            },
+            None => Ok(Input::CommandLine {
+                stdin: stdin(),
+                line: String::new(),
+                options: Options::default(),
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Read lines of JSON as `options` says, rather than by default.
+    pub fn with_options(mut self, new: Options) -> Self {
+        if let Input::CommandLine { options, .. } | Input::Files { options, .. } = &mut self {
+            *options = new;
+        }
+        self
+    }
+
+    /// Read JSON values written one after another rather than a line each, as buggy writers and
+    /// some streaming APIs do, from the given files or stdin when there are none.
+    pub fn stream(paths: Option<Vec<PathBuf>>) -> Result<Self, String> {
+        let mut paths = match paths {
+            Some(paths) => paths,
+            None => {
+                return Ok(Input::Stream {
+                    paths: Vec::new(),
+                    stream: stream(Box::new(stdin())),
+                    done: 0,
+                    path: None,
+                })
+            }
+        };
+        // Files are read from the end
+        paths.reverse();
+        let path = paths
+            .pop()
+            .ok_or("No input files provided, use -i or --input to specify one or more files")?;
+        let file = open_stream(&path)?;
+        Ok(Input::Stream {
+            paths,
+            stream: stream(file),
+            done: 0,
+            path: Some(path),
+        })
+    }
+
+    /// The input file being read, counting from 0, or 0 when not reading files.
+    pub fn file(&self) -> usize {
+        match self {
+            Input::Files { done, .. } | Input::Stream { done, .. } => *done,
+            _ => 0,
+        }
+    }
+
+    /// The input file being read, `None` when not reading files one at a time.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Input::Files { path, .. } => Some(path),
+            Input::Stream { path, .. } => path.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Stop reading the current input file and move on to the next, does nothing when not
+    /// reading files one at a time.
+    pub fn skip_file(&mut self) -> io::Result<()> {
+        let reader = match self {
+            Input::Files { reader, .. } => reader,
+            Input::Stream { stream: s, .. } => {
+                *s = stream(Box::new(io::empty()));
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        match reader {
+            Reader::Lines { buffer, cache, .. } => {
+                // Only part of the file has been read, so the cache of it would be incomplete
+                if let Some(w) = cache.take() {
+                    w.abandon();
+                }
+                buffer.seek(io::SeekFrom::End(0)).map(|_| ())
+            }
+            Reader::Cached(buffer) => buffer.seek(io::SeekFrom::End(0)).map(|_| ()),
+            Reader::Compressed { buffer, cache, .. } => {
+                if let Some(w) = cache.take() {
+                    w.abandon();
+                }
+                *buffer = io::BufReader::new(Decoder {
+                    reader: Box::new(io::empty()),
+                    failed: false,
+                });
+                Ok(())
+            }
+            Reader::Ranges { ranges, .. } => {
+                ranges.clear();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Iterator for Input {
+    type Item = Result<serde_json::Value, Box<dyn Error>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Input::Events(events) => events.next().map(Ok),
+            Input::Channel(rx) => rx.recv().ok().map(|r| r.map_err(|e| e.into())),
+            Input::Batches { rx, batch } => loop {
+                match batch.next() {
+                    Some(r) => return Some(r.map_err(|e| e.into())),
+                    None => *batch = rx.recv().ok()?.into_iter(),
+                }
+            },
+            Input::CommandLine {
+                stdin,
+                line,
+                options,
+                pending,
+            } => {
+                if let Some(json) = pending.pop_front() {
+                    return Some(Ok(json));
+                }
+                line.clear();
+                match read_line(&mut stdin.lock(), line, options.max_line_bytes) {
+                    Ok((0, _)) => None,
+                    Ok((n, true)) => Some(long_line(n, line, options)),
+                    Ok(_) => Some(
+                        lenient::parse(line, options.lenient)
+                            .map(|events| first(events, pending))
+                            .map_err(|e| e.into()),
+                    ),
+                    Err(e) => Some(Err(e.into())),
+                }
+            }
+            Input::Files {
+                ref mut paths,
+                ref mut reader,
+                ref cache,
+                ref plan,
+                ref mut done,
+                ref mut path,
+                ref options,
+                ref mut pending,
+            } => {
+                if let Some(json) = pending.pop_front() {
+                    return Some(Ok(json));
+                }
+                match reader.read(options) {
+                    Some(res) => Some(res.map(|events| first(events, pending))),
+                    None => match paths.pop() {
+                        Some(p) => {
+                            *done += 1;
+                            // The path is the new file's before it's opened, so an error names it
+                            *path = p;
+                            match Reader::open(path, cache.as_ref(), plan.as_ref()) {
+                                Ok(r) => *reader = r,
+                                Err(e) => return Some(Err(e.into())),
+                            }
+                            self.next()
+                        }
+                        None => {
+                            if let Some(Err(e)) = cache.as_ref().map(|c| c.prune()) {
+                                return Some(Err(e.into()));
+                            }
+                            None
+                        }
+                    },
+                }
+            }
+            Input::Stream {
+                ref mut paths,
+                stream: ref mut s,
+                ref mut done,
+                ref mut path,
+            } => match s.next() {
+                // A value which can't be read ends the file, as where the next starts is unknown
+                Some(res) => {
+                    if res.is_err() {
+                        *s = stream(Box::new(io::empty()));
+                    }
+                    Some(res.map_err(|e| e.into()))
+                }
+                None => {
+                    let p = paths.pop()?;
+                    *done += 1;
+                    let opened = open_stream(&p);
+                    *path = Some(p);
+                    match opened {
+                        Ok(file) => *s = stream(file),
+                        Err(e) => return Some(Err(e.into())),
+                    }
+                    self.next()
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn gzip(lines: usize) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        for i in 0..lines {
+            writeln!(encoder, "{{\"i\": {}}}", i).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compressed_files_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("whole.json.gz");
+        fs::write(&file, gzip(100)).unwrap();
+        let events: Vec<_> = Input::open(Some(vec![file.clone()]), None, None)
+            .unwrap()
+            .collect();
+        assert_eq!(events.len(), 100);
+        assert!(events.iter().all(|e| e.is_ok()));
+    }
+
+    #[test]
+    fn truncated_compressed_files_are_an_error() {
+        let data = gzip(10_000);
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("truncated.json.gz");
+        fs::write(&file, &data[..data.len() / 2]).unwrap();
+        let events: Vec<_> = Input::open(Some(vec![file.clone()]), None, None)
+            .unwrap()
+            .collect();
+        let last = events.last().unwrap().as_ref().unwrap_err();
+        assert!(last.to_string().starts_with("Unable to decompress"));
+        assert!(events[..events.len() - 1].iter().all(|e| e.is_ok()));
+    }
+
+    #[test]
+    fn lines_of_several_events_are_read_in_turn() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("several.json");
+        fs::write(&file, b"{\"a\": 1}{\"a\": 2}\n{\"a\": 3,}\n").unwrap();
+        let open = |lenient| {
+            Input::open(Some(vec![file.clone()]), None, None)
+                .unwrap()
+                .with_options(Options {
+                    lenient,
+                    ..Default::default()
+                })
+                .map(|e| e.map_err(|e| e.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert!(open(false).iter().all(|e| e.is_err()));
+        let events: Vec<_> = open(true)
+            .into_iter()
+            .map(|e| e.unwrap()["a"].clone())
+            .collect();
+        assert_eq!(events, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn unreadable_stream_files_are_named() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("stream.json");
+        fs::write(&file, b"{\"a\": 1} {\"a\": 2}").unwrap();
+        let missing = dir.path().join("missing.json");
+        let events: Vec<_> = Input::stream(Some(vec![file.clone(), missing.clone()]))
+            .unwrap()
+            .map(|e| e.map_err(|e| e.to_string()))
+            .collect();
+        assert_eq!(events.len(), 3);
+        let error = events[2].as_ref().unwrap_err();
+        assert!(error.starts_with(&format!(
+            "Unable to read input file at {}",
+            missing.display()
+        )));
+    }
+
+    #[test]
+    fn the_line_limit_counts_what_the_line_already_holds() {
+        let mut line = "abcd".to_string();
+        let (n, long) = read_line(&mut &b"efgh\n"[..], &mut line, 6).unwrap();
+        assert_eq!((n, long, line.as_str()), (5, true, "abcdef"));
+        let mut line = "abcd".to_string();
+        let (n, long) = read_line(&mut &b"ef\n"[..], &mut line, 6).unwrap();
+        assert_eq!((n, long, line.as_str()), (3, false, "abcdef\n"));
+    }
+
+    #[test]
+    fn unreadable_files_are_the_current_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("path.json");
+        fs::write(&file, b"{\"a\": 1}\n").unwrap();
+        let missing = dir.path().join("missing.json");
+        // Files are read from the end of the list
+        let mut input = Input::open(Some(vec![missing.clone(), file.clone()]), None, None).unwrap();
+        assert!(input.next().unwrap().is_ok());
+        assert_eq!(input.path(), Some(file.as_path()));
+        assert!(input.next().unwrap().is_err());
+        assert_eq!(input.path(), Some(missing.as_path()));
+    }
+}
@@ -0,0 +1,264 @@
+use std::{borrow::Cow, collections::HashSet, str::FromStr};
+
+use serde_json::{Map, Value};
+use serde_yaml::Value as Yaml;
+use tau_engine::Rule;
+
+use crate::event;
+
+/// The field the results of a rule's indexed lists are added to while it's matched, the rule
+/// reading `tau_ioc_index.N: true` in place of the Nth list.
+const KEY: &str = "tau_ioc_index";
+
+/// How --ioc-index stores the value lists taken out of rules.
+#[derive(Clone, Copy)]
+pub enum Mode {
+    /// Every value, looked up in a hash set.
+    Exact,
+    /// Every value behind a bloom filter of around 1.8 bytes a value at a 0.1% false positive
+    /// rate. Most values not in the list are turned away by the filter, only those it passes are
+    /// looked up in the set, so matches are exact.
+    Bloom,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Mode::Exact),
+            "bloom" => Ok(Mode::Bloom),
+            _ => Err(format!(
+                "Unknown ioc index mode {}, expected exact or bloom",
+                s
+            )),
+        }
+    }
+}
+
+/// How a rule's value lists are indexed.
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub mode: Mode,
+    /// The fewest values a list must have to be indexed, scanning a shorter list is as quick.
+    pub min_values: usize,
+    pub false_positive_rate: f64,
+}
+
+/// FNV-1a seeded with `seed`, two seeds give the pair of hashes the filter's probes are made from.
+fn hash(seed: u64, value: &str) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for b in value.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h
+}
+
+struct Bloom {
+    bits: Vec<u64>,
+    probes: u32,
+}
+
+impl Bloom {
+    fn new(values: &[&str], false_positive_rate: f64) -> Self {
+        let n = values.len().max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as usize;
+        let probes = ((bits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+        let mut bloom = Bloom {
+            bits: vec![0; bits.div_ceil(64)],
+            probes,
+        };
+        for value in values {
+            for bit in bloom.positions(value) {
+                bloom.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        bloom
+    }
+
+    fn positions(&self, value: &str) -> impl Iterator<Item = usize> {
+        let (a, b) = (hash(0, value), hash(0x9e37_79b9_7f4a_7c15, value) | 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..self.probes as u64).map(move |i| (a.wrapping_add(i.wrapping_mul(b)) % len) as usize)
+    }
+
+    fn contains(&self, value: &str) -> bool {
+        self.positions(value)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// A list's values, with the bloom filter a value is checked against first in bloom mode.
+struct Values {
+    filter: Option<Bloom>,
+    values: HashSet<String>,
+}
+
+impl Values {
+    fn contains(&self, value: &Value) -> bool {
+        let s = match value {
+            Value::String(s) => Cow::Borrowed(s.as_str()),
+            Value::Number(n) => Cow::Owned(n.to_string()),
+            Value::Array(a) => return a.iter().any(|v| self.contains(v)),
+            _ => return false,
+        };
+        match &self.filter {
+            Some(bloom) if !bloom.contains(&s) => false,
+            _ => self.values.contains(s.as_ref()),
+        }
+    }
+}
+
+/// A rule whose long lists of literal values were taken out into sets, so each event looks its
+/// fields up once rather than the rule comparing them against every value.
+pub struct IndexedRule {
+    rule: Rule,
+    lists: Vec<(String, Values)>,
+}
+
+/// A value a list can be indexed with, patterns and case insensitive values can't be looked up.
+fn literal(value: &Yaml) -> Option<&str> {
+    let v = value.as_str()?;
+    match !v.is_empty() && !v.starts_with(&['*', '?', 'i'][..]) && !v.ends_with('*') {
+        true => Some(v),
+        false => None,
+    }
+}
+
+impl IndexedRule {
+    /// Take the lists of at least `min_values` literals out of a validated rule, `None` when it has
+    /// none. Lists mixing in patterns are left for the rule to match.
+    pub fn load(rule: &str, settings: Settings) -> Option<Self> {
+        let mut yaml: Yaml = serde_yaml::from_str(rule).ok()?;
+        let detection = yaml.get_mut("detection")?.as_mapping_mut()?;
+        let mut lists = Vec::new();
+        for (name, identifier) in detection.iter_mut() {
+            let identifier = match (name.as_str(), identifier.as_mapping_mut()) {
+                (Some("condition"), _) | (_, None) => continue,
+                (_, Some(i)) => i,
+            };
+            let fields: Vec<(Yaml, Values)> = identifier
+                .iter()
+                .filter_map(|(field, value)| {
+                    let name = field.as_str()?;
+                    let values = value.as_sequence()?;
+                    if name.contains(&['(', ')'][..]) || values.len() < settings.min_values {
+                        return None;
+                    }
+                    let literals = values.iter().map(literal).collect::<Option<Vec<_>>>()?;
+                    let indexed = Values {
+                        filter: match settings.mode {
+                            Mode::Exact => None,
+                            Mode::Bloom => {
+                                Some(Bloom::new(&literals, settings.false_positive_rate))
+                            }
+                        },
+                        values: literals.iter().map(|v| v.to_string()).collect(),
+                    };
+                    Some((field.clone(), indexed))
+                })
+                .collect();
+            for (field, indexed) in fields {
+                identifier.remove(&field);
+                identifier.insert(
+                    Yaml::String(format!("{}.{}", KEY, lists.len())),
+                    Yaml::Bool(true),
+                );
+                lists.push((field.as_str().unwrap_or_default().to_string(), indexed));
+            }
+        }
+        if lists.is_empty() {
+            return None;
+        }
+        let rule = Rule::load(&serde_yaml::to_string(&yaml).ok()?).ok()?;
+        Some(IndexedRule { rule, lists })
+    }
+
+    /// Look up the event's fields in the lists, then match the rule with the results added to the
+    /// event, leaving it as it was.
+    pub fn matches(&self, json: &mut Value) -> bool {
+        let hits: Map<String, Value> = self
+            .lists
+            .iter()
+            .enumerate()
+            .map(|(i, (field, values))| {
+                let hit = event::get(json, field)
+                    .map(|v| values.contains(v))
+                    .unwrap_or(false);
+                (i.to_string(), Value::Bool(hit))
+            })
+            .collect();
+        let object = match json.as_object_mut() {
+            Some(o) => o,
+            None => return self.rule.matches(json),
+        };
+        let previous = object.insert(KEY.to_string(), Value::Object(hits));
+        let is_match = self.rule.matches(json);
+        if let Some(object) = json.as_object_mut() {
+            match previous {
+                Some(p) => object.insert(KEY.to_string(), p),
+                None => object.remove(KEY),
+            };
+        }
+        is_match
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn values(mode: Mode, false_positive_rate: f64) -> Values {
+        let literals: Vec<String> = (0..1000)
+            .map(|i| format!("10.0.{}.{}", i / 256, i % 256))
+            .collect();
+        let literals: Vec<&str> = literals.iter().map(|v| v.as_str()).collect();
+        Values {
+            filter: match mode {
+                Mode::Exact => None,
+                Mode::Bloom => Some(Bloom::new(&literals, false_positive_rate)),
+            },
+            values: literals.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn bloom_hits_are_confirmed() {
+        // At this rate nearly every value passes the filter, so matches rest on the set
+        let values = values(Mode::Bloom, 0.9);
+        assert!(values.contains(&json!("10.0.3.231")));
+        assert!(values.contains(&json!(["192.168.0.1", "10.0.0.7"])));
+        for i in 0..1000 {
+            assert!(!values.contains(&json!(format!("192.168.{}.{}", i / 256, i % 256))));
+        }
+    }
+
+    #[test]
+    fn exact_lookups() {
+        let values = values(Mode::Exact, 0.001);
+        assert!(values.contains(&json!("10.0.0.0")));
+        assert!(!values.contains(&json!("10.0.0.0 ")));
+        assert!(!values.contains(&json!({ "ip": "10.0.0.0" })));
+    }
+
+    #[test]
+    fn the_filter_has_no_false_negatives() {
+        let literals = ["a", "b", "c"];
+        let bloom = Bloom::new(&literals, 0.001);
+        assert!(literals.iter().all(|v| bloom.contains(v)));
+    }
+
+    #[test]
+    fn only_literals_are_indexed() {
+        let yaml = |s: &str| Yaml::String(s.into());
+        assert_eq!(literal(&yaml("evil.exe")), Some("evil.exe"));
+        for pattern in ["*evil", "evil*", "?evil", "ievil", ""] {
+            assert_eq!(literal(&yaml(pattern)), None);
+        }
+    }
+}
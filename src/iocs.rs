@@ -0,0 +1,300 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{stdout, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use regex::Regex;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+
+use crate::input::Input;
+use crate::output::csv_escape;
+
+/// MD5, SHA1 and SHA256 hashes.
+pub const HASH_PATTERN: &str = r"\b(?:[[:xdigit:]]{64}|[[:xdigit:]]{40}|[[:xdigit:]]{32})\b";
+
+/// Field names which are likely to hold domains, other fields are only searched for IPs, hashes and URLs
+/// to avoid file names like `cmd.exe` being reported as domains.
+const DOMAIN_FIELDS: &[&str] = &["domain", "host", "query", "dns", "fqdn", "url", "server"];
+
+pub enum Format {
+    Csv,
+    Stix,
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "stix" => Ok(Format::Stix),
+            _ => Err(format!("Unknown format {}, expected csv or stix", s)),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub struct ExtractIocs {
+    /// Files of matched events to scan, stdin is used when no files are given.
+    #[structopt(short, long, parse(from_os_str))]
+    input: Option<Vec<PathBuf>>,
+
+    /// The format to output indicators in, either 'csv' or 'stix'.
+    #[structopt(long, default_value = "csv")]
+    format: Format,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Kind {
+    Ipv4,
+    Domain,
+    Url,
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Ipv4 => "ipv4",
+            Kind::Domain => "domain",
+            Kind::Url => "url",
+            Kind::Md5 => "md5",
+            Kind::Sha1 => "sha1",
+            Kind::Sha256 => "sha256",
+        }
+    }
+
+    fn stix_pattern(self, value: &str) -> String {
+        let value = value.replace('\\', "\\\\").replace('\'', "\\'");
+        match self {
+            Kind::Ipv4 => format!("[ipv4-addr:value = '{}']", value),
+            Kind::Domain => format!("[domain-name:value = '{}']", value),
+            Kind::Url => format!("[url:value = '{}']", value),
+            Kind::Md5 => format!("[file:hashes.MD5 = '{}']", value),
+            Kind::Sha1 => format!("[file:hashes.'SHA-1' = '{}']", value),
+            Kind::Sha256 => format!("[file:hashes.'SHA-256' = '{}']", value),
+        }
+    }
+}
+
+struct Extractor {
+    ipv4: Regex,
+    url: Regex,
+    domain: Regex,
+    hash: Regex,
+}
+
+impl Extractor {
+    fn new() -> Self {
+        Extractor {
+            ipv4: Regex::new(
+                r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b",
+            )
+            .expect("valid regex"),
+            url: Regex::new(r#"\b(?i:https?|ftp)://[^\s"'<>]+"#).expect("valid regex"),
+            domain: Regex::new(r"(?i)\b(?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+[a-z]{2,63}\b")
+                .expect("valid regex"),
+            hash: Regex::new(HASH_PATTERN).expect("valid regex"),
+        }
+    }
+
+    /// Collect the indicators found in every string value of an event.
+    fn extract(&self, field: &str, json: &Value, found: &mut HashSet<(Kind, String)>) {
+        match json {
+            Value::Object(o) => {
+                for (k, v) in o.iter() {
+                    self.extract(k, v, found);
+                }
+            }
+            Value::Array(a) => {
+                for v in a.iter() {
+                    self.extract(field, v, found);
+                }
+            }
+            Value::String(s) => {
+                for m in self.ipv4.find_iter(s) {
+                    if !m.as_str().starts_with("127.") && m.as_str() != "0.0.0.0" {
+                        found.insert((Kind::Ipv4, m.as_str().to_string()));
+                    }
+                }
+                for m in self.url.find_iter(s) {
+                    let url = m.as_str().trim_end_matches(&['.', ',', ')', ';'][..]);
+                    found.insert((Kind::Url, url.to_string()));
+                    if let Some(host) = url
+                        .split("://")
+                        .nth(1)
+                        .and_then(|r| r.split(&['/', ':', '?', '#'][..]).next())
+                    {
+                        self.domains(host, found);
+                    }
+                }
+                let field = field.to_lowercase();
+                if DOMAIN_FIELDS.iter().any(|f| field.contains(f)) {
+                    self.domains(s, found);
+                }
+                for m in self.hash.find_iter(s) {
+                    let kind = match m.as_str().len() {
+                        32 => Kind::Md5,
+                        40 => Kind::Sha1,
+                        _ => Kind::Sha256,
+                    };
+                    found.insert((kind, m.as_str().to_lowercase()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn domains(&self, s: &str, found: &mut HashSet<(Kind, String)>) {
+        for m in self.domain.find_iter(s) {
+            // The TLD must be alphabetic so IPs are never reported as domains
+            found.insert((Kind::Domain, m.as_str().to_lowercase()));
+        }
+    }
+}
+
+impl ExtractIocs {
+    pub fn run(self) -> Result<(), String> {
+        let extractor = Extractor::new();
+        let mut counts: HashMap<(Kind, String), usize> = HashMap::new();
+        let mut stderr = std::io::stderr();
+        for res in Input::open(self.input, None, None)? {
+            match res {
+                Ok(json) => {
+                    let mut found = HashSet::new();
+                    extractor.extract("", &json, &mut found);
+                    for ioc in found {
+                        *counts.entry(ioc).or_insert(0) += 1;
+                    }
+                }
+                Err(e) => {
+                    let _ = writeln!(stderr, "{}", e);
+                }
+            }
+        }
+        let mut iocs: Vec<_> = counts.into_iter().collect();
+        iocs.sort();
+        let mut stdout = stdout();
+        match self.format {
+            Format::Csv => {
+                let mut write = || -> std::io::Result<()> {
+                    writeln!(stdout, "Type, Value, Count")?;
+                    for ((kind, value), count) in iocs.iter() {
+                        writeln!(stdout, "{}, {}, {}", kind.name(), csv_escape(value), count)?;
+                    }
+                    Ok(())
+                };
+                write().map_err(|e| format!("Unable to write indicators, {}", e))
+            }
+            Format::Stix => {
+                let now = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+                let objects: Vec<Value> = iocs
+                    .iter()
+                    .map(|((kind, value), count)| {
+                        let id = uuid::Uuid::new_v5(
+                            &uuid::Uuid::NAMESPACE_URL,
+                            format!("tau-cli:{}:{}", kind.name(), value).as_bytes(),
+                        );
+                        json!({
+                            "type": "indicator",
+                            "spec_version": "2.1",
+                            "id": format!("indicator--{}", id),
+                            "created": now,
+                            "modified": now,
+                            "valid_from": now,
+                            "name": value,
+                            "description": format!("Seen in {} matched events", count),
+                            "pattern_type": "stix",
+                            "pattern": kind.stix_pattern(value),
+                        })
+                    })
+                    .collect();
+                let bundle = json!({
+                    "type": "bundle",
+                    "id": format!("bundle--{}", uuid::Uuid::new_v4()),
+                    "objects": objects,
+                });
+                writeln!(stdout, "{}", bundle)
+                    .map_err(|e| format!("Unable to write indicators, {}", e))
+            }
+        }
+    }
+}
+
+/// A set of indicators compared case-insensitively against the values of specific fields.
+pub struct IocSet {
+    fields: Vec<String>,
+    values: HashSet<String>,
+}
+
+impl IocSet {
+    pub fn load(path: &Path, fields: Vec<String>) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+        let mut lines = data
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .peekable();
+        // Use the 'Value' column when there is a header, as written by extract-iocs
+        let column = match lines.peek().map(|l| split_csv(l)) {
+            Some(header) => match header.iter().position(|h| h.eq_ignore_ascii_case("value")) {
+                Some(i) => {
+                    lines.next();
+                    i
+                }
+                None => 0,
+            },
+            None => 0,
+        };
+        let values = lines
+            .filter_map(|l| split_csv(l).into_iter().nth(column))
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_lowercase())
+            .collect();
+        Ok(IocSet { fields, values })
+    }
+
+    pub fn matches(&self, json: &Value) -> bool {
+        self.fields
+            .iter()
+            .filter_map(|f| crate::event::get(json, f))
+            .any(|v| self.contains(v))
+    }
+
+    fn contains(&self, value: &Value) -> bool {
+        match value {
+            Value::String(s) => self.values.contains(&s.to_lowercase()),
+            Value::Number(n) => self.values.contains(&n.to_string()),
+            Value::Array(a) => a.iter().any(|v| self.contains(v)),
+            _ => false,
+        }
+    }
+}
+
+/// Split a CSV line into trimmed fields, handling quoted fields.
+pub fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
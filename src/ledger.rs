@@ -0,0 +1,85 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::event;
+
+/// The keys of matches already output, kept in a file so a re-run or resumed job skips them.
+/// Keys are only written once the outputs have been flushed, so a crash can repeat matches but
+/// never lose them.
+pub struct Ledger {
+    path: PathBuf,
+    fields: Vec<String>,
+    seen: HashSet<Uuid>,
+    pending: Vec<Uuid>,
+}
+
+impl Ledger {
+    pub fn open(path: &Path, fields: Vec<String>) -> Result<Self, String> {
+        let mut seen = HashSet::new();
+        match fs::File::open(path) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    let line =
+                        line.map_err(|e| format!("Unable to read {}, {}", path.display(), e))?;
+                    let key = Uuid::parse_str(line.trim())
+                        .map_err(|_| format!("Invalid ledger {}", path.display()))?;
+                    seen.insert(key);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(_) => return Err(format!("Unable to read data from {}.", path.display())),
+        }
+        Ok(Ledger {
+            path: path.to_path_buf(),
+            fields,
+            seen,
+            pending: Vec::new(),
+        })
+    }
+
+    /// The key of a match by a rule, or of an event output as a whole when there is no rule.
+    pub fn key(&self, rule: Option<&str>, json: &Value) -> Uuid {
+        let key = event::key(json, &self.fields);
+        let key = match rule {
+            Some(rule) => format!("{}\n{}", rule, key),
+            None => key,
+        };
+        Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes())
+    }
+
+    /// Whether a match has already been output, in this run or an earlier one.
+    pub fn contains(&self, key: &Uuid) -> bool {
+        self.seen.contains(key)
+    }
+
+    /// Record a match which has been written to the outputs.
+    pub fn insert(&mut self, key: Uuid) {
+        if self.seen.insert(key) {
+            self.pending.push(key);
+        }
+    }
+
+    /// Write the keys recorded since the last commit, called once the outputs are flushed.
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut file = io::BufWriter::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?,
+        );
+        for key in self.pending.drain(..) {
+            writeln!(file, "{}", key)?;
+        }
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::Value;
+
+/// Lines which only parsed once repaired.
+static RECOVERED: AtomicU64 = AtomicU64::new(0);
+
+pub fn recovered() -> u64 {
+    RECOVERED.load(Ordering::Relaxed)
+}
+
+/// The events of a line, which holds several when it was recovered from events written one after
+/// another.
+#[derive(Debug, PartialEq)]
+pub enum Events {
+    One(Value),
+    Several(Vec<Value>),
+}
+
+/// Repair the mistakes exporters make writing JSON by hand, escaping control characters within
+/// strings and dropping commas left before a closing bracket or at the end of the line.
+fn repair(line: &str) -> String {
+    let mut repaired = String::with_capacity(line.len());
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, c) in line.char_indices() {
+        if in_string {
+            match (escaped, c) {
+                (true, _) => escaped = false,
+                (false, '\\') => escaped = true,
+                (false, '"') => in_string = false,
+                (false, '\n') => {
+                    repaired.push_str("\\n");
+                    continue;
+                }
+                (false, '\r') => {
+                    repaired.push_str("\\r");
+                    continue;
+                }
+                (false, '\t') => {
+                    repaired.push_str("\\t");
+                    continue;
+                }
+                (false, c) if c < ' ' => {
+                    repaired.push_str(&format!("\\u{:04x}", c as u32));
+                    continue;
+                }
+                _ => {}
+            }
+        } else {
+            match c {
+                '"' => in_string = true,
+                ',' if matches!(
+                    line[i + 1..].trim_start().chars().next(),
+                    None | Some('}' | ']')
+                ) =>
+                {
+                    continue
+                }
+                _ => {}
+            }
+        }
+        repaired.push(c);
+    }
+    repaired
+}
+
+/// The events of a line which didn't parse, once repaired, or `None` if it still doesn't.
+fn recover(line: &str) -> Option<Vec<Value>> {
+    let repaired = repair(line);
+    let events = serde_json::Deserializer::from_str(&repaired)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<Value>, _>>()
+        .ok()?;
+    match events.is_empty() {
+        true => None,
+        false => {
+            RECOVERED.fetch_add(1, Ordering::Relaxed);
+            Some(events)
+        }
+    }
+}
+
+/// Parse a line of JSON, repairing it when it's malformed and `lenient`, for --lenient-json.
+pub fn parse(line: &str, lenient: bool) -> Result<Events, serde_json::Error> {
+    let line = line.trim_end();
+    let error = match serde_json::from_str(line) {
+        Ok(json) => return Ok(Events::One(json)),
+        Err(e) => e,
+    };
+    match lenient.then(|| recover(line)).flatten() {
+        Some(mut events) if events.len() == 1 => Ok(Events::One(events.remove(0))),
+        Some(events) => Ok(Events::Several(events)),
+        None => Err(error),
+    }
+}
+
+/// Parse a line into a batch of events, as readers parsing lines on other threads collect them.
+pub fn parse_into(line: &[u8], lenient: bool, batch: &mut Vec<Result<Value, String>>) {
+    let line = line.trim_ascii_end();
+    let error = match serde_json::from_slice(line) {
+        Ok(json) => return batch.push(Ok(json)),
+        Err(e) => e,
+    };
+    let recovered = match lenient {
+        true => std::str::from_utf8(line).ok().and_then(recover),
+        false => None,
+    };
+    match recovered {
+        Some(events) => batch.extend(events.into_iter().map(Ok)),
+        None => batch.push(Err(error.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lines_are_only_repaired_when_lenient() {
+        let line = "{\"a\": \"x\ty\", \"b\": [1, 2,],}\n";
+        assert!(parse(line, false).is_err());
+        assert_eq!(
+            parse(line, true).unwrap(),
+            Events::One(json!({ "a": "x\ty", "b": [1, 2] }))
+        );
+    }
+
+    #[test]
+    fn several_events_on_a_line() {
+        assert_eq!(
+            parse("{\"a\": 1}{\"a\": 2}", true).unwrap(),
+            Events::Several(vec![json!({ "a": 1 }), json!({ "a": 2 })])
+        );
+        let mut batch = Vec::new();
+        parse_into(b"{\"a\": 1} {\"a\": 2}\n", true, &mut batch);
+        assert_eq!(batch, vec![Ok(json!({ "a": 1 })), Ok(json!({ "a": 2 }))]);
+    }
+
+    #[test]
+    fn unrepairable_lines_are_errors() {
+        assert!(parse("{\"a\": ", true).is_err());
+        let mut batch = Vec::new();
+        parse_into(b"not json", true, &mut batch);
+        assert!(batch[0].is_err());
+    }
+}
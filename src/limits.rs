@@ -0,0 +1,156 @@
+use std::{
+    io::{stderr, Write},
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Events between checks of memory and CPU use.
+const CHECK_EVERY: u64 = 1024;
+/// How far batches can shrink under memory pressure, as a power of two.
+const MAX_SHRINK: u32 = 8;
+
+/// Halvings applied to the batch sizes of the threaded readers while memory use is over the limit.
+static SHRINK: AtomicU32 = AtomicU32::new(0);
+
+/// A batch size for the threaded readers, smaller while memory use is over --max-memory.
+pub fn batch(size: usize) -> usize {
+    (size >> SHRINK.load(Ordering::Relaxed)).max(1)
+}
+
+/// Set the scheduling priority of the process, as the `nice` command would.
+#[cfg(unix)]
+pub fn nice(n: i32) -> Result<(), String> {
+    // The priority set is the absolute niceness, not one relative to the current value
+    match unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, n) } {
+        0 => Ok(()),
+        _ => Err(format!(
+            "Unable to set priority to {}, {}",
+            n,
+            std::io::Error::last_os_error()
+        )),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn nice(_n: i32) -> Result<(), String> {
+    Err("Setting priority is only supported on unix".into())
+}
+
+/// The CPU time used by every thread of the process.
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    match unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) } {
+        0 => Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+/// The resident memory of the process in bytes.
+#[cfg(target_os = "linux")]
+fn memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page.max(0) as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn memory() -> Option<u64> {
+    None
+}
+
+/// Throttles matching to stay within a share of the CPU, and shrinks the batches read ahead while
+/// memory use is over a limit.
+pub struct Limits {
+    max_memory: Option<u64>,
+    cpu_limit: Option<f64>,
+    start: Instant,
+    cpu_start: Duration,
+    events: u64,
+    warned: bool,
+}
+
+impl Limits {
+    /// Limits of `max_memory` MiB and `cpu_limit` percent of one core.
+    pub fn new(max_memory: Option<u64>, cpu_limit: Option<f64>) -> Result<Self, String> {
+        if max_memory.is_some() && memory().is_none() {
+            return Err("Limiting memory is only supported on Linux".into());
+        }
+        let max_memory = match max_memory.map(|m| m.checked_mul(1024 * 1024)) {
+            Some(None) => return Err("Invalid memory limit, it's too large".into()),
+            max => max.flatten(),
+        };
+        let cpu_start = match cpu_limit {
+            Some(limit) if limit.is_nan() || limit <= 0.0 => {
+                return Err(format!(
+                    "Invalid CPU limit {}, expected a percentage",
+                    limit
+                ))
+            }
+            Some(_) => cpu_time().ok_or("Limiting CPU is only supported on unix")?,
+            None => Duration::default(),
+        };
+        Ok(Limits {
+            max_memory,
+            cpu_limit: cpu_limit.map(|c| c / 100.0),
+            start: Instant::now(),
+            cpu_start,
+            events: 0,
+            warned: false,
+        })
+    }
+
+    /// Called for every event, sleeping when the CPU used is over the limit and adjusting batch
+    /// sizes to the memory in use.
+    pub fn check(&mut self) {
+        self.events += 1;
+        if !self.events.is_multiple_of(CHECK_EVERY) {
+            return;
+        }
+        if let (Some(max), Some(used)) = (self.max_memory, memory()) {
+            let shrink = SHRINK.load(Ordering::Relaxed);
+            if used > max && shrink < MAX_SHRINK {
+                if !self.warned {
+                    let _ = writeln!(
+                        stderr(),
+                        "Memory use of {} MiB is over --max-memory, reading in smaller batches",
+                        used / 1024 / 1024
+                    );
+                    self.warned = true;
+                }
+                SHRINK.store(shrink + 1, Ordering::Relaxed);
+            } else if used < max / 4 * 3 && shrink > 0 {
+                SHRINK.store(shrink - 1, Ordering::Relaxed);
+            }
+        }
+        if let (Some(limit), Some(cpu)) = (self.cpu_limit, cpu_time()) {
+            // Sleep until the CPU used is the allowed share of the time since starting
+            let allowed = (cpu - self.cpu_start).div_f64(limit);
+            let elapsed = self.start.elapsed();
+            if allowed > elapsed {
+                thread::sleep(allowed - elapsed);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_limits_which_overflow_are_rejected() {
+        assert!(Limits::new(Some(1024), None).is_ok());
+        assert!(Limits::new(Some(u64::MAX / 1024), None).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{NaiveDate, NaiveDateTime, SecondsFormat};
+use glob::Pattern;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::event;
+
+/// The order of the day, month and year in dates written without names.
+#[derive(Clone, Copy, Deserialize)]
+enum DateOrder {
+    #[serde(rename = "day-first")]
+    Dmy,
+    #[serde(rename = "month-first")]
+    Mdy,
+    #[serde(rename = "year-first")]
+    Ymd,
+}
+
+/// How one source writes numbers and dates, an entry of the --locales file.
+#[derive(Deserialize)]
+struct Locale {
+    /// A glob of the input files the entry applies to, matched against the file name when it has
+    /// no directory. Entries without one apply to every event.
+    source: Option<String>,
+    /// The decimal separator, either '.' or ','. The other is taken as a thousands separator, as
+    /// are spaces and apostrophes.
+    #[serde(default = "default_decimal")]
+    decimal: char,
+    dates: Option<DateOrder>,
+    /// Fields holding numbers written as strings, converted to numbers.
+    #[serde(default)]
+    numbers: Vec<String>,
+    /// Fields holding dates and times, converted to RFC 3339 timestamps in UTC.
+    #[serde(default)]
+    times: Vec<String>,
+}
+
+fn default_decimal() -> char {
+    '.'
+}
+
+/// Read a number as written in a locale, such as `1.234,5` with a comma decimal separator.
+/// Thousands separators must separate groups of three digits, so a value written in another
+/// locale isn't read as the wrong number.
+fn number(s: &str, decimal: char) -> Option<Value> {
+    let s = s.trim();
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", s),
+    };
+    let (whole, fraction) = match digits.split_once(decimal) {
+        Some((w, f)) => (w, Some(f)),
+        None => (digits, None),
+    };
+    let thousands = |c: char| {
+        (c == '.' || c == ',' || c == ' ' || c == '\'' || c == '\u{a0}' || c == '\u{202f}')
+            && c != decimal
+    };
+    let groups: Vec<&str> = whole.split(thousands).collect();
+    let digits_only = |g: &str| !g.is_empty() && g.bytes().all(|b| b.is_ascii_digit());
+    let grouped = groups.len() == 1
+        || ((1..=3).contains(&groups[0].len()) && groups[1..].iter().all(|g| g.len() == 3));
+    if !grouped
+        || !groups.iter().all(|g| digits_only(g))
+        || !fraction.map(digits_only).unwrap_or(true)
+    {
+        return None;
+    }
+    let whole = groups.concat();
+    match fraction {
+        None => format!("{}{}", sign, whole)
+            .parse::<i64>()
+            .ok()
+            .map(Value::from),
+        Some(f) => format!("{}{}.{}", sign, whole, f)
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+    }
+}
+
+/// Read a date, with an optional time, written with its day, month and year in the given order,
+/// e.g. `03/04/2024 13:45` day first is the 3rd of April.
+fn time(s: &str, order: DateOrder) -> Option<Value> {
+    let s = s.trim();
+    let date = match order {
+        DateOrder::Dmy => ["%d/%m/%Y", "%d.%m.%Y", "%d-%m-%Y"],
+        DateOrder::Mdy => ["%m/%d/%Y", "%m.%d.%Y", "%m-%d-%Y"],
+        DateOrder::Ymd => ["%Y/%m/%d", "%Y.%m.%d", "%Y-%m-%d"],
+    };
+    let times = [" %H:%M:%S%.f", "T%H:%M:%S%.f", " %H:%M", ", %H:%M:%S%.f"];
+    let parsed = date
+        .iter()
+        .flat_map(|d| times.iter().map(move |t| format!("{}{}", d, t)))
+        .find_map(|f| NaiveDateTime::parse_from_str(s, &f).ok())
+        .or_else(|| {
+            date.iter()
+                .find_map(|d| NaiveDate::parse_from_str(s, d).ok())
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })?;
+    Some(Value::String(
+        parsed
+            .and_utc()
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    ))
+}
+
+/// Converts numbers and dates written in the locale of their source, chosen for each input file
+/// by the --locales file.
+pub struct Locales {
+    entries: Vec<(Option<Pattern>, Locale)>,
+    /// The file last looked up and the entry chosen for it, files are read one event at a time.
+    chosen: Option<(Option<PathBuf>, Option<usize>)>,
+    /// Values which couldn't be read in their source's locale, and were left as they were.
+    pub unparsed: u64,
+}
+
+impl Locales {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+        let locales: Vec<Locale> = serde_yaml::from_str(&data)
+            .map_err(|e| format!("Unable to parse the locales in {}, {}", path.display(), e))?;
+        let mut entries = Vec::new();
+        for locale in locales {
+            if locale.decimal != '.' && locale.decimal != ',' {
+                return Err(format!(
+                    "Unknown decimal separator {} in {}, expected . or ,",
+                    locale.decimal,
+                    path.display()
+                ));
+            }
+            if !locale.times.is_empty() && locale.dates.is_none() {
+                return Err(format!(
+                    "The times in {} need the order of their dates, add dates: day-first, month-first or year-first",
+                    path.display()
+                ));
+            }
+            let pattern = match &locale.source {
+                Some(s) => {
+                    Some(Pattern::new(s).map_err(|e| format!("Invalid source glob {}, {}", s, e))?)
+                }
+                None => None,
+            };
+            entries.push((pattern, locale));
+        }
+        Ok(Locales {
+            entries,
+            chosen: None,
+            unparsed: 0,
+        })
+    }
+
+    /// The first entry applying to events read from `path`, or from stdin when there is none.
+    fn entry(&mut self, path: Option<&Path>) -> Option<usize> {
+        if let Some((last, chosen)) = &self.chosen {
+            if last.as_deref() == path {
+                return *chosen;
+            }
+        }
+        let chosen = self
+            .entries
+            .iter()
+            .position(|(pattern, _)| match (pattern, path) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(p), Some(path)) => match p.as_str().contains('/') {
+                    true => p.matches_path(path),
+                    false => path
+                        .file_name()
+                        .map(|n| p.matches_path(Path::new(n)))
+                        .unwrap_or(false),
+                },
+            });
+        self.chosen = Some((path.map(Path::to_path_buf), chosen));
+        chosen
+    }
+
+    /// Convert the event's numbers and dates as the locale of the file it was read from says.
+    pub fn apply(&mut self, json: &mut Value, path: Option<&Path>) {
+        let locale = match self.entry(path) {
+            Some(i) => &self.entries[i].1,
+            None => return,
+        };
+        let mut unparsed = 0;
+        let fields = locale
+            .numbers
+            .iter()
+            .map(|f| (f, true))
+            .chain(locale.times.iter().map(|f| (f, false)));
+        for (field, is_number) in fields {
+            let value = match event::get_mut(json, field) {
+                Some(v) => v,
+                None => continue,
+            };
+            let s = match value {
+                Value::String(s) if !s.trim().is_empty() => s,
+                _ => continue,
+            };
+            let converted = match (is_number, locale.dates) {
+                (true, _) => number(s, locale.decimal),
+                (false, Some(order)) => time(s, order),
+                (false, None) => None,
+            };
+            match converted {
+                Some(v) => *value = v,
+                None => unparsed += 1,
+            }
+        }
+        self.unparsed += unparsed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn numbers_are_read_with_their_separators() {
+        assert_eq!(number("1.234,5", ','), Some(json!(1234.5)));
+        assert_eq!(number("1,234.5", '.'), Some(json!(1234.5)));
+        assert_eq!(number("-12 345", ','), Some(json!(-12345)));
+        // Thousands must be groups of three, so a value in the other locale isn't misread
+        assert_eq!(number("1,5", '.'), None);
+        assert_eq!(number("12.34.5", ','), None);
+    }
+
+    #[test]
+    fn dates_are_read_in_their_order() {
+        let april = json!("2024-04-03T00:00:00Z");
+        assert_eq!(time("03/04/2024", DateOrder::Dmy), Some(april.clone()));
+        assert_eq!(time("04/03/2024", DateOrder::Mdy), Some(april));
+        assert_eq!(
+            time("2024.04.03 13:45", DateOrder::Ymd),
+            Some(json!("2024-04-03T13:45:00Z"))
+        );
+        assert_eq!(time("13/13/2024", DateOrder::Dmy), None);
+    }
+
+    #[test]
+    fn each_file_uses_the_first_entry_for_its_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locales.yml");
+        fs::write(
+            &path,
+            "- source: 'de-*.csv'\n  decimal: ','\n  dates: day-first\n  numbers: [amount]\n  times: [when]\n- numbers: [amount]\n",
+        )
+        .unwrap();
+        let mut locales = Locales::load(&path).unwrap();
+        let mut event = json!({"amount": "1.234,5", "when": "03/04/2024"});
+        locales.apply(&mut event, Some(Path::new("exports/de-1.csv")));
+        assert_eq!(
+            event,
+            json!({"amount": 1234.5, "when": "2024-04-03T00:00:00Z"})
+        );
+        let mut event = json!({"amount": "1.234,5"});
+        locales.apply(&mut event, Some(Path::new("us.csv")));
+        assert_eq!(event, json!({"amount": "1.234,5"}));
+        assert_eq!(locales.unparsed, 1);
+    }
+}
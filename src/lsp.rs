@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    io::{self, stdin, stdout, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+use serde_yaml::Value as Yaml;
+use structopt::StructOpt;
+use tau_engine::Rule;
+
+use crate::optimize::{self, parse};
+use crate::rules::Metadata;
+
+// LSP diagnostic severities
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+
+#[derive(StructOpt)]
+pub struct Lsp {}
+
+/// A diagnostic range covering a whole line, in the UTF-16 columns LSP counts in.
+fn line_range(text: &str, line: usize) -> Value {
+    let end = text
+        .lines()
+        .nth(line)
+        .map(|l| l.encode_utf16().count())
+        .unwrap_or(0);
+    json!({
+        "start": { "line": line, "character": 0 },
+        "end": { "line": line, "character": end },
+    })
+}
+
+/// The line of the first `key:` at or after the line `from`, for putting a diagnostic on the part
+/// of the rule it's about.
+fn find(text: &str, key: &str, from: usize) -> Option<usize> {
+    let prefix = format!("{}:", key);
+    text.lines()
+        .enumerate()
+        .skip(from)
+        .find(|(_, l)| l.trim_start().starts_with(&prefix))
+        .map(|(i, _)| i)
+}
+
+fn diagnostic(text: &str, line: usize, severity: u8, message: String) -> Value {
+    json!({
+        "range": line_range(text, line),
+        "severity": severity,
+        "source": "tau",
+        "message": message,
+    })
+}
+
+/// Every problem found in a rule's text, each placed on the line it's about where that can be
+/// worked out and on the first line otherwise.
+pub fn diagnostics(text: &str) -> Vec<Value> {
+    let rule: Yaml = match serde_yaml::from_str(text) {
+        Ok(rule) => rule,
+        Err(e) => {
+            let (line, character) = e
+                .location()
+                .map(|l| (l.line().saturating_sub(1), l.column().saturating_sub(1)))
+                .unwrap_or((0, 0));
+            return vec![json!({
+                "range": {
+                    "start": { "line": line, "character": character },
+                    "end": { "line": line, "character": character + 1 },
+                },
+                "severity": ERROR,
+                "source": "tau",
+                "message": e.to_string(),
+            })];
+        }
+    };
+    let mut found = Vec::new();
+    if let Err(e) = Metadata::load(text).phase() {
+        let line = find(text, "phase", 0).unwrap_or(0);
+        found.push(diagnostic(text, line, ERROR, e));
+    }
+    let detection_line = find(text, "detection", 0).unwrap_or(0);
+    let detection = match rule.get("detection").and_then(|d| d.as_mapping()) {
+        Some(d) => d,
+        None => {
+            found.push(diagnostic(text, 0, ERROR, "There is no detection".into()));
+            return found;
+        }
+    };
+    let condition_line = find(text, "condition", detection_line).unwrap_or(detection_line);
+    if let Some(condition) = detection.get(&"condition".into()).and_then(|c| c.as_str()) {
+        // Conditions using functions or comparisons aren't parsed, tau-engine still checks them below
+        if let Some(expression) = parse(condition) {
+            let mut used = Vec::new();
+            expression.identifiers(&mut used);
+            for i in used.iter() {
+                if !detection.contains_key(&(*i).into()) {
+                    let message = format!("The identifier {} is not defined", i);
+                    found.push(diagnostic(text, condition_line, ERROR, message));
+                }
+            }
+            for (k, _) in detection.iter() {
+                let k = k.as_str().unwrap_or_default();
+                if k != "condition" && !used.contains(&k) {
+                    let line = find(text, k, detection_line).unwrap_or(detection_line);
+                    let message = format!("The identifier {} is not used by the condition", k);
+                    found.push(diagnostic(text, line, WARNING, message));
+                }
+            }
+        }
+    }
+    let optimized = optimize::text(text);
+    let loaded = match Rule::load(optimized.as_deref().unwrap_or(text)) {
+        Ok(r) => r,
+        Err(e) => {
+            let message = format!("Unable to load as a rule, {}", e);
+            found.push(diagnostic(text, detection_line, ERROR, message));
+            return found;
+        }
+    };
+    for (key, expected) in [("true_positives", true), ("true_negatives", false)] {
+        let tests = match rule.get(key).and_then(|t| t.as_sequence()) {
+            Some(t) => t,
+            None => continue,
+        };
+        let line = find(text, key, 0).unwrap_or(0);
+        for (i, test) in tests.iter().enumerate() {
+            let event: Value = match serde_json::to_value(test) {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            if loaded.matches(&event) != expected {
+                let message = match expected {
+                    true => format!("True positive {} is not matched", i + 1),
+                    false => format!("True negative {} is matched", i + 1),
+                };
+                found.push(diagnostic(text, line, ERROR, message));
+            }
+        }
+    }
+    found
+}
+
+/// Read a message framed with a Content-Length header, `None` at the end of stdin.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(n) = header.strip_prefix("Content-Length:") {
+            length = n.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+impl Lsp {
+    /// Serve the language server protocol on stdin and stdout, publishing diagnostics for each rule
+    /// as it's opened and changed. Only full document syncing is offered, so each change carries the
+    /// whole text.
+    pub fn run(self) -> Result<(), String> {
+        let (stdin, mut stdout) = (stdin(), stdout());
+        let mut reader = stdin.lock();
+        let mut documents: HashMap<String, String> = HashMap::new();
+        let mut shutdown = false;
+        let error = |e: io::Error| format!("Unable to serve the language server, {}", e);
+        while let Some(message) = read_message(&mut reader).map_err(error)? {
+            let method = message["method"].as_str().unwrap_or_default();
+            let params = &message["params"];
+            let uri = params["textDocument"]["uri"]
+                .as_str()
+                .map(|u| u.to_string());
+            let changed = match (method, uri) {
+                ("textDocument/didOpen", Some(uri)) => {
+                    let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                    documents.insert(uri.clone(), text.to_string());
+                    Some(uri)
+                }
+                ("textDocument/didChange", Some(uri)) => {
+                    // With full syncing the last change holds the whole text
+                    let changes = params["contentChanges"].as_array();
+                    if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+                        documents.insert(uri.clone(), text.to_string());
+                    }
+                    Some(uri)
+                }
+                ("textDocument/didClose", Some(uri)) => {
+                    documents.remove(&uri);
+                    let cleared = json!({ "uri": uri, "diagnostics": [] });
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "textDocument/publishDiagnostics",
+                        "params": cleared,
+                    });
+                    write_message(&mut stdout, &notification).map_err(error)?;
+                    None
+                }
+                ("exit", _) => std::process::exit(if shutdown { 0 } else { 1 }),
+                _ => None,
+            };
+            if let Some(uri) = changed {
+                let text = documents.get(&uri).map(|t| t.as_str()).unwrap_or_default();
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": diagnostics(text) },
+                });
+                write_message(&mut stdout, &notification).map_err(error)?;
+            }
+            // Only requests have an id and need a response
+            let id = match message.get("id") {
+                Some(id) => id.clone(),
+                None => continue,
+            };
+            let response = match method {
+                "initialize" => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "result": {
+                        "capabilities": { "textDocumentSync": 1 },
+                        "serverInfo": { "name": "tau-cli", "version": env!("CARGO_PKG_VERSION") },
+                    },
+                }),
+                "shutdown" => {
+                    shutdown = true;
+                    json!({ "jsonrpc": "2.0", "id": id, "result": null })
+                }
+                _ => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("Unknown method {}", method) },
+                }),
+            };
+            write_message(&mut stdout, &response).map_err(error)?;
+        }
+        Ok(())
+    }
+}
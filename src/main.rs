@@ -1,188 +1,1212 @@
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
+    ffi::OsString,
     fs,
-    io::{self, prelude::*, stderr, stdin, stdout, BufRead, Stdin, Stdout},
-    path::PathBuf,
+    io::{self, prelude::*, stderr, stdout},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
 use tau_engine::Rule;
 
-type ValidatedRules = Vec<(Option<Rule>, String)>;
+mod artifacts;
+mod batch;
+mod cache;
+mod chunked;
+mod compare;
+mod compute;
+mod control;
+mod convert;
+mod dedupe;
+mod docker;
+mod docs;
+mod enrich;
+mod environment;
+mod error;
+mod evaluate;
+mod event;
+mod evtx;
+mod explain;
+mod filter;
+mod fuzz;
+mod generate;
+mod group;
+mod index;
+mod input;
+mod ioc_index;
+mod iocs;
+mod ledger;
+mod lenient;
+mod limits;
+mod locale;
+mod lsp;
+mod merge;
+mod mft;
+mod multiline;
+mod mutate;
+mod new_rule;
+mod normalize;
+mod optimize;
+mod output;
+mod paths;
+mod pcap;
+mod pipeline;
+mod proc_events;
+mod profile;
+mod progress;
+mod reghive;
+mod retention;
+mod review;
+mod route;
+mod rules;
+mod sarif;
+mod schedule;
+mod schema;
+mod secrets;
+mod serve;
+mod shard;
+mod shrink;
+mod siem;
+mod suggest;
+mod timechart;
+mod triage;
+mod update;
+mod uring;
+mod validate;
+mod version;
+mod watch;
+mod whatif;
+
+use cache::Cache;
+use compute::{Capture, Computation};
+use control::Control;
+use enrich::{ContextEnricher, DnsEnricher, Enricher, HashEnricher};
+use error::{CliError, ErrorsFormat};
+use filter::Filter;
+use group::{Agg, Groups};
+use index::Index;
+use input::{Format, Input, LongLine, OnProcessed, Oversize};
+use ioc_index::IndexedRule;
+use iocs::IocSet;
+use ledger::Ledger;
+use limits::Limits;
+use locale::Locales;
+use normalize::Normalization;
+use output::{Encryption, FileOptions, Output, OutputFormat, Owner, SharedOutput, Sink};
+use pipeline::{Depths, Writer};
+use progress::Progress;
+use retention::Retention;
+use route::Router;
+use rules::{Claim, Level, Matcher, Metadata, NameBy, ValidatedRule};
+use sarif::Sarif;
+use schedule::Scheduler;
+use schema::Schema;
+use secrets::Secret;
+use serve::AuditServer;
+use shard::Shard;
+use siem::FieldMapping;
+use timechart::{ChartFormat, Timechart};
+use watch::{DirWatcher, RuleWatcher};
+
+type ValidatedRules = Vec<ValidatedRule>;
 
 #[derive(StructOpt)]
 #[structopt(
     name = "tau-cli",
     about = "A CLI for matching rules against JSON using the Tau Engine."
 )]
+struct Cli {
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt)]
 struct Opt {
-    /// Glob matching one or more Rule files. Rules must be '.yml' files.
+    /// Glob matching one or more Rule files, or directories of them. Rules must be '.yml' files.
     #[structopt(short, long, parse(from_os_str))]
     rules: Vec<PathBuf>,
 
-    /// Glob matching one or more files, to be used as the input files.
+    /// Glob matching one or more files, to be used as the input files, or 'docker://' or 'proc://'.
     #[structopt(short, long, parse(from_os_str))]
     input: Option<Vec<PathBuf>>,
 
+    /// The format of the input files: json, concatenated, evtx, triage, pcap, reghive, mft, bodyfile, prefetch, shimcache or amcache.
+    #[structopt(long, default_value = "json")]
+    input_format: Format,
+
+    /// Read plain text logs where a line matching this regex starts a new event, e.g. '^\d{4}-\d{2}-\d{2} '.
+    #[structopt(long, conflicts_with_all = &["cache-dir", "use-index", "shard-lines", "threads", "io-uring", "pipeline", "max-count-per-file", "per-file-stats", "schedule", "watch-input", "serve-k8s-audit"])]
+    multiline_start: Option<String>,
+
+    /// The longest input line read in bytes, longer lines are handled by --oversize-lines without holding more than this much of them in memory.
+    #[structopt(long, conflicts_with_all = &["threads", "io-uring", "pipeline", "multiline-start"])]
+    max_line_bytes: Option<usize>,
+
+    /// What to do with lines over --max-line-bytes, 'skip', 'truncate' or write them to the 'dead-letter' file.
+    #[structopt(long, default_value = "skip")]
+    oversize_lines: Oversize,
+
+    /// Repair malformed JSON lines, such as trailing commas or several events on one line, rather than report them.
+    #[structopt(long)]
+    lenient_json: bool,
+
+    /// Wait at the end of each JSON input file until it hasn't grown for this many seconds.
+    #[structopt(long, requires = "input", conflicts_with_all = &["cache-dir", "use-index", "shard-lines", "threads", "io-uring", "pipeline", "multiline-start"])]
+    read_until_eof_stable: Option<u64>,
+
+    /// Directory to cache parsed input files in, so repeated runs over the same files skip parsing them.
+    #[structopt(long, parse(from_os_str))]
+    cache_dir: Option<PathBuf>,
+
+    /// The number of most recently used input files kept in the cache.
+    #[structopt(long, default_value = "64")]
+    cache_max_files: usize,
+
+    /// Index built by the index subcommand, used to skip blocks of the input files which can't match any rule.
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["cache-dir", "passthrough"])]
+    use_index: Option<PathBuf>,
+
     /// Overwrite the output files.
     #[structopt(short = "f", long)]
     overwrite: bool,
 
-    /// Overwrite the output files.
+    /// Print whether each rule loads and passes its tests, then exit without matching. The validate and test subcommands report more about each rule.
     #[structopt(short, long)]
     validate: bool,
 
+    /// Print the rules which would run, in the order they run, and exit without opening any input or output.
+    #[structopt(long, conflicts_with_all = &["validate", "dry-run"])]
+    list_rules: bool,
+
+    /// Load and validate everything and check the outputs, then print what would run without reading any events.
+    #[structopt(long, conflicts_with = "validate")]
+    dry_run: bool,
+
     /// Path to write all matches, if path points to a directory then matches are written to files named after the associated rules.
     #[structopt(short, long, parse(from_os_str))]
     output: Option<PathBuf>,
 
+    /// How matches are written: 'json' (a line each), 'sarif' (one log at the end), 'cef' or 'leef' (a SIEM line each)
+    #[structopt(long, default_value = "json")]
+    output_format: OutputFormat,
+
+    /// Comma separated CEF or LEEF keys and the fields they're taken from, e.g. 'src=SourceIp,suser=User'.
+    #[structopt(long, use_delimiter = true)]
+    output_fields: Vec<FieldMapping>,
+
+    /// Encrypt the output files, e.g. 'age:<recipient>'.
+    #[structopt(long)]
+    encrypt_output: Option<Encryption>,
+
+    /// Share output files between processes, 'lock' to append under an advisory lock or 'suffix' for a file per process.
+    #[structopt(long, requires = "output")]
+    shared_output: Option<SharedOutput>,
+
+    /// Remove rotated copies of the output files once they are older than this, e.g. '14d'.
+    #[structopt(long, requires = "output")]
+    retain: Option<humantime::Duration>,
+
+    /// Remove the oldest rotated copies of the output files while together they take up more than this, e.g. '10GB'.
+    #[structopt(long, requires = "output")]
+    retain_size: Option<retention::Size>,
+
+    /// Octal permissions applied to the output files, e.g. '0600'.
+    #[structopt(long, parse(try_from_str = output::parse_mode))]
+    output_mode: Option<u32>,
+
+    /// Owner applied to the output files as 'UID[:GID]', this usually requires running as root.
+    #[structopt(long)]
+    output_owner: Option<Owner>,
+
+    /// Extension used for the per rule output files instead of the rule's own, e.g. 'jsonl'.
+    #[structopt(long)]
+    output_extension: Option<String>,
+
+    /// Path to a YAML config routing matches to destinations based on the level and tags of the rule that matched.
+    #[structopt(long, parse(from_os_str))]
+    route: Option<PathBuf>,
+
+    /// A file holding the token sent to webhook routes as a bearer token, read from TAU_SINK_TOKEN when neither this nor --secret-cmd is given.
+    #[structopt(long, parse(from_os_str), requires = "route")]
+    token_file: Option<PathBuf>,
+
+    /// A shell command writing the token sent to webhook routes to stdout, e.g. reading it from a secret store.
+    #[structopt(long, requires = "route", conflicts_with = "token-file")]
+    secret_cmd: Option<String>,
+
+    /// Only output matches from rules at or above this level, rules without a level are always output. All rules are still evaluated and counted.
+    #[structopt(long)]
+    emit_min_level: Option<Level>,
+
+    /// Only load the rules whose metadata passes this expression, e.g. 'level >= high && author != "legacy"'.
+    #[structopt(long)]
+    filter_rules: Option<Filter>,
+
+    /// Optimize rules as they're loaded, as the optimize subcommand does, keeping a rule as written when its optimized form doesn't validate.
+    #[structopt(long)]
+    optimize_rules: bool,
+
+    /// Output each matching event once with the names of the rules it matched in a 'tau.tags' array, instead of once per matching rule.
+    #[structopt(long, conflicts_with = "route")]
+    tag_only: bool,
+
+    /// Output every input event in order, matching events are tagged as with --tag-only.
+    #[structopt(long, conflicts_with = "route")]
+    passthrough: bool,
+
+    /// Add the matching rule's detection logic, in pseudo-English, to each match in a 'tau.explanation' field.
+    #[structopt(long, conflicts_with_all = &["tag-only", "passthrough", "extract"])]
+    explain: bool,
+
+    /// Add the matching rule's path, pack and git commit to each match in a 'tau.origin' field.
+    #[structopt(long, conflicts_with_all = &["tag-only", "passthrough", "extract"])]
+    stamp_origin: bool,
+
+    /// Output each match in an envelope with the rule's name, level and tags and a timestamp.
+    #[structopt(long, conflicts_with_all = &["tag-only", "passthrough", "extract"])]
+    wrap: bool,
+
+    /// Instead of the matching events, output the distinct values of this field across them with a count of each.
+    #[structopt(long, conflicts_with_all = &["route", "tag-only", "passthrough"])]
+    extract: Option<String>,
+
+    /// Instead of the matching events, output a row for each distinct value of these comma separated fields.
+    #[structopt(long, use_delimiter = true, conflicts_with_all = &["route", "tag-only", "passthrough", "extract", "explain"])]
+    group_by: Option<Vec<String>>,
+
+    /// Comma separated aggregates for each --group-by row: count, first_seen, last_seen or rules.
+    #[structopt(long, use_delimiter = true, default_value = "count")]
+    agg: Vec<Agg>,
+
+    /// Instead of the matching events, output the N most common values of the --by field across them with a count for each rule.
+    #[structopt(long, requires = "by", conflicts_with_all = &["route", "tag-only", "passthrough", "extract", "group-by", "explain"])]
+    top: Option<usize>,
+
+    /// The field --top counts the values of.
+    #[structopt(long, requires = "top")]
+    by: Option<String>,
+
+    /// The field holding each event's time, as an RFC 3339 timestamp or seconds since the unix epoch.
+    #[structopt(long, alias = "timestamp-field")]
+    time_field: Option<String>,
+
+    /// Instead of the matching events, count each rule's matches in time buckets this wide, e.g. '1h'.
+    #[structopt(long, requires = "time-field", conflicts_with_all = &["output", "route", "tag-only", "passthrough", "extract", "group-by", "top", "explain"])]
+    timechart: Option<humantime::Duration>,
+
+    /// How to write the --timechart table, 'csv', 'json' lines or a 'sparkline' for each rule.
+    #[structopt(long, default_value = "csv")]
+    timechart_format: ChartFormat,
+
+    /// Print the number of matches per rule to stderr once all input has been processed.
+    #[structopt(long)]
+    stats: bool,
+
+    /// How errors stopping tau-cli from starting are written to stderr, 'text' or 'json'.
+    #[structopt(long, default_value = "text")]
+    errors_format: ErrorsFormat,
+
+    /// Stop reading input once this many events have matched, e.g. 1 for a yes or no answer over a large archive.
+    #[structopt(short = "m", long, conflicts_with_all = &["schedule", "watch-input", "serve-k8s-audit"])]
+    max_count: Option<u64>,
+
+    /// Apply --max-count to each JSON input file rather than the whole input, moving on to the next file once it's reached.
+    #[structopt(long, requires = "max-count", conflicts_with_all = &["threads", "io-uring", "pipeline", "fail-fast"])]
+    max_count_per_file: bool,
+
+    /// Stop at the first match, or after --max-count matches, and exit with status 2 if anything matched.
+    #[structopt(long, conflicts_with_all = &["schedule", "watch-input", "serve-k8s-audit"])]
+    fail_fast: bool,
+
+    /// Print the events, parse errors and matches of each JSON input file to stderr.
+    #[structopt(long, requires = "input", conflicts_with_all = &["threads", "io-uring", "pipeline", "schedule", "watch-input", "serve-k8s-audit"])]
+    per_file_stats: bool,
+
+    /// CSV files of indicators to match alongside the rules, using the 'Value' column or the first column. Matches are named after the file.
+    #[structopt(long, parse(from_os_str), requires = "ioc-fields")]
+    ioc_file: Vec<PathBuf>,
+
+    /// Comma separated fields compared against the indicators, e.g. 'dst_ip,domain,sha256'.
+    #[structopt(long, use_delimiter = true)]
+    ioc_fields: Vec<String>,
+
+    /// Index long lists of literal values in rules for lookup, 'exact' or 'bloom' to check a bloom filter first.
+    #[structopt(long)]
+    ioc_index: Option<ioc_index::Mode>,
+
+    /// The fewest values a list must have to be indexed by --ioc-index.
+    #[structopt(long, default_value = "1000")]
+    ioc_index_min_values: usize,
+
+    /// The rate the --ioc-index bloom filter passes values not in a list on to be looked up.
+    #[structopt(long, default_value = "0.001")]
+    ioc_index_false_positive_rate: f64,
+
+    /// Comma separated fields holding file hashes to look up for matching events, verdicts are added to 'tau.hashes'.
+    #[structopt(long, use_delimiter = true)]
+    enrich_hashes: Vec<String>,
+
+    /// CSV of known hashes with an optional verdict in the second column, used by --enrich-hashes.
+    #[structopt(long, parse(from_os_str), requires = "enrich-hashes")]
+    hash_set: Option<PathBuf>,
+
+    /// Look up hashes missing from --hash-set with VirusTotal in the background, they're 'pending' until looked up. Reads the key from VT_API_KEY.
+    #[structopt(long, requires = "enrich-hashes")]
+    virustotal: bool,
+
+    /// The number of VirusTotal lookups allowed per minute.
+    #[structopt(long, default_value = "4")]
+    virustotal_rate: u32,
+
+    /// Comma separated fields holding IPs or domains to resolve for matching events, results are added to 'tau.dns'.
+    #[structopt(long, use_delimiter = true)]
+    enrich_dns: Vec<String>,
+
+    /// Milliseconds to wait for each DNS lookup.
+    #[structopt(long, default_value = "2000")]
+    dns_timeout: u64,
+
+    /// An inventory of assets as JSON, joined onto matches by --context-key and added to 'tau.context'.
+    #[structopt(long, parse(from_os_str), requires = "context-key")]
+    context: Option<PathBuf>,
+
+    /// The field the inventory is joined on, e.g. 'hostname', compared without case.
+    #[structopt(long, requires = "context")]
+    context_key: Option<String>,
+
+    /// How often the inventory is checked for changes, e.g. '30s'.
+    #[structopt(long, default_value = "1m")]
+    context_reload: humantime::Duration,
+
+    /// A YAML file of how each source writes numbers and dates, e.g. '1.234,5' and '03/04/2024'.
+    #[structopt(long, parse(from_os_str))]
+    locales: Option<PathBuf>,
+
+    /// Comma separated normalizations (numbers, paths, base64, url) written to a '_decoded' shadow field.
+    #[structopt(long, use_delimiter = true)]
+    normalize: Vec<Normalization>,
+
+    /// Add a deobfuscated copy of these comma separated command line fields, e.g. 'CommandLine_normalized'.
+    #[structopt(long, use_delimiter = true, min_values = 0)]
+    deobfuscate: Option<Vec<String>>,
+
+    /// Comma separated features to compute before matching, e.g. 'entropy(ServiceName)' or 'length(Url)'.
+    #[structopt(long, use_delimiter = true)]
+    compute: Vec<Computation>,
+
+    /// Extract a regex's named groups from a field as field:regex, e.g. 'Url:\.(?P<extension>\w+)$'.
+    #[structopt(long, number_of_values = 1)]
+    capture: Vec<Capture>,
+
+    /// Convert numeric and 'true'/'false' strings in these comma separated fields, or every field, before matching.
+    #[structopt(long, use_delimiter = true, min_values = 0)]
+    coerce_types: Option<Vec<String>>,
+
+    /// Comma separated fields whose string values are lowercased before matching, matching events are output lowercased.
+    #[structopt(long, use_delimiter = true)]
+    lowercase_fields: Vec<String>,
+
+    /// Lowercase every string value before matching, matching events are output lowercased.
+    #[structopt(long)]
+    case_insensitive_values: bool,
+
+    /// Previous match outputs, matches identical to one of these are not output again.
+    #[structopt(long, parse(from_os_str))]
+    baseline: Vec<PathBuf>,
+
+    /// Comma separated fields identifying a match when comparing with --baseline, the whole event is used when none are given.
+    #[structopt(long, use_delimiter = true)]
+    baseline_key: Vec<String>,
+
+    /// Name rules in their output files, tags and stats by their id, title or file name. Rules without a title are named by their id.
+    #[structopt(long, default_value = "id")]
+    name_by: NameBy,
+
+    /// Comma separated fields identifying a match, so matches already in the ledger aren't output again.
+    #[structopt(long, use_delimiter = true, conflicts_with_all = &["extract", "group-by", "top"])]
+    idempotency_key: Option<Vec<String>>,
+
+    /// The ledger for --idempotency-key, by default the output file with a .ledger extension.
+    #[structopt(long, parse(from_os_str), requires = "idempotency-key")]
+    ledger: Option<PathBuf>,
+
+    /// JSON Schema events are validated against before matching, invalid events are not matched.
+    #[structopt(long, parse(from_os_str))]
+    schema: Option<PathBuf>,
+
+    /// Only match events passing this expression over their fields as read, e.g. 'Channel == "Security"'.
+    #[structopt(long)]
+    prefilter_expr: Option<Filter>,
+
+    /// Read ahead in smaller batches with --threads or --pipeline while memory use is over this many MiB. Requires Linux.
+    #[structopt(long)]
+    max_memory: Option<u64>,
+
+    /// Run at this scheduling priority, from -20 to 19 as with the nice command.
+    #[structopt(long, allow_hyphen_values = true)]
+    nice: Option<i32>,
+
+    /// Sleep between events to keep CPU use under this percentage of one core, e.g. 50 or 200.
+    #[structopt(long)]
+    cpu_limit: Option<f64>,
+
+    /// Disable a rule once it takes longer than this many milliseconds on several events in a row.
+    #[structopt(long)]
+    rule_timeout: Option<u64>,
+
+    /// Path to write a CSV trace of every rule evaluated against every event, with the result and time taken.
+    #[structopt(long, parse(from_os_str))]
+    trace: Option<PathBuf>,
+
+    /// Path to write events which could not be matched to, such as those failing --schema validation.
+    #[structopt(long, parse(from_os_str))]
+    dead_letter: Option<PathBuf>,
+
+    /// Path to write progress to as JSON lines, usually a descriptor such as fd:4.
+    #[structopt(long, parse(from_os_str))]
+    progress: Option<PathBuf>,
+
+    /// Seconds between progress lines, which are written even when nothing has changed as a heartbeat.
+    #[structopt(long, default_value = "1")]
+    progress_interval: u64,
+
+    /// Keep running, re-scanning the inputs for new lines on this cron schedule, e.g. '*/15 * * * *'.
+    #[structopt(long, requires = "input", conflicts_with_all = &["cache-dir", "use-index", "encrypt-output", "extract", "validate"])]
+    schedule: Option<String>,
+
+    /// Path to save how far each input has been read, so a restarted --schedule carries on where it stopped.
+    #[structopt(long, parse(from_os_str), requires = "schedule")]
+    schedule_state: Option<PathBuf>,
+
+    /// Reload rule files when they change, keeping the previous version of any which no longer load.
+    #[structopt(long, conflicts_with_all = &["validate", "list-rules", "use-index", "filter-rules"])]
+    watch_rules: bool,
+
+    /// Keep running, matching files as they are dropped into this directory. Each file is read once and matches are appended to the outputs.
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["input", "schedule", "cache-dir", "use-index", "encrypt-output", "extract", "validate"])]
+    watch_input: Option<PathBuf>,
+
+    /// What to do with input files once matched, 'move', 'move:<dir>', 'delete' or 'mark'.
+    #[structopt(long, conflicts_with = "schedule")]
+    on_processed: Option<OnProcessed>,
+
+    /// Keep running, serving the Kubernetes audit webhook on this address, e.g. '0.0.0.0:8080'.
+    #[structopt(long, conflicts_with_all = &["input", "schedule", "watch-input", "on-processed", "cache-dir", "use-index", "encrypt-output", "extract", "validate"])]
+    serve_k8s_audit: Option<String>,
+
+    /// Only process the Nth of M shards of the input files, e.g. '3/8'.
+    #[structopt(long, requires = "input", conflicts_with_all = &["schedule", "watch-input", "serve-k8s-audit"])]
+    shard: Option<Shard>,
+
+    /// Split every input file into line ranges between the shards, rather than sharing out whole files.
+    #[structopt(long, requires = "shard", conflicts_with_all = &["use-index", "on-processed"])]
+    shard_lines: bool,
+
+    /// Read and parse JSON input files on this many threads, split into chunks, 0 for one per core. Compressed files are rejected.
+    #[structopt(long, requires = "input", conflicts_with_all = &["cache-dir", "use-index", "shard-lines", "schedule", "watch-input", "serve-k8s-audit"])]
+    threads: Option<usize>,
+
+    /// Read JSON input files with io_uring, on Linux with the uring feature. Compressed files are rejected.
+    #[structopt(long, requires = "input", conflicts_with_all = &["threads", "cache-dir", "use-index", "shard-lines", "schedule", "watch-input", "serve-k8s-audit"])]
+    io_uring: bool,
+
+    /// Run reading, parsing, matching and writing as stages joined by channels of this depth, or 'read,parse,write'.
+    #[structopt(long, conflicts_with_all = &["threads", "io-uring", "cache-dir", "use-index", "shard-lines"])]
+    pipeline: Option<Depths>,
+
+    /// Run as a child process driven by JSON-RPC over stdio, for editors and other frontends.
+    #[structopt(long, conflicts_with_all = &["input", "output", "validate", "list-rules", "dry-run", "schedule", "watch-input", "serve-k8s-audit"])]
+    control: Option<Control>,
+
     #[structopt(skip)]
     inner_input: Option<Input>,
     #[structopt(skip)]
     inner_output: Option<Output>,
+    #[structopt(skip)]
+    inner_router: Option<Router>,
+    #[structopt(skip)]
+    inner_sarif: Option<Sarif>,
+    #[structopt(skip)]
+    inner_writer: Option<Writer>,
+    #[structopt(skip)]
+    inner_limits: Option<Limits>,
+    #[structopt(skip)]
+    inner_enrichers: Vec<Enricher>,
+    #[structopt(skip)]
+    inner_schema: Option<Schema>,
+    #[structopt(skip)]
+    inner_locales: Option<Locales>,
+    #[structopt(skip)]
+    inner_baseline: Option<HashSet<String>>,
+    #[structopt(skip)]
+    inner_ledger: Option<Ledger>,
+    #[structopt(skip)]
+    inner_dead_letter: Option<Sink>,
+    #[structopt(skip)]
+    inner_retention: Option<Retention>,
+    #[structopt(skip)]
+    inner_trace: Option<io::BufWriter<Sink>>,
+    #[structopt(skip)]
+    inner_progress: Option<Progress>,
+    #[structopt(skip)]
+    inner_scheduler: Option<Scheduler>,
+    #[structopt(skip)]
+    inner_watcher: Option<DirWatcher>,
+    #[structopt(skip)]
+    inner_rule_watcher: Option<RuleWatcher>,
+    #[structopt(skip)]
+    inner_server: Option<AuditServer>,
+    #[structopt(skip)]
+    inner_groups: Option<Groups>,
+    #[structopt(skip)]
+    inner_origins: rules::Origins,
 }
 
-enum Input {
-    CommandLine(Stdin),
-    Files {
-        paths: Vec<PathBuf>,
-        buffer: io::BufReader<fs::File>,
-    },
+/// Evaluations in a row over --rule-timeout before a rule is disabled.
+const RULE_TIMEOUT_TRIPS: u32 = 3;
+
+/// The exit status of a --fail-fast run which matched, apart from the 1 of an error.
+const MATCHED: i32 = 2;
+
+/// Counts kept across all of the input, reported once it has been processed.
+#[derive(Default)]
+struct Totals {
+    events: u64,
+    counts: Vec<usize>,
+    /// Evaluations in a row which took longer than --rule-timeout.
+    overruns: Vec<u32>,
+    /// The event each rule was disabled at by --rule-timeout.
+    disabled: Vec<Option<u64>>,
+    /// Events which matched a rule, after --emit-min-level and the baseline, for --max-count.
+    hits: u64,
+    /// The input file being read and its hits, for --max-count-per-file.
+    file_hits: (usize, u64),
+    extracted: Vec<(serde_json::Value, usize)>,
+    extracted_index: HashMap<String, usize>,
+    groups: Option<Groups>,
+    timechart: Option<Timechart>,
+    /// Counts for each input file in the order they were read, for --per-file-stats.
+    files: Vec<FileTotals>,
+    /// Lines longer than --max-line-bytes.
+    oversize: u64,
+    /// Events which didn't pass --prefilter-expr.
+    prefiltered: u64,
 }
 
-impl Iterator for Input {
-    type Item = Result<serde_json::Value, Box<dyn Error>>;
-    fn next(&mut self) -> Option<Self::Item> {
+struct FileTotals {
+    path: PathBuf,
+    events: u64,
+    errors: u64,
+    counts: Vec<usize>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Match rules against events, as when no subcommand is given.
+    Match(Box<Opt>),
+    /// Check that rules load, reporting why those which don't are invalid.
+    Validate(validate::Validate),
+    /// Run each rule's true positives and negatives, reporting the tests which fail.
+    Test(validate::Test),
+    /// Extract indicators (IPs, domains, hashes and URLs) from matched events.
+    ExtractIocs(iocs::ExtractIocs),
+    /// Build an index of the field values in a corpus, for use with --use-index.
+    Index(index::BuildIndex),
+    /// Report the fields in a sample of events, how often they are present, their types and most common values.
+    Profile(profile::Profile),
+    /// Compare two match outputs, reporting the matches added and removed per rule.
+    Compare(compare::Compare),
+    /// Generate events from a template, for load testing, optionally matching them against rules.
+    Generate(generate::Generate),
+    /// Mutate sample events and report the mutations which change whether rules match.
+    Fuzz(fuzz::Fuzz),
+    /// Mutate rule conditions and report whether their tests and a labelled corpus catch the change.
+    Mutate(mutate::Mutate),
+    /// Match rules against a labelled corpus, reporting each rule's precision and recall.
+    Evaluate(evaluate::Evaluate),
+    /// Run many jobs of rules, inputs and outputs from a YAML file on a pool of workers, summarising each.
+    Batch(batch::Batch),
+    /// Write a skeleton rule matching fields of an example event, with the event as its test, prompting for anything not given.
+    NewRule(new_rule::NewRule),
+    /// Suggest field values which set example events apart from benign ones, as a starting point for a rule.
+    Suggest(suggest::Suggest),
+    /// Rewrite rules into a simpler form which matches the same events, showing the difference.
+    Optimize(optimize::Optimize),
+    /// Describe each rule's detection logic in indented pseudo-English.
+    Explain(explain::Explain),
+    /// Convert rules to another format, such as Sigma, warning about anything which doesn't carry over.
+    Convert(convert::Convert),
+    /// Find rules which match the same events, or only events another rule also matches.
+    Dedupe(dedupe::Dedupe),
+    /// Walk through matches in the terminal recording a verdict on each, and draft suppression rules for the false positives.
+    Review(review::Review),
+    /// Serve the language server protocol on stdin and stdout, so editors can show a rule's problems as it's written.
+    Lsp(lsp::Lsp),
+    /// Shrink the events of a corpus a rule matches to the smallest event it still matches, as a compact test or to show what the rule depends on.
+    Shrink(shrink::Shrink),
+    /// Override fields of events and report the rules whose verdict changes, e.g. whether a rule still fires if a binary is renamed.
+    Whatif(whatif::WhatIf),
+    /// Combine match outputs, such as those of a sharded run, optionally removing duplicates and sorting them.
+    Merge(merge::Merge),
+    /// Replace this binary with the latest signed release, for hosts without a package manager. Requires tau-cli built with the self-update feature.
+    SelfUpdate(update::SelfUpdate),
+    /// Report the version of tau-cli and tau-engine, the features built in, and the commit and target it was built from.
+    Version(version::Version),
+    /// Write a reference of every option, subcommand and environment variable, as a man page or Markdown.
+    Docs(docs::Docs),
+}
+
+impl Command {
+    fn run(self) -> Result<(), String> {
         match self {
-            Input::CommandLine(stdin) => match stdin.lock().lines().next() {
-                Some(Ok(l)) => Some(serde_json::from_str(&l).map_err(|e| e.into())),
-                Some(Err(e)) => Some(Err(e.into())),
-                None => None,
+            Command::Match(opt) => match_events(*opt).map_err(|e| e.to_string()),
+            Command::Validate(c) => c.run(),
+            Command::Test(c) => c.run(),
+            Command::ExtractIocs(c) => c.run(),
+            Command::Index(c) => c.run(),
+            Command::Profile(c) => c.run(),
+            Command::Compare(c) => c.run(),
+            Command::Generate(c) => c.run(),
+            Command::Fuzz(c) => c.run(),
+            Command::Mutate(c) => c.run(),
+            Command::Evaluate(c) => c.run(),
+            Command::Batch(c) => c.run(),
+            Command::NewRule(c) => c.run(),
+            Command::Suggest(c) => c.run(),
+            Command::Optimize(c) => c.run(),
+            Command::Explain(c) => c.run(),
+            Command::Convert(c) => c.run(),
+            Command::Dedupe(c) => c.run(),
+            Command::Review(c) => c.run(),
+            Command::Lsp(c) => c.run(),
+            Command::Shrink(c) => c.run(),
+            Command::Whatif(c) => c.run(),
+            Command::Merge(c) => c.run(),
+            Command::SelfUpdate(c) => c.run(),
+            Command::Version(c) => c.run(),
+            Command::Docs(c) => c.run(),
+        }
+    }
+}
+
+impl Opt {
+    /// Expand the globs given to --rules and --input, an error names the glob which failed.
+    fn expand_paths(&mut self) -> Result<(), CliError> {
+        let mut rules = Vec::new();
+        for path in self.rules.iter() {
+            let expanded = paths::expand(std::slice::from_ref(path), Some(paths::RULE_FILES));
+            rules.extend(expanded.map_err(|e| CliError::rule(path, e))?);
+        }
+        self.rules = rules;
+        if let Some(input) = self.input.as_mut() {
+            let mut inputs = Vec::new();
+            for path in input.iter() {
+                let expanded = paths::expand(std::slice::from_ref(path), None);
+                inputs.extend(expanded.map_err(|e| CliError::io(Some(path), e))?);
+            }
+            *input = inputs;
+        }
+        Ok(())
+    }
+
+    /// How lines of JSON input are read.
+    fn read_options(&self) -> input::Options {
+        input::Options {
+            lenient: self.lenient_json,
+            max_line_bytes: self.max_line_bytes.unwrap_or(0),
+            eof_stable: Duration::from_secs(self.read_until_eof_stable.unwrap_or(0)),
+        }
+    }
+
+    /// Load a rule from its text, as read from `path`. A rule which doesn't validate is kept, to be
+    /// reported, but never matches.
+    fn load_rule(&self, path: &Path, data: &str) -> Result<ValidatedRule, CliError> {
+        let optimized = match self.optimize_rules {
+            true => optimize::text(data),
+            false => None,
+        };
+        let load = |text: &str| {
+            Rule::load(text)
+                .ok()
+                .filter(|r| r.validate().unwrap_or(false))
+        };
+        let (text, rule) = match optimized.as_deref().and_then(|t| Some((t, load(t)?))) {
+            Some((text, rule)) => (text, Some(rule)),
+            None => (data, load(data)),
+        };
+        let rule = match rule {
+            // Only a rule which validates is indexed
+            Some(r) => match self.ioc_index.and_then(|mode| {
+                let settings = ioc_index::Settings {
+                    mode,
+                    min_values: self.ioc_index_min_values,
+                    false_positive_rate: self.ioc_index_false_positive_rate,
+                };
+                IndexedRule::load(text, settings)
+            }) {
+                Some(indexed) => Some(Matcher::Indexed(indexed)),
+                None => Some(Matcher::Rule(r)),
             },
-            Input::Files {
-                ref mut paths,
-                ref mut buffer,
-            } => {
-                // Try read from buffer
-                let mut line = String::new();
-                match buffer.read_line(&mut line) {
-                    Err(_) | Ok(0) => {
-                        match paths.pop() {
-                            Some(p) => {
-                                // Create a BufReader
-                                match fs::OpenOptions::new().read(true).open(p) {
-                                    Ok(f) => *buffer = io::BufReader::new(f),
-                                    Err(e) => return Some(Err(e.into())),
-                                }
-                                self.next()
-                            }
-                            None => None,
-                        }
-                    }
-                    Ok(_) => Some(serde_json::from_str(line.trim_end()).map_err(|e| e.into())),
+            None => None,
+        };
+        let metadata = Metadata::load(data);
+        let explanation = match self.explain {
+            true => explain::text(data),
+            false => None,
+        };
+        let origin = match self.stamp_origin {
+            true => Some(self.inner_origins.origin(path)),
+            false => None,
+        };
+        let phase = metadata
+            .phase()
+            .map_err(|e| CliError::rule(path, format!("{} in {}", e, path.display())))?;
+        let id = rules::id(path, &metadata).map_err(|e| CliError::rule(path, e))?;
+        let name =
+            rules::name(path, &metadata, self.name_by).map_err(|e| CliError::rule(path, e))?;
+        Ok(ValidatedRule {
+            rule,
+            id,
+            name,
+            path: path.to_path_buf(),
+            metadata,
+            phase,
+            explanation,
+            origin,
+        })
+    }
+
+    /// Swap in the rules changed since the last event for --watch-rules, keeping the previous
+    /// version of any which no longer loads or which would need restarting to change.
+    fn reload_rules(&mut self, rules: &mut ValidatedRules, totals: &mut Totals) -> io::Result<()> {
+        let changed = match self.inner_rule_watcher.as_mut() {
+            Some(watcher) => watcher.changed(),
+            None => return Ok(()),
+        };
+        let mut stderr = stderr();
+        // A changed rule may have been committed since it was last read
+        if !changed.is_empty() {
+            self.inner_origins.clear();
+        }
+        for path in changed {
+            let i = match rules.iter().position(|r| r.path == path) {
+                Some(i) => i,
+                None => continue,
+            };
+            let reloaded = fs::read_to_string(&path)
+                .map_err(|_| {
+                    CliError::rule(
+                        &path,
+                        format!("Unable to read data from {}.", path.display()),
+                    )
+                })
+                .and_then(|data| self.load_rule(&path, &data));
+            let old = &rules[i];
+            match reloaded {
+                Ok(rule) if rule.rule.is_none() => writeln!(
+                    stderr,
+                    "{} failed to validate, its previous version is still matching",
+                    path.display()
+                )?,
+                Ok(rule) if rule.id != old.id || rule.name != old.name || rule.phase != old.phase => {
+                    writeln!(
+                        stderr,
+                        "{} changed its id, name or phase which needs a restart, its previous version is still matching",
+                        path.display()
+                    )?
+                }
+                Ok(rule) => {
+                    rules[i] = rule;
+                    // The new version is timed afresh against --rule-timeout
+                    totals.overruns[i] = 0;
+                    totals.disabled[i] = None;
+                    writeln!(stderr, "Reloaded {}", path.display())?;
                 }
+                Err(e) => writeln!(stderr, "{}, its previous version is still matching", e)?,
             }
         }
+        Ok(())
     }
-}
 
-enum Output {
-    CommandLine(Stdout),
-    Files(Vec<(fs::File, String)>),
-}
-
-impl Opt {
-    pub fn validate_rules(mut self) -> Result<(Self, ValidatedRules), String> {
+    pub fn validate_rules(mut self) -> Result<(Self, ValidatedRules), CliError> {
         //
+        if !(self.ioc_index_false_positive_rate > 0.0 && self.ioc_index_false_positive_rate < 1.0) {
+            return Err(CliError::config(
+                "--ioc-index-false-positive-rate must be between 0 and 1",
+            ));
+        }
+        if let Some(n) = self.nice {
+            limits::nice(n).map_err(CliError::config)?;
+        }
+        let batched = matches!(self.input_format, Format::Json)
+            && (self.pipeline.is_some() || (self.threads.is_some() && !self.io_uring));
+        if self.max_memory.is_some() && !batched {
+            return Err(CliError::config("--max-memory shrinks the batches JSON input is read ahead in with --threads or --pipeline, it does nothing without them"));
+        }
+        if self.max_memory.is_some() || self.cpu_limit.is_some() {
+            self.inner_limits =
+                Some(Limits::new(self.max_memory, self.cpu_limit).map_err(CliError::config)?);
+        }
         let mut validated_rules = Vec::new();
+        let mut required_terms = Vec::new();
+        let (mut taken, mut taken_names) = (HashMap::new(), HashMap::new());
         for path in self.rules.iter() {
-            let rule = match Rule::load(
-                &fs::read_to_string(&path)
-                    .map_err(|_| format!("Unable to read data from {}.", path.display()))?,
-            ) {
-                Ok(r) => match r.validate() {
-                    Ok(true) => Some(r),
-                    _ => None,
+            let data = fs::read_to_string(path).map_err(|_| {
+                CliError::rule(
+                    path,
+                    format!("Unable to read data from {}.", path.display()),
+                )
+            })?;
+            if let Some(filter) = &self.filter_rules {
+                let metadata = serde_yaml::from_str(&data).unwrap_or(serde_json::Value::Null);
+                if !filter.matches(&metadata) {
+                    continue;
+                }
+            }
+            required_terms.push(index::required_terms(&data));
+            let rule = self.load_rule(path, &data)?;
+            rules::claim(&mut taken, Claim::Id, &rule.id, path)
+                .map_err(|e| CliError::rule(path, e))?;
+            if !matches!(self.name_by, NameBy::Id) {
+                rules::claim(&mut taken_names, Claim::Name, &rule.name, path)
+                    .map_err(|e| CliError::rule(path, e))?;
+            }
+            validated_rules.push(rule);
+        }
+        if self.filter_rules.is_some() && validated_rules.is_empty() && self.ioc_file.is_empty() {
+            return Err(CliError::config("None of the rules pass --filter-rules"));
+        }
+        for path in self.ioc_file.iter() {
+            required_terms.push(None);
+            let iocs =
+                IocSet::load(path, self.ioc_fields.clone()).map_err(|e| CliError::rule(path, e))?;
+            let id = rules::id(path, &Metadata::default()).map_err(|_| {
+                CliError::rule(
+                    path,
+                    format!("Unable to load {} as indicators", path.display()),
+                )
+            })?;
+            rules::claim(&mut taken, Claim::Id, &id, path).map_err(|e| CliError::rule(path, e))?;
+            if !matches!(self.name_by, NameBy::Id) {
+                rules::claim(&mut taken_names, Claim::Name, &id, path)
+                    .map_err(|e| CliError::rule(path, e))?;
+            }
+            validated_rules.push(ValidatedRule {
+                rule: Some(Matcher::Iocs(iocs)),
+                name: id.clone(),
+                id,
+                path: path.clone(),
+                metadata: Metadata::default(),
+                phase: Metadata::default().phase().map_err(CliError::config)?,
+                explanation: None,
+                origin: match self.stamp_origin {
+                    true => Some(self.inner_origins.origin(path)),
+                    false => None,
                 },
-                Err(_) => None,
-            };
-            match path.as_path().file_name().map(|f| f.to_str()).flatten() {
-                Some(f) => validated_rules.push((rule, f.to_string())),
-                None => return Err(format!("Unable to validate {} as a rule", path.display())),
-            }
-            // if rule
-            //     .validate()
-            //     .map_err(|_| format!("Unable to validate {} as a rule", path.display()))?
-            // {
-            //     match path
-            //         .as_path()
-            //         .file_name()
-            //         .map(|f| {
-            //             f.to_str()
-            //                 .map(|n| n.strip_suffix(".yml").map(|s| format!("{}.match", s)))
-            //         })
-            //         .flatten()
-            //         .flatten()
-            //     {
-            //         Some(f) => validated_rules.push((rule, f)),
-            //         None => return Err(format!("Unable to validate {} as a rule", path.display())),
-            //     }
-            // }
+            });
+        }
+        // Rules run in phase order, keeping the given order within a phase
+        validated_rules.sort_by_key(|r| r.phase);
+        if self.list_rules {
+            return Ok((self, validated_rules));
         }
         //
-        self.inner_input = Some(match self.input {
-            Some(ref mut v) => match v.pop() {
-                Some(p) => {
-                    let f = fs::File::open(&p)
-                        .map_err(|_e| format!("Unable to read input file at {}.", p.display()))?;
-                    Input::Files {
-                        paths: v.clone(),
-                        buffer: io::BufReader::new(f),
-                    }
+        if self.max_count == Some(0) {
+            return Err(CliError::config("--max-count must be at least 1"));
+        }
+        if let Some(bytes) = self.max_line_bytes {
+            if bytes == 0 {
+                return Err(CliError::config("--max-line-bytes must be at least 1"));
+            }
+            if matches!(self.oversize_lines, Oversize::DeadLetter) && self.dead_letter.is_none() {
+                return Err(CliError::config(
+                    "Writing oversize lines to the dead letter file requires --dead-letter",
+                ));
+            }
+        }
+        let cache = match &self.cache_dir {
+            Some(dir) => Some(
+                Cache::new(dir, self.cache_max_files, self.lenient_json)
+                    .map_err(|e| CliError::io(Some(dir), e))?,
+            ),
+            None => None,
+        };
+        let plan = match (&self.use_index, &self.shard) {
+            (Some(p), _) => {
+                let inputs = self.input.as_deref().unwrap_or_default();
+                if let Some(path) = inputs.iter().find(|p| input::is_compressed(p)) {
+                    return Err(CliError::io(
+                        Some(path),
+                        format!(
+                            "{} is compressed, so can't be read by the byte ranges of --use-index",
+                            path.display()
+                        ),
+                    ));
+                }
+                Some(
+                    Index::load(p)
+                        .map_err(|e| CliError::io(Some(p), e))?
+                        .plan(&required_terms),
+                )
+            }
+            (None, Some(shard)) if self.shard_lines => {
+                if !matches!(self.input_format, Format::Json) {
+                    return Err(CliError::config(
+                        "Sharding by lines is only supported for JSON input",
+                    ));
                 }
-                None => {
-                    return Err(
-                        "No rule files provided, use -r or --rules to specify one or more rules"
-                            .into(),
+                Some(
+                    shard
+                        .plan(self.input.as_deref().unwrap_or_default())
+                        .map_err(|e| CliError::io(None, e))?,
+                )
+            }
+            _ => None,
+        };
+        let json = matches!(self.input_format, Format::Json | Format::Concatenated);
+        if self.max_count_per_file && !json {
+            return Err(CliError::config(
+                "--max-count-per-file is only supported for JSON input",
+            ));
+        }
+        if self.per_file_stats && !json {
+            return Err(CliError::config(
+                "--per-file-stats is only supported for JSON input",
+            ));
+        }
+        if let Some(fields) = &self.group_by {
+            self.inner_groups = Some(
+                Groups::new(fields.clone(), self.agg.clone(), self.time_field.clone())
+                    .map_err(CliError::config)?,
+            );
+        }
+        // --top is a group of the --by field, counting the matches of each rule in it
+        if let Some(by) = &self.by {
+            self.inner_groups = Some(
+                Groups::new(vec![by.clone()], vec![Agg::Rules], None).map_err(CliError::config)?,
+            );
+        }
+        match (&self.serve_k8s_audit, &self.schedule, &self.watch_input) {
+            (Some(addr), _, _) => {
+                self.inner_server = Some(AuditServer::new(addr).map_err(CliError::config)?)
+            }
+            (None, Some(expression), _) => {
+                self.inner_scheduler = Some(
+                    Scheduler::new(
+                        expression,
+                        self.input.clone().unwrap_or_default(),
+                        self.schedule_state.clone(),
                     )
+                    .map_err(CliError::config)?,
+                )
+            }
+            (None, None, Some(dir)) => {
+                self.inner_watcher = Some(
+                    DirWatcher::new(dir, self.on_processed.take())
+                        .map_err(|e| CliError::io(Some(dir), e))?,
+                )
+            }
+            (None, None, None) => {
+                if self.on_processed.is_some() {
+                    match self.input.as_mut() {
+                        // Files marked by an earlier run have already been matched
+                        Some(paths) => paths.retain(|p| !OnProcessed::is_marked(p)),
+                        None => {
+                            return Err(CliError::config("Processed actions require input files, use -i or --input or --watch-input"))
+                        }
+                    }
+                    if self.input.as_ref().map(|p| p.is_empty()).unwrap_or(false) {
+                        return Err(CliError::config(
+                            "Every input file has already been processed",
+                        ));
+                    }
                 }
-            },
-            None => Input::CommandLine(stdin()),
-        });
+                if let (Some(shard), Some(paths), false) =
+                    (&self.shard, self.input.as_mut(), self.shard_lines)
+                {
+                    shard.files(paths);
+                }
+                let options = self.read_options();
+                let open = || match (&self.input_format, &self.pipeline) {
+                    (Format::Json, Some(depths)) => {
+                        pipeline::input(self.input.clone(), depths, options)
+                    }
+                    _ => Input::open(self.input.clone(), cache, plan)
+                        .map(|i| i.with_options(options)),
+                };
+                if self.multiline_start.is_some() && !matches!(self.input_format, Format::Json) {
+                    return Err(CliError::config("--multiline-start reads plain text logs, it can't be used with another input format"));
+                }
+                if matches!(self.input_format, Format::Concatenated)
+                    && (self.threads.is_some()
+                        || self.io_uring
+                        || self.pipeline.is_some()
+                        || self.cache_dir.is_some()
+                        || self.use_index.is_some())
+                {
+                    return Err(CliError::config("--threads, --io-uring, --pipeline, --cache-dir and --use-index read JSON lines, they can't be used with concatenated input"));
+                }
+                if matches!(self.input_format, Format::Concatenated)
+                    && (self.max_line_bytes.is_some()
+                        || self.lenient_json
+                        || self.read_until_eof_stable.is_some())
+                {
+                    return Err(CliError::config("--max-line-bytes, --lenient-json and --read-until-eof-stable read JSON lines, they can't be used with concatenated input"));
+                }
+                // Every way of reading input opens files, sockets or devices
+                let opened = (|| -> Result<Input, String> {
+                    Ok(match (&self.input_format, self.input.as_deref()) {
+                        (_, Some([])) if self.multiline_start.is_some() => {
+                            Input::Events(Vec::new().into_iter())
+                        }
+                        (_, _) if self.multiline_start.is_some() => multiline::open(
+                            self.input.clone(),
+                            self.multiline_start.as_deref().unwrap_or_default(),
+                        )?,
+                        (Format::Triage, Some(paths)) => triage::open(paths.to_vec())?,
+                        (Format::Pcap, Some(paths)) => pcap::open(paths.to_vec())?,
+                        (Format::RegHive, Some(paths)) => reghive::open(paths.to_vec())?,
+                        (Format::Mft, Some(paths)) => mft::open(paths.to_vec(), false)?,
+                        (Format::Bodyfile, Some(paths)) => mft::open(paths.to_vec(), true)?,
+                        (Format::Artifact(a), Some(paths)) => artifacts::open(*a, paths.to_vec())?,
+                        (Format::Evtx, Some(paths)) => evtx::open(paths.to_vec())?,
+                        // A shard can be left without any files
+                        (_, Some([])) => Input::Events(Vec::new().into_iter()),
+                        (Format::Concatenated, _) => Input::stream(self.input.clone())?,
+                        (Format::Json, Some(paths)) if self.io_uring => {
+                            uring::open(paths.to_vec(), options)?
+                        }
+                        (Format::Json, Some(paths)) if self.threads.is_some() => chunked::open(
+                            paths.to_vec(),
+                            self.threads.unwrap_or_default(),
+                            options,
+                        )?,
+                        (_, Some([p])) if proc_events::is_source(p) => proc_events::open()?,
+                        (_, Some([p])) => match docker::socket(p) {
+                            Some(socket) => docker::open(&socket)?,
+                            None => open()?,
+                        },
+                        _ => open()?,
+                    })
+                })()
+                .map_err(|message| {
+                    // The failing input is only known for certain when there's one, or it names it
+                    let inputs = self.input.as_deref().unwrap_or_default();
+                    let path = match inputs {
+                        [path] => Some(path),
+                        _ => inputs
+                            .iter()
+                            .find(|p| message.contains(&p.display().to_string())),
+                    };
+                    CliError::io(path.map(|p| p.as_path()), message)
+                })?;
+                self.inner_input = Some(opened);
+            }
+        }
         //
+        if self.output.is_none() && self.encrypt_output.is_some() {
+            return Err(CliError::config(
+                "Encrypting matches requires an output path, use -o or --output",
+            ));
+        }
+        if let Some(fields) = &self.idempotency_key {
+            let path = match (&self.ledger, &self.output) {
+                (Some(p), _) => p.clone(),
+                (None, Some(o)) if output::descriptor(o).is_some() => {
+                    return Err(CliError::config(
+                        "Writing matches to a file descriptor requires a ledger path, use --ledger",
+                    ))
+                }
+                (None, Some(o)) if o.is_dir() => o.join(".tau-ledger"),
+                (None, Some(o)) => {
+                    let mut p = o.clone().into_os_string();
+                    p.push(".ledger");
+                    p.into()
+                }
+                (None, None) => {
+                    return Err(CliError::config(
+                        "Writing matches to stdout requires a ledger path, use --ledger",
+                    ))
+                }
+            };
+            self.inner_ledger =
+                Some(Ledger::open(&path, fields.clone()).map_err(|e| CliError::output(&path, e))?);
+        }
+        let encryption = self.encrypt_output.as_ref();
+        let locked = matches!(self.shared_output, Some(SharedOutput::Lock));
+        if locked && encryption.is_some() {
+            return Err(CliError::config("Encrypted output files can't be shared by locking them, use --shared-output suffix"));
+        }
+        let suffix = match (&self.shared_output, &self.shard) {
+            (Some(SharedOutput::Suffix), Some(shard)) => Some(shard.suffix()),
+            (Some(SharedOutput::Suffix), None) => Some(std::process::id().to_string()),
+            _ => None,
+        };
+        let shared = |path: PathBuf| match &suffix {
+            Some(suffix) if output::descriptor(&path).is_none() => {
+                output::with_suffix(&path, suffix)
+            }
+            _ => path,
+        };
+        let sink = |f| match locked {
+            true => Ok(Sink::locked(f)),
+            false => Sink::new(f, encryption),
+        };
+        let file_options = FileOptions {
+            overwrite: self.overwrite,
+            // Matches recorded in the ledger must stay in the output, and shared files are
+            // appended to by every process
+            append: self.schedule.is_some()
+                || self.watch_input.is_some()
+                || self.serve_k8s_audit.is_some()
+                || self.inner_ledger.is_some()
+                || locked,
+            mode: self.output_mode,
+            owner: self.output_owner.take(),
+            dry_run: self.dry_run,
+        };
+        // The files written to, so their rotated copies can be found
+        let mut outputs = Vec::new();
         self.inner_output = Some(match &self.output {
             Some(p) => match p.is_dir() {
-                false => Output::Files(vec![(
-                    fs::OpenOptions::new()
-                        .write(true)
-                        // Flags here ensure we're overwriting data not appending, this might tamper with match results
-                        .create_new(!self.overwrite)
-                        .create(self.overwrite)
-                        .truncate(self.overwrite)
-                        .open(&p)
-                        .map_err(|_| format!("Could not create output file at {}", p.display()))?,
-                    "".into(),
-                )]),
+                false => {
+                    let path = shared(p.clone());
+                    let file = output::create_file(&path, &file_options)
+                        .and_then(sink)
+                        .map_err(|_| {
+                            CliError::output(
+                                p,
+                                format!("Could not create output file at {}", p.display()),
+                            )
+                        })?;
+                    outputs.push(path);
+                    Output::Files(vec![(file, "".into())])
+                }
                 true => {
                     let mut files = Output::Files(Vec::new());
-                    for (_, filename) in validated_rules.iter() {
+                    let mut taken = HashSet::new();
+                    for ValidatedRule { name, .. } in validated_rules.iter() {
+                        let plain = shared(p.join(output::file_name(
+                            name,
+                            self.output_extension.as_deref(),
+                            &mut taken,
+                        )));
+                        let path = output::long_path(&plain);
+                        outputs.push(plain);
                         if let Output::Files(ref mut v) = files {
                             v.push(
-                                (fs::OpenOptions::new()
-                                    .write(true)
-                                    // Flags here ensure we're overwriting data not appending, this might tamper with match results
-                                    .create_new(!self.overwrite)
-                                    .create(self.overwrite)
-                                    .truncate(self.overwrite)
-                                    .open(p.join(filename))
-                                    .map_err(|e| match e.kind() {
+                                (output::create_file(&path, &file_options)
+                                    .and_then(sink)
+                                    .map_err(|e| CliError::output(&path, match e.kind() {
                                         io::ErrorKind::AlreadyExists => {
-                                            format!("{} already exists, either remove this file or re-run with the -f / --overwrite flag ", p.join(filename).display())
+                                            format!("{} already exists, either remove this file or re-run with the -f / --overwrite flag ", path.display())
                                         },
                                         io::ErrorKind::NotFound => {
-                                            format!("Part of the path to {} does not exist", p.join(filename).display())
+                                            format!("Part of the path to {} does not exist", path.display())
                                         }
                                         _ => format!("{:?}", e.kind()),
-                                    })?,filename.into())
+                                    }))?,name.into())
                             );
                         }
                     }
@@ -191,34 +1215,417 @@ impl Opt {
             },
             None => Output::CommandLine(stdout()),
         });
+        if (self.retain.is_some() || self.retain_size.is_some()) && !self.dry_run {
+            let mut retention = Retention::new(outputs, self.retain.map(|r| *r), self.retain_size);
+            retention.prune(true);
+            self.inner_retention = Some(retention);
+        }
+        if (self.tag_only
+            || self.passthrough
+            || self.extract.is_some()
+            || self.group_by.is_some()
+            || self.top.is_some())
+            && matches!(&self.inner_output, Some(Output::Files(f)) if f.len() > 1)
+        {
+            return Err(CliError::config("Tagged events, extracted values, groups and top values can't be written per rule, use a file or stdout as the output"));
+        }
+        // Clap can't make options conflict with one value of another, so they're listed here
+        let reshaping = [
+            ("tag-only", self.tag_only),
+            ("passthrough", self.passthrough),
+            ("wrap", self.wrap),
+            ("extract", self.extract.is_some()),
+            ("group-by", self.group_by.is_some()),
+            ("top", self.top.is_some()),
+            ("timechart", self.timechart.is_some()),
+            ("route", self.route.is_some()),
+            ("pipeline", self.pipeline.is_some()),
+        ];
+        let continuous = [
+            ("schedule", self.schedule.is_some()),
+            ("watch-input", self.watch_input.is_some()),
+            ("serve-k8s-audit", self.serve_k8s_audit.is_some()),
+        ];
+        let conflict = |format: &str, options: &[(&str, bool)]| match options.iter().find(|o| o.1) {
+            Some((option, _)) => Err(CliError::config(format!(
+                "--output-format {} can't be used with --{}",
+                format, option
+            ))),
+            None => Ok(()),
+        };
+        if !self.output_fields.is_empty()
+            && !matches!(self.output_format, OutputFormat::Cef | OutputFormat::Leef)
+        {
+            return Err(CliError::config(
+                "--output-fields requires --output-format cef or leef",
+            ));
+        }
+        match self.output_format {
+            OutputFormat::Cef => conflict("cef", &reshaping)?,
+            OutputFormat::Leef => conflict("leef", &reshaping)?,
+            OutputFormat::Sarif => {
+                conflict("sarif", &reshaping)?;
+                conflict("sarif", &continuous)?;
+            }
+            OutputFormat::Json => {}
+        }
+        if self.output_format == OutputFormat::Sarif {
+            if matches!(&self.inner_output, Some(Output::Files(f)) if f.len() > 1) {
+                return Err(CliError::config(
+                    "A SARIF log can't be written per rule, use a file or stdout as the output",
+                ));
+            }
+            self.inner_sarif = Some(Sarif::new(&validated_rules));
+        }
+        if !self.enrich_hashes.is_empty() {
+            let virustotal = match self.virustotal {
+                true => Some((
+                    std::env::var("VT_API_KEY").map_err(|_| {
+                        CliError::config(
+                            "VirusTotal lookups require the VT_API_KEY environment variable",
+                        )
+                    })?,
+                    self.virustotal_rate,
+                )),
+                false => None,
+            };
+            self.inner_enrichers.push(Enricher::Hashes(
+                HashEnricher::new(
+                    self.enrich_hashes.clone(),
+                    self.hash_set.as_deref(),
+                    virustotal,
+                )
+                .map_err(CliError::config)?,
+            ));
+        }
+        if !self.enrich_dns.is_empty() {
+            self.inner_enrichers.push(Enricher::Dns(DnsEnricher::new(
+                self.enrich_dns.clone(),
+                Duration::from_millis(self.dns_timeout),
+            )));
+        }
+        if let (Some(path), Some(key)) = (&self.context, &self.context_key) {
+            self.inner_enrichers.push(Enricher::Context(
+                ContextEnricher::new(path.clone(), key.clone(), *self.context_reload)
+                    .map_err(|e| CliError::io(Some(path), e))?,
+            ));
+        }
+        if !self.baseline.is_empty() {
+            let mut baseline = HashSet::new();
+            // Unreadable lines in the baseline can't suppress anything, so they are skipped
+            let events = Input::open(Some(self.baseline.clone()), None, None)
+                .map_err(|e| CliError::io(None, e))?;
+            for json in events.flatten() {
+                baseline.insert(event::key(&json, &self.baseline_key));
+            }
+            self.inner_baseline = Some(baseline);
+        }
+        if let Some(p) = &self.locales {
+            self.inner_locales = Some(Locales::load(p).map_err(CliError::config)?);
+        }
+        if let Some(p) = &self.schema {
+            self.inner_schema = Some(Schema::load(p).map_err(CliError::config)?);
+        }
+        if let Some(p) = &self.dead_letter {
+            self.inner_dead_letter = Some(
+                output::create_file(p, &file_options)
+                    .and_then(|f| Sink::new(f, encryption))
+                    .map_err(|_| {
+                        CliError::output(
+                            p,
+                            format!("Could not create dead letter file at {}", p.display()),
+                        )
+                    })?,
+            );
+        }
+        if let Some(p) = &self.trace {
+            let mut trace = io::BufWriter::new(
+                output::create_file(p, &file_options)
+                    .and_then(|f| Sink::new(f, encryption))
+                    .map_err(|_| {
+                        CliError::output(
+                            p,
+                            format!("Could not create trace file at {}", p.display()),
+                        )
+                    })?,
+            );
+            writeln!(trace, "Event, Rule, Result, Micros")
+                .map_err(|e| CliError::output(p, format!("Unable to write trace, {}", e)))?;
+            self.inner_trace = Some(trace);
+        }
+        if let Some(p) = &self.progress {
+            self.inner_progress = Some(
+                Progress::new(
+                    p,
+                    Duration::from_secs(self.progress_interval.max(1)),
+                    validated_rules.len(),
+                    &file_options,
+                )
+                .map_err(|e| CliError::output(p, e))?,
+            );
+        }
+        if let Some(p) = &self.route {
+            let secret = Secret::load(self.token_file.as_deref(), self.secret_cmd.as_deref())
+                .map_err(CliError::config)?;
+            self.inner_router = Some(
+                Router::load(p, &file_options, encryption, secret.as_ref())
+                    .map_err(CliError::config)?,
+            );
+        }
+        if self.watch_rules {
+            self.inner_rule_watcher =
+                Some(RuleWatcher::new(&self.rules).map_err(|e| CliError::io(None, e))?);
+        }
+        if let (Some(depths), false) = (&self.pipeline, self.dry_run) {
+            self.inner_writer = Some(Writer::new(
+                self.inner_output.take(),
+                self.inner_router.take(),
+                depths,
+            ));
+        }
         //
         match validated_rules.is_empty() {
-            true => Err(format!(
-                "Could not validate any of the following rules: {:?}",
-                self.rules
-            )),
+            true => Err(CliError::RuleLoad {
+                path: None,
+                message: format!(
+                    "Could not validate any of the following rules: {:?}",
+                    self.rules
+                ),
+            }),
             false => Ok((self, validated_rules)),
         }
     }
     pub fn output_match(
         &mut self,
         json: &serde_json::Value,
-        rule_filename: &str,
+        rule: &ValidatedRule,
+    ) -> Result<(), Option<io::Error>> {
+        let key = self
+            .inner_ledger
+            .as_ref()
+            .map(|l| l.key(Some(&rule.id), json));
+        if let (Some(ledger), Some(key)) = (&self.inner_ledger, &key) {
+            if ledger.contains(key) {
+                return Ok(());
+            }
+        }
+        self.write_match(json, rule)?;
+        if let (Some(ledger), Some(key)) = (self.inner_ledger.as_mut(), key) {
+            ledger.insert(key);
+        }
+        Ok(())
+    }
+    fn write_match(
+        &mut self,
+        json: &serde_json::Value,
+        rule: &ValidatedRule,
     ) -> Result<(), Option<io::Error>> {
+        let (annotated, wrapped);
+        let json = match (&rule.explanation, &rule.origin) {
+            (None, None) => json,
+            (explanation, origin) => {
+                let mut copy = json.clone();
+                if let Some(explanation) = explanation {
+                    rules::explain(&mut copy, explanation);
+                }
+                if let Some(origin) = origin {
+                    rules::stamp(&mut copy, origin);
+                }
+                annotated = copy;
+                &annotated
+            }
+        };
+        let time = || {
+            self.time_field
+                .as_ref()
+                .and_then(|f| event::get(json, f))
+                .and_then(event::time)
+        };
+        let json = match self.wrap {
+            true => {
+                wrapped = rules::wrap(json, rule, time());
+                &wrapped
+            }
+            false => json,
+        };
+        let line = match self.output_format {
+            OutputFormat::Cef => Some(siem::cef(json, rule, &self.output_fields, time())),
+            OutputFormat::Leef => Some(siem::leef(json, rule, &self.output_fields, time())),
+            OutputFormat::Json | OutputFormat::Sarif => None,
+        };
+        if let Some(line) = line {
+            return match self.inner_output.as_mut() {
+                Some(o) => o.write_line(&line, &rule.name).map_err(Some),
+                None => Err(None),
+            };
+        }
+        if let Some(sarif) = self.inner_sarif.as_mut() {
+            sarif.add(json, rule, self.inner_input.as_ref().and_then(|i| i.path()));
+            return Ok(());
+        }
+        if let Some(writer) = self.inner_writer.as_mut() {
+            return writer.write_match(json, rule).map_err(Some);
+        }
+        if let Some(router) = self.inner_router.as_mut() {
+            if !router.dispatch(&rule.metadata, json).map_err(Some)? {
+                return Ok(());
+            }
+        }
         match self.inner_output.as_mut() {
-            Some(Output::Files(o)) => {
-                let len = o.len();
-                for (file, filename) in o.iter_mut() {
-                    if filename == rule_filename || len == 1 {
-                        writeln!(file, "{}", json.to_string()).map_err(Some)?;
+            Some(o) => o.write_match(json, &rule.name).map_err(Some),
+            None => Err(None),
+        }
+    }
+    /// Report an error ending the run on stderr and in the progress stream, then exit. The
+    /// matches of a SARIF log so far are still written.
+    fn fail(&mut self, message: String) -> ! {
+        let _ = writeln!(stderr(), "{}", message);
+        if let Some(progress) = &self.inner_progress {
+            progress.error(&message);
+        }
+        self.salvage_sarif();
+        std::process::exit(1);
+    }
+
+    /// Write the SARIF log of the matches so far when the run ends early, as best it can.
+    fn salvage_sarif(&mut self) {
+        if self.inner_sarif.is_some() {
+            let _ = self
+                .write_sarif()
+                .and_then(|_| match self.inner_output.take() {
+                    Some(o) => o.finish(),
+                    None => Ok(()),
+                });
+        }
+    }
+
+    /// Write the SARIF log of every match, once nothing more will be added to it.
+    fn write_sarif(&mut self) -> io::Result<()> {
+        match (self.inner_sarif.take(), self.inner_output.as_mut()) {
+            (Some(sarif), Some(o)) => o.write_event(&sarif.document()),
+            _ => Ok(()),
+        }
+    }
+    /// Write an event which could not be matched, along with why, to the dead letter file.
+    pub fn dead_letter(&mut self, json: &serde_json::Value, errors: &[String]) -> io::Result<()> {
+        match self.inner_dead_letter.as_mut() {
+            Some(sink) => writeln!(
+                sink,
+                "{}",
+                serde_json::json!({ "event": json, "errors": errors })
+            ),
+            None => Ok(()),
+        }
+    }
+    /// Record the result of evaluating a rule against an event in the trace.
+    pub fn trace(&mut self, event: u64, rule: &str, result: &str, micros: u128) -> io::Result<()> {
+        match self.inner_trace.as_mut() {
+            Some(trace) => writeln!(
+                trace,
+                "{}, {}, {}, {}",
+                event,
+                output::csv_escape(rule),
+                result,
+                micros
+            ),
+            None => Ok(()),
+        }
+    }
+    /// Describe what would run for --dry-run, returning false when a webhook can't be reached.
+    fn plan(&self, rules: &ValidatedRules, w: &mut impl Write) -> io::Result<bool> {
+        let loaded = rules.iter().filter(|r| r.rule.is_some()).count();
+        writeln!(w, "Rules: {} loaded", loaded)?;
+        for r in rules.iter().filter(|r| r.rule.is_none()) {
+            writeln!(w, "    {} failed to validate", r.name)?;
+        }
+        let input = match (
+            &self.serve_k8s_audit,
+            &self.schedule,
+            &self.watch_input,
+            &self.input,
+        ) {
+            (Some(addr), _, _, _) => format!("Kubernetes audit events served on {}", addr),
+            (None, Some(expression), _, _) => format!(
+                "{} files on the schedule {}",
+                self.input.as_ref().map_or(0, |p| p.len()),
+                expression
+            ),
+            (None, None, Some(dir), _) => format!("files added to {}", dir.display()),
+            (None, None, None, Some(paths)) => format!("{} files", paths.len()),
+            (None, None, None, None) => "stdin".to_string(),
+        };
+        writeln!(w, "Input: {}", input)?;
+        let output = match &self.output {
+            Some(p) if p.is_dir() => format!("{} files in {}", rules.len(), p.display()),
+            Some(p) => p.display().to_string(),
+            None => "stdout".to_string(),
+        };
+        writeln!(w, "Output: {}", output)?;
+        for (name, path) in [("Trace", &self.trace), ("Dead letter", &self.dead_letter)] {
+            if let Some(p) = path {
+                writeln!(w, "{}: {}", name, p.display())?;
+            }
+        }
+        let mut ok = true;
+        if let (Some(path), Some(router)) = (&self.route, &self.inner_router) {
+            writeln!(w, "Route: {}", path.display())?;
+            for (url, result) in router.probe() {
+                match result {
+                    Ok(()) => writeln!(w, "    {} reachable", url)?,
+                    Err(e) => {
+                        ok = false;
+                        writeln!(w, "    {} unreachable, {}", url, e)?
                     }
                 }
-                Ok(())
             }
-            Some(Output::CommandLine(ref mut stdout)) => {
-                writeln!(stdout, "{}", json.to_string()).map_err(Some)?;
-                Ok(())
+        }
+        Ok(ok)
+    }
+    /// Flush everything written so far, used between scheduled runs.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(trace) = self.inner_trace.as_mut() {
+            trace.flush()?;
+        }
+        if let Some(sink) = self.inner_dead_letter.as_mut() {
+            sink.flush()?;
+        }
+        if let Some(writer) = self.inner_writer.as_mut() {
+            writer.flush()?;
+        }
+        if let Some(router) = self.inner_router.as_mut() {
+            router.flush()?;
+        }
+        if let Some(o) = self.inner_output.as_mut() {
+            o.flush()?;
+        }
+        if let Some(retention) = self.inner_retention.as_mut() {
+            retention.prune(false);
+        }
+        // Only matches which have reached the outputs are recorded
+        match self.inner_ledger.as_mut() {
+            Some(ledger) => ledger.commit(),
+            None => Ok(()),
+        }
+    }
+    pub fn output_event(&mut self, json: &serde_json::Value) -> Result<(), Option<io::Error>> {
+        let key = self.inner_ledger.as_ref().map(|l| l.key(None, json));
+        if let (Some(ledger), Some(key)) = (&self.inner_ledger, &key) {
+            if ledger.contains(key) {
+                return Ok(());
             }
+        }
+        self.write_event(json)?;
+        if let (Some(ledger), Some(key)) = (self.inner_ledger.as_mut(), key) {
+            ledger.insert(key);
+        }
+        Ok(())
+    }
+    fn write_event(&mut self, json: &serde_json::Value) -> Result<(), Option<io::Error>> {
+        if let Some(writer) = self.inner_writer.as_mut() {
+            return writer.write_event(json).map_err(Some);
+        }
+        match self.inner_output.as_mut() {
+            Some(o) => o.write_event(json).map_err(Some),
             None => Err(None),
         }
     }
@@ -227,41 +1634,555 @@ impl Opt {
 impl Iterator for Opt {
     type Item = Result<serde_json::Value, Box<dyn Error>>;
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner_input
-            .as_mut()
-            .map(|ref mut i| i.next())
-            .flatten()
+        self.inner_input.as_mut()?.next()
     }
 }
 
+/// The command line with the match subcommand added when none is given, so command lines from
+/// before there were subcommands keep working.
+fn match_by_default(mut args: Vec<OsString>) -> Vec<OsString> {
+    let subcommand = match args.get(1).and_then(|a| a.to_str()) {
+        Some("-h" | "--help" | "-V" | "--version") => true,
+        Some(arg) => !arg.starts_with('-'),
+        None => false,
+    };
+    if !subcommand {
+        args.insert(1, "match".into());
+    }
+    args
+}
+
 fn main() -> Result<(), io::Error> {
+    let args = match_by_default(std::env::args_os().collect());
+    let cli = match Cli::from_iter_safe(&args) {
+        // Subcommands other than match only take options from the command line
+        Ok(cli) if !matches!(cli.cmd, Command::Match(_)) => cli,
+        _ => Cli::from_iter(environment::args(args)),
+    };
+    match cli.cmd {
+        Command::Match(opt) => match_events(*opt),
+        cmd => {
+            if let Err(e) = cmd.run() {
+                writeln!(stderr(), "{}", e)?;
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Match the rules against the events, the default when no subcommand is given.
+fn match_events(mut opt: Opt) -> Result<(), io::Error> {
     let (mut stdout, mut stderr) = (stdout(), stderr());
-    let (mut opt, rules) = match Opt::from_args().validate_rules() {
+    let errors_format = opt.errors_format;
+    if let Err(e) = opt.expand_paths() {
+        errors_format.write(&mut stderr, &e)?;
+        std::process::exit(1);
+    }
+    if let Some(Control::Stdio) = opt.control {
+        if let Err(e) = control::serve(&opt.rules) {
+            writeln!(stderr, "{}", e)?;
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let (mut opt, mut rules) = match opt.validate_rules() {
         Ok(x) => x,
         Err(e) => {
-            writeln!(stderr, "{}", e)?;
+            errors_format.write(&mut stderr, &e)?;
             std::process::exit(1);
         }
     };
     if opt.validate {
         writeln!(stdout, "Rule Name, Is Valid")?;
-        for (r, n) in rules.iter() {
-            writeln!(stdout, "{}, {}", n, r.is_some())?;
+        for r in rules.iter() {
+            writeln!(stdout, "{}, {}", r.name, r.rule.is_some())?;
+        }
+        std::process::exit(0);
+    }
+    if opt.list_rules {
+        writeln!(stdout, "Rule Name, Path, Phase, Level, Tags, Is Valid")?;
+        for r in rules.iter() {
+            writeln!(
+                stdout,
+                "{}, {}, {}, {}, {}, {}",
+                output::csv_escape(&r.name),
+                output::csv_escape(&r.path.display().to_string()),
+                r.phase,
+                output::csv_escape(r.metadata.level.as_deref().unwrap_or("")),
+                output::csv_escape(&r.metadata.tags.join(" ")),
+                r.rule.is_some()
+            )?;
         }
         std::process::exit(0);
     }
+    if opt.dry_run {
+        let ok = opt.plan(&rules, &mut stdout)?;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+    let mut totals = Totals {
+        counts: vec![0; rules.len()],
+        overruns: vec![0; rules.len()],
+        disabled: vec![None; rules.len()],
+        groups: opt.inner_groups.take(),
+        timechart: match opt.timechart {
+            Some(width) => match Timechart::new(*width, opt.timechart_format, rules.len()) {
+                Ok(chart) => Some(chart),
+                Err(e) => {
+                    writeln!(stderr, "{}", e)?;
+                    std::process::exit(1);
+                }
+            },
+            None => None,
+        },
+        ..Default::default()
+    };
+    if let Some(mut server) = opt.inner_server.take() {
+        loop {
+            match server.next_input() {
+                Ok(input) => {
+                    opt.inner_input = Some(input);
+                    process(&mut opt, &mut rules, &mut totals)?;
+                    opt.flush()?;
+                }
+                Err(e) => {
+                    writeln!(stderr, "{}", e)?;
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if let Some(mut watcher) = opt.inner_watcher.take() {
+        loop {
+            match watcher.next_input() {
+                Ok((input, paths)) => {
+                    opt.inner_input = Some(input.with_options(opt.read_options()));
+                    process(&mut opt, &mut rules, &mut totals)?;
+                    opt.flush()?;
+                    if let Err(e) = watcher.processed(&paths) {
+                        writeln!(stderr, "{}", e)?;
+                    }
+                }
+                Err(e) => {
+                    writeln!(stderr, "{}", e)?;
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    match opt.inner_scheduler.take() {
+        Some(mut scheduler) => loop {
+            scheduler.wait();
+            match scheduler.next_input() {
+                Ok(Some(input)) => {
+                    opt.inner_input = Some(input.with_options(opt.read_options()));
+                    process(&mut opt, &mut rules, &mut totals)?;
+                    opt.flush()?;
+                    if let Err(e) = scheduler.save() {
+                        writeln!(stderr, "{}", e)?;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => writeln!(stderr, "{}", e)?,
+            }
+        },
+        None => {
+            if let Err(e) = process(&mut opt, &mut rules, &mut totals) {
+                opt.salvage_sarif();
+                return Err(e);
+            }
+        }
+    }
+    // Most common values first, ties keep the order they were first seen in
+    totals
+        .extracted
+        .sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (value, count) in totals.extracted {
+        let row = serde_json::json!({ "value": value, "count": count });
+        if let Err(Some(e)) = opt.output_event(&row) {
+            opt.fail(format!("An error occured whilst outputting data, {}", e));
+        }
+    }
+    let rows = totals.groups.take().map(|g| match opt.top {
+        Some(n) => g.top(n),
+        None => g.rows(),
+    });
+    for row in rows.unwrap_or_default() {
+        if let Err(Some(e)) = opt.output_event(&row) {
+            opt.fail(format!("An error occured whilst outputting data, {}", e));
+        }
+    }
+    if let Some(chart) = &totals.timechart {
+        let names: Vec<&str> = rules.iter().map(|r| r.name.as_str()).collect();
+        chart.write(&names, &mut stdout)?;
+        if chart.untimed > 0 {
+            writeln!(
+                stderr,
+                "{} matches had no readable time in {}",
+                chart.untimed,
+                opt.time_field.as_deref().unwrap_or_default()
+            )?;
+        }
+    }
+    if opt.stats {
+        writeln!(stderr, "Rule Name, Matches")?;
+        for (r, c) in rules.iter().zip(totals.counts.iter()) {
+            writeln!(stderr, "{}, {}", r.name, c)?;
+        }
+        if totals.disabled.iter().any(|d| d.is_some()) {
+            writeln!(stderr, "Disabled Rule, Event")?;
+            for (r, d) in rules.iter().zip(totals.disabled.iter()) {
+                if let Some(event) = d {
+                    writeln!(stderr, "{}, {}", r.name, event)?;
+                }
+            }
+        }
+        if opt.prefilter_expr.is_some() {
+            writeln!(
+                stderr,
+                "{} events were skipped by --prefilter-expr",
+                totals.prefiltered
+            )?;
+        }
+    }
+    if opt.per_file_stats {
+        writeln!(stderr, "File, Events, Parse Errors, Matches, Top Rule")?;
+        let mut files: Vec<(usize, &FileTotals)> = totals
+            .files
+            .iter()
+            .map(|f| (f.counts.iter().sum(), f))
+            .collect();
+        files.sort_by_key(|(matches, _)| std::cmp::Reverse(*matches));
+        for (matches, file) in files {
+            // The first rule wins a tie, as rules are in the order they run
+            let top = file
+                .counts
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c > 0)
+                .min_by_key(|(i, c)| (std::cmp::Reverse(**c), *i))
+                .map(|(i, _)| rules[i].name.as_str())
+                .unwrap_or("");
+            writeln!(
+                stderr,
+                "{}, {}, {}, {}, {}",
+                output::csv_escape(&file.path.display().to_string()),
+                file.events,
+                file.errors,
+                matches,
+                output::csv_escape(top)
+            )?;
+        }
+    }
+    if totals.oversize > 0 {
+        let action = match opt.oversize_lines {
+            Oversize::Skip => "skipped",
+            Oversize::Truncate => "truncated",
+            Oversize::DeadLetter => "written to the dead letter file",
+        };
+        writeln!(
+            stderr,
+            "{} lines over --max-line-bytes were {}",
+            totals.oversize, action
+        )?;
+    }
+    if lenient::recovered() > 0 {
+        writeln!(
+            stderr,
+            "{} malformed lines were recovered by --lenient-json",
+            lenient::recovered()
+        )?;
+    }
+    if let Some(locales) = opt.inner_locales.as_ref().filter(|l| l.unparsed > 0) {
+        writeln!(
+            stderr,
+            "{} values couldn't be read in the locale --locales gives their source",
+            locales.unparsed
+        )?;
+    }
+    if let Some(schema) = &opt.inner_schema {
+        writeln!(stderr, "Schema Keyword, Violations")?;
+        for (keyword, count) in schema.violations.iter() {
+            writeln!(stderr, "{}, {}", keyword, count)?;
+        }
+    }
+    if let Some(trace) = opt.inner_trace.take() {
+        trace.into_inner().map_err(|e| e.into_error())?.finish()?;
+    }
+    if let Some(sink) = opt.inner_dead_letter.take() {
+        sink.finish()?;
+    }
+    if let Some(w) = opt.inner_writer.take() {
+        w.finish()?;
+    }
+    if let Some(r) = opt.inner_router.take() {
+        for (url, n, error) in r.finish()? {
+            writeln!(stderr, "Unable to send {} matches to {}, {}", n, url, error)?;
+        }
+    }
+    opt.write_sarif()?;
+    if let Some(o) = opt.inner_output.take() {
+        o.finish()?;
+    }
+    if let Some(mut ledger) = opt.inner_ledger.take() {
+        ledger.commit()?;
+    }
+    if let Some(progress) = opt.inner_progress.take() {
+        progress.finish()?;
+    }
+    // Only once every match has been written are the input files done with
+    if let (Some(action), Some(paths)) = (&opt.on_processed, &opt.input) {
+        for path in paths {
+            if let Err(e) = action.apply(path) {
+                writeln!(stderr, "{}", e)?;
+            }
+        }
+    }
+    if opt.fail_fast && totals.hits > 0 {
+        std::process::exit(MATCHED);
+    }
+    Ok(())
+}
+
+/// Match every event from the current input against the rules, writing out the results.
+fn process(opt: &mut Opt, rules: &mut ValidatedRules, totals: &mut Totals) -> io::Result<()> {
+    let mut stderr = stderr();
     while let Some(res) = opt.next() {
+        opt.reload_rules(rules, totals)?;
+        totals.events += 1;
+        if opt.per_file_stats {
+            if let Some(path) = opt.inner_input.as_ref().and_then(|i| i.path()) {
+                if totals.files.last().map(|f| f.path.as_path()) != Some(path) {
+                    totals.files.push(FileTotals {
+                        path: path.to_path_buf(),
+                        events: 0,
+                        errors: 0,
+                        counts: vec![0; rules.len()],
+                    });
+                }
+            }
+            if let Some(file) = totals.files.last_mut() {
+                file.events += 1;
+                if res.is_err() {
+                    file.errors += 1;
+                }
+            }
+        }
+        if let Some(progress) = &opt.inner_progress {
+            progress.event();
+        }
+        if let Some(limits) = opt.inner_limits.as_mut() {
+            limits.check();
+        }
+        let res = match res.map_err(|e| e.downcast::<LongLine>()) {
+            Err(Ok(long)) => {
+                totals.oversize += 1;
+                match opt.oversize_lines {
+                    Oversize::Skip => continue,
+                    Oversize::Truncate => Ok(serde_json::json!({ "message": long.start })),
+                    Oversize::DeadLetter => {
+                        let error = long.to_string();
+                        opt.dead_letter(&serde_json::Value::String(long.start), &[error])?;
+                        continue;
+                    }
+                }
+            }
+            Err(Err(e)) => Err(e),
+            Ok(json) => Ok(json),
+        };
         match res {
-            Ok(json) => {
-                for (rule, path) in rules.iter() {
-                    if let Some(r) = rule {
-                        if r.matches(&json) {
-                            if let Err(Some(e)) = opt.output_match(&json, &path) {
-                                writeln!(stderr, "An error occured whilst outputting data, {}", e)?;
-                                std::process::exit(1);
+            Ok(mut json) => {
+                // The gate sees the event as it was read, before any option rewrites its values
+                if matches!(&opt.prefilter_expr, Some(f) if !f.matches(&json)) {
+                    totals.prefiltered += 1;
+                    opt.trace(totals.events, "", "prefiltered", 0)?;
+                    if opt.passthrough {
+                        if let Err(Some(e)) = opt.output_event(&json) {
+                            opt.fail(format!("An error occured whilst outputting data, {}", e));
+                        }
+                    }
+                    continue;
+                }
+                if let Some(locales) = opt.inner_locales.as_mut() {
+                    locales.apply(&mut json, opt.inner_input.as_ref().and_then(|i| i.path()));
+                }
+                if !opt.normalize.is_empty() {
+                    normalize::normalize(&mut json, &opt.normalize);
+                }
+                if let Some(fields) = &opt.deobfuscate {
+                    normalize::deobfuscate_fields(&mut json, fields);
+                }
+                if !opt.compute.is_empty() {
+                    compute::compute(&mut json, &opt.compute);
+                }
+                if !opt.capture.is_empty() {
+                    compute::capture(&mut json, &opt.capture);
+                }
+                match opt.coerce_types.as_deref() {
+                    Some([]) => event::coerce(&mut json),
+                    Some(fields) => {
+                        for field in fields {
+                            if let Some(v) = event::get_mut(&mut json, field) {
+                                event::coerce(v);
                             }
                         }
                     }
+                    None => {}
+                }
+                if opt.case_insensitive_values {
+                    event::lowercase(&mut json);
+                }
+                for field in opt.lowercase_fields.iter() {
+                    if let Some(v) = event::get_mut(&mut json, field) {
+                        event::lowercase(v);
+                    }
+                }
+                if let Some(errors) = opt.inner_schema.as_mut().and_then(|s| s.check(&json)) {
+                    opt.trace(totals.events, "", "invalid", 0)?;
+                    opt.dead_letter(&json, &errors)?;
+                    continue;
+                }
+                let mut phase = rules.first().map(|r| r.phase);
+                let mut tags = Vec::new();
+                let mut matched = Vec::new();
+                let mut enriched = false;
+                let mut hit = false;
+                let time = match (&totals.timechart, &opt.time_field) {
+                    (Some(_), Some(field)) => event::get(&json, field).and_then(event::time),
+                    _ => None,
+                };
+                let baselined = opt
+                    .inner_baseline
+                    .as_ref()
+                    .map(|b| b.contains(&event::key(&json, &opt.baseline_key)))
+                    .unwrap_or(false);
+                for (i, rule) in rules.iter().enumerate() {
+                    if phase != Some(rule.phase) {
+                        rules::annotate(&mut json, &tags);
+                        tags.clear();
+                        phase = Some(rule.phase);
+                    }
+                    if let (Some(r), None) = (&rule.rule, totals.disabled[i]) {
+                        let start = Instant::now();
+                        let is_match = r.matches(&mut json);
+                        if let Some(timeout) = opt.rule_timeout {
+                            match start.elapsed() > Duration::from_millis(timeout) {
+                                true => totals.overruns[i] += 1,
+                                false => totals.overruns[i] = 0,
+                            }
+                            if totals.overruns[i] == RULE_TIMEOUT_TRIPS {
+                                totals.disabled[i] = Some(totals.events);
+                                writeln!(
+                                    stderr,
+                                    "Disabling {}, it took over {}ms on {} events in a row",
+                                    rule.name, timeout, RULE_TIMEOUT_TRIPS
+                                )?;
+                            }
+                        }
+                        if opt.inner_trace.is_some() {
+                            let result = if is_match { "match" } else { "miss" };
+                            opt.trace(
+                                totals.events,
+                                &rule.name,
+                                result,
+                                start.elapsed().as_micros(),
+                            )?;
+                        }
+                        if is_match {
+                            totals.counts[i] += 1;
+                            if let Some(file) = totals.files.last_mut() {
+                                file.counts[i] += 1;
+                            }
+                            if let Some(progress) = &opt.inner_progress {
+                                progress.matched();
+                            }
+                            tags.extend(rule.metadata.tags.iter().cloned());
+                            if let (Some(min), Some(level)) =
+                                (opt.emit_min_level, rule.metadata.level())
+                            {
+                                if level < min {
+                                    continue;
+                                }
+                            }
+                            if baselined {
+                                continue;
+                            }
+                            hit = true;
+                            if let Some(chart) = totals.timechart.as_mut() {
+                                chart.add(time, i);
+                                continue;
+                            }
+                            if !enriched {
+                                for e in opt.inner_enrichers.iter_mut() {
+                                    e.enrich(&mut json, &mut stderr)?;
+                                }
+                                enriched = true;
+                            }
+                            if opt.tag_only
+                                || opt.passthrough
+                                || opt.extract.is_some()
+                                || totals.groups.is_some()
+                            {
+                                matched.push(rule.name.as_str());
+                            } else if let Err(Some(e)) = opt.output_match(&json, rule) {
+                                opt.fail(format!("An error occured whilst outputting data, {}", e));
+                            }
+                        }
+                    }
+                }
+                if let Some(field) = &opt.extract {
+                    if let (false, Some(value)) = (matched.is_empty(), event::get(&json, field)) {
+                        match totals.extracted_index.get(&value.to_string()) {
+                            Some(&i) => totals.extracted[i].1 += 1,
+                            None => {
+                                totals
+                                    .extracted_index
+                                    .insert(value.to_string(), totals.extracted.len());
+                                totals.extracted.push((value.clone(), 1));
+                            }
+                        }
+                    }
+                } else if let Some(groups) = totals.groups.as_mut() {
+                    // Only events with the --by field count towards --top
+                    let keyed = opt
+                        .by
+                        .as_ref()
+                        .is_none_or(|f| event::get(&json, f).is_some());
+                    if !matched.is_empty() && keyed {
+                        groups.add(&json, &matched);
+                    }
+                } else if !matched.is_empty() || opt.passthrough {
+                    if !matched.is_empty() {
+                        rules::mark(&mut json, &matched);
+                    }
+                    if let Err(Some(e)) = opt.output_event(&json) {
+                        opt.fail(format!("An error occured whilst outputting data, {}", e));
+                    }
+                }
+                if !hit {
+                    continue;
+                }
+                totals.hits += 1;
+                let max = match (opt.max_count, opt.fail_fast) {
+                    (Some(n), _) => n,
+                    (None, true) => 1,
+                    (None, false) => continue,
+                };
+                if !opt.max_count_per_file {
+                    if totals.hits >= max {
+                        break;
+                    }
+                    continue;
+                }
+                let file = opt.inner_input.as_ref().map(|i| i.file()).unwrap_or(0);
+                if totals.file_hits.0 != file {
+                    totals.file_hits = (file, 0);
+                }
+                totals.file_hits.1 += 1;
+                if totals.file_hits.1 >= max {
+                    if let Some(input) = opt.inner_input.as_mut() {
+                        input.skip_file()?;
+                    }
                 }
             }
             Err(e) => writeln!(stderr, "{}", e)?,
@@ -269,3 +2190,37 @@ fn main() -> Result<(), io::Error> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Opt, structopt::clap::Error> {
+        Opt::from_iter_safe(std::iter::once("tau-cli").chain(args.split_whitespace()))
+    }
+
+    fn command(args: &str) -> Result<Command, structopt::clap::Error> {
+        let args = std::iter::once("tau-cli").chain(args.split_whitespace());
+        Cli::from_iter_safe(match_by_default(args.map(OsString::from).collect())).map(|c| c.cmd)
+    }
+
+    #[test]
+    fn options_without_a_subcommand_are_matched() {
+        assert!(matches!(command("-r a.yml"), Ok(Command::Match(_))));
+        assert!(matches!(command("match -r a.yml"), Ok(Command::Match(_))));
+        assert!(matches!(command(""), Ok(Command::Match(_))));
+        assert!(matches!(
+            command("validate -r a.yml"),
+            Ok(Command::Validate(_))
+        ));
+        assert!(command("-f match -r a.yml").is_err());
+        assert!(command("-f validate -r a.yml").is_err());
+    }
+
+    #[test]
+    fn watched_rules_are_not_planned_or_filtered() {
+        assert!(parse("-r a.yml --watch-rules").is_ok());
+        assert!(parse("-r a.yml --watch-rules --use-index i.idx").is_err());
+        assert!(parse("-r a.yml --watch-rules --filter-rules level").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+    io::{self, stderr, stdout, Write},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::event;
+use crate::input::Input;
+use crate::output::{self, FileOptions};
+
+#[derive(StructOpt)]
+pub struct Merge {
+    /// The match outputs to combine, such as the files of a sharded run.
+    #[structopt(parse(from_os_str), required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Path to write the merged matches to, by default they're written to stdout.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Overwrite the output file.
+    #[structopt(short = "f", long)]
+    overwrite: bool,
+
+    /// Keep only the first of matches which are the same, or which have the same --key.
+    #[structopt(long)]
+    dedupe: bool,
+
+    /// Comma separated fields identifying a match for --dedupe, the whole event is used when none are given.
+    #[structopt(long, use_delimiter = true, requires = "dedupe")]
+    key: Vec<String>,
+
+    /// Order the matches by a field, e.g. 'timestamp', merging inputs which are each already in that order.
+    #[structopt(long)]
+    sort_by: Option<String>,
+}
+
+/// Where a match sorts by the --sort-by field.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Order {
+    Time(SystemTime),
+    Text(String),
+    Missing,
+}
+
+fn order(json: &Value, field: &str) -> Order {
+    match event::get(json, field) {
+        None | Some(Value::Null) => Order::Missing,
+        Some(v) => match event::time(v) {
+            Some(t) => Order::Time(t),
+            None => Order::Text(match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            }),
+        },
+    }
+}
+
+/// The next match read from an input, reporting any which can't be read.
+fn next_match(input: &mut Input, stderr: &mut dyn Write) -> Option<Value> {
+    loop {
+        match input.next()? {
+            Ok(json) => return Some(json),
+            Err(e) => {
+                let _ = writeln!(stderr, "{}", e);
+            }
+        }
+    }
+}
+
+impl Merge {
+    pub fn run(self) -> Result<(), String> {
+        let mut stderr = stderr();
+        let mut inputs = self
+            .inputs
+            .iter()
+            .map(|path| Input::open(Some(vec![path.clone()]), None, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        let out: Box<dyn Write> = match &self.output {
+            Some(path) => {
+                let options = FileOptions {
+                    overwrite: self.overwrite,
+                    append: false,
+                    mode: None,
+                    owner: None,
+                    dry_run: false,
+                };
+                let file = output::create_file(path, &options)
+                    .map_err(|_| format!("Could not create output file at {}", path.display()))?;
+                Box::new(file)
+            }
+            None => Box::new(stdout()),
+        };
+        let mut out = io::BufWriter::new(out);
+        let failed = |e: io::Error| match &self.output {
+            Some(path) => format!("Unable to write matches to {}, {}", path.display(), e),
+            None => format!("Unable to write matches, {}", e),
+        };
+        let key = |json: &Value| match &self.sort_by {
+            Some(field) => order(json, field),
+            None => Order::Missing,
+        };
+        // Each input is already in order, so only the next match of each is held, and the least
+        // of those is written next. Ties go to the earlier input, so without --sort-by the inputs
+        // are written one after another in the order given.
+        let mut heads: Vec<Option<Value>> = inputs
+            .iter_mut()
+            .map(|input| next_match(input, &mut stderr))
+            .collect();
+        let mut heap: BinaryHeap<_> = heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| Some(Reverse((key(head.as_ref()?), i))))
+            .collect();
+        let mut seen = HashSet::new();
+        let (mut merged, mut duplicates) = (0, 0);
+        while let Some(Reverse((_, i))) = heap.pop() {
+            let json = match heads[i].take() {
+                Some(json) => json,
+                None => continue,
+            };
+            if let Some(next) = next_match(&mut inputs[i], &mut stderr) {
+                heap.push(Reverse((key(&next), i)));
+                heads[i] = Some(next);
+            }
+            if self.dedupe && !seen.insert(event::key(&json, &self.key)) {
+                duplicates += 1;
+                continue;
+            }
+            output::write_json(&mut out, &json).map_err(failed)?;
+            merged += 1;
+        }
+        out.flush().map_err(failed)?;
+        let _ = writeln!(
+            stderr,
+            "Merged {} matches from {} files, {} duplicates removed",
+            merged,
+            self.inputs.len(),
+            duplicates
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_inputs_are_merged_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.json"), dir.path().join("b.json"));
+        std::fs::write(
+            &a,
+            "{\"t\":1,\"k\":1}\n{\"t\":4,\"k\":2}\n{\"t\":6,\"k\":3}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &b,
+            "{\"t\":2,\"k\":4}\n{\"t\":4,\"k\":2}\n{\"t\":5,\"k\":5}\n",
+        )
+        .unwrap();
+        let output = dir.path().join("merged.json");
+        Merge {
+            inputs: vec![a, b],
+            output: Some(output.clone()),
+            overwrite: false,
+            dedupe: true,
+            key: vec!["k".into()],
+            sort_by: Some("t".into()),
+        }
+        .run()
+        .unwrap();
+        let merged: Vec<Value> = std::fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap()["t"].clone())
+            .collect();
+        assert_eq!(merged, vec![1, 2, 4, 5, 6]);
+    }
+}
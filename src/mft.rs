@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::event::{filetime, RFC3339_MAX};
+use crate::input::Input;
+
+/// The record of the volume's root directory.
+const ROOT: u64 = 5;
+const STANDARD_INFORMATION: u32 = 0x10;
+const FILE_NAME: u32 = 0x30;
+const DATA: u32 = 0x80;
+/// Namespace of the short 8.3 names, only used when a file has no other name.
+const DOS_NAMESPACE: u8 = 2;
+
+fn u16_at(b: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(b.get(at..at + 2)?.try_into().ok()?))
+}
+fn u32_at(b: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(b.get(at..at + 4)?.try_into().ok()?))
+}
+fn u64_at(b: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(b.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// The MACB times held by both the standard information and file name attributes.
+struct Times([u64; 4]);
+
+impl Times {
+    fn read(b: &[u8]) -> Option<Self> {
+        Some(Times([
+            u64_at(b, 0)?,
+            u64_at(b, 8)?,
+            u64_at(b, 16)?,
+            u64_at(b, 24)?,
+        ]))
+    }
+    fn json(&self) -> Value {
+        json!({
+            "created": filetime(self.0[0]),
+            "modified": filetime(self.0[1]),
+            "mft_modified": filetime(self.0[2]),
+            "accessed": filetime(self.0[3]),
+        })
+    }
+}
+
+/// The parts of a file record used for events.
+struct Record {
+    in_use: bool,
+    directory: bool,
+    sequence: u16,
+    /// The parent record and name from the preferred file name attribute.
+    name: Option<(u64, u8, String)>,
+    si: Option<Times>,
+    fn_times: Option<Times>,
+    size: Option<u64>,
+}
+
+/// Apply the update sequence array, which replaces the last two bytes of every sector to detect torn writes.
+fn fixup(record: &mut [u8]) -> Option<()> {
+    let offset = u16_at(record, 4)? as usize;
+    let count = u16_at(record, 6)? as usize;
+    let usn = u16_at(record, offset)?;
+    for i in 1..count {
+        let end = i * 512;
+        if u16_at(record, end - 2)? != usn {
+            return None;
+        }
+        let replacement = record.get(offset + i * 2..offset + i * 2 + 2)?.to_vec();
+        record.get_mut(end - 2..end)?.copy_from_slice(&replacement);
+    }
+    Some(())
+}
+
+fn parse(record: &mut [u8]) -> Option<Record> {
+    if record.get(..4)? != b"FILE" {
+        return None;
+    }
+    fixup(record)?;
+    let flags = u16_at(record, 0x16)?;
+    // Extension records hold overflow attributes of another record
+    if u64_at(record, 0x20)? & 0xffff_ffff_ffff != 0 {
+        return None;
+    }
+    let mut parsed = Record {
+        in_use: flags & 1 != 0,
+        directory: flags & 2 != 0,
+        sequence: u16_at(record, 0x10)?,
+        name: None,
+        si: None,
+        fn_times: None,
+        size: None,
+    };
+    let mut offset = u16_at(record, 0x14)? as usize;
+    while let Some(kind) = u32_at(record, offset) {
+        let len = u32_at(record, offset + 4)? as usize;
+        if kind == 0xffff_ffff || len == 0 {
+            break;
+        }
+        let attribute = record.get(offset..offset + len)?;
+        let resident = attribute.get(8)? == &0;
+        let content = match resident {
+            true => {
+                let start = u16_at(attribute, 0x14)? as usize;
+                attribute.get(start..start + u32_at(attribute, 0x10)? as usize)
+            }
+            false => None,
+        };
+        match (kind, content) {
+            (STANDARD_INFORMATION, Some(c)) => parsed.si = Times::read(c),
+            (FILE_NAME, Some(c)) => {
+                let namespace = *c.get(0x41)?;
+                let better = match &parsed.name {
+                    None => true,
+                    Some((_, ns, _)) => *ns == DOS_NAMESPACE && namespace != DOS_NAMESPACE,
+                };
+                if better {
+                    let name_len = *c.get(0x40)? as usize * 2;
+                    let name: Vec<u16> = c
+                        .get(0x42..0x42 + name_len)?
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                        .collect();
+                    let parent = u64_at(c, 0)? & 0xffff_ffff_ffff;
+                    parsed.name = Some((parent, namespace, String::from_utf16_lossy(&name)));
+                    parsed.fn_times = Times::read(c.get(8..)?);
+                }
+            }
+            // Only the unnamed data stream gives the file's size
+            (DATA, _) if attribute.get(9)? == &0 => {
+                parsed.size = match content {
+                    Some(c) => Some(c.len() as u64),
+                    None => u64_at(attribute, 0x30),
+                };
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+    Some(parsed)
+}
+
+/// Read every record of an MFT, calling `record` with each record's number and contents.
+fn records(reader: impl Read, record: &mut dyn FnMut(u64, Record) -> bool) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    // Records are almost always 1024 bytes, but the first record gives the real size
+    let size = match u32_at(reader.fill_buf()?, 0x1c) {
+        Some(s) if (512..=65536).contains(&s) && s % 512 == 0 => s as usize,
+        _ => 1024,
+    };
+    let mut buffer = vec![0; size];
+    let mut number = 0;
+    loop {
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if let Some(r) = parse(&mut buffer) {
+            if !record(number, r) {
+                return Ok(());
+            }
+        }
+        number += 1;
+    }
+}
+
+/// Build the full path of a record from the names of its parent directories.
+fn full_path(names: &HashMap<u64, (u64, String)>, number: u64) -> String {
+    let mut parts = Vec::new();
+    let mut current = number;
+    // Bounded so corrupt parent references can't loop forever
+    for _ in 0..256 {
+        if current == ROOT {
+            parts.reverse();
+            return format!("\\{}", parts.join("\\"));
+        }
+        match names.get(&current) {
+            Some((parent, name)) => {
+                parts.push(name.as_str());
+                current = *parent;
+            }
+            None => break,
+        }
+    }
+    parts.reverse();
+    format!("\\$Orphan\\{}", parts.join("\\"))
+}
+
+/// Opens an MFT from its start, as it's read twice.
+pub type Open<'a> = &'a mut dyn FnMut() -> io::Result<Box<dyn Read>>;
+
+fn read_mft(
+    open: Open,
+    source: &str,
+    send: &mut dyn FnMut(Result<Value, String>) -> bool,
+) -> Result<bool, String> {
+    let error = |e: io::Error| format!("Unable to read MFT {}, {}", source, e);
+    // Paths need every directory's name, so the names are read before any events are sent
+    let mut names = HashMap::new();
+    records(open().map_err(error)?, &mut |number, r| {
+        if let Some((parent, _, name)) = r.name {
+            names.insert(number, (parent, name));
+        }
+        true
+    })
+    .map_err(error)?;
+    let mut sending = true;
+    records(open().map_err(error)?, &mut |number, r| {
+        let (name, path) = match &r.name {
+            Some((_, _, name)) => (json!(name), json!(full_path(&names, number))),
+            None => (Value::Null, Value::Null),
+        };
+        let si_before_fn = match (&r.si, &r.fn_times) {
+            (Some(si), Some(f)) => json!(si.0[0] < f.0[0]),
+            _ => Value::Null,
+        };
+        let event = json!({
+            "record": number,
+            "sequence": r.sequence,
+            "in_use": r.in_use,
+            "directory": r.directory,
+            "name": name,
+            "path": path,
+            "size": r.size,
+            "si": r.si.as_ref().map(Times::json),
+            "fn": r.fn_times.as_ref().map(Times::json),
+            "si_before_fn": si_before_fn,
+        });
+        sending = send(Ok(event));
+        sending
+    })
+    .map_err(error)?;
+    Ok(sending)
+}
+
+/// Send an event per file record of an MFT, returning false once events are no longer wanted.
+pub fn mft_events(
+    open: Open,
+    source: &str,
+    send: &mut dyn FnMut(Result<Value, String>) -> bool,
+) -> bool {
+    match read_mft(open, source, send) {
+        Ok(sending) => sending,
+        Err(e) => send(Err(e)),
+    }
+}
+
+/// Format a bodyfile time, seconds since the unix epoch with an optional fraction, 0 when unknown.
+/// Corrupt times past 9999 are `null`.
+fn epoch(s: &str) -> Value {
+    match s.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs <= RFC3339_MAX as f64 => {
+            let time = UNIX_EPOCH + Duration::from_secs_f64(secs);
+            json!(humantime::format_rfc3339_micros(time).to_string())
+        }
+        _ => Value::Null,
+    }
+}
+
+/// Send an event per line of a bodyfile, returning false once events are no longer wanted.
+pub fn bodyfile_events(
+    reader: impl BufRead,
+    source: &str,
+    send: &mut dyn FnMut(Result<Value, String>) -> bool,
+) -> bool {
+    for (i, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => return send(Err(format!("Unable to read {}, {}", source, e))),
+        };
+        // Names may contain '|', so the fields after the name are taken from the end
+        let fields: Vec<&str> = line.split('|').collect();
+        let event = match fields.len() {
+            n if n >= 11 => {
+                let rest = &fields[n - 9..];
+                Ok(json!({
+                    "md5": fields[0],
+                    "name": fields[1..n - 9].join("|"),
+                    "inode": rest[0],
+                    "mode": rest[1],
+                    "uid": rest[2].parse::<u64>().ok(),
+                    "gid": rest[3].parse::<u64>().ok(),
+                    "size": rest[4].parse::<u64>().ok(),
+                    "accessed": epoch(rest[5]),
+                    "modified": epoch(rest[6]),
+                    "changed": epoch(rest[7]),
+                    "created": epoch(rest[8]),
+                }))
+            }
+            _ if line.trim().is_empty() => continue,
+            _ => Err(format!("Invalid bodyfile line {} in {}", i + 1, source)),
+        };
+        if !send(event) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Read filesystem timelines, either raw `$MFT` files or bodyfiles as used by mactime, as an event per file.
+pub fn open(paths: Vec<PathBuf>, bodyfile: bool) -> Result<Input, String> {
+    for path in paths.iter() {
+        if !path.is_file() {
+            return Err(format!("Unable to read input file at {}.", path.display()));
+        }
+    }
+    let (tx, rx) = mpsc::sync_channel(1024);
+    thread::spawn(move || {
+        for path in paths {
+            let source = path.display().to_string();
+            let send = &mut |e| tx.send(e).is_ok();
+            let open = match bodyfile {
+                true => match fs::File::open(&path) {
+                    Ok(file) => bodyfile_events(BufReader::new(file), &source, send),
+                    Err(e) => send(Err(format!("Unable to read {}, {}", source, e))),
+                },
+                false => mft_events(
+                    &mut || Ok(Box::new(fs::File::open(&path)?) as Box<dyn Read>),
+                    &source,
+                    send,
+                ),
+            };
+            if !open {
+                break;
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bodyfile_times() {
+        assert_eq!(epoch("0"), Value::Null);
+        assert_eq!(epoch("1700000000.5"), json!("2023-11-14T22:13:20.500000Z"));
+        assert_eq!(epoch("1e13"), Value::Null);
+        assert_eq!(epoch("1e20"), Value::Null);
+        assert_eq!(epoch("inf"), Value::Null);
+        assert_eq!(epoch("NaN"), Value::Null);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fs,
+    io::{self, BufRead},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::input::Input;
+
+/// The most lines joined into one event, past it the event is sent and the next line starts a new
+/// one, so a log which never matches the start pattern can't be held in memory whole.
+const MAX_LINES: usize = 10_000;
+
+/// Lines being joined into an event.
+struct Pending {
+    lines: Vec<String>,
+    fields: Map<String, Value>,
+}
+
+impl Pending {
+    fn event(self) -> Value {
+        let mut fields = self.fields;
+        fields.insert("message".into(), Value::String(self.lines.join("\n")));
+        Value::Object(fields)
+    }
+}
+
+/// Join the lines of a plain text log into events, a line matching `start` begins a new event and
+/// every line until the next is added to it. Returns whether the input should still be read.
+fn read(
+    reader: impl BufRead,
+    start: &Regex,
+    tx: &mpsc::SyncSender<Result<Value, String>>,
+) -> io::Result<bool> {
+    let mut pending: Option<Pending> = None;
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        let full = pending
+            .as_ref()
+            .map(|p| p.lines.len() >= MAX_LINES)
+            .unwrap_or(false);
+        match start.captures(line) {
+            Some(caps) => {
+                if let Some(p) = pending.take() {
+                    if tx.send(Ok(p.event())).is_err() {
+                        return Ok(false);
+                    }
+                }
+                // The start pattern's named groups are kept as fields, e.g. the time or level
+                let fields = start
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|n| Some((n.to_string(), caps.name(n)?.as_str().into())))
+                    .collect();
+                pending = Some(Pending {
+                    lines: vec![line.to_string()],
+                    fields,
+                });
+            }
+            None => match pending.as_mut() {
+                Some(p) if !full => p.lines.push(line.to_string()),
+                _ => {
+                    // Lines before the first start are joined into an event without fields, and a
+                    // line past the limit starts a new event which the lines after it join
+                    if let Some(p) = pending.take() {
+                        if tx.send(Ok(p.event())).is_err() {
+                            return Ok(false);
+                        }
+                    }
+                    pending = Some(Pending {
+                        lines: vec![line.to_string()],
+                        fields: Map::new(),
+                    });
+                }
+            },
+        }
+    }
+    match pending {
+        Some(p) => Ok(tx.send(Ok(p.event())).is_ok()),
+        None => Ok(true),
+    }
+}
+
+/// Read plain text logs whose events span several lines, such as stack traces, from the given
+/// files or stdin, joining each event's lines into its `message` field.
+pub fn open(paths: Option<Vec<PathBuf>>, start: &str) -> Result<Input, String> {
+    let start =
+        Regex::new(start).map_err(|e| format!("Invalid multiline start regex {}, {}", start, e))?;
+    if let Some(paths) = &paths {
+        for path in paths.iter() {
+            if !path.is_file() {
+                return Err(format!("Unable to read input file at {}.", path.display()));
+            }
+        }
+    }
+    let (tx, rx) = mpsc::sync_channel(1024);
+    thread::spawn(move || match paths {
+        Some(paths) => {
+            for path in paths {
+                let result = fs::File::open(&path)
+                    .and_then(|f| read(io::BufReader::new(f), &start, &tx))
+                    .map_err(|e| format!("Unable to read input file at {}, {}", path.display(), e));
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        if tx.send(Err(e)).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        None => {
+            if let Err(e) = read(io::stdin().lock(), &start, &tx) {
+                let _ = tx.send(Err(format!("Unable to read stdin, {}", e)));
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lines_are_joined_from_each_start() {
+        let start = Regex::new(r"^(?P<level>[A-Z]+) ").unwrap();
+        let log = "preamble\nmore\nERROR one\n  at a\nINFO two\n";
+        let (tx, rx) = mpsc::sync_channel(16);
+        assert!(read(log.as_bytes(), &start, &tx).unwrap());
+        drop(tx);
+        let events: Vec<Value> = rx.into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            events,
+            vec![
+                json!({"message": "preamble\nmore"}),
+                json!({"level": "ERROR", "message": "ERROR one\n  at a"}),
+                json!({"level": "INFO", "message": "INFO two"}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use regex::Regex;
+use serde_json::Value;
+use serde_yaml::{Mapping, Value as Yaml};
+use structopt::StructOpt;
+use tau_engine::Rule;
+
+use crate::input::Input;
+use crate::output::csv_escape;
+use crate::rules;
+
+#[derive(StructOpt)]
+pub struct Mutate {
+    /// The rules to mutate.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+
+    /// A labelled corpus to check the mutants against, as well as each rule's own tests.
+    #[structopt(short, long, parse(from_os_str))]
+    input: Option<Vec<PathBuf>>,
+
+    /// The field holding the names of the rules an event should match.
+    #[structopt(long, default_value = "labels")]
+    label_field: String,
+}
+
+/// Widen a numeric comparison by one step, e.g. `> 5` to `>= 5` and `>= 5` to `>= 4`.
+fn widen(op: &str, n: i64) -> Option<String> {
+    match op {
+        ">" => Some(format!(">={}", n)),
+        "<" => Some(format!("<={}", n)),
+        ">=" => Some(format!(">={}", n - 1)),
+        "<=" => Some(format!("<={}", n + 1)),
+        _ => None,
+    }
+}
+
+/// Mutations of a rule's condition, negating each identifier, swapping `and` and `or`, and widening
+/// numeric comparisons.
+fn condition_mutants(condition: &str, identifiers: &[String]) -> Vec<(String, String)> {
+    let mut mutants = Vec::new();
+    let replace = |start: usize, end: usize, with: &str| {
+        format!("{}{}{}", &condition[..start], with, &condition[end..])
+    };
+    for identifier in identifiers {
+        let pattern = match Regex::new(&format!(r"\b{}\b", regex::escape(identifier))) {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        for m in pattern.find_iter(condition) {
+            mutants.push((
+                format!("negate {} in condition", identifier),
+                replace(m.start(), m.start(), "not "),
+            ));
+        }
+    }
+    let operators = Regex::new(r"\b(and|or)\b").expect("valid regex");
+    for m in operators.find_iter(condition) {
+        let swapped = match m.as_str() {
+            "and" => "or",
+            _ => "and",
+        };
+        mutants.push((
+            format!("swap {} for {} in condition", m.as_str(), swapped),
+            replace(m.start(), m.end(), swapped),
+        ));
+    }
+    let comparisons = Regex::new(r"(>=|<=|>|<)\s*(-?\d+)\b").expect("valid regex");
+    for c in comparisons.captures_iter(condition) {
+        let (all, op) = (c.get(0).expect("whole match"), &c[1]);
+        if let Some(widened) = c[2].parse().ok().and_then(|n| widen(op, n)) {
+            mutants.push((
+                format!("widen {} to {} in condition", all.as_str(), widened),
+                replace(all.start(), all.end(), &widened),
+            ));
+        }
+    }
+    mutants
+}
+
+/// Mutations of an identifier's fields, dropping each field when there are several, and widening
+/// numeric comparisons.
+fn identifier_mutants(identifier: &str, fields: &Mapping) -> Vec<(String, Mapping)> {
+    let mut mutants = Vec::new();
+    let comparison = Regex::new(r"^(>=|<=|>|<)(-?\d+)$").expect("valid regex");
+    for (key, value) in fields.iter() {
+        let name = key.as_str().unwrap_or_default();
+        if fields.len() > 1 {
+            let mut dropped = fields.clone();
+            dropped.remove(key);
+            mutants.push((format!("drop {}.{}", identifier, name), dropped));
+        }
+        let widened = value
+            .as_str()
+            .and_then(|v| comparison.captures(v))
+            .and_then(|c| widen(&c[1], c[2].parse().ok()?));
+        if let Some(widened) = widened {
+            let mut changed = fields.clone();
+            changed.insert(key.clone(), Yaml::String(widened.clone()));
+            mutants.push((
+                format!("widen {}.{} to {}", identifier, name, widened),
+                changed,
+            ));
+        }
+    }
+    mutants
+}
+
+/// Every mutant of a rule, as a description and the mutated rule.
+fn mutants(rule: &Yaml) -> Vec<(String, Yaml)> {
+    let detection = match rule.get("detection").and_then(|d| d.as_mapping()) {
+        Some(d) => d,
+        None => return vec![],
+    };
+    let identifiers: Vec<String> = detection
+        .iter()
+        .filter_map(|(k, _)| k.as_str())
+        .filter(|k| *k != "condition")
+        .map(|k| k.to_string())
+        .collect();
+    let with_detection = |key: Yaml, value: Yaml| {
+        let mut detection = detection.clone();
+        detection.insert(key, value);
+        let mut rule = rule.clone();
+        if let Some(r) = rule.as_mapping_mut() {
+            r.insert("detection".into(), Yaml::Mapping(detection));
+        }
+        rule
+    };
+    let mut mutants = Vec::new();
+    if let Some(condition) = detection.get(&"condition".into()).and_then(|c| c.as_str()) {
+        for (description, condition) in condition_mutants(condition, &identifiers) {
+            mutants.push((
+                description,
+                with_detection("condition".into(), condition.into()),
+            ));
+        }
+    }
+    for identifier in identifiers.iter() {
+        let key: Yaml = identifier.as_str().into();
+        if let Some(fields) = detection.get(&key).and_then(|f| f.as_mapping()) {
+            for (description, fields) in identifier_mutants(identifier, fields) {
+                mutants.push((
+                    description,
+                    with_detection(key.clone(), Yaml::Mapping(fields)),
+                ));
+            }
+        }
+    }
+    mutants
+}
+
+/// Whether a mutant is caught, either by the rule's own tests or by an event in the corpus it
+/// disagrees with the labels on. `None` when the mutant isn't a valid rule.
+fn killed(mutant: &Yaml, corpus: &[Value], name: &str, label_field: &str) -> Option<bool> {
+    let rule = Rule::load(&serde_yaml::to_string(mutant).ok()?).ok()?;
+    match rule.validate() {
+        Ok(true) => {}
+        Ok(false) => return Some(true),
+        Err(_) => return None,
+    }
+    Some(
+        corpus
+            .iter()
+            .any(|json| rule.matches(json) != rules::labelled(json, label_field, name)),
+    )
+}
+
+impl Mutate {
+    pub fn run(self) -> Result<(), String> {
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let mut corpus = Vec::new();
+        if self.input.is_some() {
+            for res in Input::open(self.input.clone(), None, None)? {
+                match res {
+                    Ok(json) => corpus.push(json),
+                    Err(e) => {
+                        let _ = writeln!(stderr, "{}", e);
+                    }
+                }
+            }
+        }
+        let mut scores = BTreeMap::new();
+        let _ = writeln!(stdout, "Rule, Mutation, Killed");
+        for ((name, rule), path) in rules::load(&self.rules)?.iter().zip(self.rules.iter()) {
+            let wrong = corpus
+                .iter()
+                .filter(|json| {
+                    rule.matches(*json) != rules::labelled(json, &self.label_field, name)
+                })
+                .count();
+            if wrong > 0 {
+                let _ = writeln!(
+                    stderr,
+                    "{} disagrees with the labels on {} events before mutation",
+                    name, wrong
+                );
+            }
+            let data = fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+            let yaml: Yaml = serde_yaml::from_str(&data)
+                .map_err(|e| format!("Unable to parse {}, {}", path.display(), e))?;
+            let (mut total, mut caught) = (0, 0);
+            for (description, mutant) in mutants(&yaml) {
+                let killed = match killed(&mutant, &corpus, name, &self.label_field) {
+                    Some(k) => k,
+                    None => continue,
+                };
+                total += 1;
+                if killed {
+                    caught += 1;
+                }
+                let _ = writeln!(
+                    stdout,
+                    "{}, {}, {}",
+                    csv_escape(name),
+                    csv_escape(&description),
+                    killed
+                );
+            }
+            scores.insert(name.clone(), (caught, total));
+        }
+        for (name, (caught, total)) in scores {
+            let score = match total {
+                0 => 100.0,
+                t => caught as f64 * 100.0 / t as f64,
+            };
+            let _ = writeln!(
+                stderr,
+                "{}, {} of {} mutants killed ({:.0}%)",
+                name, caught, total, score
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs,
+    io::{self, stderr, stdin, stdout, BufRead, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+use serde_yaml::{Mapping, Value as Yaml};
+use structopt::StructOpt;
+use tau_engine::Rule;
+
+use crate::input::Input;
+use crate::profile::walk;
+use crate::rules::Level;
+
+#[derive(StructOpt)]
+pub struct NewRule {
+    /// The rule's title.
+    #[structopt(long)]
+    title: Option<String>,
+
+    /// The rule's level, one of informational, low, medium, high or critical.
+    #[structopt(long)]
+    level: Option<String>,
+
+    /// Comma separated tags for the rule.
+    #[structopt(long, use_delimiter = true)]
+    tags: Vec<String>,
+
+    /// A file whose first event the rule should match, used as its values and true positive.
+    #[structopt(long, parse(from_os_str))]
+    event: PathBuf,
+
+    /// Comma separated fields of the example event to match on.
+    #[structopt(long, use_delimiter = true)]
+    fields: Vec<String>,
+
+    /// Where to write the rule, stdout when not given. Existing files are not overwritten.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+/// Ask a question on stderr, returning the answer or `default` when nothing is entered.
+fn prompt(question: &str, default: &str) -> Result<String, String> {
+    let mut stderr = stderr();
+    let _ = match default.is_empty() {
+        true => write!(stderr, "{}: ", question),
+        false => write!(stderr, "{} [{}]: ", question, default),
+    };
+    let _ = stderr.flush();
+    let mut answer = String::new();
+    stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| format!("Unable to read answer, {}", e))?;
+    Ok(match answer.trim() {
+        "" => default.to_string(),
+        a => a.to_string(),
+    })
+}
+
+/// A value for a field which matches exactly. Strings which Tau would read as a pattern, those
+/// with a `*` or starting with `?` or `i`, are written as an anchored regex.
+pub fn exact(value: &Value) -> Yaml {
+    match value {
+        Value::String(s) if s.contains('*') || s.starts_with('?') || s.starts_with('i') => {
+            Yaml::String(format!("?^{}$", regex::escape(s)))
+        }
+        v => serde_yaml::to_value(v).unwrap_or(Yaml::Null),
+    }
+}
+
+/// A value the rule shouldn't match in place of `value`, for the true negative.
+fn different(value: &Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(format!("not {}", s)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::from(i.wrapping_add(1)),
+            None => Value::from(n.as_f64().unwrap_or(0.0) + 1.0),
+        },
+        Value::Bool(b) => Value::Bool(!b),
+        _ => Value::String("not matched".into()),
+    }
+}
+
+impl NewRule {
+    /// The example event's scalar fields, in order and without repeats.
+    fn leaves(event: &Value) -> Vec<(String, Value)> {
+        let mut leaves = Vec::new();
+        walk(String::new(), event, &mut leaves);
+        let mut fields: Vec<(String, Value)> = Vec::new();
+        for (field, value) in leaves {
+            if matches!(value, Value::Null | Value::Array(_) | Value::Object(_))
+                || fields.iter().any(|(f, _)| *f == field)
+            {
+                continue;
+            }
+            fields.push((field, value.clone()));
+        }
+        fields
+    }
+
+    /// Fill in whatever wasn't given as a flag, prompting when stdin is a terminal.
+    fn ask(&mut self, leaves: &[(String, Value)]) -> Result<(), String> {
+        if !stdin().is_terminal() {
+            return Ok(());
+        }
+        if self.title.is_none() {
+            self.title = Some(prompt("Title", "")?);
+        }
+        if self.level.is_none() {
+            self.level = Some(prompt(
+                "Level (informational, low, medium, high or critical)",
+                "medium",
+            )?);
+        }
+        if self.tags.is_empty() {
+            self.tags = prompt("Tags, comma separated", "")?
+                .split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect();
+        }
+        if self.fields.is_empty() {
+            let mut stderr = stderr();
+            for (i, (field, value)) in leaves.iter().enumerate() {
+                let _ = writeln!(stderr, "{:>4}. {} = {}", i + 1, field, value);
+            }
+            for choice in prompt("Fields to match, by number or name, comma separated", "")?
+                .split(',')
+                .map(|c| c.trim())
+                .filter(|c| !c.is_empty())
+            {
+                let field = match choice.parse::<usize>() {
+                    Ok(i) if i >= 1 && i <= leaves.len() => leaves[i - 1].0.clone(),
+                    Ok(_) => return Err(format!("There is no field {}", choice)),
+                    Err(_) => choice.to_string(),
+                };
+                self.fields.push(field);
+            }
+        }
+        Ok(())
+    }
+
+    fn rule(&self, event: &Value, leaves: &[(String, Value)]) -> Result<Mapping, String> {
+        let mut rule = Mapping::new();
+        let mut text = |key: &str, value: &Option<String>| {
+            if let Some(v) = value.as_ref().filter(|v| !v.is_empty()) {
+                rule.insert(Yaml::from(key), Yaml::from(v.clone()));
+            }
+        };
+        text("title", &self.title);
+        let level = match &self.level {
+            Some(l) => {
+                l.parse::<Level>()?;
+                Some(l.to_lowercase())
+            }
+            None => None,
+        };
+        text("level", &level);
+        if !self.tags.is_empty() {
+            let tags = self.tags.iter().map(|t| Yaml::from(t.clone())).collect();
+            rule.insert(Yaml::from("tags"), Yaml::Sequence(tags));
+        }
+        let mut selection = Mapping::new();
+        let mut negative = event.clone();
+        for (i, field) in self.fields.iter().enumerate() {
+            let value = leaves
+                .iter()
+                .find(|(f, _)| f == field)
+                .map(|(_, v)| v)
+                .ok_or_else(|| format!("The example event has no value for {}", field))?;
+            selection.insert(Yaml::from(field.clone()), exact(value));
+            // Changing one field is enough for a selection of fields joined by and to miss
+            if i == 0 {
+                if let Some(v) = crate::event::get_mut(&mut negative, field) {
+                    *v = different(value);
+                }
+            }
+        }
+        let mut detection = Mapping::new();
+        detection.insert(Yaml::from("selection"), Yaml::Mapping(selection));
+        detection.insert(Yaml::from("condition"), Yaml::from("selection"));
+        rule.insert(Yaml::from("detection"), Yaml::Mapping(detection));
+        let tests = |json: &Value| {
+            serde_yaml::to_value(json)
+                .map(|v| Yaml::Sequence(vec![v]))
+                .map_err(|e| e.to_string())
+        };
+        rule.insert(Yaml::from("true_positives"), tests(event)?);
+        rule.insert(Yaml::from("true_negatives"), tests(&negative)?);
+        Ok(rule)
+    }
+
+    pub fn run(mut self) -> Result<(), String> {
+        let event = match Input::open(Some(vec![self.event.clone()]), None, None)?.next() {
+            Some(Ok(json)) => json,
+            Some(Err(e)) => return Err(format!("Invalid example event, {}", e)),
+            None => return Err(format!("There are no events in {}", self.event.display())),
+        };
+        let leaves = Self::leaves(&event);
+        self.ask(&leaves)?;
+        if self.fields.is_empty() {
+            return Err("Choose at least one field to match, use --fields".into());
+        }
+        let yaml = serde_yaml::to_string(&self.rule(&event, &leaves)?)
+            .map_err(|e| format!("Unable to write the rule, {}", e))?;
+        if !matches!(Rule::load(&yaml).map(|r| r.validate()), Ok(Ok(true))) {
+            let _ = writeln!(
+                stderr(),
+                "The new rule doesn't pass its own tests, check it before use"
+            );
+        }
+        match &self.output {
+            Some(path) => fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .and_then(|mut f| f.write_all(yaml.as_bytes()))
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::AlreadyExists => format!("{} already exists", path.display()),
+                    _ => format!("Could not create output file at {}", path.display()),
+                }),
+            None => stdout()
+                .write_all(yaml.as_bytes())
+                .map_err(|e| format!("Unable to write the rule, {}", e)),
+        }
+    }
+}
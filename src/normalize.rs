@@ -0,0 +1,439 @@
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use crate::event;
+
+/// A way of normalizing string values into a shadow field, so trivially encoded values still match.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// Strip thousands separators from numbers, e.g. `1,024` to `1024`, but not from lists such as
+    /// `135,139,445`.
+    Numbers,
+    /// Use backslashes throughout Windows paths and collapse repeated ones.
+    Paths,
+    /// Decode base64 into a shadow field.
+    Base64,
+    /// Decode percent encoding into a shadow field.
+    Url,
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "numbers" => Ok(Normalization::Numbers),
+            "paths" => Ok(Normalization::Paths),
+            "base64" => Ok(Normalization::Base64),
+            "url" => Ok(Normalization::Url),
+            _ => Err(format!(
+                "Unknown normalization {}, expected numbers, paths, base64 or url",
+                s
+            )),
+        }
+    }
+}
+
+/// The suffix added to a field's name for its normalized shadow field. A field can't hold both a
+/// value and a `decoded` child, so the shadow sits alongside it.
+const DECODED: &str = "_decoded";
+
+/// The shortest run of base64 decoded, shorter runs are too often ordinary words.
+const MIN_BASE64: usize = 16;
+
+fn numbers(s: &str) -> Option<String> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let (whole, fraction) = match digits.split_once('.') {
+        Some((w, f)) => (w, Some(f)),
+        None => (digits, None),
+    };
+    let groups: Vec<&str> = whole.split(',').collect();
+    // Three digits before the first separator could as well be a list, unless a later group
+    // starts with a zero no list would have or there's a fraction
+    let unambiguous =
+        groups[0].len() < 3 || fraction.is_some() || groups[1..].iter().any(|g| g.starts_with('0'));
+    let grouped = groups.len() > 1
+        && unambiguous
+        && (1..=3).contains(&groups[0].len())
+        && !groups[0].starts_with('0')
+        && groups[1..].iter().all(|g| g.len() == 3)
+        && groups.iter().all(|g| g.bytes().all(|b| b.is_ascii_digit()))
+        && fraction
+            .map(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+            .unwrap_or(true);
+    match grouped {
+        true => Some(s.replace(',', "")),
+        false => None,
+    }
+}
+
+fn paths(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let drive = bytes.len() > 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'\\' || bytes[2] == b'/');
+    // Paths starting with // are as often URLs without a scheme, so only \\ is taken as UNC
+    let unc = s.starts_with("\\\\");
+    if !drive && !unc {
+        return None;
+    }
+    // A UNC path keeps its leading pair of separators, every other run becomes one
+    let (mut normalized, rest) = match unc {
+        true => (String::from("\\\\"), &s[2..]),
+        false => (String::new(), s),
+    };
+    for c in rest.chars() {
+        let c = if c == '/' { '\\' } else { c };
+        if !(c == '\\' && normalized.ends_with('\\')) {
+            normalized.push(c);
+        }
+    }
+    match normalized != s {
+        true => Some(normalized),
+        false => None,
+    }
+}
+
+fn sextet(b: u8) -> Option<u32> {
+    match b {
+        b'A'..=b'Z' => Some((b - b'A') as u32),
+        b'a'..=b'z' => Some((b - b'a' + 26) as u32),
+        b'0'..=b'9' => Some((b - b'0' + 52) as u32),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decode standard or URL safe base64, with or without padding.
+fn base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    if s.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    for chunk in s.as_bytes().chunks(4) {
+        let mut n = 0;
+        for (i, b) in chunk.iter().enumerate() {
+            n |= sextet(*b)? << (18 - 6 * i);
+        }
+        let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        bytes.extend_from_slice(&decoded[..chunk.len() - 1]);
+    }
+    Some(bytes)
+}
+
+/// Decoded bytes as text, as UTF-8 or the UTF-16 PowerShell's -EncodedCommand takes. Anything
+/// which isn't mostly printable is taken to be binary, or not base64 at all.
+fn text(bytes: &[u8]) -> Option<String> {
+    let mostly = |s: &str, printable: fn(&char) -> bool| {
+        let total = s.chars().count();
+        total > 0 && s.chars().filter(printable).count() * 10 >= total * 9
+    };
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        if mostly(s, |c| !c.is_control() || c.is_whitespace()) {
+            return Some(s.to_string());
+        }
+    }
+    let pairs = bytes.chunks_exact(2);
+    if pairs.remainder().is_empty() {
+        let units: Vec<u16> = pairs.map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        // Most random bytes are valid UTF-16, so only ASCII text is taken as a command
+        if let Ok(s) = String::from_utf16(&units) {
+            if mostly(&s, |c| c.is_ascii_graphic() || c.is_ascii_whitespace()) {
+                return Some(s);
+            }
+        }
+    }
+    None
+}
+
+/// Decode each whitespace separated run of base64 in a value, such as an encoded command in a
+/// command line, leaving the rest as it is.
+fn base64_runs(s: &str) -> Option<String> {
+    let mut decoded = false;
+    let words: Vec<String> = s
+        .split(' ')
+        .map(|word| {
+            let trimmed = word.trim_matches(|c| c == '"' || c == '\'');
+            let text = Some(trimmed)
+                .filter(|w| w.len() >= MIN_BASE64)
+                .and_then(base64)
+                .and_then(|b| text(&b));
+            match text {
+                Some(t) => {
+                    decoded = true;
+                    t
+                }
+                None => word.to_string(),
+            }
+        })
+        .collect();
+    match decoded {
+        true => Some(words.join(" ")),
+        false => None,
+    }
+}
+
+fn url(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    match decoded.len() != bytes.len() {
+        true => Some(String::from_utf8_lossy(&decoded).into_owned()),
+        false => None,
+    }
+}
+
+/// The normalized form of a value, when any of the enabled normalizations change it.
+fn normalize_str(s: &str, kinds: &[Normalization]) -> Option<String> {
+    type Step = fn(&str) -> Option<String>;
+    let mut value = None;
+    let steps: [(Normalization, Step); 4] = [
+        (Normalization::Url, url),
+        (Normalization::Base64, base64_runs),
+        (Normalization::Numbers, numbers),
+        (Normalization::Paths, paths),
+    ];
+    for (kind, step) in steps {
+        if kinds.contains(&kind) {
+            if let Some(n) = step(value.as_deref().unwrap_or(s)) {
+                value = Some(n);
+            }
+        }
+    }
+    value
+}
+
+/// The shadow of a string, or of an array when any of its strings change.
+fn shadow(v: &Value, kinds: &[Normalization]) -> Option<Value> {
+    match v {
+        Value::String(s) => normalize_str(s, kinds).map(Value::String),
+        Value::Array(a) => {
+            let shadows: Vec<Option<Value>> = a.iter().map(|v| shadow(v, kinds)).collect();
+            if shadows.iter().all(Option::is_none) {
+                return None;
+            }
+            let values = a
+                .iter()
+                .zip(shadows)
+                .map(|(v, s)| s.unwrap_or_else(|| v.clone()));
+            Some(Value::Array(values.collect()))
+        }
+        _ => None,
+    }
+}
+
+fn normalize_object(o: &mut Map<String, Value>, kinds: &[Normalization]) {
+    let mut shadows = Vec::new();
+    for (k, v) in o.iter_mut() {
+        if let Some(s) = shadow(v, kinds) {
+            shadows.push((format!("{}{}", k, DECODED), s));
+        }
+        normalize(v, kinds);
+    }
+    o.extend(shadows);
+}
+
+/// Add a shadow field alongside each string field the normalizations change, holding the
+/// normalized value, throughout an event. The event's own values are left as they are.
+pub fn normalize(json: &mut Value, kinds: &[Normalization]) {
+    match json {
+        Value::Object(o) => normalize_object(o, kinds),
+        Value::Array(a) => a.iter_mut().for_each(|v| normalize(v, kinds)),
+        _ => {}
+    }
+}
+
+/// The suffix added to a command line field's name for its deobfuscated shadow field, which sits
+/// alongside it as `DECODED` does.
+const NORMALIZED: &str = "_normalized";
+
+/// Whether a field's name marks it as holding a command line, for --deobfuscate without fields.
+fn is_command_line(key: &str) -> bool {
+    let key = key.to_lowercase();
+    ["commandline", "command_line", "cmdline"]
+        .iter()
+        .any(|n| key.ends_with(n))
+}
+
+/// Whether a switch is PowerShell's -EncodedCommand, which takes any unambiguous prefix.
+fn is_encoded_command(switch: &str) -> bool {
+    let name = match switch
+        .strip_prefix('-')
+        .or_else(|| switch.strip_prefix('/'))
+    {
+        Some(n) if !n.is_empty() => n.to_lowercase(),
+        _ => return false,
+    };
+    name == "e" || name == "ec" || (name.len() >= 2 && "encodedcommand".starts_with(&name))
+}
+
+/// A command line with common obfuscation undone, for rules to match however it was written:
+/// cmd's caret escapes and PowerShell's backtick escapes are removed, as are empty quotes and
+/// joins of quoted strings, whitespace is collapsed, -EncodedCommand arguments are decoded and
+/// the whole is lowercased.
+pub fn deobfuscate(command: &str) -> String {
+    let mut unescaped = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '^' | '`' => {
+                if let Some(next) = chars.next() {
+                    unescaped.push(next);
+                }
+            }
+            c => unescaped.push(c),
+        }
+    }
+    for joined in ["'+'", "\"+\"", "' + '", "\" + \"", "\"\"", "''"] {
+        unescaped = unescaped.replace(joined, "");
+    }
+    let words: Vec<&str> = unescaped.split_whitespace().collect();
+    let mut decoded = Vec::with_capacity(words.len());
+    let mut i = 0;
+    while i < words.len() {
+        decoded.push(words[i].to_string());
+        if is_encoded_command(words[i]) {
+            let text = words
+                .get(i + 1)
+                .map(|w| w.trim_matches(|c| c == '"' || c == '\''))
+                .and_then(base64)
+                .and_then(|b| text(&b));
+            if let Some(text) = text {
+                decoded.push(text.split_whitespace().collect::<Vec<_>>().join(" "));
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+    decoded.join(" ").to_lowercase()
+}
+
+fn deobfuscate_object(o: &mut Map<String, Value>) {
+    let mut shadows = Vec::new();
+    for (k, v) in o.iter_mut() {
+        match v {
+            Value::String(s) if is_command_line(k) => {
+                shadows.push((format!("{}{}", k, NORMALIZED), deobfuscate(s).into()));
+            }
+            v => deobfuscate_all(v),
+        }
+    }
+    o.extend(shadows);
+}
+
+fn deobfuscate_all(json: &mut Value) {
+    match json {
+        Value::Object(o) => deobfuscate_object(o),
+        Value::Array(a) => a.iter_mut().for_each(deobfuscate_all),
+        _ => {}
+    }
+}
+
+/// Add a deobfuscated shadow field, named with a `_normalized` suffix, alongside each of the given
+/// fields, or alongside every field named like a command line when none are given.
+pub fn deobfuscate_fields(json: &mut Value, fields: &[String]) {
+    if fields.is_empty() {
+        return deobfuscate_all(json);
+    }
+    for field in fields {
+        if let Some(Value::String(s)) = event::get(json, field) {
+            let normalized = deobfuscate(s);
+            event::set(json, &format!("{}{}", field, NORMALIZED), normalized.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn only_unambiguous_numbers() {
+        assert_eq!(numbers("1,024").as_deref(), Some("1024"));
+        assert_eq!(numbers("-12,345.5").as_deref(), Some("-12345.5"));
+        assert_eq!(numbers("100,000").as_deref(), Some("100000"));
+        assert_eq!(numbers("135,139,445"), None);
+        assert_eq!(numbers("22,80,443"), None);
+        assert_eq!(numbers("0,123"), None);
+    }
+
+    #[test]
+    fn only_windows_paths() {
+        assert_eq!(
+            paths("C:/Windows//System32").as_deref(),
+            Some("C:\\Windows\\System32")
+        );
+        assert_eq!(paths("\\\\host/share").as_deref(), Some("\\\\host\\share"));
+        assert_eq!(paths("//cdn.example.com/x.js"), None);
+        assert_eq!(paths("C:\\Windows"), None);
+    }
+
+    #[test]
+    fn values_are_left_as_they_are() {
+        let mut event = json!({
+            "Size": "1,024",
+            "Ports": ["80", "1,024"],
+            "Url": "//host/x",
+            "Nested": { "Path": "C:/x" },
+        });
+        normalize(&mut event, &[Normalization::Numbers, Normalization::Paths]);
+        assert_eq!(
+            event,
+            json!({
+                "Size": "1,024",
+                "Size_decoded": "1024",
+                "Ports": ["80", "1,024"],
+                "Ports_decoded": ["80", "1024"],
+                "Url": "//host/x",
+                "Nested": { "Path": "C:/x", "Path_decoded": "C:\\x" },
+            })
+        );
+    }
+
+    #[test]
+    fn command_lines_are_deobfuscated_alongside() {
+        let command = "C^m^D /c P^o`wer'+'Shell";
+        let mut event = json!({ "CommandLine": command, "Other": command });
+        deobfuscate_fields(&mut event, &[]);
+        assert_eq!(
+            event,
+            json!({
+                "CommandLine": command,
+                "CommandLine_normalized": "cmd /c powershell",
+                "Other": command,
+            })
+        );
+        let mut event = json!({ "Process": { "Args": command } });
+        deobfuscate_fields(&mut event, &["Process.Args".into()]);
+        assert_eq!(event["Process"]["Args_normalized"], "cmd /c powershell");
+    }
+
+    #[test]
+    fn decodings_combine() {
+        let encoded = "aGVsbG8gd29ybGQgZnJvbSBiYXNlNjQ%3D";
+        assert_eq!(
+            normalize_str(encoded, &[Normalization::Url, Normalization::Base64]).as_deref(),
+            Some("hello world from base64")
+        );
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    fs,
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use serde_yaml::{Mapping, Value as Yaml};
+use structopt::StructOpt;
+use tau_engine::Rule;
+
+#[derive(StructOpt)]
+pub struct Optimize {
+    /// The rules to optimize.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+
+    /// Rewrite the rule files with their optimized form rather than only showing the difference.
+    #[structopt(long)]
+    write: bool,
+}
+
+/// A condition built only from identifiers, `and`, `or`, `not` and brackets.
+#[derive(Clone, PartialEq)]
+pub enum Expression {
+    Identifier(String),
+    Not(Box<Expression>),
+    And(Vec<Expression>),
+    Or(Vec<Expression>),
+}
+
+/// Split a condition into words and brackets, `None` when it uses anything the optimizer doesn't
+/// understand, such as functions or comparisons.
+fn tokens(condition: &str) -> Option<Vec<String>> {
+    let mut tokens = Vec::new();
+    for word in condition
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+    {
+        let plain = word == "("
+            || word == ")"
+            || word
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !plain {
+            return None;
+        }
+        tokens.push(word.to_string());
+    }
+    Some(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(|t| t.as_str())
+    }
+
+    fn or(&mut self) -> Option<Expression> {
+        let mut terms = vec![self.and()?];
+        while self.peek() == Some("or") {
+            self.next += 1;
+            terms.push(self.and()?);
+        }
+        Some(Expression::Or(terms))
+    }
+
+    fn and(&mut self) -> Option<Expression> {
+        let mut terms = vec![self.not()?];
+        while self.peek() == Some("and") {
+            self.next += 1;
+            terms.push(self.not()?);
+        }
+        Some(Expression::And(terms))
+    }
+
+    fn not(&mut self) -> Option<Expression> {
+        let token = self.peek()?.to_string();
+        self.next += 1;
+        match token.as_str() {
+            "not" => Some(Expression::Not(Box::new(self.not()?))),
+            "(" => {
+                let inner = self.or()?;
+                match self.peek() {
+                    Some(")") => {
+                        self.next += 1;
+                        Some(inner)
+                    }
+                    _ => None,
+                }
+            }
+            ")" | "and" | "or" => None,
+            _ => Some(Expression::Identifier(token)),
+        }
+    }
+}
+
+pub fn parse(condition: &str) -> Option<Expression> {
+    let mut parser = Parser {
+        tokens: tokens(condition)?,
+        next: 0,
+    };
+    let expression = parser.or()?;
+    match parser.next == parser.tokens.len() {
+        true => Some(simplify(expression)),
+        false => None,
+    }
+}
+
+/// Collapse nested `and`s and `or`s into their parent, drop repeated terms and double negation.
+fn simplify(expression: Expression) -> Expression {
+    let flatten = |terms: Vec<Expression>, and: bool| {
+        let mut flat: Vec<Expression> = Vec::new();
+        for term in terms.into_iter().map(simplify) {
+            let inner = match (term, and) {
+                (Expression::And(t), true) | (Expression::Or(t), false) => t,
+                (t, _) => vec![t],
+            };
+            for t in inner {
+                if !flat.contains(&t) {
+                    flat.push(t);
+                }
+            }
+        }
+        match (flat.len(), and) {
+            (1, _) => flat.pop().expect("one term"),
+            (_, true) => Expression::And(flat),
+            (_, false) => Expression::Or(flat),
+        }
+    };
+    match expression {
+        Expression::Not(inner) => match simplify(*inner) {
+            Expression::Not(inner) => *inner,
+            inner => Expression::Not(Box::new(inner)),
+        },
+        Expression::And(terms) => flatten(terms, true),
+        Expression::Or(terms) => flatten(terms, false),
+        e => e,
+    }
+}
+
+impl Expression {
+    /// The condition as text, bracketing every nested `and` and `or` so precedence doesn't matter.
+    fn text(&self, nested: bool) -> String {
+        let join = |terms: &[Expression], op: &str| {
+            let text: Vec<String> = terms.iter().map(|t| t.text(true)).collect();
+            match nested {
+                true => format!("({})", text.join(op)),
+                false => text.join(op),
+            }
+        };
+        match self {
+            Expression::Identifier(i) => i.clone(),
+            Expression::Not(inner) => format!("not {}", inner.text(true)),
+            Expression::And(terms) => join(terms, " and "),
+            Expression::Or(terms) => join(terms, " or "),
+        }
+    }
+
+    /// The identifiers the expression uses, in order and with repeats.
+    pub fn identifiers<'a>(&'a self, found: &mut Vec<&'a str>) {
+        match self {
+            Expression::Identifier(i) => found.push(i),
+            Expression::Not(inner) => inner.identifiers(found),
+            Expression::And(terms) | Expression::Or(terms) => {
+                for t in terms {
+                    t.identifiers(found);
+                }
+            }
+        }
+    }
+
+    /// Replace an identifier with another expression wherever it's used.
+    fn replace(self, identifier: &str, with: &Expression) -> Expression {
+        match self {
+            Expression::Identifier(i) if i == identifier => with.clone(),
+            Expression::Not(inner) => Expression::Not(Box::new(inner.replace(identifier, with))),
+            Expression::And(terms) => Expression::And(
+                terms
+                    .into_iter()
+                    .map(|t| t.replace(identifier, with))
+                    .collect(),
+            ),
+            Expression::Or(terms) => Expression::Or(
+                terms
+                    .into_iter()
+                    .map(|t| t.replace(identifier, with))
+                    .collect(),
+            ),
+            e => e,
+        }
+    }
+}
+
+/// Drop repeated values from a field's list of alternatives, and the list when one value is left.
+fn values(value: &Yaml) -> Yaml {
+    match value {
+        Yaml::Sequence(s) => {
+            let mut unique: Vec<Yaml> = Vec::new();
+            for v in s {
+                if !unique.contains(v) {
+                    unique.push(v.clone());
+                }
+            }
+            match unique.len() {
+                1 => unique.pop().expect("one value"),
+                _ => Yaml::Sequence(unique),
+            }
+        }
+        v => v.clone(),
+    }
+}
+
+fn fields(fields: &Mapping) -> Mapping {
+    fields.iter().map(|(k, v)| (k.clone(), values(v))).collect()
+}
+
+/// Alternatives which each test only the same field become one list of values for that field.
+fn alternatives(alternatives: Vec<Mapping>) -> Yaml {
+    let key = match alternatives[0].iter().next() {
+        Some((k, _))
+            if alternatives
+                .iter()
+                .all(|a| a.len() == 1 && a.contains_key(k)) =>
+        {
+            k.clone()
+        }
+        _ => return Yaml::Sequence(alternatives.into_iter().map(Yaml::Mapping).collect()),
+    };
+    let mut merged = Vec::new();
+    for a in alternatives {
+        match a.get(&key) {
+            Some(Yaml::Sequence(s)) => merged.extend(s.iter().cloned()),
+            Some(v) => merged.push(v.clone()),
+            None => {}
+        }
+    }
+    let mut field = Mapping::new();
+    field.insert(key, values(&Yaml::Sequence(merged)));
+    Yaml::Mapping(field)
+}
+
+/// Optimize an identifier, returning it and the fields shared by all of its alternatives when
+/// `factor` allows them to be taken out.
+fn identifier(value: &Yaml, factor: bool) -> (Yaml, Option<Mapping>) {
+    let listed = match value {
+        Yaml::Mapping(m) => return (Yaml::Mapping(fields(m)), None),
+        Yaml::Sequence(s) if s.iter().all(|a| a.is_mapping()) => s,
+        v => return (v.clone(), None),
+    };
+    let mut unique: Vec<Mapping> = Vec::new();
+    for a in listed.iter().filter_map(|a| a.as_mapping()).map(fields) {
+        if !unique.contains(&a) {
+            unique.push(a);
+        }
+    }
+    if unique.len() == 1 {
+        return (Yaml::Mapping(unique.pop().expect("one alternative")), None);
+    }
+    let common: Mapping = unique[0]
+        .iter()
+        .filter(|(k, v)| unique[1..].iter().all(|a| a.get(k) == Some(v)))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    if common.is_empty() {
+        return (alternatives(unique), None);
+    }
+    let rest: Vec<Mapping> = unique
+        .iter()
+        .map(|a| {
+            a.iter()
+                .filter(|(k, _)| !common.contains_key(k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        })
+        .collect();
+    // An alternative of only the shared fields matches whenever they do, making the others moot
+    if rest.iter().any(|a: &Mapping| a.is_empty()) {
+        return (Yaml::Mapping(common), None);
+    }
+    match alternatives(rest) {
+        // What's left differs in one field, so it joins the shared fields in a single mapping
+        Yaml::Mapping(m) => {
+            let mut all = common;
+            all.extend(m);
+            (Yaml::Mapping(all), None)
+        }
+        rest if factor => (rest, Some(common)),
+        _ => (alternatives(unique), None),
+    }
+}
+
+/// A rule rewritten into a simpler form which matches the same events, `None` when there is
+/// nothing to simplify or the rule is one the optimizer doesn't understand.
+pub fn optimize(rule: &Yaml) -> Option<Yaml> {
+    let detection = rule.get("detection")?.as_mapping()?;
+    let mut expression = parse(detection.get(&"condition".into())?.as_str()?);
+    let mut optimized = Mapping::new();
+    for (key, value) in detection.iter() {
+        let name = key.as_str()?;
+        if name == "condition" {
+            continue;
+        }
+        // Shared fields are factored into an identifier of their own, so the condition must be
+        // one which can be rewritten to use it
+        let (value, common) = identifier(value, expression.is_some());
+        if let (Some(common), Some(e)) = (common, &expression) {
+            let mut factored = format!("{}_common", name);
+            while detection.contains_key(&factored.as_str().into())
+                || optimized.contains_key(&factored.as_str().into())
+            {
+                factored.push('_');
+            }
+            let with = Expression::And(vec![
+                Expression::Identifier(factored.clone()),
+                Expression::Identifier(name.to_string()),
+            ]);
+            expression = Some(simplify(e.clone().replace(name, &with)));
+            optimized.insert(factored.into(), Yaml::Mapping(common));
+            optimized.insert(key.clone(), value);
+            continue;
+        }
+        optimized.insert(key.clone(), value);
+    }
+    match &expression {
+        Some(e) => {
+            let mut used = Vec::new();
+            e.identifiers(&mut used);
+            // Identifiers the condition never uses can't change what the rule matches
+            optimized = optimized
+                .into_iter()
+                .filter(|(k, _)| used.contains(&k.as_str().unwrap_or_default()))
+                .collect();
+            optimized.insert("condition".into(), e.text(false).into());
+        }
+        None => {
+            optimized.insert(
+                "condition".into(),
+                detection.get(&"condition".into())?.clone(),
+            );
+        }
+    }
+    if optimized == *detection {
+        return None;
+    }
+    let mut rule = rule.as_mapping()?.clone();
+    rule.insert("detection".into(), Yaml::Mapping(optimized));
+    Some(Yaml::Mapping(rule))
+}
+
+/// The optimized form of a rule's YAML, `None` when it can't be improved.
+pub fn text(data: &str) -> Option<String> {
+    let rule: Yaml = serde_yaml::from_str(data).ok()?;
+    serde_yaml::to_string(&optimize(&rule)?).ok()
+}
+
+/// The lines removed from `before` and added in `after`, prefixed with `-` and `+`.
+fn diff(before: &str, after: &str) -> Vec<String> {
+    let (a, b): (Vec<&str>, Vec<&str>) = (before.lines().collect(), after.lines().collect());
+    // The longest common subsequence of lines, from each position to the end
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j, mut lines) = (0, 0, Vec::new());
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push(format!(" {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("-{}", a[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", b[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+impl Optimize {
+    pub fn run(self) -> Result<(), String> {
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        for path in self.rules.iter() {
+            let data = fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+            let rule: Yaml = serde_yaml::from_str(&data)
+                .map_err(|e| format!("Unable to load {} as a rule, {}", path.display(), e))?;
+            let optimized = match optimize(&rule) {
+                Some(o) => o,
+                None => {
+                    let _ = writeln!(stderr, "{} is already optimized", path.display());
+                    continue;
+                }
+            };
+            let (before, after) = match (
+                serde_yaml::to_string(&rule),
+                serde_yaml::to_string(&optimized),
+            ) {
+                (Ok(b), Ok(a)) => (b, a),
+                _ => return Err(format!("Unable to write {} as YAML", path.display())),
+            };
+            // The rule's own tests guard against a rewrite which changes what it matches
+            let passes =
+                |yaml: &str| matches!(Rule::load(yaml).map(|r| r.validate()), Ok(Ok(true)));
+            if passes(&before) && !passes(&after) {
+                return Err(format!(
+                    "The optimized form of {} fails its tests, leaving it unchanged",
+                    path.display()
+                ));
+            }
+            let _ = writeln!(stdout, "--- {}", path.display());
+            let _ = writeln!(stdout, "+++ {} (optimized)", path.display());
+            for line in diff(&before, &after) {
+                let _ = writeln!(stdout, "{}", line);
+            }
+            if self.write {
+                fs::write(path, after)
+                    .map_err(|e| format!("Unable to write {}, {}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(rule: &str) -> Yaml {
+        let optimized = text(rule).expect("optimized");
+        let yaml: Yaml = serde_yaml::from_str(&optimized).unwrap();
+        yaml["detection"].clone()
+    }
+
+    #[test]
+    fn conditions_are_flattened() {
+        let e = parse("a and (b and not not c) and a").unwrap();
+        assert_eq!(e.text(false), "a and b and c");
+        let e = parse("(a or (b or c)) and d").unwrap();
+        assert_eq!(e.text(false), "(a or b or c) and d");
+        assert!(parse("a and int(b) == 1").is_none());
+    }
+
+    #[test]
+    fn repeated_values_and_unused_identifiers_are_dropped() {
+        let d = detection(
+            "detection:\n  a:\n    Image: [cmd.exe, cmd.exe]\n  unused:\n    x: 1\n  condition: a\n",
+        );
+        assert_eq!(d["a"]["Image"], Yaml::String("cmd.exe".into()));
+        assert!(d.get("unused").is_none());
+    }
+
+    #[test]
+    fn shared_fields_are_factored_out() {
+        let d = detection(
+            "detection:\n  a:\n    - {User: root, Image: sh}\n    - {User: root, Image: bash}\n  condition: a\n",
+        );
+        assert_eq!(d["a"]["User"], Yaml::String("root".into()));
+        assert_eq!(
+            d["a"]["Image"],
+            serde_yaml::from_str::<Yaml>("[sh, bash]").unwrap()
+        );
+    }
+
+    #[test]
+    fn rules_with_nothing_to_simplify_are_left() {
+        assert!(text("detection:\n  a:\n    x: 1\n  condition: a\n").is_none());
+    }
+}
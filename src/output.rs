@@ -0,0 +1,511 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs,
+    io::{self, Stdout, Write},
+    iter,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Write an event as a line of JSON, serialising straight into the writer.
+pub fn write_json(w: &mut impl Write, json: &serde_json::Value) -> io::Result<()> {
+    serde_json::to_writer(&mut *w, json)?;
+    w.write_all(b"\n")
+}
+
+pub enum Output {
+    CommandLine(Stdout),
+    Files(Vec<(Sink, String)>),
+}
+
+impl Output {
+    /// Write a match to the rule's file, or the only output.
+    pub fn write_match(&mut self, json: &serde_json::Value, rule: &str) -> io::Result<()> {
+        self.write_to(rule, |mut w| write_json(&mut w, json))
+    }
+
+    /// Write a match already formatted as a line of text, such as CEF, as `write_match` does.
+    pub fn write_line(&mut self, line: &str, rule: &str) -> io::Result<()> {
+        self.write_to(rule, |w| writeln!(w, "{}", line))
+    }
+
+    fn write_to(
+        &mut self,
+        rule: &str,
+        mut write: impl FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match self {
+            Output::Files(o) => {
+                let len = o.len();
+                for (file, filename) in o.iter_mut() {
+                    if filename == rule || len == 1 {
+                        write(file)?;
+                    }
+                }
+                Ok(())
+            }
+            Output::CommandLine(stdout) => write(&mut stdout.lock()),
+        }
+    }
+
+    /// Write an event to every output.
+    pub fn write_event(&mut self, json: &serde_json::Value) -> io::Result<()> {
+        match self {
+            Output::Files(o) => {
+                for (file, _) in o.iter_mut() {
+                    write_json(file, json)?;
+                }
+                Ok(())
+            }
+            Output::CommandLine(stdout) => write_json(&mut stdout.lock(), json),
+        }
+    }
+
+    /// Flush every output without finalising it.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::CommandLine(stdout) => stdout.flush(),
+            Output::Files(files) => {
+                for (sink, _) in files.iter_mut() {
+                    sink.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Flush every output, finalising any encrypted streams.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::CommandLine(mut stdout) => stdout.flush(),
+            Output::Files(files) => {
+                for (sink, _) in files {
+                    sink.finish()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How matches are written.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Each match as a line of JSON.
+    Json,
+    /// Every match collected into one SARIF log, written once all input has been processed.
+    Sarif,
+    /// A line of ArcSight's Common Event Format for each match.
+    Cef,
+    /// A line of QRadar's Log Event Extended Format for each match.
+    Leef,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "sarif" => Ok(OutputFormat::Sarif),
+            "cef" => Ok(OutputFormat::Cef),
+            "leef" => Ok(OutputFormat::Leef),
+            _ => Err(format!(
+                "Unknown output format {}, expected json, sarif, cef or leef",
+                s
+            )),
+        }
+    }
+}
+
+/// How match files should be encrypted before they are written to disk.
+pub enum Encryption {
+    Age(age::x25519::Recipient),
+}
+
+impl FromStr for Encryption {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("age", recipient)) => {
+                Ok(Encryption::Age(recipient.parse().map_err(|e| {
+                    format!("Invalid age recipient {}, {}", recipient, e)
+                })?))
+            }
+            _ => Err(format!(
+                "Unsupported encryption {}, expected the form age:<recipient>",
+                s
+            )),
+        }
+    }
+}
+
+/// How output files shared by several tau-cli processes, such as the shards of a run, are kept
+/// from being corrupted.
+#[derive(Clone, Copy)]
+pub enum SharedOutput {
+    /// Append whole lines to the files while holding an advisory lock.
+    Lock,
+    /// Give each process files of its own, named after its shard or process id, to be merged later.
+    Suffix,
+}
+
+impl FromStr for SharedOutput {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lock" => Ok(SharedOutput::Lock),
+            "suffix" => Ok(SharedOutput::Suffix),
+            _ => Err(format!(
+                "Unknown shared output {}, expected lock or suffix",
+                s
+            )),
+        }
+    }
+}
+
+/// A path with a suffix added before its extension, e.g. `matches.json` to `matches.3-of-8.json`.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// The bytes of whole lines held for a locked file before they are written.
+const LOCKED_BUFFER: usize = 64 * 1024;
+
+#[cfg(unix)]
+fn lock(file: &fs::File, locked: bool) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let operation = if locked { libc::LOCK_EX } else { libc::LOCK_UN };
+    match unsafe { libc::flock(file.as_raw_fd(), operation) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &fs::File, _locked: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "locking output files is only supported on unix",
+    ))
+}
+
+/// Write the whole lines held for a locked file, keeping any partial line for the next write.
+fn write_locked(file: &mut fs::File, buffer: &mut Vec<u8>, partial: bool) -> io::Result<()> {
+    let end = match partial {
+        true => buffer.len(),
+        false => match buffer.iter().rposition(|b| *b == b'\n') {
+            Some(i) => i + 1,
+            None => return Ok(()),
+        },
+    };
+    if end == 0 {
+        return Ok(());
+    }
+    lock(file, true)?;
+    // The file is opened to append, so each write lands after those of every other process
+    let written = file.write_all(&buffer[..end]);
+    lock(file, false)?;
+    written?;
+    buffer.drain(..end);
+    Ok(())
+}
+
+/// A match file, optionally wrapped in an encrypting stream.
+pub enum Sink {
+    Plain(io::BufWriter<fs::File>),
+    Age(age::stream::StreamWriter<fs::File>),
+    /// A file shared with other processes, written a batch of whole lines at a time under a lock.
+    Locked {
+        file: fs::File,
+        buffer: Vec<u8>,
+    },
+}
+
+impl Sink {
+    pub fn new(file: fs::File, encryption: Option<&Encryption>) -> io::Result<Self> {
+        match encryption {
+            None => Ok(Sink::Plain(io::BufWriter::new(file))),
+            Some(Encryption::Age(recipient)) => {
+                let encryptor =
+                    age::Encryptor::with_recipients(iter::once(recipient as &dyn age::Recipient))
+                        .map_err(io::Error::other)?;
+                Ok(Sink::Age(encryptor.wrap_output(file)?))
+            }
+        }
+    }
+
+    pub fn locked(file: fs::File) -> Self {
+        Sink::Locked {
+            file,
+            buffer: Vec::with_capacity(LOCKED_BUFFER),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Sink::Plain(mut f) => f.flush(),
+            Sink::Age(w) => w.finish()?.flush(),
+            Sink::Locked {
+                mut file,
+                mut buffer,
+            } => write_locked(&mut file, &mut buffer, true),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(f) => f.write(buf),
+            Sink::Age(w) => w.write(buf),
+            Sink::Locked { file, buffer } => {
+                buffer.extend_from_slice(buf);
+                if buffer.len() >= LOCKED_BUFFER {
+                    write_locked(file, buffer, false)?;
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(f) => f.flush(),
+            Sink::Age(w) => w.flush(),
+            Sink::Locked { file, buffer } => write_locked(file, buffer, false),
+        }
+    }
+}
+
+/// The owner applied to created output files, given as `UID[:GID]`.
+pub struct Owner {
+    uid: u32,
+    gid: Option<u32>,
+}
+
+impl FromStr for Owner {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |id: &str| {
+            id.parse::<u32>()
+                .map_err(|_| format!("Invalid owner {}, expected the form UID[:GID]", s))
+        };
+        match s.split_once(':') {
+            Some((uid, gid)) => Ok(Owner {
+                uid: parse(uid)?,
+                gid: Some(parse(gid)?),
+            }),
+            None => Ok(Owner {
+                uid: parse(s)?,
+                gid: None,
+            }),
+        }
+    }
+}
+
+/// Parse an octal file mode such as `0600`.
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8)
+        .map_err(|_| format!("Invalid file mode {}, expected octal e.g. 0600", s))
+}
+
+/// Options used when creating output files.
+pub struct FileOptions {
+    pub overwrite: bool,
+    /// Append to existing files, used by long running processes which reopen their outputs.
+    pub append: bool,
+    pub mode: Option<u32>,
+    pub owner: Option<Owner>,
+    /// Check files could be created but open the null device instead, for --dry-run.
+    pub dry_run: bool,
+}
+
+/// Check an output file could be opened without creating or changing it, returning the null device
+/// to write to instead.
+fn dry_run_file(path: &Path, opts: &FileOptions) -> io::Result<fs::File> {
+    match fs::metadata(path) {
+        Ok(_) if !opts.overwrite && !opts.append => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the file already exists",
+            ))
+        }
+        // Opening for writing without truncating leaves the file as it is
+        Ok(_) => drop(fs::OpenOptions::new().write(true).open(path)?),
+        Err(_) => {
+            let parent = match path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p,
+                _ => Path::new("."),
+            };
+            if fs::metadata(parent)?.permissions().readonly() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "the directory is read only",
+                ));
+            }
+        }
+    }
+    let null = if cfg!(windows) { "NUL" } else { "/dev/null" };
+    fs::OpenOptions::new().write(true).open(null)
+}
+
+/// The file descriptor named by an output path such as `fd:3`, for writing to a descriptor the
+/// parent process passed in.
+pub fn descriptor(path: &Path) -> Option<i32> {
+    path.to_str()?.strip_prefix("fd:")?.parse().ok()
+}
+
+/// Open a copy of an inherited file descriptor, so several outputs can share it and closing them
+/// leaves the original open.
+#[cfg(unix)]
+fn open_descriptor(fd: i32, dry_run: bool) -> io::Result<fs::File> {
+    use std::os::unix::io::FromRawFd;
+    if fd <= 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the descriptor must be 1 or above",
+        ));
+    }
+    let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+    if copy == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { fs::File::from_raw_fd(copy) };
+    match dry_run {
+        true => fs::OpenOptions::new().write(true).open("/dev/null"),
+        false => Ok(file),
+    }
+}
+
+#[cfg(not(unix))]
+fn open_descriptor(_fd: i32, _dry_run: bool) -> io::Result<fs::File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "writing to file descriptors is only supported on unix",
+    ))
+}
+
+/// Open an output file, refusing to touch existing files unless `overwrite` or `append` is set.
+pub fn create_file(path: &Path, opts: &FileOptions) -> io::Result<fs::File> {
+    if let Some(fd) = descriptor(path) {
+        return open_descriptor(fd, opts.dry_run);
+    }
+    if opts.dry_run {
+        return dry_run_file(path, opts);
+    }
+    let mut options = fs::OpenOptions::new();
+    if opts.append {
+        options.append(true).create(true);
+    } else {
+        options
+            .write(true)
+            // Flags here ensure we're overwriting data not appending, this might tamper with match results
+            .create_new(!opts.overwrite)
+            .create(opts.overwrite)
+            .truncate(opts.overwrite);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{chown, OpenOptionsExt, PermissionsExt};
+        if let Some(mode) = opts.mode {
+            options.mode(mode);
+        }
+        let file = options.open(path)?;
+        // The mode passed to open is only used for new files, so existing files are updated here
+        if let Some(mode) = opts.mode {
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        if let Some(owner) = &opts.owner {
+            chown(path, Some(owner.uid), owner.gid)?;
+        }
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    {
+        if opts.mode.is_some() || opts.owner.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "output file modes and owners are only supported on unix",
+            ));
+        }
+        options.open(path)
+    }
+}
+
+/// Build a file name for a rule's matches which is valid on every platform, replacing the rule's
+/// extension with `extension` when given and avoiding names (compared case-insensitively) already in
+/// `taken`.
+pub fn file_name(rule: &str, extension: Option<&str>, taken: &mut HashSet<String>) -> String {
+    let stem = match extension {
+        Some(_) => Path::new(rule)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(rule),
+        None => rule,
+    };
+    let mut base: String = stem
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows silently drops trailing dots and spaces
+    base.truncate(base.trim_end_matches(&['.', ' '][..]).len());
+    if base.is_empty() {
+        base.push('_');
+    }
+    let device = base.split('.').next().unwrap_or("").to_uppercase();
+    let reserved = matches!(device.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((device.starts_with("COM") || device.starts_with("LPT"))
+            && device.len() == 4
+            && device[3..].chars().all(|c| c.is_ascii_digit()));
+    if reserved {
+        base.insert(0, '_');
+    }
+    let extension = extension
+        .map(|e| format!(".{}", e.trim_start_matches('.')))
+        .unwrap_or_default();
+    let mut name = format!("{}{}", base, extension);
+    let mut n = 1;
+    while !taken.insert(name.to_lowercase()) {
+        n += 1;
+        name = format!("{}_{}{}", base, n, extension);
+    }
+    name
+}
+
+/// Allow paths beyond `MAX_PATH` on Windows by using the extended-length form.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    if path.as_os_str().len() < 260 {
+        return path.to_path_buf();
+    }
+    // canonicalize returns a \\?\ prefixed path, the file itself may not exist yet so only the parent is resolved
+    match (path.parent().map(fs::canonicalize), path.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Quote a CSV field when it contains a separator, quote or new line.
+pub fn csv_escape(s: &str) -> Cow<'_, str> {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
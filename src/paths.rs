@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+/// The files within a directory given as rules.
+pub const RULE_FILES: &str = "**/*.yml";
+
+/// Whether a path holds glob characters, paths without them are used as given.
+fn is_pattern(path: &str) -> bool {
+    path.contains(&['*', '?', '['][..])
+}
+
+/// The files matching a glob pattern, in order. A pattern which matches nothing is an error.
+fn glob(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let mut matches = Vec::new();
+    for entry in glob::glob(pattern).map_err(|e| format!("Invalid glob {}, {}", pattern, e))? {
+        let path =
+            entry.map_err(|e| format!("Unable to read {}, {}", e.path().display(), e.error()))?;
+        if path.is_file() {
+            matches.push(path);
+        }
+    }
+    match matches.is_empty() {
+        true => Err(format!("No files match {}", pattern)),
+        false => Ok(matches),
+    }
+}
+
+/// Expand glob patterns such as `rules/**/*.yml`, for shells which don't or when the pattern is
+/// quoted. `**` matches any number of directories. Paths which exist are used as given, even
+/// with glob characters in their names, except directories, which are walked for the files
+/// matching `directories` when given. A pattern which matches nothing is an error, rather than
+/// silently reading nothing.
+pub fn expand(paths: &[PathBuf], directories: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        match (path.to_str(), directories) {
+            (_, Some(files)) if path.is_dir() => {
+                let pattern =
+                    Path::new(&glob::Pattern::escape(&path.to_string_lossy())).join(files);
+                expanded.extend(glob(&pattern.to_string_lossy())?);
+            }
+            (Some(p), _) if is_pattern(p) && !path.exists() => expanded.extend(glob(p)?),
+            _ => expanded.push(path.clone()),
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn directories_and_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        for file in [
+            "x.yml",
+            "a/y.yml",
+            "a/b/z.yml",
+            "a/b/notes.txt",
+            "logs[1].json",
+        ] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+
+        let mut rules = expand(&[dir.path().to_path_buf()], Some(RULE_FILES)).unwrap();
+        rules.sort();
+        let expected: Vec<PathBuf> = ["a/b/z.yml", "a/y.yml", "x.yml"]
+            .iter()
+            .map(|f| dir.path().join(f))
+            .collect();
+        assert_eq!(rules, expected);
+
+        let existing = dir.path().join("logs[1].json");
+        assert_eq!(
+            expand(std::slice::from_ref(&existing), None).unwrap(),
+            vec![existing]
+        );
+
+        let pattern = dir.path().join("**").join("*.txt");
+        assert_eq!(
+            expand(&[pattern], None).unwrap(),
+            vec![nested.join("notes.txt")]
+        );
+
+        let missing = dir.path().join("*.json.gz");
+        assert!(expand(&[missing], None)
+            .unwrap_err()
+            .starts_with("No files match"));
+
+        // Directories given as inputs, such as triage collections, are left to the reader
+        assert_eq!(
+            expand(&[dir.path().to_path_buf()], None).unwrap(),
+            vec![dir.path().to_path_buf()]
+        );
+    }
+}
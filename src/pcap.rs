@@ -0,0 +1,517 @@
+use std::path::PathBuf;
+
+use crate::input::Input;
+
+/// Read DNS queries and answers, and HTTP requests, from pcap and pcapng files as events. Each packet is
+/// parsed on its own, so requests split across TCP segments only have what is in their first segment.
+#[cfg(feature = "pcap")]
+pub fn open(paths: Vec<PathBuf>) -> Result<Input, String> {
+    use std::{fs, io::BufReader, sync::mpsc, thread};
+
+    let mut files = Vec::new();
+    for path in paths {
+        let file = fs::File::open(&path)
+            .map_err(|_| format!("Unable to read input file at {}.", path.display()))?;
+        files.push((path, BufReader::new(file)));
+    }
+    let (tx, rx) = mpsc::sync_channel(1024);
+    thread::spawn(move || {
+        for (path, reader) in files {
+            let result = packets::read(reader, &mut |ts, link, data| match packets::event(
+                ts, link, data,
+            ) {
+                Some(event) => tx.send(Ok(event)).is_ok(),
+                None => true,
+            });
+            if let Err(e) = result {
+                let error = format!("Unable to read packets from {}, {}", path.display(), e);
+                if tx.send(Err(error)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
+
+#[cfg(not(feature = "pcap"))]
+pub fn open(_paths: Vec<PathBuf>) -> Result<Input, String> {
+    Err("Packet captures require tau-cli built with the pcap feature".into())
+}
+
+#[cfg(feature = "pcap")]
+mod packets {
+    use std::{
+        convert::{TryFrom, TryInto},
+        io::{self, Read},
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use serde_json::{json, Value};
+
+    use crate::event::RFC3339_MAX;
+
+    const LINKTYPE_ETHERNET: u32 = 1;
+    const LINKTYPE_RAW: u32 = 101;
+    const LINKTYPE_LINUX_SLL: u32 = 113;
+    /// The largest block or packet read, larger lengths are corrupt, as pcapng readers are only
+    /// expected to handle blocks up to 16 MiB.
+    const MAX_BLOCK: usize = 16 * 1024 * 1024;
+    const HTTP_METHODS: &[&str] = &[
+        "GET ", "POST ", "PUT ", "DELETE ", "HEAD ", "OPTIONS ", "PATCH ", "CONNECT ", "TRACE ",
+    ];
+
+    fn invalid(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+    }
+
+    /// Reads integers in the byte order of the capture.
+    #[derive(Clone, Copy)]
+    struct Order(bool);
+
+    impl Order {
+        fn u16(self, b: &[u8]) -> u16 {
+            match self.0 {
+                true => u16::from_be_bytes([b[0], b[1]]),
+                false => u16::from_le_bytes([b[0], b[1]]),
+            }
+        }
+        fn u32(self, b: &[u8]) -> u32 {
+            match self.0 {
+                true => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+                false => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            }
+        }
+    }
+
+    /// Read exactly `buf.len()` bytes, `false` at a clean end of file.
+    fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+        match reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Called with each packet's timestamp, link type and data, returning false to stop reading.
+    type OnPacket<'a> = dyn FnMut(Option<SystemTime>, u32, &[u8]) -> bool + 'a;
+
+    /// The time `ticks` units of `resolution` per second after the unix epoch, `None` past 9999.
+    fn time(ticks: u64, resolution: u64) -> Option<SystemTime> {
+        let nanos = ticks as u128 * 1_000_000_000 / resolution as u128;
+        let secs = u64::try_from(nanos / 1_000_000_000).ok()?;
+        if secs > RFC3339_MAX {
+            return None;
+        }
+        UNIX_EPOCH.checked_add(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+    }
+
+    /// Call `packet` with the timestamp, link type and data of every packet in a pcap or pcapng
+    /// capture, stopping early when it returns false. Corrupt timestamps past 9999 are `None`.
+    pub fn read(mut reader: impl Read, packet: &mut OnPacket<'_>) -> io::Result<()> {
+        let mut magic = [0; 4];
+        if !fill(&mut reader, &mut magic)? {
+            return Ok(());
+        }
+        match magic {
+            [0x0a, 0x0d, 0x0d, 0x0a] => read_pcapng(reader, packet),
+            _ => read_pcap(magic, reader, packet),
+        }
+    }
+
+    fn read_pcap(
+        magic: [u8; 4],
+        mut reader: impl Read,
+        packet: &mut OnPacket<'_>,
+    ) -> io::Result<()> {
+        let (order, nanos) = match magic {
+            [0xa1, 0xb2, 0xc3, 0xd4] => (Order(true), false),
+            [0xd4, 0xc3, 0xb2, 0xa1] => (Order(false), false),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (Order(true), true),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (Order(false), true),
+            _ => return Err(invalid("not a pcap or pcapng file")),
+        };
+        let mut header = [0; 20];
+        if !fill(&mut reader, &mut header)? {
+            return Err(invalid("truncated pcap header"));
+        }
+        let link = order.u32(&header[16..]);
+        let mut record = [0; 16];
+        let mut data = Vec::new();
+        while fill(&mut reader, &mut record)? {
+            let (secs, frac) = (order.u32(&record[0..]), order.u32(&record[4..]));
+            let len = order.u32(&record[8..]) as usize;
+            if len > MAX_BLOCK {
+                return Err(invalid("invalid pcap packet length"));
+            }
+            data.resize(len, 0);
+            if !fill(&mut reader, &mut data)? {
+                break;
+            }
+            let ts = match nanos {
+                true => time(secs as u64 * 1_000_000_000 + frac as u64, 1_000_000_000),
+                false => time(secs as u64 * 1_000_000 + frac as u64, 1_000_000),
+            };
+            if !packet(ts, link, &data) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_pcapng(mut reader: impl Read, packet: &mut OnPacket<'_>) -> io::Result<()> {
+        // The section header's byte order magic decides how the rest of the section is read
+        let mut order = Order(false);
+        // The link type and timestamp units per second of each interface
+        let mut interfaces: Vec<(u32, u64)> = Vec::new();
+        let mut kind = [0x0a, 0x0d, 0x0d, 0x0a];
+        let mut length = [0; 4];
+        loop {
+            if !fill(&mut reader, &mut length)? {
+                break;
+            }
+            let mut body = Vec::new();
+            if kind == [0x0a, 0x0d, 0x0d, 0x0a] {
+                let mut magic = [0; 4];
+                if !fill(&mut reader, &mut magic)? {
+                    break;
+                }
+                order = match magic {
+                    [0x1a, 0x2b, 0x3c, 0x4d] => Order(true),
+                    [0x4d, 0x3c, 0x2b, 0x1a] => Order(false),
+                    _ => return Err(invalid("invalid pcapng byte order")),
+                };
+                interfaces.clear();
+                body.extend_from_slice(&magic);
+            }
+            // The length covers the type, both copies of the length and the body
+            let total = order.u32(&length) as usize;
+            if total < 12 + body.len() || total > MAX_BLOCK {
+                return Err(invalid("invalid pcapng block length"));
+            }
+            let start = body.len();
+            body.resize(total - 8, 0);
+            if !fill(&mut reader, &mut body[start..])? {
+                break;
+            }
+            body.truncate(total - 12);
+            let body = &body[..];
+            match order.u32(&kind) {
+                // Interface description
+                1 if body.len() >= 8 => {
+                    let mut resolution = 1_000_000;
+                    let mut options = &body[8..];
+                    while options.len() >= 4 {
+                        let (code, len) = (order.u16(options), order.u16(&options[2..]) as usize);
+                        if code == 0 || options.len() < 4 + len {
+                            break;
+                        }
+                        if code == 9 && len >= 1 {
+                            let r = options[4];
+                            resolution = match r & 0x80 {
+                                0 => 10u64.saturating_pow((r & 0x7f) as u32),
+                                _ => 2u64.saturating_pow((r & 0x7f) as u32),
+                            };
+                        }
+                        // Option values are padded to 32 bits
+                        options = options.get((4 + len).div_ceil(4) * 4..).unwrap_or_default();
+                    }
+                    interfaces.push((order.u16(body) as u32, resolution));
+                }
+                // Enhanced packet
+                6 if body.len() >= 20 => {
+                    let (link, resolution) = interfaces
+                        .get(order.u32(body) as usize)
+                        .copied()
+                        .unwrap_or((LINKTYPE_ETHERNET, 1_000_000));
+                    let ticks =
+                        ((order.u32(&body[4..]) as u64) << 32) | order.u32(&body[8..]) as u64;
+                    let len = (order.u32(&body[12..]) as usize).min(body.len() - 20);
+                    if !packet(time(ticks, resolution), link, &body[20..20 + len]) {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            if !fill(&mut reader, &mut kind)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a packet into a DNS or HTTP event, other packets give `None`.
+    pub fn event(ts: Option<SystemTime>, link: u32, data: &[u8]) -> Option<Value> {
+        let ip = match link {
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                let mut ethertype = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]);
+                // Skip any VLAN tags
+                while ethertype == 0x8100 || ethertype == 0x88a8 {
+                    offset += 4;
+                    ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+                }
+                data.get(offset + 2..)?
+            }
+            LINKTYPE_LINUX_SLL => data.get(16..)?,
+            LINKTYPE_RAW => data,
+            _ => return None,
+        };
+        let (src, dst, protocol, payload) = match ip.first()? >> 4 {
+            4 => {
+                let header = ((ip[0] & 0x0f) as usize) * 4;
+                let total = (u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize).min(ip.len());
+                let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+                let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+                (
+                    IpAddr::V4(Ipv4Addr::from(src)),
+                    IpAddr::V4(Ipv4Addr::from(dst)),
+                    ip[9],
+                    ip.get(header..total)?,
+                )
+            }
+            6 => {
+                let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+                let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+                (
+                    IpAddr::V6(Ipv6Addr::from(src)),
+                    IpAddr::V6(Ipv6Addr::from(dst)),
+                    *ip.get(6)?,
+                    ip.get(40..)?,
+                )
+            }
+            _ => return None,
+        };
+        let port = |b: &[u8], i: usize| Some(u16::from_be_bytes([*b.get(i)?, *b.get(i + 1)?]));
+        let (src_port, dst_port, body, kind) = match protocol {
+            17 => (
+                port(payload, 0)?,
+                port(payload, 2)?,
+                payload.get(8..)?,
+                "udp",
+            ),
+            6 => {
+                let offset = ((*payload.get(12)? >> 4) as usize) * 4;
+                (
+                    port(payload, 0)?,
+                    port(payload, 2)?,
+                    payload.get(offset..)?,
+                    "tcp",
+                )
+            }
+            _ => return None,
+        };
+        let mut event = json!({
+            "timestamp": ts.map(|t| humantime::format_rfc3339_micros(t).to_string()),
+            "src_ip": src.to_string(),
+            "src_port": src_port,
+            "dst_ip": dst.to_string(),
+            "dst_port": dst_port,
+            "transport": kind,
+        });
+        if src_port == 53 || dst_port == 53 || src_port == 5353 || dst_port == 5353 {
+            // DNS over TCP starts with the length of the message
+            let body = match kind {
+                "tcp" => body.get(2..)?,
+                _ => body,
+            };
+            event["event"] = json!("dns");
+            event["dns"] = dns(body)?;
+            return Some(event);
+        }
+        if kind == "tcp" && HTTP_METHODS.iter().any(|m| body.starts_with(m.as_bytes())) {
+            event["event"] = json!("http");
+            event["http"] = http(body)?;
+            return Some(event);
+        }
+        None
+    }
+
+    /// Read a possibly compressed name from a DNS message, returning it and the offset after it.
+    fn name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+        let mut labels = Vec::new();
+        let mut end = None;
+        // Bound the pointers followed so a malicious loop can't hang
+        for _ in 0..128 {
+            let len = *message.get(offset)? as usize;
+            if len == 0 {
+                return Some((labels.join("."), end.unwrap_or(offset + 1)));
+            }
+            if len & 0xc0 == 0xc0 {
+                end.get_or_insert(offset + 2);
+                offset = ((len & 0x3f) << 8) | *message.get(offset + 1)? as usize;
+                continue;
+            }
+            let label = message.get(offset + 1..offset + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            offset += 1 + len;
+        }
+        None
+    }
+
+    fn record_type(t: u16) -> Value {
+        match t {
+            1 => json!("A"),
+            2 => json!("NS"),
+            5 => json!("CNAME"),
+            6 => json!("SOA"),
+            12 => json!("PTR"),
+            15 => json!("MX"),
+            16 => json!("TXT"),
+            28 => json!("AAAA"),
+            33 => json!("SRV"),
+            65 => json!("HTTPS"),
+            255 => json!("ANY"),
+            t => json!(t),
+        }
+    }
+
+    fn dns(message: &[u8]) -> Option<Value> {
+        let u16_at = |i: usize| Some(u16::from_be_bytes([*message.get(i)?, *message.get(i + 1)?]));
+        let flags = u16_at(2)?;
+        let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+        let mut offset = 12;
+        let mut query = None;
+        let mut query_type = Value::Null;
+        for _ in 0..questions {
+            let (n, next) = name(message, offset)?;
+            if query.is_none() {
+                query = Some(n);
+                query_type = record_type(u16_at(next)?);
+            }
+            offset = next + 4;
+        }
+        let mut records = Vec::new();
+        for _ in 0..answers {
+            let (n, next) = match name(message, offset) {
+                Some(x) => x,
+                None => break,
+            };
+            let (t, len) = match (u16_at(next), u16_at(next + 8)) {
+                (Some(t), Some(len)) => (t, len as usize),
+                _ => break,
+            };
+            let start = next + 10;
+            let data = match message.get(start..start + len) {
+                Some(d) => d,
+                None => break,
+            };
+            let value = match (t, data.len()) {
+                (1, 4) => json!(Ipv4Addr::new(data[0], data[1], data[2], data[3]).to_string()),
+                (28, 16) => {
+                    let b: [u8; 16] = data.try_into().ok()?;
+                    json!(Ipv6Addr::from(b).to_string())
+                }
+                (2, _) | (5, _) | (12, _) => json!(name(message, start).map(|(n, _)| n)),
+                _ => Value::Null,
+            };
+            records.push(json!({ "name": n, "type": record_type(t), "data": value }));
+            offset = start + len;
+        }
+        Some(json!({
+            "id": u16_at(0)?,
+            "response": flags & 0x8000 != 0,
+            "rcode": flags & 0x000f,
+            "query": query,
+            "query_type": query_type,
+            "answers": records,
+        }))
+    }
+
+    fn http(body: &[u8]) -> Option<Value> {
+        let head = String::from_utf8_lossy(body);
+        let mut lines = head.split("\r\n");
+        let mut request = lines.next()?.splitn(3, ' ');
+        let (method, uri, version) = (request.next()?, request.next()?, request.next());
+        let mut headers = serde_json::Map::new();
+        for line in lines.take_while(|l| !l.is_empty()) {
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), json!(value.trim()));
+            }
+        }
+        Some(json!({
+            "method": method,
+            "uri": uri,
+            "version": version,
+            "host": headers.get("host"),
+            "user_agent": headers.get("user-agent"),
+            "headers": headers,
+        }))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// A little endian pcapng block of the given type around its body.
+        fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+            let total = (12 + body.len()) as u32;
+            let mut block = kind.to_le_bytes().to_vec();
+            block.extend(total.to_le_bytes());
+            block.extend(body);
+            block.extend(total.to_le_bytes());
+            block
+        }
+
+        /// A capture of one empty packet at `ticks` on an interface with the given if_tsresol.
+        fn pcapng(tsresol: u8, ticks: u64) -> Vec<u8> {
+            let mut capture = block(0x0a0d0d0a, &[0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0]);
+            let mut interface = vec![1, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 0, tsresol, 0, 0, 0];
+            interface.extend([0; 4]);
+            capture.extend(block(1, &interface));
+            let mut packet = vec![0; 4];
+            packet.extend(((ticks >> 32) as u32).to_le_bytes());
+            packet.extend((ticks as u32).to_le_bytes());
+            packet.extend([0; 8]);
+            capture.extend(block(6, &packet));
+            capture
+        }
+
+        fn times(capture: &[u8]) -> io::Result<Vec<Option<SystemTime>>> {
+            let mut times = Vec::new();
+            read(capture, &mut |ts, _, _| {
+                times.push(ts);
+                true
+            })?;
+            Ok(times)
+        }
+
+        #[test]
+        fn pcapng_timestamps() {
+            let expected = UNIX_EPOCH + Duration::from_micros(1_700_000_000_500_000);
+            let times = times(&pcapng(6, 1_700_000_000_500_000)).unwrap();
+            assert_eq!(times, vec![Some(expected)]);
+        }
+
+        #[test]
+        fn pcapng_timestamps_past_9999_are_unset() {
+            assert_eq!(times(&pcapng(0, u64::MAX)).unwrap(), vec![None]);
+            assert_eq!(times(&pcapng(0x80 | 63, u64::MAX)).unwrap().len(), 1);
+            assert_eq!(times(&pcapng(0x7f, u64::MAX)).unwrap().len(), 1);
+        }
+
+        #[test]
+        fn oversized_blocks_are_rejected() {
+            let mut capture = pcapng(6, 0);
+            let at = capture.len();
+            capture.extend(6u32.to_le_bytes());
+            capture.extend(u32::MAX.to_le_bytes());
+            assert!(times(&capture).is_err());
+            capture.truncate(at);
+            assert_eq!(times(&capture).unwrap().len(), 1);
+        }
+
+        #[test]
+        fn pcap_timestamps() {
+            let mut capture = vec![0xd4, 0xc3, 0xb2, 0xa1];
+            capture.extend([0; 16]);
+            capture.extend(1u32.to_le_bytes());
+            capture.extend(1_700_000_000u32.to_le_bytes());
+            capture.extend(250_000u32.to_le_bytes());
+            capture.extend([0; 8]);
+            let expected = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+            assert_eq!(times(&capture).unwrap(), vec![Some(expected)]);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use serde_json::Value;
+
+use crate::input::{self, Input, Options};
+use crate::lenient;
+use crate::limits;
+use crate::output::Output;
+use crate::route::Router;
+use crate::rules::{Metadata, ValidatedRule};
+
+/// Lines read or parsed before being passed on to the next stage together, fewer under memory
+/// pressure.
+const BATCH: usize = 256;
+
+/// The depths of the channels between the stages, given as one depth for every channel or
+/// `read,parse,write`. Read and parse depths count batches of lines, the write depth counts
+/// matches.
+pub struct Depths {
+    read: usize,
+    parse: usize,
+    write: usize,
+}
+
+impl FromStr for Depths {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pipeline depths {}, expected N or N,N,N", s);
+        let depths = s
+            .split(',')
+            .map(|d| d.trim().parse::<usize>().map_err(|_| invalid()))
+            .collect::<Result<Vec<usize>, String>>()?;
+        match depths[..] {
+            [d] if d > 0 => Ok(Depths {
+                read: d,
+                parse: d,
+                write: d,
+            }),
+            [read, parse, write] if read > 0 && parse > 0 && write > 0 => {
+                Ok(Depths { read, parse, write })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Read the lines of every file, or stdin when there are none, in batches, stopping once the parse
+/// stage has gone.
+fn read(paths: Option<Vec<PathBuf>>, tx: SyncSender<Result<Vec<String>, String>>) {
+    // Files are read last to first, as they are without a pipeline
+    let sources: Vec<Option<PathBuf>> = match paths {
+        Some(paths) => paths.into_iter().rev().map(Some).collect(),
+        None => vec![None],
+    };
+    for source in sources {
+        let (name, mut reader): (String, Box<dyn BufRead>) = match source {
+            Some(path) => match fs::File::open(&path).and_then(input::decompress) {
+                Ok(f) => (path.display().to_string(), Box::new(BufReader::new(f))),
+                Err(_) => {
+                    let error = format!("Unable to read input file at {}.", path.display());
+                    if tx.send(Err(error)).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            },
+            None => ("stdin".into(), Box::new(BufReader::new(io::stdin()))),
+        };
+        let mut batch = Vec::with_capacity(BATCH);
+        loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => batch.push(line),
+                Err(e) => {
+                    if tx
+                        .send(Err(format!("Unable to read {}, {}", name, e)))
+                        .is_err()
+                    {
+                        return;
+                    }
+                    break;
+                }
+            }
+            if batch.len() >= limits::batch(BATCH) {
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH));
+                if tx.send(Ok(full)).is_err() {
+                    return;
+                }
+            }
+        }
+        if !batch.is_empty() && tx.send(Ok(batch)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Parse each batch of lines in order.
+fn parse(
+    rx: Receiver<Result<Vec<String>, String>>,
+    tx: SyncSender<Vec<Result<Value, String>>>,
+    options: Options,
+) {
+    for lines in rx {
+        let batch = match lines {
+            Ok(lines) => {
+                let mut batch = Vec::with_capacity(lines.len());
+                for l in lines.iter() {
+                    lenient::parse_into(l.as_bytes(), options.lenient, &mut batch);
+                }
+                batch
+            }
+            Err(e) => vec![Err(e)],
+        };
+        if tx.send(batch).is_err() {
+            break;
+        }
+    }
+}
+
+/// Read and parse JSON lines input on their own threads, each stage waiting on the next once its
+/// channel is full rather than reading ahead without limit.
+pub fn input(
+    paths: Option<Vec<PathBuf>>,
+    depths: &Depths,
+    options: Options,
+) -> Result<Input, String> {
+    if let Some(paths) = paths.as_ref() {
+        if paths.is_empty() {
+            return Err(
+                "No input files provided, use -i or --input to specify one or more files".into(),
+            );
+        }
+        for path in paths.iter() {
+            if !path.is_file() {
+                return Err(format!("Unable to read input file at {}.", path.display()));
+            }
+        }
+    }
+    let (lines_tx, lines_rx) = mpsc::sync_channel(depths.read);
+    let (tx, rx) = mpsc::sync_channel(depths.parse);
+    thread::spawn(move || read(paths, lines_tx));
+    thread::spawn(move || parse(lines_rx, tx, options));
+    Ok(Input::Batches {
+        rx,
+        batch: Vec::new().into_iter(),
+    })
+}
+
+enum Message {
+    Match {
+        json: Value,
+        rule: String,
+        metadata: Metadata,
+    },
+    Event(Value),
+    Flush(SyncSender<io::Result<()>>),
+}
+
+/// The write stage, a thread owning the outputs and router which matches are sent to. A slow
+/// output fills the channel, holding matching back until it catches up.
+pub struct Writer {
+    tx: Option<SyncSender<Message>>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+fn write(
+    rx: Receiver<Message>,
+    mut output: Option<Output>,
+    mut router: Option<Router>,
+) -> io::Result<()> {
+    for message in rx {
+        match message {
+            Message::Match {
+                json,
+                rule,
+                metadata,
+            } => {
+                if let Some(router) = router.as_mut() {
+                    if !router.dispatch(&metadata, &json)? {
+                        continue;
+                    }
+                }
+                if let Some(o) = output.as_mut() {
+                    o.write_match(&json, &rule)?;
+                }
+            }
+            Message::Event(json) => {
+                if let Some(o) = output.as_mut() {
+                    o.write_event(&json)?;
+                }
+            }
+            Message::Flush(ack) => {
+                let mut flush = || -> io::Result<()> {
+                    if let Some(router) = router.as_mut() {
+                        router.flush()?;
+                    }
+                    match output.as_mut() {
+                        Some(o) => o.flush(),
+                        None => Ok(()),
+                    }
+                };
+                let _ = ack.send(flush());
+            }
+        }
+    }
+    if let Some(r) = router {
+        r.finish()?;
+    }
+    if let Some(o) = output {
+        o.finish()?;
+    }
+    Ok(())
+}
+
+impl Writer {
+    pub fn new(output: Option<Output>, router: Option<Router>, depths: &Depths) -> Self {
+        let (tx, rx) = mpsc::sync_channel(depths.write);
+        Writer {
+            tx: Some(tx),
+            handle: Some(thread::spawn(move || write(rx, output, router))),
+        }
+    }
+
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        let sent = self.tx.as_ref().map(|tx| tx.send(message).is_ok());
+        match sent {
+            Some(true) => Ok(()),
+            // The thread only stops early on an error
+            _ => self.join(),
+        }
+    }
+
+    /// Stop the thread, returning the error it stopped on.
+    fn join(&mut self) -> io::Result<()> {
+        self.tx = None;
+        match self.handle.take().map(|h| h.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("the write stage panicked")),
+            None => Err(io::Error::other("the write stage has stopped")),
+        }
+    }
+
+    pub fn write_match(&mut self, json: &Value, rule: &ValidatedRule) -> io::Result<()> {
+        self.send(Message::Match {
+            json: json.clone(),
+            // Output files are named by the rule's name, which --name-by may set apart from its id
+            rule: rule.name.clone(),
+            metadata: rule.metadata.clone(),
+        })
+    }
+
+    pub fn write_event(&mut self, json: &Value) -> io::Result<()> {
+        self.send(Message::Event(json.clone()))
+    }
+
+    /// Wait for every match sent so far to be written and flushed.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let (ack, done) = mpsc::sync_channel(1);
+        self.send(Message::Flush(ack))?;
+        match done.recv() {
+            Ok(result) => result,
+            Err(_) => self.join(),
+        }
+    }
+
+    /// Write everything still queued and finish the outputs.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Sink;
+
+    #[test]
+    fn matches_are_written_to_the_file_named_after_their_rule() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = ["First rule", "Second rule"];
+        let files = names
+            .iter()
+            .map(|name| {
+                let file = fs::File::create(dir.path().join(name)).unwrap();
+                (Sink::new(file, None).unwrap(), name.to_string())
+            })
+            .collect();
+        let mut writer = Writer::new(Some(Output::Files(files)), None, &"4".parse().unwrap());
+        for (i, name) in names.iter().enumerate() {
+            let rule = ValidatedRule {
+                rule: None,
+                id: format!("rule{}.yml-0000000{}", i, i),
+                name: name.to_string(),
+                path: PathBuf::from(format!("rule{}.yml", i)),
+                metadata: Metadata::default(),
+                phase: 0,
+                explanation: None,
+                origin: None,
+            };
+            writer
+                .write_match(&serde_json::json!({ "n": i }), &rule)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        for (i, name) in names.iter().enumerate() {
+            let written = fs::read_to_string(dir.path().join(name)).unwrap();
+            assert_eq!(written, format!("{{\"n\":{}}}\n", i));
+        }
+    }
+}
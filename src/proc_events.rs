@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use crate::input::Input;
+
+/// Whether an input names the process event source, `proc://`.
+pub fn is_source(input: &Path) -> bool {
+    input.as_os_str() == "proc://"
+}
+
+/// Stream process fork, exec and exit events from the kernel's netlink process connector, this
+/// requires root or CAP_NET_ADMIN. Exec events are completed from `/proc` where the process still
+/// exists, and otherwise take their parent from the process's fork event. Connect events would need
+/// eBPF, which isn't supported, so only process events are read.
+#[cfg(all(feature = "proc-events", target_os = "linux"))]
+pub fn open() -> Result<Input, String> {
+    use serde_json::{json, Value};
+    use std::{collections::HashMap, fs, io, mem, sync::mpsc, thread};
+
+    const NETLINK_CONNECTOR: libc::c_int = 11;
+    const CN_IDX_PROC: u32 = 1;
+    const CN_VAL_PROC: u32 = 1;
+    const PROC_CN_MCAST_LISTEN: u32 = 1;
+    const PROC_EVENT_FORK: u32 = 0x1;
+    const PROC_EVENT_EXEC: u32 = 0x2;
+    const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+    // nlmsghdr is 16 bytes and cn_msg 20, the proc_event follows them
+    const HEADER: usize = 16 + 20;
+
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            NETLINK_CONNECTOR,
+        )
+    };
+    if fd < 0 {
+        return Err(format!(
+            "Unable to open the process connector, {}",
+            io::Error::last_os_error()
+        ));
+    }
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as u16;
+    addr.nl_pid = std::process::id();
+    addr.nl_groups = CN_IDX_PROC;
+    let bound = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as u32,
+        )
+    };
+    let mut listen = Vec::with_capacity(HEADER + 4);
+    listen.extend_from_slice(&((HEADER + 4) as u32).to_ne_bytes());
+    listen.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+    listen.extend_from_slice(&0u16.to_ne_bytes());
+    listen.extend_from_slice(&0u32.to_ne_bytes());
+    listen.extend_from_slice(&std::process::id().to_ne_bytes());
+    listen.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+    listen.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+    listen.extend_from_slice(&0u32.to_ne_bytes());
+    listen.extend_from_slice(&0u32.to_ne_bytes());
+    listen.extend_from_slice(&4u16.to_ne_bytes());
+    listen.extend_from_slice(&0u16.to_ne_bytes());
+    listen.extend_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+    let sent = match bound {
+        0 => unsafe { libc::send(fd, listen.as_ptr() as *const libc::c_void, listen.len(), 0) },
+        _ => -1,
+    };
+    if sent < 0 {
+        let e = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(format!(
+            "Unable to subscribe to process events, this requires root or CAP_NET_ADMIN, {}",
+            e
+        ));
+    }
+
+    let field = |buffer: &[u8], offset: usize| -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&buffer[offset..offset + 4]);
+        u32::from_ne_bytes(bytes)
+    };
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; 4096];
+        // The parent of each process forked since starting, for processes gone before `/proc` is read
+        let mut parents = HashMap::new();
+        loop {
+            let n = unsafe {
+                libc::recv(
+                    fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                // Events dropped under load leave the socket with ENOBUFS, which isn't fatal
+                if io::Error::last_os_error().raw_os_error() == Some(libc::ENOBUFS) {
+                    continue;
+                }
+                break;
+            }
+            let n = n as usize;
+            if n < HEADER + 16 + 8 {
+                continue;
+            }
+            let event = &buffer[HEADER..n];
+            let what = field(event, 0);
+            let mut timestamp = [0; 8];
+            timestamp.copy_from_slice(&event[8..16]);
+            let timestamp_ns = u64::from_ne_bytes(timestamp);
+            let data = &event[16..];
+            let json = match what {
+                PROC_EVENT_FORK if data.len() >= 16 => {
+                    let (ppid, pid, tgid) = (field(data, 4), field(data, 8), field(data, 12));
+                    // Threads share their process's id, so only new processes are tracked
+                    if pid == tgid {
+                        parents.insert(tgid, ppid);
+                    }
+                    json!({
+                        "event": "fork",
+                        "timestamp_ns": timestamp_ns,
+                        "ppid": ppid,
+                        "pid": pid,
+                        "tgid": tgid,
+                    })
+                }
+                PROC_EVENT_EXEC => {
+                    let (pid, tgid) = (field(data, 0), field(data, 4));
+                    let proc = format!("/proc/{}", tgid);
+                    let status = fs::read_to_string(format!("{}/status", proc)).unwrap_or_default();
+                    let status_field = |name: &str| -> Option<u32> {
+                        status
+                            .lines()
+                            .find_map(|l| l.strip_prefix(name))
+                            .and_then(|v| v.split_whitespace().next())
+                            .and_then(|v| v.parse().ok())
+                    };
+                    let cmdline: Vec<String> = fs::read(format!("{}/cmdline", proc))
+                        .unwrap_or_default()
+                        .split(|b| *b == 0)
+                        .filter(|a| !a.is_empty())
+                        .map(|a| String::from_utf8_lossy(a).into_owned())
+                        .collect();
+                    json!({
+                        "event": "exec",
+                        "timestamp_ns": timestamp_ns,
+                        "pid": pid,
+                        "tgid": tgid,
+                        "ppid": status_field("PPid:").or_else(|| parents.get(&tgid).copied()),
+                        "uid": status_field("Uid:"),
+                        "exe": fs::read_link(format!("{}/exe", proc)).ok().map(|p| p.display().to_string()),
+                        "cmdline": cmdline.join(" "),
+                        "argv": cmdline,
+                    })
+                }
+                PROC_EVENT_EXIT if data.len() >= 12 => {
+                    let (pid, tgid) = (field(data, 0), field(data, 4));
+                    if pid == tgid {
+                        parents.remove(&tgid);
+                    }
+                    json!({
+                        "event": "exit",
+                        "timestamp_ns": timestamp_ns,
+                        "pid": pid,
+                        "tgid": tgid,
+                        "exit_code": field(data, 8),
+                    })
+                }
+                _ => continue,
+            };
+            if tx.send(Ok::<Value, String>(json)).is_err() {
+                break;
+            }
+        }
+        unsafe { libc::close(fd) };
+    });
+    Ok(Input::Channel(rx))
+}
+
+#[cfg(not(all(feature = "proc-events", target_os = "linux")))]
+pub fn open() -> Result<Input, String> {
+    Err("Process events require Linux and tau-cli built with the proc-events feature".into())
+}
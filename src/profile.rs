@@ -0,0 +1,140 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::input::Input;
+use crate::output::csv_escape;
+
+/// The number of distinct values tracked per field, values first seen after this are not counted.
+const MAX_DISTINCT: usize = 1000;
+
+#[derive(StructOpt)]
+pub struct Profile {
+    /// The corpus files to profile, stdin is used when no files are given.
+    #[structopt(short, long, parse(from_os_str))]
+    input: Option<Vec<PathBuf>>,
+
+    /// The number of events to sample from the start of the input.
+    #[structopt(long, default_value = "10000")]
+    sample: usize,
+
+    /// The number of most common values to show per field.
+    #[structopt(long, default_value = "5")]
+    top: usize,
+}
+
+#[derive(Default)]
+struct FieldProfile {
+    /// The number of events the field appeared in.
+    present: usize,
+    types: BTreeMap<&'static str, usize>,
+    values: HashMap<String, usize>,
+}
+
+fn type_name(json: &Value) -> &'static str {
+    match json {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Record every leaf of an event against its dot separated path, array elements share their parent's path.
+pub fn walk<'a>(field: String, json: &'a Value, leaves: &mut Vec<(String, &'a Value)>) {
+    match json {
+        Value::Object(o) => {
+            for (k, v) in o.iter() {
+                let path = match field.is_empty() {
+                    true => k.clone(),
+                    false => format!("{}.{}", field, k),
+                };
+                walk(path, v, leaves);
+            }
+        }
+        Value::Array(a) if a.iter().any(|v| v.is_object() || v.is_array()) => {
+            for v in a.iter() {
+                walk(field.clone(), v, leaves);
+            }
+        }
+        _ => leaves.push((field, json)),
+    }
+}
+
+impl Profile {
+    pub fn run(self) -> Result<(), String> {
+        let mut fields: BTreeMap<String, FieldProfile> = BTreeMap::new();
+        let mut events = 0;
+        let mut stderr = stderr();
+        for res in Input::open(self.input, None, None)?.take(self.sample) {
+            let json = match res {
+                Ok(json) => json,
+                Err(e) => {
+                    let _ = writeln!(stderr, "{}", e);
+                    continue;
+                }
+            };
+            events += 1;
+            let mut leaves = Vec::new();
+            walk(String::new(), &json, &mut leaves);
+            let mut seen = Vec::new();
+            for (field, value) in leaves {
+                let profile = fields.entry(field.clone()).or_default();
+                // Fields repeated within arrays only count once towards presence
+                if !seen.contains(&field) {
+                    profile.present += 1;
+                    seen.push(field);
+                }
+                *profile.types.entry(type_name(value)).or_insert(0) += 1;
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                };
+                let tracked = profile.values.len();
+                match profile.values.get_mut(&value) {
+                    Some(c) => *c += 1,
+                    None if tracked < MAX_DISTINCT => {
+                        profile.values.insert(value, 1);
+                    }
+                    None => {}
+                }
+            }
+        }
+        let top = self.top;
+        let write = || -> std::io::Result<()> {
+            let mut stdout = stdout();
+            writeln!(stdout, "Field, Present, Types, Top Values")?;
+            for (field, profile) in fields.iter() {
+                let types: Vec<String> = profile
+                    .types
+                    .iter()
+                    .map(|(t, c)| format!("{}:{}", t, c))
+                    .collect();
+                let mut values: Vec<(&String, &usize)> = profile.values.iter().collect();
+                values.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                let top: Vec<String> = values
+                    .iter()
+                    .take(top)
+                    .map(|(v, c)| format!("{} ({})", v, c))
+                    .collect();
+                writeln!(
+                    stdout,
+                    "{}, {:.1}%, {}, {}",
+                    csv_escape(field),
+                    profile.present as f64 * 100.0 / events as f64,
+                    types.join(" "),
+                    csv_escape(&top.join("; "))
+                )?;
+            }
+            Ok(())
+        };
+        write().map_err(|e| format!("Unable to write profile, {}", e))
+    }
+}
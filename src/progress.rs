@@ -0,0 +1,106 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::output::{self, FileOptions};
+
+/// Events and matches counted so far, shared with the heartbeat thread.
+#[derive(Default)]
+struct Counts {
+    events: AtomicU64,
+    matches: AtomicU64,
+}
+
+/// A machine readable stream of progress, kept apart from the matches and stderr so a wrapping
+/// program can follow a run without parsing text. Each line is a JSON object with a `type` of
+/// `start`, `progress`, `error` or `done`; `progress` is written on every interval even when
+/// nothing has changed, as a heartbeat.
+pub struct Progress {
+    sink: Arc<Mutex<fs::File>>,
+    counts: Arc<Counts>,
+    start: Instant,
+    stop: mpsc::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+fn send(sink: &Mutex<fs::File>, line: Value) -> io::Result<()> {
+    let mut sink = sink.lock().unwrap_or_else(|e| e.into_inner());
+    output::write_json(&mut *sink, &line)?;
+    sink.flush()
+}
+
+fn counted(kind: &str, counts: &Counts, start: Instant) -> Value {
+    json!({
+        "type": kind,
+        "events": counts.events.load(Ordering::Relaxed),
+        "matches": counts.matches.load(Ordering::Relaxed),
+        "seconds": start.elapsed().as_secs_f64(),
+    })
+}
+
+impl Progress {
+    pub fn new(
+        path: &Path,
+        interval: Duration,
+        rules: usize,
+        opts: &FileOptions,
+    ) -> Result<Self, String> {
+        let file = output::create_file(path, opts)
+            .map_err(|_| format!("Could not create progress file at {}", path.display()))?;
+        let sink = Arc::new(Mutex::new(file));
+        send(
+            &sink,
+            json!({ "type": "start", "rules": rules, "pid": std::process::id() }),
+        )
+        .map_err(|e| format!("Unable to write progress, {}", e))?;
+        let (counts, start) = (Arc::new(Counts::default()), Instant::now());
+        let (stop, stopped) = mpsc::channel();
+        let handle = {
+            let (sink, counts) = (sink.clone(), counts.clone());
+            thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    // A reader which has gone away shouldn't stop the matching
+                    let _ = send(&sink, counted("progress", &counts, start));
+                }
+            })
+        };
+        Ok(Progress {
+            sink,
+            counts,
+            start,
+            stop,
+            handle,
+        })
+    }
+
+    pub fn event(&self) {
+        self.counts.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn matched(&self) {
+        self.counts.matches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Report an error which is ending the run.
+    pub fn error(&self, message: &str) {
+        let _ = send(&self.sink, json!({ "type": "error", "message": message }));
+    }
+
+    /// Stop the heartbeat and write the final counts.
+    pub fn finish(self) -> io::Result<()> {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+        send(&self.sink, counted("done", &self.counts, self.start))
+    }
+}
@@ -0,0 +1,250 @@
+use std::{collections::HashSet, convert::TryInto, fs, path::PathBuf, sync::mpsc, thread};
+
+use serde_json::{json, Value};
+
+use crate::event::filetime;
+use crate::input::Input;
+
+/// Hive bins start after the 4096 byte base block, cell offsets are relative to this.
+const HBIN_START: usize = 4096;
+
+/// A registry hive held in memory, with offsets checked on every read so a corrupt hive can't panic.
+struct Hive {
+    data: Vec<u8>,
+}
+
+impl Hive {
+    fn u32(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            self.data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+
+    /// The contents of the cell at a hive offset, without its size.
+    fn cell(&self, offset: u32) -> Option<&[u8]> {
+        let start = HBIN_START + offset as usize;
+        let size = (self.u32(start)? as i32).unsigned_abs() as usize;
+        self.data.get(start + 4..start + size.max(4))
+    }
+
+    fn name(bytes: &[u8], ascii: bool) -> String {
+        match ascii {
+            true => bytes.iter().map(|b| *b as char).collect(),
+            false => String::from_utf16_lossy(
+                &bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+
+    /// The offsets of a key's subkeys, following index roots to their leaves.
+    fn subkeys(&self, list: u32, out: &mut Vec<u32>, depth: usize) -> Option<()> {
+        let cell = self.cell(list)?;
+        let count = u16::from_le_bytes(cell.get(2..4)?.try_into().ok()?) as usize;
+        let stride = match cell.get(..2)? {
+            b"lf" | b"lh" => 8,
+            b"li" | b"ri" => 4,
+            _ => return None,
+        };
+        for i in 0..count {
+            let at = 4 + i * stride;
+            let offset = u32::from_le_bytes(cell.get(at..at + 4)?.try_into().ok()?);
+            match cell.get(..2)? {
+                b"ri" if depth < 8 => {
+                    self.subkeys(offset, out, depth + 1);
+                }
+                b"ri" => {}
+                _ => out.push(offset),
+            }
+        }
+        Some(())
+    }
+
+    /// The data of a value, reading big data segments when it is spread across several cells.
+    fn value_data(&self, vk: &[u8]) -> Option<Vec<u8>> {
+        let size = u32::from_le_bytes(vk.get(4..8)?.try_into().ok()?);
+        // Data of 4 bytes or less is stored in the offset field itself
+        if size & 0x8000_0000 != 0 {
+            let len = (size & 0x7fff_ffff).min(4) as usize;
+            return Some(vk.get(8..8 + len)?.to_vec());
+        }
+        let size = size as usize;
+        let cell = self.cell(u32::from_le_bytes(vk.get(8..12)?.try_into().ok()?))?;
+        if cell.get(..2) == Some(b"db") && size > 16344 {
+            let count = u16::from_le_bytes(cell.get(2..4)?.try_into().ok()?) as usize;
+            let list = self.cell(u32::from_le_bytes(cell.get(4..8)?.try_into().ok()?))?;
+            let mut data = Vec::with_capacity(size);
+            for i in 0..count {
+                let segment = u32::from_le_bytes(list.get(i * 4..i * 4 + 4)?.try_into().ok()?);
+                let segment = self.cell(segment)?;
+                let take = (size - data.len()).min(segment.len()).min(16344);
+                data.extend_from_slice(&segment[..take]);
+            }
+            return Some(data);
+        }
+        Some(cell.get(..size.min(cell.len()))?.to_vec())
+    }
+}
+
+fn utf16(data: &[u8]) -> String {
+    Hive::name(data, false).trim_end_matches('\0').to_string()
+}
+
+/// Decode value data by its registry type, unknown types are given as hex.
+fn decode(kind: u32, data: &[u8]) -> (&'static str, Value) {
+    let hex = || {
+        json!(data
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>())
+    };
+    match kind {
+        0 => ("REG_NONE", hex()),
+        1 => ("REG_SZ", json!(utf16(data))),
+        2 => ("REG_EXPAND_SZ", json!(utf16(data))),
+        3 => ("REG_BINARY", hex()),
+        4 if data.len() >= 4 => (
+            "REG_DWORD",
+            json!(u32::from_le_bytes([data[0], data[1], data[2], data[3]])),
+        ),
+        5 if data.len() >= 4 => (
+            "REG_DWORD_BIG_ENDIAN",
+            json!(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+        ),
+        6 => ("REG_LINK", json!(utf16(data))),
+        7 => (
+            "REG_MULTI_SZ",
+            json!(utf16(data)
+                .split('\0')
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()),
+        ),
+        11 if data.len() >= 8 => (
+            "REG_QWORD",
+            json!(u64::from_le_bytes(data[..8].try_into().unwrap_or_default())),
+        ),
+        _ => ("REG_UNKNOWN", hex()),
+    }
+}
+
+/// Walk every key of a hive depth first, sending an event per value and one for each key without values.
+fn walk(
+    hive: &Hive,
+    source: &str,
+    send: &mut dyn FnMut(Result<Value, String>) -> bool,
+) -> Result<bool, String> {
+    if hive.data.get(..4) != Some(b"regf") {
+        return Err(format!("{} is not a registry hive", source));
+    }
+    let root = hive
+        .u32(0x24)
+        .ok_or_else(|| format!("{} is not a registry hive", source))?;
+    // Keys are visited once, so a corrupt hive with a loop still ends
+    let mut seen = HashSet::new();
+    let mut stack = vec![(root, String::new())];
+    while let Some((offset, path)) = stack.pop() {
+        if !seen.insert(offset) {
+            continue;
+        }
+        let nk = match hive.cell(offset) {
+            Some(c) if c.get(..2) == Some(b"nk") && c.len() >= 0x4c => c,
+            _ => continue,
+        };
+        let field = |at: usize| u32::from_le_bytes(nk[at..at + 4].try_into().unwrap_or_default());
+        let flags = u16::from_le_bytes([nk[2], nk[3]]);
+        let name_len = u16::from_le_bytes([nk[0x48], nk[0x49]]) as usize;
+        let name = Hive::name(
+            nk.get(0x4c..0x4c + name_len).unwrap_or_default(),
+            flags & 0x20 != 0,
+        );
+        // The root key's name varies between hives, so paths start below it
+        let path = match (offset == root, path.is_empty()) {
+            (true, _) => String::new(),
+            (false, true) => name,
+            (false, false) => format!("{}\\{}", path, name),
+        };
+        let last_written = filetime(u64::from_le_bytes(nk[4..12].try_into().unwrap_or_default()));
+        let event = |value: Value, kind: Value, data: Value| {
+            json!({
+                "hive": source,
+                "path": path,
+                "last_written": last_written,
+                "value": value,
+                "type": kind,
+                "data": data,
+            })
+        };
+        let values = field(0x24) as usize;
+        let list = hive.cell(field(0x28));
+        let mut sent = false;
+        for i in 0..values {
+            let vk = match list
+                .and_then(|l| l.get(i * 4..i * 4 + 4))
+                .and_then(|o| hive.cell(u32::from_le_bytes(o.try_into().ok()?)))
+            {
+                Some(c) if c.get(..2) == Some(b"vk") && c.len() >= 0x14 => c,
+                _ => continue,
+            };
+            let name_len = u16::from_le_bytes([vk[2], vk[3]]) as usize;
+            let name = match name_len {
+                0 => "(default)".to_string(),
+                _ => Hive::name(
+                    vk.get(0x14..0x14 + name_len).unwrap_or_default(),
+                    u16::from_le_bytes([vk[0x10], vk[0x11]]) & 1 != 0,
+                ),
+            };
+            let kind = u32::from_le_bytes(vk[0x0c..0x10].try_into().unwrap_or_default());
+            let (kind, data) = decode(kind, &hive.value_data(vk).unwrap_or_default());
+            if !send(Ok(event(json!(name), json!(kind), data))) {
+                return Ok(false);
+            }
+            sent = true;
+        }
+        if !sent && !send(Ok(event(Value::Null, Value::Null, Value::Null))) {
+            return Ok(false);
+        }
+        let mut subkeys = Vec::new();
+        if field(0x14) > 0 {
+            hive.subkeys(field(0x1c), &mut subkeys, 0);
+        }
+        // Reversed so keys come off the stack in the order they are stored
+        for subkey in subkeys.into_iter().rev() {
+            stack.push((subkey, path.clone()));
+        }
+    }
+    Ok(true)
+}
+
+/// Send an event per value of a hive held in memory, returning false once events are no longer wanted.
+pub fn events(
+    data: Vec<u8>,
+    source: &str,
+    send: &mut dyn FnMut(Result<Value, String>) -> bool,
+) -> bool {
+    match walk(&Hive { data }, source, send) {
+        Ok(open) => open,
+        Err(e) => send(Err(e)),
+    }
+}
+
+/// Read offline registry hives, emitting an event per value with the key's path, last written
+/// time, and the value's name, type and data.
+pub fn open(paths: Vec<PathBuf>) -> Result<Input, String> {
+    let mut hives = Vec::new();
+    for path in paths {
+        let data = fs::read(&path)
+            .map_err(|_| format!("Unable to read input file at {}.", path.display()))?;
+        hives.push((path.display().to_string(), data));
+    }
+    let (tx, rx) = mpsc::sync_channel(1024);
+    thread::spawn(move || {
+        for (source, data) in hives {
+            if !events(data, &source, &mut |e| tx.send(e).is_ok()) {
+                break;
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
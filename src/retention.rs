@@ -0,0 +1,187 @@
+use std::{
+    fs,
+    io::{stderr, Write},
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often rotated files are looked for while running.
+const PRUNE_EVERY: Duration = Duration::from_secs(60);
+
+/// A number of bytes, given with an optional unit, e.g. `10GB` or `512M`. Units are powers of 1024.
+#[derive(Clone, Copy)]
+pub struct Size(u64);
+
+impl FromStr for Size {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid size {}, expected a number of bytes e.g. 10GB", s);
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let power = match unit
+            .trim()
+            .to_uppercase()
+            .trim_end_matches("IB")
+            .trim_end_matches('B')
+        {
+            "" => 0,
+            "K" => 1,
+            "M" => 2,
+            "G" => 3,
+            "T" => 4,
+            _ => return Err(invalid()),
+        };
+        number
+            .checked_mul(1024u64.pow(power))
+            .map(Size)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Whether what follows an output's name is one logrotate gives its copies, a number, `.1`, or a
+/// date, `-20240101`, either maybe compressed, e.g. `.2.gz`.
+fn is_rotated_suffix(rest: &str) -> bool {
+    let rest = [".gz", ".bz2", ".xz", ".zst"]
+        .iter()
+        .find_map(|ext| rest.strip_suffix(ext))
+        .unwrap_or(rest);
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    match (rest.strip_prefix('.'), rest.strip_prefix('-')) {
+        (Some(n), _) => digits(n),
+        (_, Some(date)) => digits(date) && matches!(date.len(), 8 | 10 | 12 | 14),
+        _ => false,
+    }
+}
+
+/// Removes rotated copies of the output files, such as `matches.json.1` or
+/// `matches.json-20240101.gz` left by logrotate, once they are too old or take up too much space.
+pub struct Retention {
+    /// The output files being written, only files named after them are removed.
+    outputs: Vec<PathBuf>,
+    age: Option<Duration>,
+    size: Option<u64>,
+    checked: Option<Instant>,
+}
+
+impl Retention {
+    pub fn new(outputs: Vec<PathBuf>, age: Option<Duration>, size: Option<Size>) -> Self {
+        Retention {
+            outputs,
+            age,
+            size: size.map(|s| s.0),
+            checked: None,
+        }
+    }
+
+    /// The rotated copies of the output files, with when they were last written and their size.
+    fn rotated(&self) -> Vec<(SystemTime, u64, PathBuf)> {
+        let mut found = Vec::new();
+        for output in self.outputs.iter() {
+            let (dir, name) = match (output.parent(), output.file_name()) {
+                (Some(d), Some(n)) => (d, n.to_string_lossy()),
+                _ => continue,
+            };
+            let dir = if dir.as_os_str().is_empty() {
+                std::path::Path::new(".")
+            } else {
+                dir
+            };
+            let entries = match fs::read_dir(dir) {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                let copy = file_name
+                    .strip_prefix(name.as_ref())
+                    .map(is_rotated_suffix)
+                    .unwrap_or(false);
+                if !copy || self.outputs.contains(&path) || found.iter().any(|(_, _, p)| *p == path)
+                {
+                    continue;
+                }
+                if let Some(metadata) = entry.metadata().ok().filter(|m| m.is_file()) {
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    found.push((modified, metadata.len(), path));
+                }
+            }
+        }
+        found
+    }
+
+    /// Remove rotated files older than the retention period, then the oldest of the rest until
+    /// they fit in the retained size. Does nothing if it has run within the last minute, unless
+    /// `now` is set.
+    pub fn prune(&mut self, now: bool) {
+        if !now
+            && self
+                .checked
+                .map(|c| c.elapsed() < PRUNE_EVERY)
+                .unwrap_or(false)
+        {
+            return;
+        }
+        self.checked = Some(Instant::now());
+        let mut rotated = self.rotated();
+        rotated.sort();
+        let mut total: u64 = rotated.iter().map(|(_, size, _)| size).sum();
+        let mut stderr = stderr();
+        for (modified, size, path) in rotated {
+            let expired = self
+                .age
+                .zip(modified.elapsed().ok())
+                .map(|(age, elapsed)| elapsed > age)
+                .unwrap_or(false);
+            let over = self.size.map(|s| total > s).unwrap_or(false);
+            if !expired && !over {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(_) => {
+                    total -= size;
+                    let _ = writeln!(stderr, "Removed rotated output {}", path.display());
+                }
+                Err(e) => {
+                    let _ = writeln!(stderr, "Unable to remove {}, {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_rotated_suffix;
+
+    #[test]
+    fn rotated_suffixes() {
+        for rest in [
+            ".1",
+            ".12",
+            ".2.gz",
+            "-20240101",
+            "-2024010112.zst",
+            "-20240101123000",
+        ] {
+            assert!(is_rotated_suffix(rest), "{}", rest);
+        }
+    }
+
+    #[test]
+    fn other_files_sharing_the_name() {
+        for rest in [
+            ".json",
+            "-notes.txt",
+            ".ledger",
+            ".gz",
+            "-2024",
+            ".1a",
+            "-20240101.txt",
+        ] {
+            assert!(!is_rotated_suffix(rest), "{}", rest);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{self, stderr, stdin, BufRead, BufReader, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+use serde_yaml::{Mapping, Value as Yaml};
+use structopt::StructOpt;
+
+use crate::event;
+use crate::input::Input;
+use crate::new_rule::exact;
+use crate::output::{self, csv_escape};
+use crate::profile::walk;
+
+#[derive(StructOpt)]
+pub struct Review {
+    /// Match files to review, as written by tau-cli. Each match's rule is taken from its 'tau.tags', or else the file's name.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    input: Vec<PathBuf>,
+
+    /// The rule the matches are from, for output files named differently to their rule.
+    #[structopt(long)]
+    rule: Option<String>,
+
+    /// The file verdicts are appended to, as JSON lines. Matches with a verdict in it already are skipped so a review can be resumed.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// A directory to write a draft suppression rule to for each rule with false positives.
+    #[structopt(long, parse(from_os_str))]
+    suppressions: Option<PathBuf>,
+
+    /// Comma separated fields the suppression rules match on, by default those every false positive shares.
+    #[structopt(long, use_delimiter = true)]
+    fields: Vec<String>,
+}
+
+/// The terminal's settings from before the first keystroke was read, for a signal to put back.
+#[cfg(unix)]
+static ORIGINAL: std::sync::OnceLock<libc::termios> = std::sync::OnceLock::new();
+
+/// Put the terminal's settings back, then end with the signal as it would have without a handler.
+#[cfg(unix)]
+extern "C" fn restore(signal: libc::c_int) {
+    if let Some(original) = ORIGINAL.get() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+    }
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Puts the terminal's settings back when dropped, including when a panic unwinds.
+#[cfg(unix)]
+struct RawMode(libc::termios);
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+/// Read a single keystroke from the terminal, without waiting for enter.
+#[cfg(unix)]
+fn keystroke() -> io::Result<char> {
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Interrupting the review mustn't leave the terminal without echo
+    ORIGINAL.get_or_init(|| {
+        let handler = restore as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+        original
+    });
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let _restore = RawMode(original);
+    let mut byte = [0u8];
+    stdin().lock().read_exact(&mut byte)?;
+    Ok(byte[0] as char)
+}
+
+/// Read the first character of a line, where the terminal can't be put in raw mode.
+#[cfg(not(unix))]
+fn keystroke() -> io::Result<char> {
+    let mut line = String::new();
+    stdin().lock().read_line(&mut line)?;
+    Ok(line.trim().chars().next().unwrap_or(' '))
+}
+
+/// The key of a match in the verdicts, so a resumed review skips it.
+fn key(rule: &str, json: &Value) -> String {
+    format!("{}\n{}", rule, event::key(json, &[]))
+}
+
+/// The verdicts already recorded, as (rule, verdict, event).
+fn verdicts(path: &Path) -> Result<Vec<(String, String, Value)>, String> {
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(_) => return Err(format!("Unable to read data from {}.", path.display())),
+    };
+    let mut verdicts = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Unable to read {}, {}", path.display(), e))?;
+        let v: Value = serde_json::from_str(&line)
+            .map_err(|_| format!("Invalid verdicts {}", path.display()))?;
+        if let (Some(rule), Some(verdict), Some(event)) =
+            (v["rule"].as_str(), v["verdict"].as_str(), v.get("event"))
+        {
+            verdicts.push((rule.to_string(), verdict.to_string(), event.clone()));
+        }
+    }
+    Ok(verdicts)
+}
+
+/// The scalar fields of an event and their values, leaving out those tau-cli added.
+fn scalars(json: &Value) -> BTreeMap<String, Value> {
+    let mut leaves = Vec::new();
+    walk(String::new(), json, &mut leaves);
+    leaves
+        .into_iter()
+        .filter(|(f, v)| {
+            !f.starts_with("tau.") && !matches!(v, Value::Null | Value::Array(_) | Value::Object(_))
+        })
+        .map(|(f, v)| (f, v.clone()))
+        .collect()
+}
+
+impl Review {
+    /// The matches to review, each with the rules it matched.
+    fn matches(&self) -> Result<Vec<(Vec<String>, Value)>, String> {
+        let mut matches = Vec::new();
+        let mut stderr = stderr();
+        for path in self.input.iter() {
+            let file_rule = path
+                .file_name()
+                .and_then(|f| f.to_str())
+                .unwrap_or_default()
+                .to_string();
+            for res in Input::open(Some(vec![path.clone()]), None, None)? {
+                let json = match res {
+                    Ok(json) => json,
+                    Err(e) => {
+                        let _ = writeln!(stderr, "{}", e);
+                        continue;
+                    }
+                };
+                let tags: Vec<String> = match (&self.rule, json["tau"]["tags"].as_array()) {
+                    (Some(rule), _) => vec![rule.clone()],
+                    (None, Some(tags)) => tags
+                        .iter()
+                        .filter_map(|t| t.as_str().map(|t| t.to_string()))
+                        .collect(),
+                    (None, None) => vec![file_rule.clone()],
+                };
+                matches.push((tags, json));
+            }
+        }
+        Ok(matches)
+    }
+
+    /// A suppression rule matching a rule's false positives, on the fields they share or on
+    /// --fields.
+    fn suppression(&self, rule: &str, fps: &[&Value], tps: &[&Value]) -> Yaml {
+        let alternatives: Vec<Mapping> = match self.fields.is_empty() {
+            false => fps
+                .iter()
+                .map(|fp| {
+                    self.fields
+                        .iter()
+                        .filter_map(|f| {
+                            let v = event::get(fp, f)?;
+                            Some((f.as_str().into(), exact(v)))
+                        })
+                        .collect()
+                })
+                .collect(),
+            true => {
+                let mut shared = scalars(fps[0]);
+                for fp in fps[1..].iter() {
+                    let other = scalars(fp);
+                    shared.retain(|f, v| other.get(f) == Some(v));
+                }
+                let tps: Vec<BTreeMap<String, Value>> = tps.iter().map(|t| scalars(t)).collect();
+                shared.retain(|f, v| !tps.iter().any(|t| t.get(f) == Some(v)));
+                vec![shared
+                    .iter()
+                    .map(|(f, v)| (f.as_str().into(), exact(v)))
+                    .collect()]
+            }
+        };
+        let mut unique: Vec<Yaml> = Vec::new();
+        for a in alternatives.into_iter().filter(|a| !a.is_empty()) {
+            if !unique.contains(&Yaml::Mapping(a.clone())) {
+                unique.push(Yaml::Mapping(a));
+            }
+        }
+        let mut detection = Mapping::new();
+        detection.insert("false_positives".into(), Yaml::Sequence(unique));
+        detection.insert("condition".into(), "false_positives".into());
+        let mut draft = Mapping::new();
+        draft.insert(
+            "title".into(),
+            format!("Suppress false positives of {}", rule).into(),
+        );
+        draft.insert("phase".into(), "suppression".into());
+        draft.insert(
+            "tags".into(),
+            Yaml::Sequence(vec![format!("suppress:{}", rule).into()]),
+        );
+        draft.insert("detection".into(), Yaml::Mapping(detection));
+        let events = |events: &[&Value]| {
+            Yaml::Sequence(
+                events
+                    .iter()
+                    .filter_map(|e| serde_yaml::to_value(e).ok())
+                    .collect(),
+            )
+        };
+        draft.insert("true_positives".into(), events(fps));
+        draft.insert("true_negatives".into(), events(tps));
+        Yaml::Mapping(draft)
+    }
+
+    /// Write a draft suppression rule for every rule with false positives in the verdicts.
+    fn write_suppressions(&self, dir: &Path) -> Result<(), String> {
+        let verdicts = verdicts(&self.output)?;
+        let rules: BTreeMap<&str, ()> = verdicts
+            .iter()
+            .filter(|(_, v, _)| v == "false_positive")
+            .map(|(r, _, _)| (r.as_str(), ()))
+            .collect();
+        let mut taken = HashSet::new();
+        for rule in rules.keys() {
+            let with = |verdict: &str| -> Vec<&Value> {
+                verdicts
+                    .iter()
+                    .filter(|(r, v, _)| r == rule && v == verdict)
+                    .map(|(_, _, e)| e)
+                    .collect()
+            };
+            let draft = self.suppression(rule, &with("false_positive"), &with("true_positive"));
+            let yaml = serde_yaml::to_string(&draft)
+                .map_err(|e| format!("Unable to write the suppression for {}, {}", rule, e))?;
+            let path = dir.join(output::file_name(rule, Some("suppression.yml"), &mut taken));
+            // The draft is regenerated from the verdicts each time, so replacing it loses nothing
+            fs::write(
+                &path,
+                format!(
+                    "# Draft from tau-cli review, check the fields before use\n{}",
+                    yaml
+                ),
+            )
+            .map_err(|_| format!("Could not create output file at {}", path.display()))?;
+            let _ = writeln!(stderr(), "Wrote {}", path.display());
+        }
+        Ok(())
+    }
+
+    pub fn run(self) -> Result<(), String> {
+        if !stdin().is_terminal() {
+            return Err("Review needs a terminal to read verdicts from".into());
+        }
+        let done: HashSet<String> = verdicts(&self.output)?
+            .iter()
+            .map(|(r, _, e)| key(r, e))
+            .collect();
+        let pending: Vec<(String, Value)> = self
+            .matches()?
+            .into_iter()
+            .flat_map(|(rules, json)| rules.into_iter().map(move |r| (r, json.clone())))
+            .filter(|(r, json)| !done.contains(&key(r, json)))
+            .collect();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.output)
+            .map_err(|_| format!("Could not create output file at {}", self.output.display()))?;
+        let mut stderr = stderr();
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        'matches: for (i, (rule, json)) in pending.iter().enumerate() {
+            let pretty = serde_json::to_string_pretty(json).unwrap_or_default();
+            let _ = writeln!(
+                stderr,
+                "\n[{}/{}] {}\n{}",
+                i + 1,
+                pending.len(),
+                rule,
+                pretty
+            );
+            let verdict = loop {
+                let _ = write!(
+                    stderr,
+                    "[t]rue positive, [f]alse positive, [b]enign, [s]kip, [q]uit? "
+                );
+                let _ = stderr.flush();
+                let key = keystroke().map_err(|e| format!("Unable to read verdict, {}", e))?;
+                let _ = writeln!(stderr, "{}", key);
+                match key.to_ascii_lowercase() {
+                    't' => break "true_positive",
+                    'f' => break "false_positive",
+                    'b' => break "benign",
+                    's' => continue 'matches,
+                    'q' => break 'matches,
+                    _ => {}
+                }
+            };
+            // Each verdict is written straight away so quitting, or a crash, loses none
+            writeln!(
+                file,
+                "{}",
+                json!({ "rule": rule, "verdict": verdict, "event": json })
+            )
+            .and_then(|_| file.flush())
+            .map_err(|e| format!("Unable to write verdict, {}", e))?;
+            *counts.entry(verdict).or_insert(0) += 1;
+        }
+        let _ = writeln!(stderr, "\nVerdict, Count");
+        for (verdict, count) in counts.iter() {
+            let _ = writeln!(stderr, "{}, {}", csv_escape(verdict), count);
+        }
+        match &self.suppressions {
+            Some(dir) => self.write_suppressions(dir),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    fs,
+    io::{self, stdout, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::mpsc::{self, SyncSender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::output::{self, Encryption, FileOptions, Sink};
+use crate::rules::Metadata;
+use crate::secrets::Secret;
+
+#[derive(Deserialize)]
+struct Config {
+    routes: Vec<RouteConfig>,
+}
+
+#[derive(Deserialize)]
+struct RouteConfig {
+    #[serde(default)]
+    level: Option<String>,
+    #[serde(default)]
+    tag: Option<String>,
+    to: Vec<String>,
+}
+
+enum Destination {
+    /// The output given by -o / --output, or stdout when there isn't one.
+    Output,
+    Stdout,
+    File(Sink),
+    Webhook(Webhook),
+}
+
+/// How long a webhook request can take before it fails.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts at sending a match before it's counted as failed, waiting twice as long after each.
+const WEBHOOK_ATTEMPTS: u32 = 3;
+const WEBHOOK_BACKOFF: Duration = Duration::from_millis(500);
+/// Matches waiting to be sent, beyond which matching waits for the webhook.
+const WEBHOOK_QUEUE: usize = 1024;
+
+/// The matches which couldn't be sent to a webhook and the last error.
+type Failures = (u64, Option<String>);
+
+/// Posts matches to a webhook on a thread of its own, so a slow or failing webhook neither holds up
+/// matching for every match nor ends the run.
+struct Webhook {
+    url: String,
+    queue: SyncSender<serde_json::Value>,
+    worker: JoinHandle<Failures>,
+}
+
+impl Webhook {
+    fn new(url: String, authorization: Option<String>) -> Self {
+        let (queue, matches) = mpsc::sync_channel::<serde_json::Value>(WEBHOOK_QUEUE);
+        let agent = ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build();
+        let to = url.clone();
+        let worker = thread::spawn(move || {
+            let mut failures = (0, None);
+            for json in matches {
+                let mut backoff = WEBHOOK_BACKOFF;
+                for attempt in 1..=WEBHOOK_ATTEMPTS {
+                    let request = agent.post(&to);
+                    let request = match &authorization {
+                        Some(a) => request.set("Authorization", a),
+                        None => request,
+                    };
+                    match request.send_json(&json) {
+                        Ok(_) => break,
+                        // Requests the webhook rejects would be rejected again
+                        Err(ureq::Error::Status(code, _))
+                            if (code < 500 && code != 429) || attempt == WEBHOOK_ATTEMPTS =>
+                        {
+                            failures = (failures.0 + 1, Some(format!("status {}", code)));
+                            break;
+                        }
+                        Err(e) if attempt == WEBHOOK_ATTEMPTS => {
+                            failures = (failures.0 + 1, Some(e.to_string()));
+                        }
+                        Err(_) => {
+                            thread::sleep(backoff);
+                            backoff *= 2;
+                        }
+                    }
+                }
+            }
+            failures
+        });
+        Webhook { url, queue, worker }
+    }
+
+    /// Send the queued matches, returning those which couldn't be sent.
+    fn finish(self) -> Failures {
+        drop(self.queue);
+        self.worker
+            .join()
+            .unwrap_or((0, Some("the webhook thread panicked".into())))
+    }
+}
+
+struct Route {
+    level: Option<String>,
+    tag: Option<String>,
+    to: Vec<usize>,
+}
+
+impl Route {
+    fn matches(&self, metadata: &Metadata) -> bool {
+        let level = match &self.level {
+            Some(l) => metadata
+                .level
+                .as_ref()
+                .map(|m| m.eq_ignore_ascii_case(l))
+                .unwrap_or(false),
+            None => true,
+        };
+        let tag = match &self.tag {
+            Some(t) => metadata.tags.iter().any(|m| m == t),
+            None => true,
+        };
+        level && tag
+    }
+}
+
+/// Connect to a webhook's host without sending anything.
+fn connect(url: &str) -> Result<(), String> {
+    let (rest, port) = match url.strip_prefix("https://") {
+        Some(rest) => (rest, 443),
+        None => (url.trim_start_matches("http://"), 80),
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+    let address = match authority.rsplit_once(':') {
+        Some((_, p)) if p.parse::<u16>().is_ok() => authority.to_string(),
+        _ => format!("{}:{}", authority, port),
+    };
+    let mut error = format!("{} did not resolve", address);
+    for addr in address.to_socket_addrs().map_err(|e| e.to_string())? {
+        match TcpStream::connect_timeout(&addr, Duration::from_secs(5)) {
+            Ok(_) => return Ok(()),
+            Err(e) => error = e.to_string(),
+        }
+    }
+    Err(error)
+}
+
+/// Sends matches to different destinations based on the metadata of the rule that matched.
+pub struct Router {
+    routes: Vec<Route>,
+    destinations: Vec<(String, Destination)>,
+}
+
+impl Router {
+    pub fn load(
+        path: &Path,
+        file_options: &FileOptions,
+        encryption: Option<&Encryption>,
+        secret: Option<&Secret>,
+    ) -> Result<Self, String> {
+        let config: Config = serde_yaml::from_str(
+            &fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?,
+        )
+        .map_err(|e| format!("Invalid route config {}, {}", path.display(), e))?;
+        // The Authorization header sent to webhooks
+        let authorization = secret.map(|s| s.bearer());
+        let mut router = Router {
+            routes: Vec::new(),
+            destinations: Vec::new(),
+        };
+        for route in config.routes {
+            let mut to = Vec::new();
+            for name in route.to {
+                let index = match router.destinations.iter().position(|(n, _)| n == &name) {
+                    Some(i) => i,
+                    None => {
+                        let destination = match name.as_str() {
+                            "output" => Destination::Output,
+                            "stdout" => Destination::Stdout,
+                            n if n.starts_with("http://") || n.starts_with("https://") => {
+                                Destination::Webhook(Webhook::new(n.into(), authorization.clone()))
+                            }
+                            n => Destination::File(
+                                output::create_file(Path::new(n), file_options)
+                                    .and_then(|f| Sink::new(f, encryption))
+                                    .map_err(|e| {
+                                        format!("Could not create route output {}, {}", n, e)
+                                    })?,
+                            ),
+                        };
+                        router.destinations.push((name, destination));
+                        router.destinations.len() - 1
+                    }
+                };
+                to.push(index);
+            }
+            router.routes.push(Route {
+                level: route.level,
+                tag: route.tag,
+                to,
+            });
+        }
+        Ok(router)
+    }
+
+    /// Send a match down the first route matching the rule's metadata, returning whether the match
+    /// should also be written to the regular output.
+    pub fn dispatch(&mut self, metadata: &Metadata, json: &serde_json::Value) -> io::Result<bool> {
+        let route = match self.routes.iter().find(|r| r.matches(metadata)) {
+            Some(r) => r,
+            None => return Ok(true),
+        };
+        let mut to_output = false;
+        for i in route.to.iter() {
+            match &mut self.destinations[*i].1 {
+                Destination::Output => to_output = true,
+                Destination::Stdout => output::write_json(&mut stdout().lock(), json)?,
+                Destination::File(sink) => output::write_json(sink, json)?,
+                Destination::Webhook(webhook) => {
+                    webhook
+                        .queue
+                        .send(json.clone())
+                        .map_err(|_| io::Error::other(format!("{} stopped", webhook.url)))?;
+                }
+            }
+        }
+        Ok(to_output)
+    }
+
+    /// Check every webhook destination accepts connections, for --dry-run.
+    pub fn probe(&self) -> Vec<(&str, Result<(), String>)> {
+        self.destinations
+            .iter()
+            .filter_map(|(name, destination)| match destination {
+                Destination::Webhook(webhook) => Some((name.as_str(), connect(&webhook.url))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Flush every file destination without finalising it.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for (_, destination) in self.destinations.iter_mut() {
+            if let Destination::File(sink) = destination {
+                sink.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish every destination, waiting for webhooks to be sent. Returns the webhooks which
+    /// matches couldn't be sent to, with how many and the last error.
+    pub fn finish(self) -> io::Result<Vec<(String, u64, String)>> {
+        let mut failed = Vec::new();
+        for (_, destination) in self.destinations {
+            match destination {
+                Destination::File(sink) => sink.finish()?,
+                Destination::Webhook(webhook) => {
+                    let url = webhook.url.clone();
+                    if let (n @ 1.., error) = webhook.finish() {
+                        failed.push((url, n, error.unwrap_or_default()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve `statuses` in turn to webhook requests, returning the URL and a count of requests.
+    fn serve(statuses: Vec<u16>) -> (String, JoinHandle<usize>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", server.server_addr().to_ip().unwrap());
+        let handle = thread::spawn(move || {
+            for status in statuses.iter() {
+                let request = server.recv().unwrap();
+                let _ = request.respond(tiny_http::Response::empty(*status));
+            }
+            statuses.len()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn failed_matches_are_retried_then_counted() {
+        let (url, server) = serve(vec![503, 200, 400]);
+        let webhook = Webhook::new(url, None);
+        webhook.queue.send(serde_json::json!({"a": 1})).unwrap();
+        webhook.queue.send(serde_json::json!({"a": 2})).unwrap();
+        assert_eq!(webhook.finish(), (1, Some("status 400".into())));
+        assert_eq!(server.join().unwrap(), 3);
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use serde::Deserialize;
+use tau_engine::Rule;
+use uuid::Uuid;
+
+use crate::event;
+use crate::ioc_index::IndexedRule;
+use crate::iocs::IocSet;
+
+/// The severity of a rule, ordered from least to most severe.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Informational,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl FromStr for Level {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "informational" | "info" => Ok(Level::Informational),
+            "low" => Ok(Level::Low),
+            "medium" => Ok(Level::Medium),
+            "high" => Ok(Level::High),
+            "critical" => Ok(Level::Critical),
+            _ => Err(format!(
+                "Unknown level {}, expected one of informational, low, medium, high or critical",
+                s
+            )),
+        }
+    }
+}
+
+/// What names a rule in its output file, tags and stats.
+#[derive(Clone, Copy)]
+pub enum NameBy {
+    Id,
+    /// The rule's title, or its id when it has no title.
+    Title,
+    Filename,
+}
+
+impl FromStr for NameBy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "id" => Ok(NameBy::Id),
+            "title" => Ok(NameBy::Title),
+            "filename" => Ok(NameBy::Filename),
+            _ => Err(format!(
+                "Unknown naming {}, expected one of id, title or filename",
+                s
+            )),
+        }
+    }
+}
+
+/// The phase used for rules which do not set one.
+const DETECTION_PHASE: u32 = 2;
+
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Phase {
+    Index(u32),
+    Name(String),
+}
+
+/// Descriptive fields read from a rule file alongside its detection.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    /// A stable identifier for the rule, used in place of its file name.
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub level: Option<String>,
+    pub tags: Vec<String>,
+    pub phase: Option<Phase>,
+}
+
+impl Metadata {
+    /// The rule's level, `None` when it is missing or not recognised.
+    pub fn level(&self) -> Option<Level> {
+        self.level.as_ref().and_then(|l| l.parse().ok())
+    }
+
+    /// The phase the rule runs in, rules in earlier phases tag the event before later phases run.
+    pub fn phase(&self) -> Result<u32, String> {
+        match &self.phase {
+            None => Ok(DETECTION_PHASE),
+            Some(Phase::Index(i)) => Ok(*i),
+            Some(Phase::Name(n)) => match n.to_lowercase().as_str() {
+                "enrichment" => Ok(0),
+                "suppression" => Ok(1),
+                "detection" => Ok(DETECTION_PHASE),
+                _ => Err(format!(
+                    "Unknown phase {}, expected a number or one of enrichment, suppression or detection",
+                    n
+                )),
+            },
+        }
+    }
+
+    /// Read the metadata from a rule's YAML, rules without any metadata get the default.
+    pub fn load(rule: &str) -> Self {
+        serde_yaml::from_str(rule).unwrap_or_default()
+    }
+}
+
+/// Something events are matched against, either a Tau rule or a set of indicators.
+pub enum Matcher {
+    Rule(Rule),
+    /// A rule with its long value lists indexed by --ioc-index.
+    Indexed(IndexedRule),
+    Iocs(IocSet),
+}
+
+impl Matcher {
+    pub fn matches(&self, json: &mut serde_json::Value) -> bool {
+        match self {
+            Matcher::Rule(r) => r.matches(json),
+            Matcher::Indexed(r) => r.matches(json),
+            Matcher::Iocs(i) => i.matches(json),
+        }
+    }
+}
+
+fn file_name(path: &Path) -> Result<String, String> {
+    match path.file_name().and_then(|f| f.to_str()) {
+        Some(f) => Ok(f.to_string()),
+        None => Err(format!("Unable to validate {} as a rule", path.display())),
+    }
+}
+
+/// The rule's id, from its metadata or else its file name and a hash of its canonical path, so
+/// rules with the same file name in different directories have different ids.
+pub fn id(path: &Path, metadata: &Metadata) -> Result<String, String> {
+    if let Some(id) = &metadata.id {
+        return Ok(id.clone());
+    }
+    let canonical = fs::canonicalize(path)
+        .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+    let hash = Uuid::new_v5(&Uuid::NAMESPACE_URL, canonical.to_string_lossy().as_bytes());
+    Ok(format!(
+        "{}-{}",
+        file_name(path)?,
+        &hash.simple().to_string()[..8]
+    ))
+}
+
+/// The rule's name as chosen by `by`.
+pub fn name(path: &Path, metadata: &Metadata, by: NameBy) -> Result<String, String> {
+    match (by, &metadata.title) {
+        (NameBy::Title, Some(title)) => Ok(title.clone()),
+        (NameBy::Filename, _) => file_name(path),
+        _ => id(path, metadata),
+    }
+}
+
+/// What a rule claims, which two rules can't share.
+#[derive(Clone, Copy)]
+pub enum Claim {
+    Id,
+    /// The name chosen by --name-by.
+    Name,
+}
+
+/// Claim an id or name for the rule at `path`, two rules with the same one would share outputs and
+/// stats.
+pub fn claim(
+    taken: &mut HashMap<String, PathBuf>,
+    claim: Claim,
+    id: &str,
+    path: &Path,
+) -> Result<(), String> {
+    let (kind, hint) = match claim {
+        Claim::Id => ("id", "give one of them a unique id in its metadata"),
+        Claim::Name => ("name", "rename one of them or use a different --name-by"),
+    };
+    match taken.insert(id.to_string(), path.to_path_buf()) {
+        Some(other) => Err(format!(
+            "{} and {} both have the rule {} {}, {}",
+            other.display(),
+            path.display(),
+            kind,
+            id,
+            hint
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Load and validate rules for the subcommands, named by their ids. Unlike matching, a rule which
+/// fails to load is an error.
+pub fn load(paths: &[PathBuf]) -> Result<Vec<(String, Rule)>, String> {
+    let (mut rules, mut taken) = (Vec::new(), HashMap::new());
+    for path in paths {
+        let data = fs::read_to_string(path)
+            .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+        let rule = match Rule::load(&data) {
+            Ok(r) if r.validate().unwrap_or(false) => r,
+            _ => return Err(format!("Unable to validate {} as a rule", path.display())),
+        };
+        let id = id(path, &Metadata::load(&data))?;
+        claim(&mut taken, Claim::Id, &id, path)?;
+        rules.push((id, rule));
+    }
+    Ok(rules)
+}
+
+/// Whether labels, a string or array of strings, name the rule by its id, or by the file name of a
+/// rule without one in its metadata with or without its extension.
+pub fn has_label(labels: &serde_json::Value, rule: &str) -> bool {
+    // An id made from the file name has a hash of the path after it, which labels leave off
+    let file = match rule.rsplit_once('-') {
+        Some((file, hash))
+            if file.contains('.')
+                && hash.len() == 8
+                && hash.bytes().all(|b| b.is_ascii_hexdigit()) =>
+        {
+            file
+        }
+        _ => rule,
+    };
+    let stem = file.rsplit_once('.').map(|(s, _)| s).unwrap_or(file);
+    let is_rule = |l: &serde_json::Value| matches!(l.as_str(), Some(l) if l == rule || l == file || l == stem);
+    match labels {
+        serde_json::Value::Array(a) => a.iter().any(is_rule),
+        l => is_rule(l),
+    }
+}
+
+/// Whether an event is labelled as one the rule should match in its label field.
+pub fn labelled(json: &serde_json::Value, field: &str, rule: &str) -> bool {
+    match crate::event::get(json, field) {
+        Some(l) => has_label(l, rule),
+        None => false,
+    }
+}
+
+pub struct ValidatedRule {
+    /// The loaded rule, `None` when the rule failed to load or validate.
+    pub rule: Option<Matcher>,
+    pub id: String,
+    /// The name chosen by --name-by, used for the rule's output file, tags and stats.
+    pub name: String,
+    pub path: PathBuf,
+    pub metadata: Metadata,
+    pub phase: u32,
+    /// The rule's detection logic in pseudo-English, for --explain.
+    pub explanation: Option<String>,
+    /// Where the rule came from, for --stamp-origin.
+    pub origin: Option<serde_json::Value>,
+}
+
+/// Put a match in an envelope naming the rule which matched it, for --wrap, timestamped with the
+/// event's time or else the current time.
+pub fn wrap(
+    json: &serde_json::Value,
+    rule: &ValidatedRule,
+    time: Option<SystemTime>,
+) -> serde_json::Value {
+    let timestamp = time
+        .and_then(event::rfc3339)
+        .or_else(|| event::rfc3339(SystemTime::now()));
+    serde_json::json!({
+        "rule": rule.name,
+        "level": rule.metadata.level,
+        "tags": rule.metadata.tags,
+        "timestamp": timestamp,
+        "event": json,
+    })
+}
+
+/// Append tags to the event's top level `tags` array so rules in later phases can match on them.
+/// A scalar `tags` is kept as it is unless there are other tags to add, then it's the array's first.
+pub fn annotate(json: &mut serde_json::Value, tags: &[String]) {
+    let object = match json.as_object_mut() {
+        Some(o) if !tags.is_empty() => o,
+        _ => return,
+    };
+    let existing = object.entry("tags").or_insert(serde_json::Value::Null);
+    match &mut *existing {
+        serde_json::Value::Array(_) => {}
+        serde_json::Value::Null => *existing = serde_json::Value::Array(Vec::new()),
+        scalar if tags.iter().all(|t| scalar.as_str() == Some(t)) => return,
+        scalar => *scalar = serde_json::Value::Array(vec![scalar.take()]),
+    }
+    if let Some(array) = existing.as_array_mut() {
+        for tag in tags {
+            if !array.iter().any(|t| t.as_str() == Some(tag)) {
+                array.push(serde_json::Value::String(tag.clone()));
+            }
+        }
+    }
+}
+
+/// Insert a value under a matched event's `tau` object.
+fn insert_tau(json: &mut serde_json::Value, key: &str, value: serde_json::Value) {
+    if let Some(object) = json.as_object_mut() {
+        let tau = object
+            .entry("tau")
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        if let Some(tau) = tau.as_object_mut() {
+            tau.insert(key.into(), value);
+        }
+    }
+}
+
+/// Add the explanation of the rule a matched event matched to its `tau.explanation`.
+pub fn explain(json: &mut serde_json::Value, explanation: &str) {
+    insert_tau(json, "explanation", explanation.into());
+}
+
+/// Add where the rule a matched event matched came from to its `tau.origin`.
+pub fn stamp(json: &mut serde_json::Value, origin: &serde_json::Value) {
+    insert_tau(json, "origin", origin.clone());
+}
+
+/// The manifest naming a pack of rules, found in the pack's top directory.
+pub const PACK: &str = "pack.yml";
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Pack {
+    name: Option<String>,
+    version: Option<serde_yaml::Value>,
+}
+
+/// Run git in a directory, returning its output when it succeeds.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string(),
+    )
+}
+
+/// The last commit to change each file in a repository, and the files with uncommitted changes.
+#[derive(Default)]
+struct Repo {
+    commits: HashMap<PathBuf, String>,
+    modified: HashSet<PathBuf>,
+}
+
+impl Repo {
+    /// Read the history and status of the repository at `top` with one `git log` and one
+    /// `git status`, rather than once for each rule in it.
+    fn load(top: &Path) -> Self {
+        let mut repo = Repo::default();
+        let log = git(
+            top,
+            &[
+                "-c",
+                "core.quotepath=off",
+                "log",
+                "--format=%x01%H",
+                "--name-only",
+            ],
+        )
+        .unwrap_or_default();
+        let mut commit = "";
+        for line in log.lines() {
+            match line.strip_prefix('\u{1}') {
+                Some(hash) => commit = hash,
+                // The log is newest first, so the first commit seen for a file is its last
+                None if !line.is_empty() => {
+                    repo.commits
+                        .entry(top.join(line))
+                        .or_insert_with(|| commit.to_string());
+                }
+                None => {}
+            }
+        }
+        let status =
+            git(top, &["-c", "core.quotepath=off", "status", "--porcelain"]).unwrap_or_default();
+        for line in status.lines().filter(|l| l.len() > 3) {
+            let file = &line[3..];
+            let file = file.rsplit(" -> ").next().unwrap_or(file);
+            repo.modified.insert(top.join(file));
+        }
+        repo
+    }
+}
+
+/// The git repositories rules have been found in, so each is only read once however many rules
+/// it holds.
+#[derive(Default)]
+pub struct Origins {
+    tops: Mutex<HashMap<PathBuf, Option<PathBuf>>>,
+    repos: Mutex<HashMap<PathBuf, Repo>>,
+}
+
+impl Origins {
+    /// Forget what's been read, so rules reloaded after a change see the repository as it is now.
+    pub fn clear(&self) {
+        self.tops.lock().unwrap().clear();
+        self.repos.lock().unwrap().clear();
+    }
+
+    /// The last commit to change `full`, and whether it has uncommitted changes.
+    fn commit(&self, full: &Path) -> Option<(String, bool)> {
+        let dir = full.parent()?;
+        let top = self
+            .tops
+            .lock()
+            .unwrap()
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                git(dir, &["rev-parse", "--show-toplevel"])
+                    .map(|top| fs::canonicalize(&top).unwrap_or_else(|_| PathBuf::from(top)))
+            })
+            .clone()?;
+        let mut repos = self.repos.lock().unwrap();
+        let repo = repos.entry(top.clone()).or_insert_with(|| Repo::load(&top));
+        let commit = repo.commits.get(full)?.clone();
+        Some((commit, repo.modified.contains(full)))
+    }
+
+    /// Where a rule came from: its path, the name and version of the pack it's in, from the
+    /// nearest `pack.yml` above it, and the last commit to change it when it's in a git
+    /// repository, which is `modified` when the file has changes which haven't been committed.
+    pub fn origin(&self, path: &Path) -> serde_json::Value {
+        let mut origin = serde_json::Map::new();
+        origin.insert("path".into(), path.display().to_string().into());
+        let full = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let manifest = full
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join(PACK))
+            .find(|manifest| manifest.is_file())
+            .and_then(|manifest| fs::read_to_string(manifest).ok())
+            .unwrap_or_default();
+        let pack = serde_yaml::from_str::<Pack>(&manifest).unwrap_or_default();
+        if let Some(name) = pack.name {
+            origin.insert("pack".into(), name.into());
+        }
+        let version = match pack.version {
+            Some(serde_yaml::Value::String(v)) => Some(v),
+            // Versions such as 1.10 read as numbers, so they're taken as written instead
+            Some(_) => manifest.lines().find_map(|l| {
+                let v = l.strip_prefix("version:")?.split('#').next()?.trim();
+                Some(v.trim_matches(|c| c == '"' || c == '\'').to_string())
+            }),
+            None => None,
+        };
+        if let Some(version) = version {
+            origin.insert("version".into(), version.into());
+        }
+        if let Some((commit, modified)) = self.commit(&full) {
+            origin.insert("commit".into(), commit.into());
+            if modified {
+                origin.insert("modified".into(), true.into());
+            }
+        }
+        serde_json::Value::Object(origin)
+    }
+}
+
+/// Record the names of the rules an event matched in its `tau.tags` array.
+pub fn mark(json: &mut serde_json::Value, names: &[&str]) {
+    let object = match json.as_object_mut() {
+        Some(o) => o,
+        None => return,
+    };
+    let tau = object
+        .entry("tau")
+        .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+    if let Some(tau) = tau.as_object_mut() {
+        tau.insert(
+            "tags".into(),
+            names
+                .iter()
+                .map(|n| serde_json::Value::String(n.to_string()))
+                .collect(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn tags_are_only_added_when_there_are_some() {
+        let mut event = json!({"a": 1});
+        annotate(&mut event, &[]);
+        assert_eq!(event, json!({"a": 1}));
+        annotate(&mut event, &["x".into()]);
+        assert_eq!(event, json!({"a": 1, "tags": ["x"]}));
+        annotate(&mut event, &["x".into(), "y".into()]);
+        assert_eq!(event, json!({"a": 1, "tags": ["x", "y"]}));
+    }
+
+    #[test]
+    fn ids_of_rules_with_the_same_file_name_differ() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a/x.yml"), dir.path().join("b/x.yml"));
+        for path in [&a, &b] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let none = Metadata::default();
+        let (id_a, id_b) = (id(&a, &none).unwrap(), id(&b, &none).unwrap());
+        assert!(id_a.starts_with("x.yml-") && id_b.starts_with("x.yml-"));
+        assert_ne!(id_a, id_b);
+        assert_eq!(id_a, id(&dir.path().join("b/../a/x.yml"), &none).unwrap());
+        assert_eq!(name(&a, &none, NameBy::Filename).unwrap(), "x.yml");
+        let metadata = Metadata {
+            id: Some("rule".into()),
+            ..Default::default()
+        };
+        assert_eq!(id(&a, &metadata).unwrap(), "rule");
+    }
+
+    #[test]
+    fn labels_name_hashed_ids_by_their_file_name() {
+        let rule = "foo.yml-dde2aaf1";
+        for label in [rule, "foo.yml", "foo"] {
+            assert!(has_label(&json!(label), rule), "{}", label);
+        }
+        assert!(has_label(&json!(["other", "foo.yml"]), rule));
+        assert!(!has_label(&json!("bar.yml"), rule));
+        assert!(has_label(&json!("custom-id"), "custom-id"));
+    }
+
+    #[test]
+    fn scalar_tags_are_kept() {
+        let mut event = json!({"tags": "x"});
+        annotate(&mut event, &[]);
+        annotate(&mut event, &["x".into()]);
+        assert_eq!(event, json!({"tags": "x"}));
+        annotate(&mut event, &["y".into()]);
+        assert_eq!(event, json!({"tags": ["x", "y"]}));
+    }
+
+    #[test]
+    fn origins_come_from_one_read_of_the_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let top = dir.path();
+        let (a, b, loose) = (top.join("a.yml"), top.join("sub/b.yml"), top.join("c.yml"));
+        fs::create_dir_all(top.join("sub")).unwrap();
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        let quiet = [
+            "-c",
+            "user.name=t",
+            "-c",
+            "user.email=t@t",
+            "-c",
+            "commit.gpgsign=false",
+        ];
+        if git(top, &["init", "-q"]).is_none() {
+            return;
+        }
+        git(top, &["add", "."]).unwrap();
+        git(
+            top,
+            &[&quiet[..], &["commit", "-q", "-m", "rules"]].concat(),
+        )
+        .unwrap();
+        let head = git(top, &["rev-parse", "HEAD"]).unwrap();
+        fs::write(&b, "changed").unwrap();
+        fs::write(&loose, "c").unwrap();
+        let origins = Origins::default();
+        assert_eq!(origins.origin(&a)["commit"], head.as_str());
+        assert!(origins.origin(&a).get("modified").is_none());
+        assert_eq!(origins.origin(&b)["commit"], head.as_str());
+        assert_eq!(origins.origin(&b)["modified"], true);
+        assert!(origins.origin(&loose).get("commit").is_none());
+        assert_eq!(origins.repos.lock().unwrap().len(), 1);
+    }
+}
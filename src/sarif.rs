@@ -0,0 +1,135 @@
+use std::{collections::HashMap, path::Path};
+
+use serde_json::{json, Value};
+
+use crate::rules::{Level, ValidatedRule};
+
+/// The SARIF level of a rule's level, rules without one are warnings.
+fn level(rule: &ValidatedRule) -> &'static str {
+    match rule.metadata.level() {
+        Some(Level::Critical) | Some(Level::High) => "error",
+        Some(Level::Medium) | None => "warning",
+        Some(Level::Low) | Some(Level::Informational) => "note",
+    }
+}
+
+/// Percent-encode a path's bytes, other than unreserved characters and separators.
+fn encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// A path as a URI, a `file` URI when it's absolute and otherwise a relative reference, which
+/// can't be taken for a scheme as its colons are encoded.
+fn uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let bytes = path.as_bytes();
+    let drive = bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1..3] == *b":/";
+    match (drive, path.starts_with('/')) {
+        (true, _) => format!("file:///{}:{}", &path[..1], encode(&path[2..])),
+        (false, true) => format!("file://{}", encode(&path)),
+        (false, false) => encode(&path),
+    }
+}
+
+/// Collects matches into a single SARIF log, written once all input has been processed.
+pub struct Sarif {
+    descriptors: Vec<Value>,
+    /// The index of each rule's descriptor, by the rule's id.
+    indices: HashMap<String, usize>,
+    results: Vec<Value>,
+}
+
+impl Sarif {
+    /// Describe every rule which loaded, whether or not it goes on to match.
+    pub fn new(rules: &[ValidatedRule]) -> Self {
+        let mut sarif = Sarif {
+            descriptors: Vec::new(),
+            indices: HashMap::new(),
+            results: Vec::new(),
+        };
+        for rule in rules.iter().filter(|r| r.rule.is_some()) {
+            let title = rule.metadata.title.as_deref().unwrap_or(&rule.name);
+            let mut descriptor = json!({
+                "id": rule.id,
+                "name": rule.name,
+                "shortDescription": { "text": title },
+                "defaultConfiguration": { "level": level(rule) },
+                "properties": { "tags": rule.metadata.tags },
+            });
+            if let Some(description) = &rule.metadata.description {
+                descriptor["fullDescription"] = json!({ "text": description });
+            }
+            sarif
+                .indices
+                .insert(rule.id.clone(), sarif.descriptors.len());
+            sarif.descriptors.push(descriptor);
+        }
+        sarif
+    }
+
+    /// Add a match as a result, located in the input file it was read from when there is one.
+    pub fn add(&mut self, json: &Value, rule: &ValidatedRule, path: Option<&Path>) {
+        let fingerprint = uuid::Uuid::new_v5(
+            &uuid::Uuid::NAMESPACE_URL,
+            format!("tau-cli:{}:{}", rule.id, json).as_bytes(),
+        );
+        let mut result = json!({
+            "ruleId": rule.id,
+            "level": level(rule),
+            "message": { "text": format!("{} matched", rule.name) },
+            "partialFingerprints": { "eventHash/v1": fingerprint.to_string() },
+            "properties": { "event": json },
+        });
+        if let Some(i) = self.indices.get(&rule.id) {
+            result["ruleIndex"] = json!(i);
+        }
+        if let Some(path) = path {
+            result["locations"] = json!([
+                { "physicalLocation": { "artifactLocation": { "uri": uri(path) } } }
+            ]);
+        }
+        self.results.push(result);
+    }
+
+    pub fn document(self) -> Value {
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "tau-cli",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": self.descriptors,
+                    }
+                },
+                "results": self.results,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_uris() {
+        assert_eq!(uri(Path::new("logs/a b#1%.json")), "logs/a%20b%231%25.json");
+        assert_eq!(uri(Path::new("/var/log/x.json")), "file:///var/log/x.json");
+        assert_eq!(
+            uri(Path::new("C:\\Logs\\x y.json")),
+            "file:///C:/Logs/x%20y.json"
+        );
+        assert_eq!(uri(Path::new("a:b.json")), "a%3Ab.json");
+    }
+}
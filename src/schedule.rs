@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+};
+
+use crate::index::Plan;
+use crate::input::Input;
+
+/// Re-scans the input files on a cron schedule, reading only what was appended since the last tick.
+pub struct Scheduler {
+    schedule: cron::Schedule,
+    inputs: Vec<PathBuf>,
+    state: Option<PathBuf>,
+    offsets: HashMap<PathBuf, u64>,
+}
+
+/// The offset just past the last complete line of a file, so lines still being written are left for the next tick.
+fn complete_len(path: &Path, start: u64, len: u64) -> io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    let mut end = len;
+    let mut buffer = vec![0; 64 * 1024];
+    while end > start {
+        let size = (end - start).min(buffer.len() as u64) as usize;
+        file.seek(io::SeekFrom::Start(end - size as u64))?;
+        file.read_exact(&mut buffer[..size])?;
+        if let Some(i) = buffer[..size].iter().rposition(|b| *b == b'\n') {
+            return Ok(end - size as u64 + i as u64 + 1);
+        }
+        end -= size as u64;
+    }
+    Ok(start)
+}
+
+impl Scheduler {
+    /// Parse a cron expression, the standard five fields or with a leading seconds field, and load
+    /// the offsets saved in `state` if it exists.
+    pub fn new(
+        expression: &str,
+        inputs: Vec<PathBuf>,
+        state: Option<PathBuf>,
+    ) -> Result<Self, String> {
+        let fields = expression.split_whitespace().count();
+        let expanded = match fields {
+            5 => format!("0 {}", expression),
+            _ => expression.to_string(),
+        };
+        let schedule = cron::Schedule::from_str(&expanded)
+            .map_err(|e| format!("Invalid schedule {}, {}", expression, e))?;
+        let offsets = match &state {
+            Some(p) if p.exists() => {
+                let data = fs::read_to_string(p)
+                    .map_err(|_| format!("Unable to read data from {}.", p.display()))?;
+                serde_json::from_str(&data)
+                    .map_err(|e| format!("Invalid schedule state {}, {}", p.display(), e))?
+            }
+            _ => HashMap::new(),
+        };
+        Ok(Scheduler {
+            schedule,
+            inputs,
+            state,
+            offsets,
+        })
+    }
+
+    /// Sleep until the next time the schedule fires.
+    pub fn wait(&self) {
+        let now = chrono::Local::now();
+        if let Some(next) = self.schedule.after(&now).next() {
+            if let Ok(d) = (next - now).to_std() {
+                thread::sleep(d);
+            }
+        }
+    }
+
+    /// Open the lines appended to the inputs since the last tick, directories are scanned for new
+    /// files. Files which shrank are assumed to have been rotated and are read from the start.
+    pub fn next_input(&mut self) -> Result<Option<Input>, String> {
+        let mut files = Vec::new();
+        for path in self.inputs.iter() {
+            match fs::read_dir(path) {
+                Ok(entries) => {
+                    let mut entries: Vec<PathBuf> = entries
+                        .flatten()
+                        .map(|e| e.path())
+                        .filter(|p| p.is_file())
+                        .collect();
+                    entries.sort();
+                    files.extend(entries);
+                }
+                Err(_) => files.push(path.clone()),
+            }
+        }
+        let mut ranges = HashMap::new();
+        let mut paths = Vec::new();
+        for path in files {
+            // Inputs which don't exist yet may appear on a later tick
+            let canonical = match fs::canonicalize(&path) {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+            let len = fs::metadata(&canonical)
+                .map_err(|_| format!("Unable to read input file at {}.", path.display()))?
+                .len();
+            let start = match self.offsets.get(&canonical) {
+                Some(&offset) if offset <= len => offset,
+                _ => 0,
+            };
+            let end = complete_len(&canonical, start, len)
+                .map_err(|_| format!("Unable to read input file at {}.", path.display()))?;
+            if end > start {
+                ranges.insert(canonical.clone(), vec![(start, end)]);
+                paths.push(path);
+            }
+            self.offsets.insert(canonical, end);
+        }
+        if paths.is_empty() {
+            return Ok(None);
+        }
+        // Input reads the paths from the end
+        paths.reverse();
+        Input::open(Some(paths), None, Some(Plan::new(ranges))).map(Some)
+    }
+
+    /// Save the offsets read up to, so a restarted process carries on where this one stopped.
+    pub fn save(&self) -> Result<(), String> {
+        match &self.state {
+            Some(p) => {
+                let tmp = p.with_extension("tmp");
+                let data = serde_json::to_vec(&self.offsets)
+                    .map_err(|e| format!("Unable to write schedule state, {}", e))?;
+                fs::write(&tmp, data)
+                    .and_then(|_| fs::rename(&tmp, p))
+                    .map_err(|_| format!("Unable to write schedule state to {}", p.display()))
+            }
+            None => Ok(()),
+        }
+    }
+}
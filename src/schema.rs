@@ -0,0 +1,49 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde_json::Value;
+
+/// Validates events against a JSON Schema, counting the violations of each schema keyword.
+pub struct Schema {
+    validator: jsonschema::Validator,
+    pub violations: BTreeMap<String, usize>,
+}
+
+impl Schema {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let schema: Value = serde_json::from_str(
+            &fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?,
+        )
+        .map_err(|e| format!("Invalid schema {}, {}", path.display(), e))?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| format!("Invalid schema {}, {}", path.display(), e))?;
+        Ok(Schema {
+            validator,
+            violations: BTreeMap::new(),
+        })
+    }
+
+    /// Returns the violations of an invalid event, or `None` when the event is valid.
+    pub fn check(&mut self, json: &Value) -> Option<Vec<String>> {
+        if self.validator.is_valid(json) {
+            return None;
+        }
+        let mut errors = Vec::new();
+        for e in self.validator.iter_errors(json) {
+            let keyword = e
+                .schema_path
+                .as_str()
+                .rsplit('/')
+                .next()
+                .unwrap_or("")
+                .to_string();
+            *self.violations.entry(keyword).or_insert(0) += 1;
+            let path = match e.instance_path.as_str() {
+                "" => "/",
+                p => p,
+            };
+            errors.push(format!("{}: {}", path, e));
+        }
+        Some(errors)
+    }
+}
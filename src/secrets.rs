@@ -0,0 +1,62 @@
+use std::{env, fs, path::Path, process::Command};
+
+/// The environment variable a sink token is read from when neither a file nor command is given.
+const TOKEN_VARIABLE: &str = "TAU_SINK_TOKEN";
+
+/// A credential for sinks such as webhooks. It's read from a file, a command or the environment
+/// rather than the command line, where it would show in process listings.
+pub struct Secret(String);
+
+impl Secret {
+    fn new(value: String, source: &str) -> Result<Self, String> {
+        let value = value.trim().to_string();
+        match value.is_empty() {
+            true => Err(format!("The secret from {} is empty", source)),
+            false => Ok(Secret(value)),
+        }
+    }
+
+    /// Read the secret from the first line of a file.
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+        let line = data.lines().next().unwrap_or("").to_string();
+        Secret::new(line, &path.display().to_string())
+    }
+
+    /// Read the secret from what a command, run by the shell, writes to stdout.
+    fn from_command(command: &str) -> Result<Self, String> {
+        let output = match cfg!(windows) {
+            true => Command::new("cmd").args(["/C", command]).output(),
+            false => Command::new("sh").args(["-c", command]).output(),
+        }
+        .map_err(|e| format!("Unable to run the secret command, {}", e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(match stderr.trim() {
+                "" => format!("The secret command failed with {}", output.status),
+                e => format!("The secret command failed with {}, {}", output.status, e),
+            });
+        }
+        let value = String::from_utf8(output.stdout)
+            .map_err(|_| "The secret command did not write UTF-8".to_string())?;
+        Secret::new(value, "the secret command")
+    }
+
+    /// Load the secret from a file or command when given, otherwise from `TAU_SINK_TOKEN`.
+    pub fn load(file: Option<&Path>, command: Option<&str>) -> Result<Option<Self>, String> {
+        match (file, command, env::var(TOKEN_VARIABLE)) {
+            (Some(path), _, _) => Secret::from_file(path).map(Some),
+            (None, Some(command), _) => Secret::from_command(command).map(Some),
+            (None, None, Ok(value)) if !value.is_empty() => {
+                Secret::new(value, TOKEN_VARIABLE).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The secret as an HTTP Authorization header value.
+    pub fn bearer(&self) -> String {
+        format!("Bearer {}", self.0)
+    }
+}
@@ -0,0 +1,60 @@
+use serde_json::Value;
+
+use crate::input::Input;
+
+/// Receives batches of events from the Kubernetes audit webhook backend, which POSTs `EventList`
+/// bodies to the configured URL.
+pub struct AuditServer {
+    server: tiny_http::Server,
+}
+
+impl AuditServer {
+    pub fn new(addr: &str) -> Result<Self, String> {
+        Ok(AuditServer {
+            server: tiny_http::Server::http(addr)
+                .map_err(|e| format!("Unable to listen on {}, {}", addr, e))?,
+        })
+    }
+
+    /// Block until the next `EventList` arrives, returning its events. Requests which aren't an
+    /// `EventList` are rejected so the API server reports the misconfiguration.
+    pub fn next_input(&mut self) -> Result<Input, String> {
+        loop {
+            let mut request = self
+                .server
+                .recv()
+                .map_err(|e| format!("Unable to receive audit events, {}", e))?;
+            let mut body = String::new();
+            let items = match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => match serde_json::from_str::<Value>(&body) {
+                    Ok(Value::Object(mut list))
+                        if list.get("kind").and_then(|k| k.as_str()) == Some("EventList") =>
+                    {
+                        match list.remove("items") {
+                            Some(Value::Array(items)) => Ok(items),
+                            _ => Ok(Vec::new()),
+                        }
+                    }
+                    Ok(_) => Err("expected an EventList".to_string()),
+                    Err(e) => Err(e.to_string()),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            let items = match items {
+                Ok(items) => items,
+                Err(e) => {
+                    let _ = request.respond(
+                        tiny_http::Response::from_string(format!("Invalid audit events, {}", e))
+                            .with_status_code(400),
+                    );
+                    continue;
+                }
+            };
+            // A failed response only means the API server retries the batch
+            let _ = request.respond(tiny_http::Response::empty(200));
+            if !items.is_empty() {
+                return Ok(Input::Events(items.into_iter()));
+            }
+        }
+    }
+}
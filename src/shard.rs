@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use crate::index::Plan;
+use crate::input;
+
+/// One of several shards of the input, given as `N/M` where N counts from 1.
+pub struct Shard {
+    index: u64,
+    count: u64,
+}
+
+impl FromStr for Shard {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid shard {}, expected N/M e.g. 3/8", s);
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let index: u64 = index.trim().parse().map_err(|_| invalid())?;
+        let count: u64 = count.trim().parse().map_err(|_| invalid())?;
+        if index == 0 || index > count {
+            return Err(invalid());
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+/// The offset of the first line starting at or after `offset`.
+pub fn line_start(file: &mut BufReader<fs::File>, offset: u64, len: u64) -> io::Result<u64> {
+    if offset == 0 || offset >= len {
+        return Ok(offset.min(len));
+    }
+    // A line starts at the offset when the byte before it ends a line
+    file.seek(SeekFrom::Start(offset - 1))?;
+    let mut skipped = Vec::new();
+    let n = file.read_until(b'\n', &mut skipped)?;
+    Ok((offset - 1 + n as u64).min(len))
+}
+
+impl Shard {
+    /// A name for this shard's own output files, e.g. `3-of-8`.
+    pub fn suffix(&self) -> String {
+        format!("{}-of-{}", self.index, self.count)
+    }
+
+    /// The files in this shard. Files are shared out by their position in sorted order, so every
+    /// shard agrees on the split whatever order the paths were given in.
+    pub fn files(&self, paths: &mut Vec<PathBuf>) {
+        let mut sorted = paths.clone();
+        sorted.sort();
+        sorted.dedup();
+        let keep: Vec<&PathBuf> = sorted
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i as u64 % self.count == self.index - 1)
+            .map(|(_, p)| p)
+            .collect();
+        paths.retain(|p| keep.contains(&p));
+    }
+
+    /// A plan reading this shard's range of lines from every file, each file is split into equal
+    /// byte ranges which are moved forward to the start of a line.
+    pub fn plan(&self, paths: &[PathBuf]) -> Result<Plan, String> {
+        let mut ranges = HashMap::new();
+        for path in paths {
+            let error = |_| format!("Unable to read input file at {}.", path.display());
+            let canonical = fs::canonicalize(path).map_err(error)?;
+            if input::is_compressed(&canonical) {
+                return Err(format!(
+                    "{} is compressed, so can't be split into byte ranges for --shard-lines",
+                    path.display()
+                ));
+            }
+            let len = fs::metadata(&canonical).map_err(error)?.len();
+            let mut file = BufReader::new(fs::File::open(&canonical).map_err(error)?);
+            let bound = |shard: u64| (len as u128 * shard as u128 / self.count as u128) as u64;
+            let start = line_start(&mut file, bound(self.index - 1), len).map_err(error)?;
+            let end = line_start(&mut file, bound(self.index), len).map_err(error)?;
+            ranges.insert(canonical, vec![(start, end)]);
+        }
+        Ok(Plan::new(ranges))
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::BinaryHeap,
+    io::{self, stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+use tau_engine::Rule;
+
+use crate::event;
+use crate::fuzz::{delete, leaves};
+use crate::input::Input;
+use crate::output::{self, FileOptions};
+use crate::rules;
+
+#[derive(StructOpt)]
+pub struct Shrink {
+    /// The rule the shrunk event must still match.
+    #[structopt(short, long, parse(from_os_str))]
+    rule: PathBuf,
+
+    /// The corpus to find matching events in.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    input: Vec<PathBuf>,
+
+    /// The number of matching events shrunk, the smallest first, before keeping the smallest result.
+    #[structopt(long, default_value = "100")]
+    candidates: usize,
+
+    /// The seed for the order fields and characters are removed in, a different seed can find a different minimal event.
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// Path to write the shrunk event to as JSON, by default it's written to stdout.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Overwrite the output file.
+    #[structopt(short = "f", long)]
+    overwrite: bool,
+}
+
+/// The size shrinking reduces, the number of fields and then the length of their values.
+type Size = (usize, usize);
+
+/// A matching event with its size and the order it was read in, ordered by both.
+struct Candidate(Size, usize, Value);
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        (self.0, self.1) == (other.0, other.1)
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.0, self.1).cmp(&(other.0, other.1))
+    }
+}
+
+fn size(json: &Value) -> Size {
+    let mut found = Vec::new();
+    leaves(String::new(), json, &mut found);
+    let length = found
+        .iter()
+        .map(|f| match event::get(json, f) {
+            Some(Value::String(s)) => s.chars().count(),
+            Some(v) => v.to_string().len(),
+            None => 0,
+        })
+        .sum();
+    (found.len(), length)
+}
+
+/// Remove fields one at a time while the rule still matches, until none can be removed.
+fn fields(rule: &Rule, rng: &mut fastrand::Rng, json: &mut Value) {
+    'removed: loop {
+        let mut found = Vec::new();
+        leaves(String::new(), json, &mut found);
+        rng.shuffle(&mut found);
+        for field in found {
+            let mut smaller = json.clone();
+            delete(&mut smaller, &field);
+            if rule.matches(&smaller) {
+                // Removing an array item moves the ones after it, so the paths are found again
+                *json = smaller;
+                continue 'removed;
+            }
+        }
+        break;
+    }
+}
+
+/// Cut each string value down to the fewest characters the rule still matches, removing runs of
+/// characters which halve in length until single characters are tried.
+fn values(rule: &Rule, rng: &mut fastrand::Rng, json: &mut Value) {
+    let mut found = Vec::new();
+    leaves(String::new(), json, &mut found);
+    rng.shuffle(&mut found);
+    for field in found {
+        let mut chars: Vec<char> = match event::get(json, &field) {
+            Some(Value::String(s)) => s.chars().collect(),
+            _ => continue,
+        };
+        let mut run = chars.len().div_ceil(2);
+        while run > 0 {
+            let mut starts: Vec<usize> = (0..chars.len()).step_by(run).collect();
+            rng.shuffle(&mut starts);
+            let mut cut = false;
+            for start in starts {
+                let end = (start + run).min(chars.len());
+                if start >= end {
+                    continue;
+                }
+                let shorter: String = chars[..start].iter().chain(&chars[end..]).collect();
+                let mut smaller = json.clone();
+                if let Some(v) = event::get_mut(&mut smaller, &field) {
+                    *v = Value::String(shorter);
+                }
+                if rule.matches(&smaller) {
+                    chars.drain(start..end);
+                    *json = smaller;
+                    cut = true;
+                    break;
+                }
+            }
+            // A cut can make another possible at the same length, so only a miss moves on
+            if !cut {
+                run /= 2;
+            }
+        }
+    }
+}
+
+impl Shrink {
+    pub fn run(self) -> Result<(), String> {
+        let (name, rule) = rules::load(std::slice::from_ref(&self.rule))?
+            .pop()
+            .ok_or_else(|| format!("Unable to load {} as a rule", self.rule.display()))?;
+        let mut stderr = stderr();
+        // Only the smallest --candidates matches are kept, the largest of them is dropped for a
+        // smaller one, and ties keep the order they were read in
+        let candidates = self.candidates.max(1);
+        let mut matches = BinaryHeap::new();
+        let mut found = 0;
+        for res in Input::open(Some(self.input.clone()), None, None)? {
+            let json = match res {
+                Ok(json) => json,
+                Err(e) => {
+                    let _ = writeln!(stderr, "{}", e);
+                    continue;
+                }
+            };
+            if rule.matches(&json) {
+                matches.push(Candidate(size(&json), found, json));
+                found += 1;
+                if matches.len() > candidates {
+                    matches.pop();
+                }
+            }
+        }
+        if matches.is_empty() {
+            return Err(format!("No events in the corpus match {}", name));
+        }
+        // The smallest matches are the likeliest to shrink furthest, so those are tried first
+        let mut smallest: Option<(Size, Value, Size)> = None;
+        for Candidate(before, _, mut json) in matches.into_sorted_vec() {
+            // Each event gets its own generator so the result doesn't depend on the others tried
+            let mut rng = fastrand::Rng::with_seed(self.seed);
+            fields(&rule, &mut rng, &mut json);
+            values(&rule, &mut rng, &mut json);
+            let after = size(&json);
+            if smallest
+                .as_ref()
+                .map(|(s, _, _)| after < *s)
+                .unwrap_or(true)
+            {
+                smallest = Some((after, json, before));
+            }
+        }
+        let (after, json, before) = smallest.expect("there is at least one match");
+        let _ = writeln!(
+            stderr,
+            "Shrunk the smallest of {} matches of {} from {} fields to {}",
+            found, name, before.0, after.0
+        );
+        let write = |out: &mut dyn Write| -> io::Result<()> {
+            let mut out = io::BufWriter::new(out);
+            serde_json::to_writer_pretty(&mut out, &json)?;
+            writeln!(out)?;
+            out.flush()
+        };
+        match &self.output {
+            Some(path) => {
+                let options = FileOptions {
+                    overwrite: self.overwrite,
+                    append: false,
+                    mode: None,
+                    owner: None,
+                    dry_run: false,
+                };
+                let mut file = output::create_file(path, &options)
+                    .map_err(|_| format!("Could not create output file at {}", path.display()))?;
+                write(&mut file)
+                    .map_err(|e| format!("Unable to write the event to {}, {}", path.display(), e))
+            }
+            None => write(&mut stdout()).map_err(|e| format!("Unable to write the event, {}", e)),
+        }
+    }
+}
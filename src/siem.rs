@@ -0,0 +1,159 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::Value;
+
+use crate::event;
+use crate::rules::{Level, ValidatedRule};
+
+const VENDOR: &str = "tau-cli";
+const PRODUCT: &str = "tau-cli";
+
+/// The pattern of the RFC 3339 timestamps written in LEEF's devTime.
+const LEEF_TIME_FORMAT: &str = "yyyy-MM-dd'T'HH:mm:ss.SSSX";
+
+/// An extension key of a CEF or LEEF line and the event field its value is taken from.
+#[derive(Clone)]
+pub struct FieldMapping {
+    key: String,
+    field: String,
+}
+
+impl FromStr for FieldMapping {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, field))
+                if !key.is_empty()
+                    && !field.is_empty()
+                    && key.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            {
+                Ok(FieldMapping {
+                    key: key.into(),
+                    field: field.into(),
+                })
+            }
+            _ => Err(format!(
+                "Invalid output field {}, expected an alphanumeric key and an event field, e.g. 'src=SourceIp'",
+                s
+            )),
+        }
+    }
+}
+
+/// The rule's severity on the 1 to 10 scale CEF and LEEF share, rules without a level are medium.
+fn severity(rule: &ValidatedRule) -> u8 {
+    match rule.metadata.level() {
+        Some(Level::Informational) => 1,
+        Some(Level::Low) => 3,
+        Some(Level::Medium) | None => 5,
+        Some(Level::High) => 8,
+        Some(Level::Critical) => 10,
+    }
+}
+
+/// Escape a header field, which is delimited by pipes and ends at a line break.
+fn header(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// The extension pairs both formats carry after their time and severity, the rule's tags then the
+/// mapped event fields which are set, objects and arrays written as JSON.
+fn pairs(json: &Value, rule: &ValidatedRule, fields: &[FieldMapping]) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    if !rule.metadata.tags.is_empty() {
+        pairs.push(("cat".to_string(), rule.metadata.tags.join(",")));
+    }
+    for mapping in fields {
+        let value = match event::get(json, &mapping.field) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => continue,
+            Some(v) => v.to_string(),
+        };
+        pairs.push((mapping.key.clone(), value));
+    }
+    pairs
+}
+
+/// Format a match as a CEF line, named by the rule's title and timed in `rt` by the event's time.
+pub fn cef(
+    json: &Value,
+    rule: &ValidatedRule,
+    fields: &[FieldMapping],
+    time: Option<SystemTime>,
+) -> String {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('=', "\\=")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    };
+    let mut extension = Vec::new();
+    if let Some(since) = time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        extension.push(format!("rt={}", since.as_millis()));
+    }
+    for (key, value) in pairs(json, rule, fields) {
+        extension.push(format!("{}={}", key, escape(&value)));
+    }
+    format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+        header(VENDOR),
+        header(PRODUCT),
+        header(env!("CARGO_PKG_VERSION")),
+        header(&rule.id),
+        header(rule.metadata.title.as_deref().unwrap_or(&rule.name)),
+        severity(rule),
+        extension.join(" ")
+    )
+}
+
+/// Format a match as a LEEF 1.0 line, its attributes separated by tabs, timed in `devTime` by the
+/// event's time.
+pub fn leef(
+    json: &Value,
+    rule: &ValidatedRule,
+    fields: &[FieldMapping],
+    time: Option<SystemTime>,
+) -> String {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    };
+    let mut attributes = vec![
+        format!("sev={}", severity(rule)),
+        format!("ruleName={}", escape(&rule.name)),
+    ];
+    if let Some(timestamp) = time.and_then(event::rfc3339) {
+        attributes.push(format!("devTime={}", timestamp));
+        attributes.push(format!("devTimeFormat={}", LEEF_TIME_FORMAT));
+    }
+    for (key, value) in pairs(json, rule, fields) {
+        attributes.push(format!("{}={}", key, escape(&value)));
+    }
+    format!(
+        "LEEF:1.0|{}|{}|{}|{}|{}",
+        header(VENDOR),
+        header(PRODUCT),
+        header(env!("CARGO_PKG_VERSION")),
+        header(&rule.id),
+        attributes.join("\t")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_stay_on_one_line() {
+        assert_eq!(header("a|b\\c"), "a\\|b\\\\c");
+        assert_eq!(header("one\r\ntwo"), "one\\r\\ntwo");
+    }
+}
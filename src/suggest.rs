@@ -0,0 +1,114 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::input::Input;
+use crate::output::csv_escape;
+use crate::profile::walk;
+
+#[derive(StructOpt)]
+pub struct Suggest {
+    /// The example events a rule should match.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    input: Vec<PathBuf>,
+
+    /// Benign events a rule should not match, to contrast the examples with.
+    #[structopt(long, parse(from_os_str), required = true)]
+    contrast: Vec<PathBuf>,
+
+    /// The number of candidate conditions to show.
+    #[structopt(long, default_value = "10")]
+    top: usize,
+}
+
+/// The number of events each field and value pair appears in.
+type Counts = HashMap<(String, String), usize>;
+
+/// The field and value pairs in an event, each once. Only scalar values are used as they're what
+/// a rule condition compares against.
+fn pairs(json: &Value) -> HashSet<(String, String)> {
+    let mut leaves = Vec::new();
+    walk(String::new(), json, &mut leaves);
+    leaves
+        .into_iter()
+        .filter_map(|(field, value)| match value {
+            Value::String(s) => Some((field, s.clone())),
+            Value::Bool(_) | Value::Number(_) => Some((field, value.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Count how many events in the files have each field and value pair, keeping only the pairs in
+/// `only` when given.
+fn count(paths: Vec<PathBuf>, only: Option<&Counts>) -> Result<(Counts, usize), String> {
+    let (mut counts, mut events) = (HashMap::new(), 0);
+    let mut stderr = stderr();
+    for res in Input::open(Some(paths), None, None)? {
+        let json = match res {
+            Ok(json) => json,
+            Err(e) => {
+                let _ = writeln!(stderr, "{}", e);
+                continue;
+            }
+        };
+        events += 1;
+        for pair in pairs(&json) {
+            if only.map(|o| o.contains_key(&pair)).unwrap_or(true) {
+                *counts.entry(pair).or_insert(0) += 1;
+            }
+        }
+    }
+    Ok((counts, events))
+}
+
+impl Suggest {
+    pub fn run(self) -> Result<(), String> {
+        let top = self.top;
+        let (examples, example_events) = count(self.input, None)?;
+        if example_events == 0 {
+            return Err("There are no example events".into());
+        }
+        let (contrast, contrast_events) = count(self.contrast, Some(&examples))?;
+        if contrast_events == 0 {
+            return Err("There are no contrast events".into());
+        }
+        let rate = |count: usize, events: usize| count as f64 * 100.0 / events as f64;
+        // Pairs common in the examples and rare in the contrast separate them best
+        let mut candidates: Vec<(&(String, String), f64, f64)> = examples
+            .iter()
+            .map(|(pair, c)| {
+                let seen = contrast.get(pair).copied().unwrap_or(0);
+                (pair, rate(*c, example_events), rate(seen, contrast_events))
+            })
+            .filter(|(_, e, c)| e > c)
+            .collect();
+        candidates.sort_by(|a, b| {
+            (b.1 - b.2)
+                .total_cmp(&(a.1 - a.2))
+                .then(b.1.total_cmp(&a.1))
+                .then(a.0.cmp(b.0))
+        });
+        let write = || -> std::io::Result<()> {
+            let mut stdout = stdout();
+            writeln!(stdout, "Field, Value, Example Rate, Contrast Rate")?;
+            for ((field, value), e, c) in candidates.iter().take(top) {
+                writeln!(
+                    stdout,
+                    "{}, {}, {:.1}%, {:.1}%",
+                    csv_escape(field),
+                    csv_escape(value),
+                    e,
+                    c
+                )?;
+            }
+            Ok(())
+        };
+        write().map_err(|e| format!("Unable to write suggestions, {}", e))
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::event;
+use crate::output::{self, csv_escape};
+
+/// How --timechart writes its table.
+#[derive(Clone, Copy)]
+pub enum ChartFormat {
+    Csv,
+    Json,
+    /// A line of block characters for each rule, for reading in a terminal.
+    Sparkline,
+}
+
+impl FromStr for ChartFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ChartFormat::Csv),
+            "json" => Ok(ChartFormat::Json),
+            "sparkline" => Ok(ChartFormat::Sparkline),
+            _ => Err(format!(
+                "Unknown timechart format {}, expected csv, json or sparkline",
+                s
+            )),
+        }
+    }
+}
+
+/// The most buckets the gaps between matches are filled to, past it only buckets with matches are
+/// written so a single stray time can't make millions of empty rows.
+const MAX_BUCKETS: u64 = 100_000;
+
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Each rule's matches counted in time buckets of a fixed width.
+pub struct Timechart {
+    width: u64,
+    format: ChartFormat,
+    /// Counts for each rule, keyed by the start of the bucket in seconds since the unix epoch.
+    buckets: BTreeMap<u64, Vec<u64>>,
+    rules: usize,
+    /// Matches without a readable time.
+    pub untimed: u64,
+}
+
+impl Timechart {
+    pub fn new(width: Duration, format: ChartFormat, rules: usize) -> Result<Self, String> {
+        if width.as_secs() == 0 {
+            return Err("The timechart buckets must be at least a second wide".into());
+        }
+        Ok(Timechart {
+            width: width.as_secs(),
+            format,
+            buckets: BTreeMap::new(),
+            rules,
+            untimed: 0,
+        })
+    }
+
+    pub fn add(&mut self, time: Option<SystemTime>, rule: usize) {
+        let secs = match time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+            Some(d) => d.as_secs(),
+            None => {
+                self.untimed += 1;
+                return;
+            }
+        };
+        let rules = self.rules;
+        self.buckets
+            .entry(secs - secs % self.width)
+            .or_insert_with(|| vec![0; rules])[rule] += 1;
+    }
+
+    /// Every bucket from the first match to the last, including the empty ones between them unless
+    /// there are too many.
+    fn filled(&self) -> Vec<(u64, Vec<u64>)> {
+        let (first, last) = match (self.buckets.keys().next(), self.buckets.keys().last()) {
+            (Some(f), Some(l)) => (*f, *l),
+            _ => return Vec::new(),
+        };
+        if (last - first) / self.width >= MAX_BUCKETS {
+            return self.buckets.clone().into_iter().collect();
+        }
+        (first..=last)
+            .step_by(self.width as usize)
+            .map(|b| {
+                let counts = self.buckets.get(&b).cloned();
+                (b, counts.unwrap_or_else(|| vec![0; self.rules]))
+            })
+            .collect()
+    }
+
+    pub fn write(&self, names: &[&str], out: &mut impl Write) -> io::Result<()> {
+        // Buckets start on whole seconds, so the milliseconds are left off. Those past 9999 are
+        // labelled with their seconds
+        let label = |b: u64| match event::rfc3339(UNIX_EPOCH + Duration::from_secs(b)) {
+            Some(time) => time.replace(".000Z", "Z"),
+            None => b.to_string(),
+        };
+        let filled = self.filled();
+        match self.format {
+            ChartFormat::Csv => {
+                write!(out, "Bucket")?;
+                for name in names {
+                    write!(out, ", {}", csv_escape(name))?;
+                }
+                writeln!(out)?;
+                for (bucket, counts) in filled {
+                    write!(out, "{}", label(bucket))?;
+                    for count in counts {
+                        write!(out, ", {}", count)?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+            ChartFormat::Json => {
+                for (bucket, counts) in filled {
+                    let counts: BTreeMap<&str, u64> = names.iter().copied().zip(counts).collect();
+                    output::write_json(out, &json!({ "bucket": label(bucket), "counts": counts }))?;
+                }
+            }
+            ChartFormat::Sparkline => {
+                if let (Some((first, _)), Some((last, _))) = (filled.first(), filled.last()) {
+                    writeln!(out, "From {} to {}", label(*first), label(*last))?;
+                }
+                writeln!(out, "Rule, Matches, Timechart")?;
+                for (i, name) in names.iter().enumerate() {
+                    let counts: Vec<u64> = filled.iter().map(|(_, c)| c[i]).collect();
+                    let max = counts.iter().copied().max().unwrap_or(0);
+                    // Empty buckets are left blank so a single match still shows
+                    let line: String = counts
+                        .iter()
+                        .map(|c| match c {
+                            0 => ' ',
+                            c => BLOCKS[((c * 8 - 1) / max) as usize],
+                        })
+                        .collect();
+                    let total: u64 = counts.iter().sum();
+                    writeln!(out, "{}, {}, {}", csv_escape(name), total, line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv(chart: &Timechart) -> String {
+        let mut out = Vec::new();
+        chart.write(&["a.yml"], &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn empty_buckets_are_filled() {
+        let mut chart = Timechart::new(Duration::from_secs(3600), ChartFormat::Csv, 1).unwrap();
+        chart.add(Some(UNIX_EPOCH + Duration::from_secs(1_800)), 0);
+        chart.add(Some(UNIX_EPOCH + Duration::from_secs(7_300)), 0);
+        chart.add(None, 0);
+        assert_eq!(
+            csv(&chart),
+            "Bucket, a.yml\n1970-01-01T00:00:00Z, 1\n1970-01-01T01:00:00Z, 0\n1970-01-01T02:00:00Z, 1\n"
+        );
+        assert_eq!(chart.untimed, 1);
+    }
+
+    #[test]
+    fn buckets_past_9999_are_labelled_with_seconds() {
+        let mut chart = Timechart::new(Duration::from_secs(1), ChartFormat::Csv, 1).unwrap();
+        let secs = 300_000_000_000;
+        chart.add(Some(UNIX_EPOCH + Duration::from_secs(secs)), 0);
+        assert_eq!(csv(&chart), format!("Bucket, a.yml\n{}, 1\n", secs));
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::mpsc::{self, SyncSender},
+    thread,
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::artifacts::prefetch;
+use crate::evtx;
+use crate::input::Input;
+use crate::iocs::split_csv;
+use crate::mft;
+use crate::reghive;
+
+/// Events read ahead of matching, so a large collection isn't held in memory.
+const BUFFER: usize = 1024;
+
+/// Name the artifact a file in a collection holds. Velociraptor names results after the artifact,
+/// e.g. `results/Windows.System.Pslist.json`, while KAPE module output is prefixed with a
+/// timestamp, e.g. `EventLogs/20230101120000_EvtxECmd_Output.csv`.
+fn artifact(name: &str) -> String {
+    let file = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let stem = file.rsplit_once('.').map(|(s, _)| s).unwrap_or(file);
+    // Velociraptor escapes the separator between an artifact and its source
+    let stem = stem.replace("%2F", "/");
+    match stem.split_once('_') {
+        Some((timestamp, rest))
+            if !timestamp.is_empty() && timestamp.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            rest.trim_end_matches("_Output").to_string()
+        }
+        _ => stem,
+    }
+}
+
+/// Label an event with where in the collection it came from.
+fn label(mut event: Value, collection: &Path, name: &str) -> Value {
+    if let Value::Object(o) = &mut event {
+        o.insert(
+            "triage".into(),
+            json!({
+                "collection": collection.display().to_string(),
+                "file": name,
+                "artifact": artifact(name),
+            }),
+        );
+    }
+    event
+}
+
+/// Parse a file from a collection by its extension, or for raw registry hives and `$MFT` files by
+/// their first bytes, sending its events. `on_disk` is the file's path when the collection is a
+/// directory. Other files, such as uploads of other kinds, are skipped. Returns false once matching
+/// has stopped.
+fn read_file(
+    collection: &Path,
+    name: &str,
+    on_disk: Option<&Path>,
+    mut reader: impl BufRead,
+    tx: &SyncSender<Result<Value, String>>,
+) -> bool {
+    let file = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(name)
+        .to_lowercase();
+    let extension = file.rsplit_once('.').map(|(_, e)| e.to_string());
+    let send = &mut |event: Result<Value, String>| {
+        tx.send(event.map(|e| label(e, collection, name))).is_ok()
+    };
+    // Hives are named e.g. SYSTEM, NTUSER.DAT or Amcache.hve, their transaction logs aren't read
+    if matches!(extension.as_deref(), None | Some("dat") | Some("hve")) {
+        let magic = match reader.fill_buf() {
+            Ok(b) => b.get(..4).map(|m| m.to_vec()),
+            Err(e) => return send(Err(format!("Unable to read {}, {}", name, e))),
+        };
+        let mut data = Vec::new();
+        match magic.as_deref() {
+            Some(b"regf") => {
+                return match reader.read_to_end(&mut data) {
+                    Ok(_) => reghive::events(data, name, send),
+                    Err(e) => send(Err(format!("Unable to read {}, {}", name, e))),
+                };
+            }
+            Some(b"FILE") if file == "$mft" => {
+                // A zipped MFT is held in memory as it's read twice, one on disk is opened again
+                let mut open: Box<dyn FnMut() -> io::Result<Box<dyn Read>>> = match on_disk {
+                    Some(path) => {
+                        let path = path.to_path_buf();
+                        Box::new(move || Ok(Box::new(fs::File::open(&path)?) as Box<dyn Read>))
+                    }
+                    None => {
+                        if let Err(e) = reader.read_to_end(&mut data) {
+                            return send(Err(format!("Unable to read {}, {}", name, e)));
+                        }
+                        let data: Rc<[u8]> = data.into();
+                        Box::new(move || {
+                            Ok(Box::new(io::Cursor::new(data.clone())) as Box<dyn Read>)
+                        })
+                    }
+                };
+                return mft::mft_events(&mut open, name, send);
+            }
+            _ => {}
+        }
+    }
+    let mut lines = reader.by_ref().lines();
+    match extension.as_deref() {
+        Some("json") | Some("jsonl") | Some("ndjson") => {
+            for line in lines.map_while(Result::ok) {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let events = match serde_json::from_str(line) {
+                    // Some tools write results as a single array
+                    Ok(Value::Array(a)) => a.into_iter().map(Ok).collect(),
+                    Ok(v) => vec![Ok(v)],
+                    Err(e) => vec![Err(format!("Invalid JSON in {}, {}", name, e))],
+                };
+                for event in events {
+                    if !send(event) {
+                        return false;
+                    }
+                }
+            }
+        }
+        Some("csv") => {
+            let header = match lines.next() {
+                Some(Ok(h)) => split_csv(h.trim_start_matches('\u{feff}')),
+                _ => return true,
+            };
+            for line in lines.map_while(Result::ok) {
+                let row: Map<String, Value> = header
+                    .iter()
+                    .cloned()
+                    .zip(split_csv(&line).into_iter().map(Value::String))
+                    .collect();
+                if !send(Ok(Value::Object(row))) {
+                    return false;
+                }
+            }
+        }
+        Some("pf") => {
+            let mut data = Vec::new();
+            let event = match reader.read_to_end(&mut data) {
+                Ok(_) => prefetch(&data, name),
+                Err(e) => Err(format!("Unable to read {}, {}", name, e)),
+            };
+            return send(event);
+        }
+        Some("evtx") => return evtx::events(reader, name, send),
+        Some("body") | Some("bodyfile") => return mft::bodyfile_events(reader, name, send),
+        None if file == "bodyfile" => return mft::bodyfile_events(reader, name, send),
+        _ => {}
+    }
+    true
+}
+
+/// Every file under a directory, in a stable order.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.flatten().map(|e| e.path()).collect();
+    entries.sort();
+    for path in entries {
+        match path.is_dir() {
+            true => walk(&path, files)?,
+            false => files.push(path),
+        }
+    }
+    Ok(())
+}
+
+fn read_collection(path: &Path, tx: &SyncSender<Result<Value, String>>) -> Result<bool, String> {
+    if path.is_dir() {
+        let mut files = Vec::new();
+        walk(path, &mut files).map_err(|e| format!("Unable to read {}, {}", path.display(), e))?;
+        for file in files {
+            let name = file
+                .strip_prefix(path)
+                .unwrap_or(&file)
+                .display()
+                .to_string();
+            let reader = match fs::File::open(&file) {
+                Ok(f) => BufReader::new(f),
+                Err(e) => {
+                    let _ = tx.send(Err(format!("Unable to read {}, {}", file.display(), e)));
+                    continue;
+                }
+            };
+            if !read_file(path, &name, Some(&file), reader, tx) {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+    let file = fs::File::open(path)
+        .map_err(|_| format!("Unable to read input file at {}.", path.display()))?;
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| {
+        format!(
+            "{} is not a collection zip or directory, {}",
+            path.display(),
+            e
+        )
+    })?;
+    for i in 0..zip.len() {
+        let entry = match zip.by_index(i) {
+            Ok(e) if e.is_file() => e,
+            Ok(_) => continue,
+            Err(e) => {
+                let _ = tx.send(Err(format!("Unable to read {}, {}", path.display(), e)));
+                continue;
+            }
+        };
+        let name = entry.name().to_string();
+        if !read_file(path, &name, None, BufReader::new(entry), tx) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Read triage collections, Velociraptor offline collector zips or KAPE output directories, as
+/// events. JSON and CSV results, event logs, prefetch files, registry hives, `$MFT` files and
+/// bodyfiles are parsed and labelled with the collection, file and artifact in `triage`.
+pub fn open(paths: Vec<PathBuf>) -> Result<Input, String> {
+    for path in paths.iter() {
+        if !path.exists() {
+            return Err(format!("Unable to read input file at {}.", path.display()));
+        }
+    }
+    let (tx, rx) = mpsc::sync_channel(BUFFER);
+    thread::spawn(move || {
+        for path in paths {
+            match read_collection(&path, &tx) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    if tx.send(Err(e)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(Input::Channel(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifacts_are_routed_by_name_and_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Registry")).unwrap();
+        fs::write(dir.path().join("Registry/SYSTEM"), b"regf").unwrap();
+        fs::write(dir.path().join("Registry/SYSTEM.LOG1"), b"regf").unwrap();
+        fs::write(
+            dir.path().join("bodyfile"),
+            "0|/etc/passwd|1|-rw-r--r--|0|0|10|1700000000|1700000000|1700000000|0\n",
+        )
+        .unwrap();
+        let events: Vec<_> = open(vec![dir.path().to_path_buf()])
+            .unwrap()
+            .map(|e| e.map_err(|e| e.to_string()))
+            .collect();
+        assert_eq!(events.len(), 2);
+        let event = events[1].as_ref().unwrap();
+        assert_eq!(event["name"], "/etc/passwd");
+        assert_eq!(event["triage"]["file"], "bodyfile");
+        assert_eq!(
+            events[0].as_ref().unwrap_err(),
+            &format!(
+                "{} is not a registry hive",
+                Path::new("Registry").join("SYSTEM").display()
+            )
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{stderr, Write};
+
+use serde::Deserialize;
+use structopt::StructOpt;
+
+/// The version being run.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(StructOpt)]
+pub struct SelfUpdate {
+    /// The https release endpoint, returning JSON with the release's 'version', 'url' and optional 'signature' url.
+    #[structopt(long)]
+    url: String,
+
+    /// The minisign public key releases are signed with, by default the key tau-cli was built with in TAU_UPDATE_PUBLIC_KEY.
+    #[structopt(long)]
+    public_key: Option<String>,
+
+    /// Only report whether a newer release is available.
+    #[structopt(long)]
+    check: bool,
+
+    /// Reinstall the release when it's the running version, older releases are never installed.
+    #[structopt(short = "f", long)]
+    force: bool,
+}
+
+/// The latest release, as described by the release endpoint.
+#[derive(Deserialize)]
+struct Release {
+    version: String,
+    url: String,
+    signature: Option<String>,
+}
+
+/// Fail unless a url is https, so the release and its signature can't be swapped in transit.
+fn https(url: &str) -> Result<&str, String> {
+    match url.starts_with("https://") {
+        true => Ok(url),
+        false => Err(format!(
+            "Updates are only downloaded over https, not {}",
+            url
+        )),
+    }
+}
+
+/// The version a release's signature vouches for, from its trusted comment of the form
+/// `tau-cli <version> <target>`. The manifest's version isn't signed, so it can't be relied on.
+fn signed_version<'a>(comment: &'a str, target: &str) -> Result<&'a str, String> {
+    let mut words = comment.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("tau-cli"), Some(version), Some(t)) if t == target => Ok(version),
+        (Some("tau-cli"), Some(_), Some(t)) => {
+            Err(format!("The release is signed for {}, not {}", t, target))
+        }
+        _ => Err(format!(
+            "The signature's trusted comment {:?} isn't of the form 'tau-cli <version> <target>'",
+            comment
+        )),
+    }
+}
+
+/// The numeric parts of a version, ignoring a leading 'v' and any pre-release or build suffix.
+fn parts(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(&['-', '+'][..])
+        .next()
+        .unwrap_or("")
+        .split('.')
+        .map(|p| p.parse().unwrap_or(0))
+        .collect()
+}
+
+impl SelfUpdate {
+    pub fn run(self) -> Result<(), String> {
+        let target = format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS);
+        let url = self.url.replace("{target}", &target);
+        https(&url)?;
+        let release: Release = ureq::get(&url)
+            .call()
+            .map_err(|e| format!("Unable to reach the release endpoint {}, {}", url, e))?
+            .into_json()
+            .map_err(|e| format!("Unable to read the release from {}, {}", url, e))?;
+        let mut stderr = stderr();
+        let newer = parts(&release.version) > parts(VERSION);
+        if self.check {
+            let _ = match newer {
+                true => writeln!(
+                    stderr,
+                    "tau-cli {} is available, {} is running",
+                    release.version, VERSION
+                ),
+                false => writeln!(stderr, "tau-cli {} is up to date", VERSION),
+            };
+            return Ok(());
+        }
+        if !newer && !self.force {
+            let _ = writeln!(stderr, "tau-cli {} is up to date", VERSION);
+            return Ok(());
+        }
+        let key = self
+            .public_key
+            .or_else(|| option_env!("TAU_UPDATE_PUBLIC_KEY").map(String::from))
+            .ok_or("A --public-key is required, this tau-cli was built without one")?;
+        let binary = release.url.replace("{target}", &target);
+        let signature = match &release.signature {
+            Some(s) => s.replace("{target}", &target),
+            None => format!("{}.minisig", binary),
+        };
+        let check = |comment: &str| {
+            let version = signed_version(comment, &target)?;
+            if version != release.version {
+                return Err(format!(
+                    "The release endpoint offers {} but the binary is signed as {}",
+                    release.version, version
+                ));
+            }
+            match parts(version) < parts(VERSION) {
+                true => Err(format!(
+                    "Refusing to downgrade from {} to {}",
+                    VERSION, version
+                )),
+                false => Ok(()),
+            }
+        };
+        let path = install::install(https(&binary)?, https(&signature)?, &key, &check)?;
+        let _ = writeln!(
+            stderr,
+            "Updated {} from {} to {}",
+            path.display(),
+            VERSION,
+            release.version
+        );
+        Ok(())
+    }
+}
+
+#[cfg(feature = "self-update")]
+mod install {
+    use std::{
+        env, fs,
+        io::{self, Read, Write},
+        path::{Path, PathBuf},
+        process,
+    };
+
+    use minisign_verify::{PublicKey, Signature};
+
+    /// The largest binary downloaded, anything bigger isn't a release of tau-cli.
+    const MAX_BINARY: u64 = 256 * 1024 * 1024;
+
+    fn download(url: &str, limit: u64) -> Result<Vec<u8>, String> {
+        let response = ureq::get(url)
+            .call()
+            .map_err(|e| format!("Unable to download {}, {}", url, e))?;
+        let mut data = Vec::new();
+        response
+            .into_reader()
+            .take(limit)
+            .read_to_end(&mut data)
+            .map_err(|e| format!("Unable to download {}, {}", url, e))?;
+        Ok(data)
+    }
+
+    /// Write the new binary beside the old one then rename it over the top, so a failure part
+    /// way leaves the old binary in place and a running tau-cli never sees a partial file.
+    fn replace(exe: &Path, binary: &[u8]) -> io::Result<()> {
+        let dir = exe.parent().unwrap_or_else(|| Path::new("."));
+        let temp = dir.join(format!(".tau-cli.update-{}", process::id()));
+        let result = (|| {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)?;
+            file.write_all(binary)?;
+            file.set_permissions(fs::metadata(exe)?.permissions())?;
+            file.sync_all()?;
+            fs::rename(&temp, exe)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// Download the release and check its signature, then `check` its signed trusted comment,
+    /// before replacing the running binary, returning the path of the binary replaced.
+    pub fn install(
+        url: &str,
+        signature_url: &str,
+        key: &str,
+        check: &dyn Fn(&str) -> Result<(), String>,
+    ) -> Result<PathBuf, String> {
+        let key = PublicKey::from_base64(key.trim())
+            .or_else(|_| PublicKey::decode(key))
+            .map_err(|e| format!("Invalid public key, {}", e))?;
+        let signature = download(signature_url, 64 * 1024)?;
+        let signature = Signature::decode(&String::from_utf8_lossy(&signature))
+            .map_err(|e| format!("Invalid signature at {}, {}", signature_url, e))?;
+        let binary = download(url, MAX_BINARY)?;
+        key.verify(&binary, &signature, false).map_err(|e| {
+            format!(
+                "The signature of {} does not match the public key, {}",
+                url, e
+            )
+        })?;
+        check(signature.trusted_comment())?;
+        let exe = env::current_exe()
+            .and_then(fs::canonicalize)
+            .map_err(|e| format!("Unable to find the running binary, {}", e))?;
+        replace(&exe, &binary)
+            .map_err(|e| format!("Unable to replace {}, {}", exe.display(), e))?;
+        Ok(exe)
+    }
+}
+
+#[cfg(not(feature = "self-update"))]
+mod install {
+    use std::path::PathBuf;
+
+    pub fn install(
+        _url: &str,
+        _signature_url: &str,
+        _key: &str,
+        _check: &dyn Fn(&str) -> Result<(), String>,
+    ) -> Result<PathBuf, String> {
+        Err("Updating requires tau-cli built with the self-update feature".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_signed_for_a_target() {
+        let target = "x86_64-linux";
+        assert_eq!(
+            signed_version("tau-cli 0.2.0 x86_64-linux", target),
+            Ok("0.2.0")
+        );
+        assert!(signed_version("tau-cli 0.2.0 aarch64-macos", target).is_err());
+        assert!(signed_version("timestamp:1700000000 file:tau-cli", target).is_err());
+    }
+
+    #[test]
+    fn only_https() {
+        assert!(https("https://releases.example.com/latest.json").is_ok());
+        assert!(https("http://releases.example.com/latest.json").is_err());
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(parts("v0.10.0") > parts("0.9.1"));
+        assert!(parts("0.2.0-rc1") == parts("0.2.0"));
+    }
+}
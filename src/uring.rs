@@ -0,0 +1,197 @@
+use std::path::PathBuf;
+
+use crate::input::{Input, Options};
+
+/// Read JSON lines files with io_uring, keeping several reads in flight at once and reusing their
+/// buffers, which suits fast NVMe storage better than one blocking read at a time.
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub fn open(paths: Vec<PathBuf>, options: Options) -> Result<Input, String> {
+    use std::{sync::mpsc, thread};
+
+    for path in paths.iter() {
+        if !path.is_file() {
+            return Err(format!("Unable to read input file at {}.", path.display()));
+        }
+        if crate::input::is_compressed(path) {
+            return Err(format!(
+                "{} is compressed, which --io-uring can't read",
+                path.display()
+            ));
+        }
+    }
+    let (tx, rx) = mpsc::sync_channel(ring::DEPTH);
+    thread::spawn(move || {
+        for path in paths {
+            let result = ring::read(&path, &options, &mut |batch| tx.send(batch).is_ok());
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    let error = format!("Unable to read {}, {}", path.display(), e);
+                    if tx.send(vec![Err(error)]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(Input::Batches {
+        rx,
+        batch: Vec::new().into_iter(),
+    })
+}
+
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+pub fn open(_paths: Vec<PathBuf>, _options: Options) -> Result<Input, String> {
+    Err("io_uring reading requires Linux and tau-cli built with the uring feature".into())
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod ring {
+    use std::{collections::BTreeMap, fs, io, os::unix::io::AsRawFd, path::Path};
+
+    use io_uring::{opcode, types, IoUring};
+    use serde_json::Value;
+
+    use crate::input::Options;
+    use crate::lenient;
+
+    /// The number of reads kept in flight, each with its own buffer.
+    pub const DEPTH: usize = 8;
+    /// The size of each read.
+    const BLOCK: usize = 1024 * 1024;
+
+    type Batch = Vec<Result<Value, String>>;
+
+    /// Split a block into lines, joining the start of the block to the partial line left by the
+    /// previous one and keeping its own partial last line.
+    fn lines(block: &[u8], partial: &mut Vec<u8>, options: &Options, batch: &mut Batch) {
+        let mut start = 0;
+        for (end, _) in block.iter().enumerate().filter(|(_, b)| **b == b'\n') {
+            match partial.is_empty() {
+                true => lenient::parse_into(&block[start..end], options.lenient, batch),
+                false => {
+                    partial.extend_from_slice(&block[start..end]);
+                    lenient::parse_into(partial, options.lenient, batch);
+                    partial.clear();
+                }
+            }
+            start = end + 1;
+        }
+        partial.extend_from_slice(&block[start..]);
+    }
+
+    /// The reads of one file, with the buffers they fill.
+    struct Reads {
+        ring: IoUring,
+        buffers: Vec<Vec<u8>>,
+        free: Vec<usize>,
+        offsets: [u64; DEPTH],
+        in_flight: usize,
+        options: Options,
+    }
+
+    impl Reads {
+        fn run(
+            &mut self,
+            file: &fs::File,
+            len: u64,
+            send: &mut dyn FnMut(Batch) -> bool,
+        ) -> io::Result<bool> {
+            // Reads complete in any order, so finished blocks wait here until those before them are done
+            let mut done: BTreeMap<u64, (usize, usize)> = BTreeMap::new();
+            let (mut next, mut consumed) = (0u64, 0u64);
+            let mut partial = Vec::new();
+            loop {
+                while next < len {
+                    let i = match self.free.pop() {
+                        Some(i) => i,
+                        None => break,
+                    };
+                    let size = (len - next).min(BLOCK as u64) as u32;
+                    let read = opcode::Read::new(
+                        types::Fd(file.as_raw_fd()),
+                        self.buffers[i].as_mut_ptr(),
+                        size,
+                    )
+                    .offset(next)
+                    .build()
+                    .user_data(i as u64);
+                    // The buffer isn't touched again until its read completes
+                    unsafe { self.ring.submission().push(&read) }
+                        .map_err(|_| io::Error::other("submission queue full"))?;
+                    self.offsets[i] = next;
+                    next += size as u64;
+                    self.in_flight += 1;
+                }
+                if self.in_flight == 0 {
+                    break;
+                }
+                self.ring.submit_and_wait(1)?;
+                for cqe in self.ring.completion() {
+                    self.in_flight -= 1;
+                    let i = cqe.user_data() as usize;
+                    let expected = (len - self.offsets[i]).min(BLOCK as u64) as i32;
+                    match cqe.result() {
+                        n if n < 0 => return Err(io::Error::from_raw_os_error(-n)),
+                        n if n != expected => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "file changed while being read",
+                            ))
+                        }
+                        n => {
+                            done.insert(self.offsets[i], (i, n as usize));
+                        }
+                    }
+                }
+                while let Some((i, n)) = done.remove(&consumed) {
+                    let mut batch = Vec::new();
+                    lines(
+                        &self.buffers[i][..n],
+                        &mut partial,
+                        &self.options,
+                        &mut batch,
+                    );
+                    consumed += n as u64;
+                    self.free.push(i);
+                    if !batch.is_empty() && !send(batch) {
+                        return Ok(false);
+                    }
+                }
+            }
+            if !partial.is_empty() {
+                let mut batch = Vec::new();
+                lenient::parse_into(&partial, self.options.lenient, &mut batch);
+                return Ok(send(batch));
+            }
+            Ok(true)
+        }
+    }
+
+    /// Read a file, calling `send` with the events of each block in file order. Returns false once
+    /// `send` does.
+    pub fn read(
+        path: &Path,
+        options: &Options,
+        send: &mut dyn FnMut(Batch) -> bool,
+    ) -> io::Result<bool> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reads = Reads {
+            ring: IoUring::new(DEPTH as u32)?,
+            buffers: (0..DEPTH).map(|_| vec![0; BLOCK]).collect(),
+            free: (0..DEPTH).collect(),
+            offsets: [0; DEPTH],
+            in_flight: 0,
+            options: *options,
+        };
+        let result = reads.run(&file, len, send);
+        // Reads still in flight write to the buffers, so they must finish before the buffers are freed
+        while reads.in_flight > 0 {
+            reads.ring.submit_and_wait(1)?;
+            reads.in_flight -= reads.ring.completion().count();
+        }
+        result
+    }
+}
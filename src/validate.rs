@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+use tau_engine::Rule;
+
+use crate::output::csv_escape;
+use crate::paths;
+use crate::rules::{self, Claim, Metadata};
+
+#[derive(StructOpt)]
+pub struct Validate {
+    /// The rules to check.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+}
+
+impl Validate {
+    pub fn run(self) -> Result<(), String> {
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let mut taken = HashMap::new();
+        let mut invalid = 0;
+        let _ = writeln!(stdout, "Rule, Path, Is Valid");
+        let rules = paths::expand(&self.rules, Some(paths::RULE_FILES))?;
+        for path in rules.iter() {
+            let data = fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+            let metadata = Metadata::load(&data);
+            let id = rules::id(path, &metadata)?;
+            let error = match Rule::load(&data) {
+                Ok(_) => metadata
+                    .phase()
+                    .and_then(|_| rules::claim(&mut taken, Claim::Id, &id, path))
+                    .err(),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(e) = &error {
+                invalid += 1;
+                let _ = writeln!(stderr, "{}: {}", path.display(), e);
+            }
+            let _ = writeln!(
+                stdout,
+                "{}, {}, {}",
+                csv_escape(&id),
+                csv_escape(&path.display().to_string()),
+                error.is_none()
+            );
+        }
+        match invalid {
+            0 => Ok(()),
+            n => Err(format!("{} of {} rules are invalid", n, rules.len())),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Test {
+    /// The rules to test.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+}
+
+/// A rule's tests, the events listed under a key such as `true_positives`.
+fn tests(yaml: &serde_yaml::Value, key: &str) -> Vec<Value> {
+    yaml.get(key)
+        .and_then(|v| serde_json::to_value(v).ok())
+        .and_then(|v| match v {
+            Value::Array(a) => Some(a),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+impl Test {
+    pub fn run(self) -> Result<(), String> {
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let mut failed_rules = 0;
+        let _ = writeln!(stdout, "Rule, Passed, Failed");
+        let rules = paths::expand(&self.rules, Some(paths::RULE_FILES))?;
+        for path in rules.iter() {
+            let data = fs::read_to_string(path)
+                .map_err(|_| format!("Unable to read data from {}.", path.display()))?;
+            let id = rules::id(path, &Metadata::load(&data))?;
+            let rule = Rule::load(&data)
+                .map_err(|e| format!("Unable to load {} as a rule, {}", path.display(), e))?;
+            let yaml: serde_yaml::Value = serde_yaml::from_str(&data)
+                .map_err(|e| format!("Unable to parse {}, {}", path.display(), e))?;
+            let (mut passed, mut failed) = (0, 0);
+            for (key, description, expected) in [
+                ("true_positives", "true positive", true),
+                ("true_negatives", "true negative", false),
+            ] {
+                for (i, event) in tests(&yaml, key).iter().enumerate() {
+                    if rule.matches(event) == expected {
+                        passed += 1;
+                        continue;
+                    }
+                    failed += 1;
+                    let _ = writeln!(
+                        stderr,
+                        "{} {} {} {}, {}",
+                        id,
+                        description,
+                        i + 1,
+                        match expected {
+                            true => "did not match",
+                            false => "matched",
+                        },
+                        event
+                    );
+                }
+            }
+            if failed > 0 {
+                failed_rules += 1;
+            }
+            let _ = writeln!(stdout, "{}, {}, {}", csv_escape(&id), passed, failed);
+        }
+        match failed_rules {
+            0 => Ok(()),
+            n => Err(format!("{} of {} rules failed their tests", n, rules.len())),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::io::{stdout, Write};
+
+use serde_json::json;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+pub struct Version {
+    /// Write the build information as JSON, for scripts to check.
+    #[structopt(long)]
+    json: bool,
+}
+
+impl Version {
+    pub fn run(self) -> Result<(), String> {
+        let features: Vec<&str> = env!("TAU_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .collect();
+        let out = match self.json {
+            true => json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "tau_engine": env!("TAU_ENGINE_VERSION"),
+                "features": features,
+                "commit": env!("TAU_COMMIT"),
+                "target": env!("TAU_TARGET"),
+            })
+            .to_string(),
+            false => format!(
+                "tau-cli {}\ntau-engine {}\nfeatures {}\ncommit {}\ntarget {}",
+                env!("CARGO_PKG_VERSION"),
+                env!("TAU_ENGINE_VERSION"),
+                match features.is_empty() {
+                    true => "none".into(),
+                    false => features.join(", "),
+                },
+                env!("TAU_COMMIT"),
+                env!("TAU_TARGET"),
+            ),
+        };
+        writeln!(stdout(), "{}", out).map_err(|e| format!("Unable to write the version, {}", e))
+    }
+}
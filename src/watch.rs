@@ -0,0 +1,156 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use notify::Watcher as _;
+
+use crate::input::{Input, OnProcessed};
+
+/// How long a directory must go without changes before new files in it are read, or changed rules
+/// reloaded, so files still being written aren't read partially.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// Watches a directory for files dropped into it, handing each one out once.
+pub struct DirWatcher {
+    dir: PathBuf,
+    // Dropping the watcher stops the notifications
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    seen: HashSet<PathBuf>,
+    on_processed: Option<OnProcessed>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: &Path, on_processed: Option<OnProcessed>) -> Result<Self, String> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)
+            .map_err(|e| format!("Unable to watch {}, {}", dir.display(), e))?;
+        watcher
+            .watch(dir, notify::RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Unable to watch {}, {}", dir.display(), e))?;
+        Ok(DirWatcher {
+            dir: dir.to_path_buf(),
+            _watcher: watcher,
+            events,
+            seen: HashSet::new(),
+            on_processed,
+        })
+    }
+
+    /// Block until there are files in the directory which haven't been read yet, files already in
+    /// the directory are returned first.
+    pub fn next_input(&mut self) -> Result<(Input, Vec<PathBuf>), String> {
+        loop {
+            let mut paths: Vec<PathBuf> = fs::read_dir(&self.dir)
+                .map_err(|e| format!("Unable to read {}, {}", self.dir.display(), e))?
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.is_file() && !self.seen.contains(p) && !OnProcessed::is_marked(p))
+                .collect();
+            if !paths.is_empty() {
+                paths.sort();
+                self.seen.extend(paths.iter().cloned());
+                // Input reads the paths from the end
+                let input = Input::open(Some(paths.iter().rev().cloned().collect()), None, None)?;
+                return Ok((input, paths));
+            }
+            self.events
+                .recv()
+                .map_err(|_| format!("Stopped watching {}", self.dir.display()))?
+                .map_err(|e| format!("Unable to watch {}, {}", self.dir.display(), e))?;
+            while self.events.recv_timeout(SETTLE).is_ok() {}
+        }
+    }
+
+    /// Apply the on processed action to files returned by `next_input` once they have been matched.
+    pub fn processed(&mut self, paths: &[PathBuf]) -> Result<(), String> {
+        if let Some(action) = &self.on_processed {
+            for path in paths {
+                action.apply(path)?;
+                // Once the file is gone a new file with the same name should be read
+                if !matches!(action, OnProcessed::Mark) {
+                    self.seen.remove(path);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Watches rule files for changes, so they can be reloaded without restarting.
+pub struct RuleWatcher {
+    _watcher: notify::RecommendedWatcher,
+    /// The rules changed, sent once they have gone without changes for a moment so a rule isn't
+    /// read while it's still being saved.
+    changed: mpsc::Receiver<HashSet<PathBuf>>,
+}
+
+impl RuleWatcher {
+    /// Watch the directories holding the rules rather than the files themselves, as editors save
+    /// by replacing the file.
+    pub fn new(rules: &[PathBuf]) -> Result<Self, String> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(tx).map_err(|e| format!("Unable to watch rules, {}", e))?;
+        // The rule paths as given, by their absolute path as notify reports it
+        let mut paths = HashMap::new();
+        let mut dirs = HashSet::new();
+        for path in rules {
+            let dir = match path.parent() {
+                Some(d) if !d.as_os_str().is_empty() => d,
+                _ => Path::new("."),
+            };
+            let dir = fs::canonicalize(dir)
+                .map_err(|e| format!("Unable to watch {}, {}", path.display(), e))?;
+            if let Some(name) = path.file_name() {
+                paths.insert(dir.join(name), path.clone());
+            }
+            if dirs.insert(dir.clone()) {
+                watcher
+                    .watch(&dir, notify::RecursiveMode::NonRecursive)
+                    .map_err(|e| format!("Unable to watch {}, {}", dir.display(), e))?;
+            }
+        }
+        let (tx, changed) = mpsc::channel();
+        thread::spawn(move || {
+            let rules = |event: notify::Result<notify::Event>| match event {
+                Ok(e) if !matches!(e.kind, notify::EventKind::Access(_)) => e
+                    .paths
+                    .iter()
+                    .filter_map(|p| paths.get(p).cloned())
+                    .collect(),
+                _ => Vec::new(),
+            };
+            while let Ok(event) = events.recv() {
+                let mut changed: HashSet<PathBuf> = rules(event).into_iter().collect();
+                if changed.is_empty() {
+                    continue;
+                }
+                while let Ok(event) = events.recv_timeout(SETTLE) {
+                    changed.extend(rules(event));
+                }
+                if tx.send(changed).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(RuleWatcher {
+            _watcher: watcher,
+            changed,
+        })
+    }
+
+    /// The rules which have changed since last asked.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = HashSet::new();
+        while let Ok(paths) = self.changed.try_recv() {
+            changed.extend(paths);
+        }
+        changed.into_iter().collect()
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    io::{stderr, stdout, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::event;
+use crate::fuzz::delete;
+use crate::input::Input;
+use crate::output::csv_escape;
+use crate::rules;
+
+/// A field to override, given as `field=value`.
+pub struct Assignment {
+    field: String,
+    value: Value,
+}
+
+impl FromStr for Assignment {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s
+            .split_once('=')
+            .filter(|(f, _)| !f.is_empty())
+            .ok_or_else(|| format!("Expected field=value, got {}", s))?;
+        // Values which read as JSON are taken as it, so numbers stay numbers
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+        Ok(Assignment {
+            field: field.to_string(),
+            value,
+        })
+    }
+}
+
+#[derive(StructOpt)]
+pub struct WhatIf {
+    /// The rules to check.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    rules: Vec<PathBuf>,
+
+    /// The events to change, as JSON.
+    #[structopt(short, long, parse(from_os_str), required = true)]
+    input: Vec<PathBuf>,
+
+    /// A field to override as field=value, e.g. 'process.name=notepad.exe'. Values are read as JSON when they can be.
+    #[structopt(long, number_of_values = 1)]
+    set: Vec<Assignment>,
+
+    /// A field to remove from the events.
+    #[structopt(long, number_of_values = 1)]
+    unset: Vec<String>,
+}
+
+impl WhatIf {
+    pub fn run(self) -> Result<(), String> {
+        if self.set.is_empty() && self.unset.is_empty() {
+            return Err("Nothing to change, use --set or --unset".into());
+        }
+        let rules = rules::load(&self.rules)?;
+        let (mut stdout, mut stderr) = (stdout(), stderr());
+        let (mut events, mut changed) = (0, 0);
+        let _ = writeln!(stdout, "Event, Rule, Before, After");
+        for (i, res) in Input::open(Some(self.input.clone()), None, None)?.enumerate() {
+            let json = match res {
+                Ok(json) => json,
+                Err(e) => {
+                    let _ = writeln!(stderr, "{}", e);
+                    continue;
+                }
+            };
+            events += 1;
+            let mut changed_json = json.clone();
+            for field in self.unset.iter() {
+                delete(&mut changed_json, field);
+            }
+            for Assignment { field, value } in self.set.iter() {
+                event::set(&mut changed_json, field, value.clone());
+            }
+            for (name, rule) in rules.iter() {
+                let (before, after) = (rule.matches(&json), rule.matches(&changed_json));
+                if before != after {
+                    changed += 1;
+                    let _ = writeln!(
+                        stdout,
+                        "{}, {}, {}, {}",
+                        i + 1,
+                        csv_escape(name),
+                        before,
+                        after
+                    );
+                }
+            }
+        }
+        let _ = writeln!(
+            stderr,
+            "{} verdicts changed across {} events and {} rules",
+            changed,
+            events,
+            rules.len()
+        );
+        Ok(())
+    }
+}